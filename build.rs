@@ -0,0 +1,30 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Let the Node.js addon resolve N-API symbols from the node binary
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    // Generate the C header from the extern "C" functions into OUT_DIR, leaving
+    // the source tree alone. A test checks include/wordpiece_rs.h against it.
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("failed to generate the C header")
+            .write_to_file(std::path::Path::new(&out_dir).join("wordpiece_rs.h"));
+    }
+
+    // Generate the gRPC service, with the bundled protoc unless PROTOC points to another
+    #[cfg(feature = "grpc")]
+    {
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("no bundled protoc for this platform, set PROTOC");
+            std::env::set_var("PROTOC", protoc);
+        }
+        tonic_prost_build::compile_protos("proto/wordpiece.proto").expect("failed to compile proto/wordpiece.proto");
+    }
+}
@@ -0,0 +1,95 @@
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#ifndef WORDPIECE_RS_H
+#define WORDPIECE_RS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A WordPiece tokenizer. It is `Send + Sync`, so one tokenizer can serve many
+// threads, and cloning it is cheap: clones share the vocabulary, its trie and
+// the word cache until one of them is changed, e.g. by `add_tokens`.
+typedef struct WordPieceTokenizer WordPieceTokenizer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The message of the last error raised on the calling thread, or NULL. The
+// string is owned by the library and valid until the next failing call.
+const char *wordpiece_last_error(void);
+
+// Load a tokenizer from a `vocab.txt` file, one token per line with the line
+// number as its id. `unk_token` may be NULL for `[UNK]`. Returns NULL on error.
+//
+// # Safety
+//
+// `path` must be a NUL-terminated string, and `unk_token` one or NULL.
+struct WordPieceTokenizer *wordpiece_tokenizer_from_vocab_file(const char *path,
+                                                               const char *unk_token,
+                                                               bool lowercase,
+                                                               bool strip_accents);
+
+// Load a tokenizer from a file written by `save_compiled`. Returns NULL on error.
+//
+// # Safety
+//
+// `path` must be a NUL-terminated string.
+struct WordPieceTokenizer *wordpiece_tokenizer_load_compiled(const char *path);
+
+// Free a tokenizer. Does nothing when `tokenizer` is NULL.
+//
+// # Safety
+//
+// `tokenizer` must come from one of the `wordpiece_tokenizer_*` constructors
+// and not be used afterwards.
+void wordpiece_tokenizer_free(struct WordPieceTokenizer *tokenizer);
+
+// Number of tokens in the vocabulary
+//
+// # Safety
+//
+// `tokenizer` must be a live tokenizer.
+size_t wordpiece_vocab_size(const struct WordPieceTokenizer *tokenizer);
+
+// Encode `len` bytes of UTF-8 `text` into `ids`, which has room for `capacity`
+// ids. Returns the number of ids of the text, writing only the first
+// `capacity` of them when there are more, so a call with a capacity of 0
+// counts the tokens. Returns -1 on error, such as a text over the tokenizer's
+// `max_input_bytes` or `max_tokens`.
+//
+// # Safety
+//
+// `tokenizer` must be a live tokenizer, `text` must point to `len` bytes, and
+// `ids` to room for `capacity` ids.
+ptrdiff_t wordpiece_encode(const struct WordPieceTokenizer *tokenizer,
+                           const char *text,
+                           size_t len,
+                           uint32_t *ids,
+                           size_t capacity);
+
+// Decode `len` ids into `text`, which has room for `capacity` bytes, as a
+// NUL-terminated UTF-8 string. Returns the length of the text in bytes without
+// the terminator. When it is longer than `capacity - 1`, as many whole
+// characters as fit are written, much as `snprintf` does. Returns -1 on error.
+//
+// # Safety
+//
+// `tokenizer` must be a live tokenizer, `ids` must point to `len` ids, and
+// `text` to room for `capacity` bytes.
+ptrdiff_t wordpiece_decode(const struct WordPieceTokenizer *tokenizer,
+                           const uint32_t *ids,
+                           size_t len,
+                           bool skip_special_tokens,
+                           bool clean_up_tokenization_spaces,
+                           char *text,
+                           size_t capacity);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WORDPIECE_RS_H */
@@ -0,0 +1,109 @@
+package io.github.novastar53.wordpiece;
+
+import java.io.IOException;
+import java.util.Objects;
+
+/**
+ * WordPiece tokenizer backed by the native wordpiece_rs library, which is
+ * loaded from {@code java.library.path}.
+ *
+ * <p>A tokenizer can be shared between threads. It holds native memory, which
+ * {@link #close()} frees; it must not be closed while other threads use it.
+ */
+public final class WordPieceTokenizer implements AutoCloseable {
+    static {
+        System.loadLibrary("wordpiece_rs");
+    }
+
+    private long handle;
+
+    private WordPieceTokenizer(long handle) {
+        this.handle = handle;
+    }
+
+    /** Load a lowercasing, accent stripping tokenizer from a vocab.txt file */
+    public static WordPieceTokenizer fromVocabFile(String path) throws IOException {
+        return fromVocabFile(path, "[UNK]", true, true);
+    }
+
+    /** Load a tokenizer from a vocab.txt file, one token per line with the line number as its id */
+    public static WordPieceTokenizer fromVocabFile(String path, String unkToken, boolean lowercase, boolean stripAccents)
+            throws IOException {
+        Objects.requireNonNull(path, "path");
+        Objects.requireNonNull(unkToken, "unkToken");
+        return new WordPieceTokenizer(nativeFromVocabFile(path, unkToken, lowercase, stripAccents));
+    }
+
+    /** Load a tokenizer written by {@code save_compiled} without rebuilding its trie */
+    public static WordPieceTokenizer loadCompiled(String path) throws IOException {
+        return new WordPieceTokenizer(nativeLoadCompiled(Objects.requireNonNull(path, "path")));
+    }
+
+    public String[] tokenize(String text) {
+        return nativeTokenize(handle(), Objects.requireNonNull(text, "text"));
+    }
+
+    /**
+     * Encode a text to token ids
+     *
+     * @throws IllegalArgumentException if the text is over the tokenizer's
+     *     {@code max_input_bytes} or encodes to more than its {@code max_tokens}
+     */
+    public int[] encode(String text) {
+        return nativeEncode(handle(), Objects.requireNonNull(text, "text"));
+    }
+
+    /** Encode many texts in parallel on native threads, failing as {@link #encode} does */
+    public int[][] encodeBatch(String[] texts) {
+        for (String text : Objects.requireNonNull(texts, "texts")) {
+            Objects.requireNonNull(text, "texts must not contain null");
+        }
+        return nativeEncodeBatch(handle(), texts);
+    }
+
+    /** Turn ids back into text, keeping special tokens and removing the spaces before punctuation */
+    public String decode(int[] ids) {
+        return decode(ids, false, true);
+    }
+
+    public String decode(int[] ids, boolean skipSpecialTokens, boolean cleanUpTokenizationSpaces) {
+        return nativeDecode(handle(), Objects.requireNonNull(ids, "ids"), skipSpecialTokens, cleanUpTokenizationSpaces);
+    }
+
+    public int vocabSize() {
+        return nativeVocabSize(handle());
+    }
+
+    @Override
+    public synchronized void close() {
+        if (handle != 0) {
+            nativeFree(handle);
+            handle = 0;
+        }
+    }
+
+    private synchronized long handle() {
+        if (handle == 0) {
+            throw new IllegalStateException("The tokenizer is closed");
+        }
+        return handle;
+    }
+
+    private static native long nativeFromVocabFile(String path, String unkToken, boolean lowercase, boolean stripAccents)
+            throws IOException;
+
+    private static native long nativeLoadCompiled(String path) throws IOException;
+
+    private static native void nativeFree(long handle);
+
+    private static native String[] nativeTokenize(long handle, String text);
+
+    private static native int[] nativeEncode(long handle, String text);
+
+    private static native int[][] nativeEncodeBatch(long handle, String[] texts);
+
+    private static native String nativeDecode(long handle, int[] ids, boolean skipSpecialTokens,
+            boolean cleanUpTokenizationSpaces);
+
+    private static native int nativeVocabSize(long handle);
+}
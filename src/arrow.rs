@@ -0,0 +1,77 @@
+use std::ffi::{c_char, c_void, CStr};
+use std::slice;
+
+/// `ArrowSchema` of the Arrow C data interface. Only some fields are read,
+/// the others are there for the layout.
+#[allow(dead_code)]
+#[repr(C)]
+pub(crate) struct ArrowSchema {
+    format: *const c_char,
+    name: *const c_char,
+    metadata: *const c_char,
+    flags: i64,
+    n_children: i64,
+    children: *mut *mut ArrowSchema,
+    dictionary: *mut ArrowSchema,
+    release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    private_data: *mut c_void,
+}
+
+/// `ArrowArray` of the Arrow C data interface
+#[allow(dead_code)]
+#[repr(C)]
+pub(crate) struct ArrowArray {
+    length: i64,
+    null_count: i64,
+    offset: i64,
+    n_buffers: i64,
+    n_children: i64,
+    buffers: *mut *const c_void,
+    children: *mut *mut ArrowArray,
+    dictionary: *mut ArrowArray,
+    release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    private_data: *mut c_void,
+}
+
+/// Borrow the rows of an Arrow `large_string` array, with null rows as empty
+/// strings, straight from its buffers
+///
+/// # Safety
+///
+/// `schema` and `array` must point to live structs exported through the Arrow
+/// C data interface, which must outlive the returned strings.
+pub(crate) unsafe fn large_strings<'a>(schema: &ArrowSchema, array: &'a ArrowArray) -> Result<Vec<&'a str>, String> {
+    let format = CStr::from_ptr(schema.format).to_string_lossy();
+    if format != "U" {
+        return Err(format!("Expected an Arrow large_string array, got format '{}'", format));
+    }
+    if array.n_buffers != 3 {
+        return Err(format!("Expected 3 buffers in an Arrow large_string array, got {}", array.n_buffers));
+    }
+
+    let length = array.length as usize;
+    let offset = array.offset as usize;
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+
+    let buffers = slice::from_raw_parts(array.buffers, 3);
+    let validity = buffers[0] as *const u8;
+    let offsets = slice::from_raw_parts(buffers[1] as *const i64, offset + length + 1);
+    let data_len = offsets[offset + length] as usize;
+    let data = if data_len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(buffers[2] as *const u8, data_len)
+    };
+
+    (offset..offset + length)
+        .map(|i| {
+            let is_null = array.null_count != 0 && !validity.is_null() && *validity.add(i / 8) & (1 << (i % 8)) == 0;
+            if is_null {
+                return Ok("");
+            }
+            std::str::from_utf8(&data[offsets[i] as usize..offsets[i + 1] as usize]).map_err(|err| err.to_string())
+        })
+        .collect()
+}
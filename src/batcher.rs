@@ -0,0 +1,137 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use rayon::prelude::*;
+
+use crate::{Error, Result, TokenId, WordPieceTokenizer};
+
+/// Requests waiting for the batcher before new ones are held back
+const QUEUE_CAPACITY: usize = 4096;
+
+/// How `BatchingTokenizer` and the tokenization services group concurrent
+/// encode requests into batches
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    /// Number of texts after which a batch is encoded without waiting for more
+    pub max_batch_size: usize,
+    /// How long the first request of a batch waits for others to join it
+    pub max_wait: Duration,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            max_batch_size: 1024,
+            max_wait: Duration::from_millis(2),
+        }
+    }
+}
+
+/// Texts of one request waiting to be encoded, with where to send their ids
+struct Job {
+    texts: Vec<String>,
+    reply: oneshot::Sender<Result<Vec<Vec<TokenId>>>>,
+}
+
+/// Handle to a task encoding the texts of concurrent requests together, so that
+/// services answering many small requests from many threads or tasks encode
+/// them in parallel batches. Texts are encoded with `try_encode`, so a text over
+/// the tokenizer's limits fails its own request only. Clones share the task,
+/// which stops once all of them are dropped.
+#[derive(Clone)]
+pub struct BatchingTokenizer {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl BatchingTokenizer {
+    /// Start the batching task on the current Tokio runtime, or on a thread of
+    /// its own when called outside of one
+    pub fn new(tokenizer: impl Into<Arc<WordPieceTokenizer>>, config: ServeConfig) -> Self {
+        let (jobs, queue) = mpsc::channel(QUEUE_CAPACITY);
+        let task = run(tokenizer.into(), queue, config);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(task);
+        } else {
+            std::thread::spawn(move || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .expect("Could not start the batching runtime")
+                    .block_on(task)
+            });
+        }
+        BatchingTokenizer { jobs }
+    }
+
+    /// Queue `text` for the next batch and wait for its ids
+    pub async fn encode(&self, text: impl Into<String>) -> Result<Vec<TokenId>> {
+        let mut ids = self.encode_batch(vec![text.into()]).await?;
+        Ok(ids.pop().unwrap_or_default())
+    }
+
+    /// Queue texts for the next batch and wait for their ids, failing if any
+    /// text fails
+    pub async fn encode_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<TokenId>>> {
+        let (reply, ids) = oneshot::channel();
+        self.jobs.send(Job { texts, reply }).await.map_err(|_| Error::BatchingStopped)?;
+        ids.await.map_err(|_| Error::BatchingStopped)?
+    }
+
+    /// `encode` for threads outside of async code. Panics when called from
+    /// within an async task.
+    pub fn encode_blocking(&self, text: impl Into<String>) -> Result<Vec<TokenId>> {
+        let mut ids = self.encode_batch_blocking(vec![text.into()])?;
+        Ok(ids.pop().unwrap_or_default())
+    }
+
+    /// `encode_batch` for threads outside of async code. Panics when called
+    /// from within an async task.
+    pub fn encode_batch_blocking(&self, texts: Vec<String>) -> Result<Vec<Vec<TokenId>>> {
+        let (reply, ids) = oneshot::channel();
+        self.jobs.blocking_send(Job { texts, reply }).map_err(|_| Error::BatchingStopped)?;
+        ids.blocking_recv().map_err(|_| Error::BatchingStopped)?
+    }
+}
+
+/// Take jobs off the queue until a batch is full or the first job of the batch
+/// has waited `max_wait`, then encode the batch while the next one gathers
+async fn run(tokenizer: Arc<WordPieceTokenizer>, mut queue: mpsc::Receiver<Job>, config: ServeConfig) {
+    while let Some(first) = queue.recv().await {
+        let deadline = Instant::now() + config.max_wait;
+        let mut size = first.texts.len();
+        let mut batch = vec![first];
+        while size < config.max_batch_size {
+            match tokio::time::timeout_at(deadline, queue.recv()).await {
+                Ok(Some(job)) => {
+                    size += job.texts.len();
+                    batch.push(job);
+                }
+                _ => break,
+            }
+        }
+
+        let tokenizer = tokenizer.clone();
+        // Encoding runs on rayon off the async workers, which keep accepting
+        // requests. A panic drops the replies, failing only this batch.
+        let _ = tokio::task::spawn_blocking(move || {
+            let texts: Vec<&str> = batch.iter().flat_map(|job| job.texts.iter().map(String::as_str)).collect();
+            // Longest texts first, so that the batch does not end waiting on a
+            // long text that was started last
+            let mut order: Vec<usize> = (0..texts.len()).collect();
+            order.sort_unstable_by_key(|&i| Reverse(texts[i].len()));
+            let mut ids: Vec<(usize, Result<Vec<TokenId>>)> =
+                order.into_par_iter().map(|i| (i, tokenizer.try_encode(texts[i]))).collect();
+            ids.sort_unstable_by_key(|&(i, _)| i);
+            let mut ids = ids.into_iter().map(|(_, ids)| ids);
+            for job in batch {
+                let job_ids = ids.by_ref().take(job.texts.len()).collect();
+                // The client may have gone away in the meantime
+                let _ = job.reply.send(job_ids);
+            }
+        })
+        .await;
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+#[cfg(feature = "config")]
+use std::fs;
+#[cfg(feature = "config")]
+use std::path::Path;
+
+use crate::{Error, Result, TokenId};
+
+/// A byte-level BPE model as used by GPT-2 and RoBERTa: text is turned into
+/// bytes, each byte into a printable character, and adjacent symbols are merged
+/// in the order the merges were learned. Every byte has a token, so any text
+/// can be encoded.
+#[derive(Debug, Clone)]
+pub struct BytePairModel {
+    vocab: HashMap<String, TokenId>,
+    vocab_lookup: HashMap<TokenId, String>,
+    /// Rank of each merge, lower merges first
+    merges: HashMap<(String, String), usize>,
+    byte_chars: [char; 256],
+    char_bytes: HashMap<char, u8>,
+}
+
+impl BytePairModel {
+    /// A model from its token -> id vocabulary and its merges in the order they
+    /// were learned. Every byte must have a token.
+    pub fn new(vocab: HashMap<String, TokenId>, merges: Vec<(String, String)>) -> Result<Self> {
+        let byte_chars = byte_chars();
+        if let Some(&missing) = byte_chars.iter().find(|c| !vocab.contains_key(c.to_string().as_str())) {
+            return Err(invalid(&format!("no token for the byte symbol '{}'", missing)));
+        }
+        for (left, right) in &merges {
+            if !vocab.contains_key(&format!("{}{}", left, right)) {
+                return Err(invalid(&format!("the merge of '{}' and '{}' is not in the vocabulary", left, right)));
+            }
+        }
+
+        Ok(BytePairModel {
+            vocab_lookup: vocab.iter().map(|(token, &id)| (id, token.clone())).collect(),
+            vocab,
+            merges: merges.into_iter().enumerate().map(|(rank, pair)| (pair, rank)).collect(),
+            char_bytes: byte_chars.iter().enumerate().map(|(byte, &c)| (c, byte as u8)).collect(),
+            byte_chars,
+        })
+    }
+
+    /// Read a `vocab.json` and a `merges.txt` file as written by `transformers`
+    /// and `tokenizers` for GPT-2 style models
+    #[cfg(feature = "config")]
+    pub fn from_files<P: AsRef<Path>, Q: AsRef<Path>>(vocab_file: P, merges_file: Q) -> Result<Self> {
+        let vocab: HashMap<String, TokenId> =
+            serde_json::from_str(&fs::read_to_string(vocab_file)?).map_err(|err| invalid(&err.to_string()))?;
+        let merges = fs::read_to_string(merges_file)?
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with("#version"))
+            .map(|line| match line.split_once(' ') {
+                Some((left, right)) => Ok((left.to_string(), right.to_string())),
+                None => Err(invalid(&format!("merge '{}' is not two symbols", line))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(vocab, merges)
+    }
+
+    /// The token -> id vocabulary
+    pub fn vocab(&self) -> &HashMap<String, TokenId> {
+        &self.vocab
+    }
+
+    /// Append the ids of `text`, merged piece by piece
+    pub fn encode_into(&self, text: &str, ids: &mut Vec<TokenId>) {
+        let mut symbols: Vec<String> = text.bytes().map(|byte| self.byte_chars[byte as usize].to_string()).collect();
+
+        // Apply the earliest learned merge among adjacent symbols until none applies
+        loop {
+            let best = symbols
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    let key = (pair[0].clone(), pair[1].clone());
+                    self.merges.get(&key).map(|&rank| (rank, i))
+                })
+                .min();
+            let Some((_, i)) = best else { break };
+            let right = symbols.remove(i + 1);
+            symbols[i].push_str(&right);
+        }
+
+        ids.extend(symbols.iter().map(|symbol| self.vocab[symbol.as_str()]));
+    }
+
+    /// The ids of `text`
+    pub fn encode(&self, text: &str) -> Vec<TokenId> {
+        let mut ids = Vec::new();
+        self.encode_into(text, &mut ids);
+        ids
+    }
+
+    /// The text spelled by `ids`. Ids missing from the vocabulary are skipped and
+    /// bytes that do not form UTF-8 are replaced.
+    pub fn decode(&self, ids: &[TokenId]) -> String {
+        let bytes: Vec<u8> = ids
+            .iter()
+            .filter_map(|id| self.vocab_lookup.get(id))
+            .flat_map(|token| token.chars())
+            .filter_map(|c| self.char_bytes.get(&c).copied())
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// The token with id `id`, if any
+    pub fn id_to_token(&self, id: TokenId) -> Option<&str> {
+        self.vocab_lookup.get(&id).map(String::as_str)
+    }
+}
+
+/// GPT-2's printable character for each byte: printable Latin-1 bytes stand for
+/// themselves and the others are shifted past U+00FF
+fn byte_chars() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut shifted = 256;
+    for (byte, c) in chars.iter_mut().enumerate() {
+        let printable = matches!(byte, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff);
+        let code = if printable {
+            byte as u32
+        } else {
+            shifted += 1;
+            shifted - 1
+        };
+        *c = char::from_u32(code).unwrap_or_default();
+    }
+    chars
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidBytePairModel(reason.to_string())
+}
@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::{
+    default_continuing_subword_prefix, load_vocab, CaseFolding, Contractions, Entity, Error, LengthUnit, Morphology,
+    NormalizationForm, Normalizer, PreTokenizer, Result, SpecialTokens, TokenId, TokenizerSnapshot,
+    TruncationStrategy, VocabBackend, WordPieceTokenizer,
+};
+
+/// Builds a `WordPieceTokenizer` option by option, see `WordPieceTokenizer::builder`
+#[derive(Debug, Clone)]
+pub struct WordPieceTokenizerBuilder {
+    vocab: Option<HashMap<String, TokenId>>,
+    vocab_file: Option<PathBuf>,
+    unk_token: String,
+    normalizer: Normalizer,
+    pre_tokenizer: PreTokenizer,
+    contractions: Contractions,
+    entities: Vec<Entity>,
+    entity_placeholders: BTreeMap<Entity, String>,
+    morphology: Option<Morphology>,
+    max_input_chars_per_word: usize,
+    max_input_chars_unit: Option<LengthUnit>,
+    special_tokens: Option<SpecialTokens>,
+    added_tokens: Vec<String>,
+    added_only_tokens: Vec<String>,
+    never_split: Vec<String>,
+    cache_capacity: usize,
+    vocab_backend: VocabBackend,
+    continuing_subword_prefix: String,
+    end_of_word_suffix: String,
+    model_max_length: Option<usize>,
+    truncation_strategy: TruncationStrategy,
+    max_input_bytes: Option<usize>,
+    max_tokens: Option<usize>,
+}
+
+impl Default for WordPieceTokenizerBuilder {
+    fn default() -> Self {
+        WordPieceTokenizerBuilder {
+            vocab: None,
+            vocab_file: None,
+            unk_token: "[UNK]".to_string(),
+            normalizer: Normalizer::default(),
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            morphology: None,
+            max_input_chars_per_word: 200,
+            max_input_chars_unit: None,
+            special_tokens: None,
+            added_tokens: Vec::new(),
+            added_only_tokens: Vec::new(),
+            never_split: Vec::new(),
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+            continuing_subword_prefix: default_continuing_subword_prefix(),
+            end_of_word_suffix: String::new(),
+            model_max_length: None,
+            truncation_strategy: TruncationStrategy::default(),
+            max_input_bytes: None,
+            max_tokens: None,
+        }
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Start building a tokenizer. A vocabulary must be given, with `vocab` or
+    /// `vocab_file`; every other option has a default matching uncased BERT.
+    ///
+    /// ```no_run
+    /// # use wordpiece_rs::{Normalizer, WordPieceTokenizer};
+    /// let tokenizer = WordPieceTokenizer::builder()
+    ///     .vocab_file("vocab.txt")
+    ///     .normalizer(Normalizer::cased())
+    ///     .build()?;
+    /// # Ok::<(), wordpiece_rs::Error>(())
+    /// ```
+    pub fn builder() -> WordPieceTokenizerBuilder {
+        WordPieceTokenizerBuilder::default()
+    }
+}
+
+impl WordPieceTokenizerBuilder {
+    /// Use a token -> id vocabulary
+    pub fn vocab(mut self, vocab: HashMap<String, TokenId>) -> Self {
+        self.vocab = Some(vocab);
+        self
+    }
+
+    /// Read the vocabulary from a `vocab.txt` file when building, one token per
+    /// line with the line number as its id
+    pub fn vocab_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.vocab_file = Some(path.into());
+        self
+    }
+
+    /// The token replacing words WordPiece cannot segment, `[UNK]` by default
+    pub fn unk_token(mut self, token: &str) -> Self {
+        self.unk_token = token.to_string();
+        self
+    }
+
+    /// Whether to lowercase text, true by default
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.normalizer.lowercase = lowercase;
+        self
+    }
+
+    /// Unicode normalization form applied before anything else, NFKC by default
+    pub fn unicode_normalization(mut self, form: NormalizationForm) -> Self {
+        self.normalizer.unicode_normalization = form;
+        self
+    }
+
+    /// How to lowercase, with the Unicode lowercase mapping by default
+    pub fn case_folding(mut self, case_folding: CaseFolding) -> Self {
+        self.normalizer.case_folding = case_folding;
+        self
+    }
+
+    /// Whether to strip accents, true by default
+    pub fn strip_accents(mut self, strip_accents: bool) -> Self {
+        self.normalizer.strip_accents = strip_accents;
+        self
+    }
+
+    /// Whether to drop NUL, U+FFFD and control characters as BERT does, true by default
+    pub fn remove_control_chars(mut self, remove: bool) -> Self {
+        self.normalizer.remove_control_chars = remove;
+        self
+    }
+
+    /// Whether to split CJK ideographs into words of their own, true by default
+    pub fn tokenize_chinese_chars(mut self, tokenize: bool) -> Self {
+        self.normalizer.tokenize_chinese_chars = tokenize;
+        self
+    }
+
+    /// Whether to keep grapheme clusters such as emoji sequences whole, false by default
+    pub fn grapheme_clusters(mut self, grapheme_clusters: bool) -> Self {
+        self.normalizer.grapheme_clusters = grapheme_clusters;
+        self
+    }
+
+    /// Set all normalization options at once
+    pub fn normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// How normalized text is split into words, as BERT does by default
+    pub fn pre_tokenizer(mut self, pre_tokenizer: PreTokenizer) -> Self {
+        self.pre_tokenizer = pre_tokenizer;
+        self
+    }
+
+    /// Contractions the BERT pre-tokenizer matches as words of their own, the
+    /// English ones by default
+    pub fn contractions(mut self, contractions: Contractions) -> Self {
+        self.contractions = contractions;
+        self
+    }
+
+    /// Kinds of text, such as URLs and emails, to keep whole as single words
+    /// instead of splitting them around their punctuation
+    pub fn entities(mut self, entities: Vec<Entity>) -> Self {
+        self.entities = entities;
+        self
+    }
+
+    /// Replace an entity with a token of the vocabulary, e.g. URLs with `[URL]`,
+    /// instead of keeping it as a word. The entity is kept whole too.
+    pub fn entity_placeholder(mut self, entity: Entity, placeholder: &str) -> Self {
+        if !self.entities.contains(&entity) {
+            self.entities.push(entity);
+        }
+        self.entity_placeholders.insert(entity, placeholder.to_string());
+        self
+    }
+
+    /// Morphological analysis to apply before text is split into words, e.g. to
+    /// find the words of Japanese text. Its dictionary is loaded when building.
+    pub fn morphology(mut self, morphology: Morphology) -> Self {
+        self.morphology = Some(morphology);
+        self
+    }
+
+    /// Words longer than this many characters become the unknown token, 200 by default
+    pub fn max_input_chars_per_word(mut self, max: usize) -> Self {
+        self.max_input_chars_per_word = max;
+        self
+    }
+
+    /// What `max_input_chars_per_word` counts, graphemes with grapheme clusters
+    /// and characters otherwise by default. `Bytes` rejects long words, such as
+    /// base64 blobs in logs, without looking at their characters.
+    pub fn max_input_chars_unit(mut self, unit: LengthUnit) -> Self {
+        self.max_input_chars_unit = Some(unit);
+        self
+    }
+
+    /// Special tokens to register instead of the BERT tokens found in the vocabulary
+    pub fn special_tokens(mut self, special_tokens: SpecialTokens) -> Self {
+        self.special_tokens = Some(special_tokens);
+        self
+    }
+
+    /// Tokens matched whole in the raw input, see `WordPieceTokenizer::add_tokens`
+    pub fn added_tokens(mut self, tokens: Vec<String>) -> Self {
+        self.added_tokens = tokens;
+        self
+    }
+
+    /// Added tokens the vocabulary holds only because they were added, e.g. by
+    /// `save_pretrained`, which WordPiece must not produce as pieces
+    #[cfg(feature = "config")]
+    pub(crate) fn added_only_tokens(mut self, tokens: Vec<String>) -> Self {
+        self.added_only_tokens = tokens;
+        self
+    }
+
+    /// Whitespace-delimited words to keep whole
+    pub fn never_split(mut self, never_split: Vec<String>) -> Self {
+        self.never_split = never_split;
+        self
+    }
+
+    /// Number of segmented words to cache, 0 (no cache) by default
+    pub fn cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity;
+        self
+    }
+
+    pub fn vocab_backend(mut self, backend: VocabBackend) -> Self {
+        self.vocab_backend = backend;
+        self
+    }
+
+    /// The prefix marking tokens that continue a word, `##` by default
+    pub fn continuing_subword_prefix(mut self, prefix: &str) -> Self {
+        self.continuing_subword_prefix = prefix.to_string();
+        self
+    }
+
+    /// The suffix marking tokens that end a word, empty by default
+    pub fn end_of_word_suffix(mut self, suffix: &str) -> Self {
+        self.end_of_word_suffix = suffix.to_string();
+        self
+    }
+
+    /// Number of tokens the model accepts, the default length to truncate to
+    pub fn model_max_length(mut self, max_length: usize) -> Self {
+        self.model_max_length = Some(max_length);
+        self
+    }
+
+    /// Which sequence of a pair loses tokens when truncating without naming a
+    /// strategy, `LongestFirst` by default
+    pub fn truncation_strategy(mut self, strategy: TruncationStrategy) -> Self {
+        self.truncation_strategy = strategy;
+        self
+    }
+
+    /// Longest text in bytes `try_encode` accepts, unlimited by default
+    pub fn max_input_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_input_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Most tokens `try_encode` produces for a text, unlimited by default
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Check the options and build the tokenizer
+    pub fn build(self) -> Result<WordPieceTokenizer> {
+        let vocab = match (self.vocab, self.vocab_file) {
+            (Some(vocab), None) => vocab,
+            (None, Some(path)) => load_vocab(path)?,
+            (None, None) => return Err(invalid("no vocabulary was given, set `vocab` or `vocab_file`")),
+            (Some(_), Some(_)) => return Err(invalid("both `vocab` and `vocab_file` were set")),
+        };
+        if self.max_input_chars_per_word == 0 {
+            return Err(invalid("`max_input_chars_per_word` must be positive"));
+        }
+        if self.model_max_length == Some(0) {
+            return Err(invalid("`model_max_length` must be positive"));
+        }
+        let mut tokens_by_id: HashMap<TokenId, &String> = HashMap::with_capacity(vocab.len());
+        for (token, &id) in &vocab {
+            if let Some(other) = tokens_by_id.insert(id, token) {
+                return Err(invalid(&format!("tokens '{}' and '{}' share the id {}", other, token, id)));
+            }
+        }
+
+        for placeholder in self.entity_placeholders.values() {
+            if !vocab.contains_key(placeholder) {
+                return Err(invalid(&format!("entity placeholder '{}' is not in the vocabulary", placeholder)));
+            }
+        }
+        for entity in self.entity_placeholders.keys() {
+            if !self.entities.contains(entity) {
+                return Err(invalid(&format!("entity {:?} has a placeholder but is not kept whole", entity)));
+            }
+        }
+
+        let special_tokens = match self.special_tokens {
+            Some(special_tokens) => special_tokens,
+            None => SpecialTokens::bert_defaults(&vocab),
+        };
+        WordPieceTokenizer::from_snapshot(TokenizerSnapshot {
+            vocab,
+            unk_token: self.unk_token,
+            max_input_chars_per_word: self.max_input_chars_per_word,
+            max_input_chars_unit: self.max_input_chars_unit,
+            strip_accents: self.normalizer.strip_accents,
+            lowercase: self.normalizer.lowercase,
+            case_folding: self.normalizer.case_folding,
+            unicode_normalization: self.normalizer.unicode_normalization,
+            remove_control_chars: self.normalizer.remove_control_chars,
+            tokenize_chinese_chars: self.normalizer.tokenize_chinese_chars,
+            grapheme_clusters: self.normalizer.grapheme_clusters,
+            morphology: self.morphology,
+            special_tokens,
+            added_tokens: self.added_tokens,
+            added_only_tokens: self.added_only_tokens,
+            never_split: self.never_split,
+            cache_capacity: self.cache_capacity,
+            vocab_backend: self.vocab_backend,
+            continuing_subword_prefix: self.continuing_subword_prefix,
+            end_of_word_suffix: self.end_of_word_suffix,
+            pre_tokenizer: self.pre_tokenizer,
+            contractions: self.contractions,
+            entities: self.entities,
+            entity_placeholders: self.entity_placeholders,
+            model_max_length: self.model_max_length,
+            truncation_strategy: self.truncation_strategy,
+            max_input_bytes: self.max_input_bytes,
+            max_tokens: self.max_tokens,
+        })
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidConfig(reason.to_string())
+}
@@ -0,0 +1,121 @@
+use lru::LruCache;
+use std::mem::size_of;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use crate::memory::table_size;
+use crate::TokenId;
+
+/// Usage statistics of the word cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Maximum number of words kept, 0 when the cache is disabled
+    pub capacity: usize,
+    /// Number of words currently cached
+    pub size: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups served from the cache, 0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Bounded LRU cache from normalized words to the ids of their WordPiece pieces.
+/// Natural language repeats the same words constantly, so most lookups skip
+/// the trie walk entirely.
+pub(crate) struct WordCache {
+    entries: Option<Mutex<LruCache<String, Vec<TokenId>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl WordCache {
+    /// A cache holding up to `capacity` words, disabled when `capacity` is 0
+    pub(crate) fn new(capacity: usize) -> Self {
+        WordCache {
+            entries: NonZeroUsize::new(capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map_or(0, |entries| entries.lock().unwrap_or_else(PoisonError::into_inner).cap().get())
+    }
+
+    /// Append the cached ids of `word` to `ids`, computing them with `compute`
+    /// and caching the result on a miss
+    pub(crate) fn extend_with<F: FnOnce(&mut Vec<TokenId>)>(&self, word: &str, ids: &mut Vec<TokenId>, compute: F) {
+        let entries = match &self.entries {
+            Some(entries) => entries,
+            None => return compute(ids),
+        };
+
+        if let Some(cached) = entries.lock().unwrap_or_else(PoisonError::into_inner).get(word) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            ids.extend_from_slice(cached);
+            return;
+        }
+
+        // Compute without holding the lock so other threads are not blocked
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let start = ids.len();
+        compute(ids);
+        entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(word.to_string(), ids[start..].to_vec());
+    }
+
+    /// Drop all cached words, keeping the statistics
+    pub(crate) fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    /// Estimated bytes of memory held by the cache: its table, allocated for
+    /// the full capacity up front, and each cached word with its ids
+    pub(crate) fn memory_usage(&self) -> usize {
+        let Some(entries) = &self.entries else {
+            return 0;
+        };
+        let entries = entries.lock().unwrap_or_else(PoisonError::into_inner);
+        // Each entry is allocated with links to its neighbors in the LRU order
+        let entry = size_of::<(String, Vec<TokenId>)>() + 2 * size_of::<usize>();
+        table_size(entries.cap().get(), 2 * size_of::<usize>())
+            + entries
+                .iter()
+                .map(|(word, ids)| entry + word.capacity() + ids.capacity() * size_of::<TokenId>())
+                .sum::<usize>()
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let (capacity, size) = match &self.entries {
+            Some(entries) => {
+                let entries = entries.lock().unwrap_or_else(PoisonError::into_inner);
+                (entries.cap().get(), entries.len())
+            }
+            None => (0, 0),
+        };
+
+        CacheStats {
+            capacity,
+            size,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
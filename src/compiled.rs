@@ -0,0 +1,116 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::{Error, Matcher, Result, TokenId, TokenizerSnapshot, WordPieceTokenizer};
+
+/// Magic bytes identifying a compiled tokenizer file
+const MAGIC: &[u8; 4] = b"WPRS";
+
+/// Bumped whenever the layout of `CompiledTokenizer` changes
+const FORMAT_VERSION: u32 = 21;
+
+/// On-disk layout of a compiled tokenizer: the configuration plus the vocabulary
+/// backend exactly as it was built, so loading skips its construction entirely
+#[derive(Serialize, Deserialize)]
+struct CompiledTokenizer {
+    snapshot: TokenizerSnapshot,
+    matcher: Matcher,
+}
+
+#[derive(Serialize)]
+struct CompiledTokenizerRef<'a> {
+    snapshot: TokenizerSnapshot,
+    matcher: &'a Matcher,
+}
+
+impl WordPieceTokenizer {
+    /// Write the tokenizer, including its built vocabulary backend, to a compact binary file
+    pub fn save_compiled<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_compiled(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// The contents of the file `save_compiled` writes, e.g. to send over the network
+    pub fn to_compiled_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write_compiled(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_compiled<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+
+        let compiled = CompiledTokenizerRef {
+            snapshot: self.snapshot(),
+            matcher: &self.matcher,
+        };
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut writer, &compiled)
+            .map_err(|e| Error::InvalidSnapshot(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Load a tokenizer written by `save_compiled` without rebuilding its vocabulary backend
+    pub fn load_compiled<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read_compiled(BufReader::new(file), len)
+    }
+
+    /// Load a tokenizer from the contents of a file written by `save_compiled`,
+    /// e.g. fetched over the network
+    pub fn from_compiled_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::read_compiled(bytes, bytes.len() as u64)
+    }
+
+    /// Read a compiled tokenizer of `len` bytes at most
+    fn read_compiled<R: Read>(mut reader: R, len: u64) -> Result<Self> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => Error::InvalidSnapshot("file is too short".to_string()),
+            _ => e.into(),
+        })?;
+        if &header[..4] != MAGIC {
+            return Err(Error::InvalidSnapshot("not a compiled wordpiece_rs tokenizer".to_string()));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != FORMAT_VERSION {
+            return Err(Error::InvalidSnapshot(format!(
+                "unsupported format version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        // A corrupt length prefix must not be able to request more memory than the file holds
+        let compiled: CompiledTokenizer = bincode::DefaultOptions::new()
+            .with_limit(len)
+            .deserialize_from(reader)
+            .map_err(|e| Error::InvalidSnapshot(e.to_string()))?;
+        let ids: HashSet<TokenId> = compiled.snapshot.vocab.values().copied().collect();
+        if !compiled.matcher.is_consistent(|id| ids.contains(&id)) {
+            return Err(Error::InvalidSnapshot("vocabulary backend does not match the vocabulary".to_string()));
+        }
+        WordPieceTokenizer::from_parts(compiled.matcher, compiled.snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{assert_added_tokens_unsplit, with_added_tokens};
+    use crate::WordPieceTokenizer;
+
+    #[test]
+    fn compiled_bytes_keep_added_tokens_out_of_wordpiece() {
+        let bytes = with_added_tokens().to_compiled_bytes().unwrap();
+        assert_added_tokens_unsplit(&WordPieceTokenizer::from_compiled_bytes(&bytes).unwrap());
+    }
+}
@@ -0,0 +1,342 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    default_continuing_subword_prefix, CaseFolding, Contractions, Entity, Error, LengthUnit, Morphology,
+    NormalizationForm, Normalizer, PreTokenizer, Result, SpecialTokens, TruncationStrategy, VocabBackend,
+    WordPieceTokenizer, WordPieceTokenizerBuilder,
+};
+
+/// File name of the configuration written by `save_pretrained`
+pub const CONFIG_FILE: &str = "tokenizer_config.json";
+
+/// File name of the vocabulary written by `save_pretrained`
+const VOCAB_FILE: &str = "vocab.txt";
+
+/// Every option of a tokenizer, as stored in `tokenizer_config.json` or a TOML
+/// file next to its `vocab.txt`. Keys shared with `transformers`, such as
+/// `do_lower_case`, `cls_token` and `model_max_length`, mean the same thing, so
+/// configurations saved by `BertTokenizer` can be read too. Missing keys take
+/// the defaults of `WordPieceTokenizer::builder`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenizerConfig {
+    /// The `vocab.txt` file, relative to the configuration file
+    pub vocab_file: PathBuf,
+    #[serde(alias = "lowercase")]
+    pub do_lower_case: bool,
+    pub case_folding: CaseFolding,
+    pub unicode_normalization: NormalizationForm,
+    /// Whether to drop NUL, U+FFFD and control characters, `clean_text` in `transformers`
+    #[serde(alias = "clean_text")]
+    pub remove_control_chars: bool,
+    pub tokenize_chinese_chars: bool,
+    pub grapheme_clusters: bool,
+    /// Whether to strip accents, following `do_lower_case` when unset as in `transformers`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_accents: Option<bool>,
+    pub pre_tokenizer: PreTokenizer,
+    pub contractions: Contractions,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<Entity>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub entity_placeholders: BTreeMap<Entity, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub morphology: Option<Morphology>,
+    #[serde(deserialize_with = "token")]
+    pub unk_token: String,
+    #[serde(deserialize_with = "optional_token", skip_serializing_if = "Option::is_none")]
+    pub cls_token: Option<String>,
+    #[serde(deserialize_with = "optional_token", skip_serializing_if = "Option::is_none")]
+    pub sep_token: Option<String>,
+    #[serde(deserialize_with = "optional_token", skip_serializing_if = "Option::is_none")]
+    pub pad_token: Option<String>,
+    #[serde(deserialize_with = "optional_token", skip_serializing_if = "Option::is_none")]
+    pub mask_token: Option<String>,
+    #[serde(deserialize_with = "tokens")]
+    pub additional_special_tokens: Vec<String>,
+    #[serde(deserialize_with = "tokens")]
+    pub added_tokens: Vec<String>,
+    /// Added tokens `vocab.txt` holds only because they were added, which
+    /// WordPiece never produces as pieces
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub added_only_tokens: Vec<String>,
+    pub never_split: Vec<String>,
+    pub max_input_chars_per_word: usize,
+    /// What `max_input_chars_per_word` counts, following `grapheme_clusters` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_input_chars_unit: Option<LengthUnit>,
+    pub continuing_subword_prefix: String,
+    pub end_of_word_suffix: String,
+    /// Number of tokens the model accepts, the default length to truncate to
+    #[serde(deserialize_with = "max_length", skip_serializing_if = "Option::is_none")]
+    pub model_max_length: Option<usize>,
+    pub truncation_strategy: TruncationStrategy,
+    /// Longest text in bytes `try_encode` accepts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_input_bytes: Option<usize>,
+    /// Most tokens `try_encode` produces for a text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    pub cache_capacity: usize,
+    pub vocab_backend: VocabBackend,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        TokenizerConfig {
+            vocab_file: PathBuf::from(VOCAB_FILE),
+            do_lower_case: true,
+            case_folding: CaseFolding::default(),
+            unicode_normalization: NormalizationForm::default(),
+            remove_control_chars: true,
+            tokenize_chinese_chars: true,
+            grapheme_clusters: false,
+            strip_accents: None,
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            morphology: None,
+            unk_token: "[UNK]".to_string(),
+            cls_token: None,
+            sep_token: None,
+            pad_token: None,
+            mask_token: None,
+            additional_special_tokens: Vec::new(),
+            added_tokens: Vec::new(),
+            added_only_tokens: Vec::new(),
+            never_split: Vec::new(),
+            max_input_chars_per_word: 200,
+            max_input_chars_unit: None,
+            continuing_subword_prefix: default_continuing_subword_prefix(),
+            end_of_word_suffix: String::new(),
+            model_max_length: None,
+            truncation_strategy: TruncationStrategy::default(),
+            max_input_bytes: None,
+            max_tokens: None,
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+        }
+    }
+}
+
+impl TokenizerConfig {
+    /// Read a configuration, as TOML when the file name ends in `.toml` and as
+    /// JSON otherwise
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let config = match is_toml(path) {
+            true => toml::from_str(&text).map_err(|err| err.to_string()),
+            false => serde_json::from_str(&text).map_err(|err| err.to_string()),
+        };
+        config.map_err(|err| Error::InvalidConfig(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Write the configuration, as TOML when the file name ends in `.toml` and
+    /// as JSON otherwise
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = match is_toml(path) {
+            true => toml::to_string_pretty(self).map_err(|err| err.to_string()),
+            false => serde_json::to_string_pretty(self).map_err(|err| err.to_string()),
+        }
+        .map_err(Error::InvalidConfig)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// A builder set up with every option of the configuration, reading the
+    /// vocabulary relative to `dir`
+    pub fn builder<P: AsRef<Path>>(&self, dir: P) -> WordPieceTokenizerBuilder {
+        let mut builder = WordPieceTokenizer::builder()
+            .vocab_file(dir.as_ref().join(&self.vocab_file))
+            .unk_token(&self.unk_token)
+            .normalizer(Normalizer {
+                unicode_normalization: self.unicode_normalization,
+                lowercase: self.do_lower_case,
+                case_folding: self.case_folding,
+                strip_accents: self.strip_accents.unwrap_or(self.do_lower_case),
+                remove_control_chars: self.remove_control_chars,
+                tokenize_chinese_chars: self.tokenize_chinese_chars,
+                grapheme_clusters: self.grapheme_clusters,
+            })
+            .pre_tokenizer(self.pre_tokenizer)
+            .contractions(self.contractions.clone())
+            .entities(self.entities.clone())
+            .max_input_chars_per_word(self.max_input_chars_per_word)
+            .added_tokens(self.added_tokens.clone())
+            .added_only_tokens(self.added_only_tokens.clone())
+            .never_split(self.never_split.clone())
+            .cache_capacity(self.cache_capacity)
+            .vocab_backend(self.vocab_backend)
+            .continuing_subword_prefix(&self.continuing_subword_prefix)
+            .end_of_word_suffix(&self.end_of_word_suffix)
+            .truncation_strategy(self.truncation_strategy);
+        if let Some(max_length) = self.model_max_length {
+            builder = builder.model_max_length(max_length);
+        }
+        if let Some(max_bytes) = self.max_input_bytes {
+            builder = builder.max_input_bytes(max_bytes);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        if let Some(unit) = self.max_input_chars_unit {
+            builder = builder.max_input_chars_unit(unit);
+        }
+        for (&entity, placeholder) in &self.entity_placeholders {
+            builder = builder.entity_placeholder(entity, placeholder);
+        }
+        if let Some(morphology) = &self.morphology {
+            builder = builder.morphology(morphology.clone());
+        }
+        // Without any role set, the BERT tokens of the vocabulary are registered
+        let special_tokens = SpecialTokens {
+            unk_token: None,
+            cls_token: self.cls_token.clone(),
+            sep_token: self.sep_token.clone(),
+            pad_token: self.pad_token.clone(),
+            mask_token: self.mask_token.clone(),
+            additional_special_tokens: self.additional_special_tokens.clone(),
+        };
+        if special_tokens != SpecialTokens::default() {
+            builder = builder.special_tokens(special_tokens);
+        }
+        builder
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Load a tokenizer from a configuration file and the vocabulary it names,
+    /// or from a directory written by `save_pretrained`, which holds
+    /// `tokenizer_config.json` or `tokenizer_config.toml`
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let (dir, file) = match path.is_dir() {
+            true => {
+                let toml = path.join("tokenizer_config.toml");
+                let file = match toml.is_file() {
+                    true => toml,
+                    false => path.join(CONFIG_FILE),
+                };
+                (path, file)
+            }
+            false => (path.parent().unwrap_or(Path::new("")), path.to_path_buf()),
+        };
+        TokenizerConfig::from_file(&file)?.builder(dir).build()
+    }
+
+    /// The configuration of this tokenizer, as written by `save_pretrained`
+    pub fn config(&self) -> TokenizerConfig {
+        let snapshot = self.snapshot();
+        let special_tokens = snapshot.special_tokens;
+        TokenizerConfig {
+            vocab_file: PathBuf::from(VOCAB_FILE),
+            do_lower_case: snapshot.lowercase,
+            case_folding: snapshot.case_folding,
+            unicode_normalization: snapshot.unicode_normalization,
+            remove_control_chars: snapshot.remove_control_chars,
+            tokenize_chinese_chars: snapshot.tokenize_chinese_chars,
+            grapheme_clusters: snapshot.grapheme_clusters,
+            strip_accents: Some(snapshot.strip_accents),
+            pre_tokenizer: snapshot.pre_tokenizer,
+            contractions: snapshot.contractions,
+            entities: snapshot.entities,
+            entity_placeholders: snapshot.entity_placeholders,
+            morphology: snapshot.morphology,
+            unk_token: special_tokens.unk_token.unwrap_or(snapshot.unk_token),
+            cls_token: special_tokens.cls_token,
+            sep_token: special_tokens.sep_token,
+            pad_token: special_tokens.pad_token,
+            mask_token: special_tokens.mask_token,
+            additional_special_tokens: special_tokens.additional_special_tokens,
+            added_tokens: snapshot.added_tokens,
+            added_only_tokens: snapshot.added_only_tokens,
+            never_split: snapshot.never_split,
+            max_input_chars_per_word: snapshot.max_input_chars_per_word,
+            max_input_chars_unit: snapshot.max_input_chars_unit,
+            continuing_subword_prefix: snapshot.continuing_subword_prefix,
+            end_of_word_suffix: snapshot.end_of_word_suffix,
+            model_max_length: snapshot.model_max_length,
+            truncation_strategy: snapshot.truncation_strategy,
+            max_input_bytes: snapshot.max_input_bytes,
+            max_tokens: snapshot.max_tokens,
+            cache_capacity: snapshot.cache_capacity,
+            vocab_backend: snapshot.vocab_backend,
+        }
+    }
+
+    /// Write `vocab.txt` and `tokenizer_config.json` to `dir`, creating it if
+    /// needed, so that `from_config` restores this tokenizer from the directory
+    pub fn save_pretrained<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        self.save_vocab(dir.join(VOCAB_FILE))?;
+        self.config().save(dir.join(CONFIG_FILE))
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "toml")
+}
+
+/// A token saved as a string, or as a serialized `AddedToken` by `transformers`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenValue {
+    Content(String),
+    AddedToken { content: String },
+}
+
+impl From<TokenValue> for String {
+    fn from(token: TokenValue) -> String {
+        match token {
+            TokenValue::Content(content) | TokenValue::AddedToken { content } => content,
+        }
+    }
+}
+
+fn token<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    TokenValue::deserialize(deserializer).map(String::from)
+}
+
+fn optional_token<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<String>, D::Error> {
+    Ok(Option::<TokenValue>::deserialize(deserializer)?.map(String::from))
+}
+
+fn tokens<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<String>, D::Error> {
+    Ok(Vec::<TokenValue>::deserialize(deserializer)?.into_iter().map(String::from).collect())
+}
+
+/// `transformers` saves a `model_max_length` of 1e30 for models without a
+/// limit, which is read as no limit
+fn max_length<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<usize>, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?
+        .filter(|max_length| *max_length >= 1.0 && *max_length <= u32::MAX as f64)
+        .map(|max_length| max_length as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::tests::{assert_added_tokens_unsplit, with_added_tokens};
+    use crate::WordPieceTokenizer;
+
+    #[test]
+    fn save_pretrained_keeps_added_tokens_out_of_wordpiece() {
+        let dir = std::env::temp_dir().join(format!("wordpiece_rs_config_{}", std::process::id()));
+        let tokenizer = with_added_tokens();
+        tokenizer.save_pretrained(&dir).unwrap();
+        let restored = WordPieceTokenizer::from_config(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let restored = restored.unwrap();
+        assert_added_tokens_unsplit(&restored);
+        assert_eq!(restored.added_tokens(), tokenizer.added_tokens());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::default_continuing_subword_prefix;
+
+/// Joins WordPiece tokens back into text: continuation pieces are glued onto
+/// the token before them, words are separated by spaces and special tokens
+/// always stand apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoder {
+    /// The prefix marking tokens that continue a word, `##` by default
+    pub continuing_subword_prefix: String,
+    /// The suffix marking tokens that end a word, empty by default. With one,
+    /// words only end on pieces carrying it.
+    pub end_of_word_suffix: String,
+}
+
+/// What decoding does with ids that are not in the vocabulary, e.g. from a
+/// corrupted model output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownIdPolicy {
+    /// Leave them out of the text
+    #[default]
+    Skip,
+    /// Fail with `Error::UnknownId`
+    Error,
+    /// Decode them as the unknown token, even when skipping special tokens
+    ReplaceWithUnk,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            continuing_subword_prefix: default_continuing_subword_prefix(),
+            end_of_word_suffix: String::new(),
+        }
+    }
+}
+
+impl Decoder {
+    pub fn new(continuing_subword_prefix: &str, end_of_word_suffix: &str) -> Self {
+        Decoder {
+            continuing_subword_prefix: continuing_subword_prefix.to_string(),
+            end_of_word_suffix: end_of_word_suffix.to_string(),
+        }
+    }
+
+    /// Join tokens into text, tidying the spaces before punctuation and
+    /// English contractions with `clean_up_tokenization_spaces`
+    pub fn decode<S: AsRef<str>>(&self, tokens: &[S], clean_up_tokenization_spaces: bool) -> String {
+        self.decode_tokens(tokens.iter().map(|token| (token.as_ref(), false)), clean_up_tokenization_spaces)
+    }
+
+    /// `decode` of tokens paired with whether each is a special token
+    pub fn decode_tokens<'a, I>(&self, tokens: I, clean_up_tokenization_spaces: bool) -> String
+    where
+        I: IntoIterator<Item = (&'a str, bool)>,
+    {
+        let suffix = &self.end_of_word_suffix;
+        let mut result = String::new();
+        let mut word_ended = true;
+
+        for (i, (token, is_special)) in tokens.into_iter().enumerate() {
+            let continuation = self.strip_continuation(token).filter(|_| !is_special);
+            if i > 0 && (word_ended || is_special) && continuation.is_none() {
+                result.push(' ');
+            }
+
+            // Only the prefix at the start marks a continuation, so tokens
+            // such as `C##` keep the `##` they spell
+            let mut piece = continuation.unwrap_or(token);
+            word_ended = suffix.is_empty() || is_special;
+            if let Some(stripped) = piece.strip_suffix(suffix.as_str()).filter(|_| !suffix.is_empty()) {
+                piece = stripped;
+                word_ended = true;
+            }
+            result.push_str(piece);
+        }
+
+        if clean_up_tokenization_spaces {
+            clean_up_tokenization(&result)
+        } else {
+            result
+        }
+    }
+
+    /// The rest of `token` if it starts with the continuation prefix
+    pub fn strip_continuation<'a>(&self, token: &'a str) -> Option<&'a str> {
+        match self.continuing_subword_prefix.is_empty() {
+            true => None,
+            false => token.strip_prefix(self.continuing_subword_prefix.as_str()),
+        }
+    }
+}
+
+/// Remove the spaces decoding leaves before punctuation and inside English
+/// contractions, matching `transformers`' `clean_up_tokenization`
+fn clean_up_tokenization(text: &str) -> String {
+    text.replace(" .", ".")
+        .replace(" ?", "?")
+        .replace(" !", "!")
+        .replace(" ,", ",")
+        .replace(" ' ", "'")
+        .replace(" n't", "n't")
+        .replace(" 'm", "'m")
+        .replace(" 's", "'s")
+        .replace(" 've", "'ve")
+        .replace(" 're", "'re")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuation_pieces_join_the_word_before() {
+        let decoder = Decoder::default();
+        assert_eq!(decoder.decode(&["un", "##aff", "##able", "day"], false), "unaffable day");
+        // Only a leading prefix marks a continuation
+        assert_eq!(decoder.decode(&["C##", "##x"], false), "C##x");
+        assert_eq!(decoder.decode::<&str>(&[], true), "");
+    }
+
+    #[test]
+    fn special_tokens_stand_apart() {
+        let decoder = Decoder::default();
+        let tokens = [("[CLS]", true), ("hi", false), ("##!", false), ("##x", true)];
+        assert_eq!(decoder.decode_tokens(tokens, false), "[CLS] hi! ##x");
+    }
+
+    #[test]
+    fn end_of_word_suffix_ends_words() {
+        let decoder = Decoder::new("", "</w>");
+        assert_eq!(decoder.decode(&["un", "aff", "able</w>", "day</w>"], false), "unaffable day");
+    }
+
+    #[test]
+    fn clean_up_removes_spaces_before_punctuation_and_contractions() {
+        let decoder = Decoder::default();
+        let tokens = ["i", "don", "'", "t", "know", ",", "it", "'", "s", "fine", "!"];
+        assert_eq!(decoder.decode(&tokens, false), "i don ' t know , it ' s fine !");
+        assert_eq!(decoder.decode(&tokens, true), "i don't know, it's fine!");
+        assert_eq!(
+            clean_up_tokenization("they 're here . we 've seen you 'm sure ? is n't it"),
+            "they're here. we've seen you'm sure? isn't it"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{TokenId, WordPieceTokenizer};
+
+/// Differences between the vocabularies of two tokenizers, see
+/// `WordPieceTokenizer::compare_vocab`. Token lists are ordered by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VocabDiff {
+    /// Tokens only in the other vocabulary, with their ids there
+    pub added: Vec<(String, TokenId)>,
+    /// Tokens only in this vocabulary, with their ids here
+    pub removed: Vec<(String, TokenId)>,
+    /// Tokens in both vocabularies under different ids, with their id here and there
+    pub moved: Vec<(String, TokenId, TokenId)>,
+    /// Number of tokens with the same id in both vocabularies
+    pub unchanged: usize,
+    /// Options that change how text maps to tokens and differ between the two,
+    /// such as `lowercase` or `continuing_subword_prefix`
+    pub changed_options: Vec<&'static str>,
+}
+
+impl VocabDiff {
+    /// Whether the vocabularies and the options mapping text to them are the same
+    pub fn is_identical(&self) -> bool {
+        self.added.is_empty() && self.is_compatible()
+    }
+
+    /// Whether a model trained with this vocabulary can take the ids of the
+    /// other without retraining: every token keeps its id and the text maps
+    /// to tokens the same way. Added tokens only need new embedding rows.
+    pub fn is_compatible(&self) -> bool {
+        self.removed.is_empty() && self.moved.is_empty() && self.changed_options.is_empty()
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Compare this tokenizer's vocabulary with `other`'s, e.g. to check whether
+    /// a new vocabulary can replace the one a deployed model was trained with
+    pub fn compare_vocab(&self, other: &WordPieceTokenizer) -> VocabDiff {
+        let mut diff = VocabDiff::default();
+        for (token, &id) in self.vocab.iter() {
+            match other.vocab.get(token) {
+                Some(&other_id) if other_id == id => diff.unchanged += 1,
+                Some(&other_id) => diff.moved.push((token.clone(), id, other_id)),
+                None => diff.removed.push((token.clone(), id)),
+            }
+        }
+        for (token, &id) in other.vocab.iter() {
+            if !self.vocab.contains_key(token) {
+                diff.added.push((token.clone(), id));
+            }
+        }
+        diff.added.sort_by_key(|&(_, id)| id);
+        diff.removed.sort_by_key(|&(_, id)| id);
+        diff.moved.sort_by_key(|&(_, id, _)| id);
+
+        let options = [
+            ("unk_token", self.unk_token != other.unk_token),
+            ("cls_token", self.cls_token != other.cls_token),
+            ("sep_token", self.sep_token != other.sep_token),
+            ("pad_token", self.pad_token != other.pad_token),
+            ("mask_token", self.mask_token != other.mask_token),
+            ("continuing_subword_prefix", self.continuing_subword_prefix != other.continuing_subword_prefix),
+            ("end_of_word_suffix", self.end_of_word_suffix != other.end_of_word_suffix),
+            ("lowercase", self.lowercase != other.lowercase),
+            ("case_folding", self.case_folding != other.case_folding),
+            ("strip_accents", self.strip_accents != other.strip_accents),
+            ("unicode_normalization", self.unicode_normalization != other.unicode_normalization),
+            ("pre_tokenizer", self.pre_tokenizer != other.pre_tokenizer),
+            ("tokenize_chinese_chars", self.tokenize_chinese_chars != other.tokenize_chinese_chars),
+        ];
+        diff.changed_options = options
+            .into_iter()
+            .filter(|&(_, changed)| changed)
+            .map(|(name, _)| name)
+            .collect();
+
+        diff
+    }
+}
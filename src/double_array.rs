@@ -0,0 +1,337 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeSet, VecDeque};
+use std::mem::size_of;
+use std::sync::OnceLock;
+
+use crate::trie::{token_locations, FailureLinks, NodeId, NONE, ROOT, SUFFIX_ROOT};
+use crate::TokenId;
+
+/// Vocabulary stored as a double-array trie over the bytes of the tokens, with
+/// the same LinMaxMatch failure links as `Trie`. The child of state `s` on byte
+/// `b` is state `base[s] + b` if its `check` is `s`, so each byte of a word
+/// costs two array reads and no hashing. In exchange, construction searches
+/// the arrays for a free place for the children of every state.
+///
+/// Matching bytes instead of characters yields the same pieces: tokens are
+/// valid UTF-8, so a token matching the start of a word ends on a character
+/// boundary of the word.
+#[derive(Clone)]
+pub(crate) struct DoubleArray {
+    /// Offset of the children of each state, `NONE` for states without any
+    base: Vec<u32>,
+    /// Parent of each state, `NONE` for the roots and unused states
+    check: Vec<NodeId>,
+    token_ids: Vec<Option<TokenId>>,
+    /// Prefix marking a token that continues a word
+    continuation_prefix: String,
+    /// Built on first use and dropped when tokens are removed
+    failure_links: OnceLock<FailureLinks>,
+}
+
+/// A node of the pointer trie the double array is laid out from: its token id
+/// and its children ordered by byte
+type PendingNode = (Option<TokenId>, Vec<(u8, NodeId)>);
+
+impl DoubleArray {
+    /// Build a double array from (token, id) pairs whose continuation tokens
+    /// start with `continuation_prefix`
+    pub(crate) fn new<'a, I: IntoIterator<Item = (&'a String, &'a TokenId)>>(vocab: I, continuation_prefix: &str) -> Self {
+        let mut nodes: Vec<PendingNode> = vec![PendingNode::default(), PendingNode::default()];
+        for (token, &id) in vocab {
+            for (root, rest) in token_locations(continuation_prefix, token) {
+                // A bare prefix would be an empty continuation and can never be matched
+                if rest.is_empty() {
+                    continue;
+                }
+                let mut node = root;
+                for &byte in rest.as_bytes() {
+                    let children = &nodes[node as usize].1;
+                    node = match children.binary_search_by_key(&byte, |&(b, _)| b) {
+                        Ok(i) => children[i].1,
+                        Err(i) => {
+                            let next = nodes.len() as NodeId;
+                            nodes[node as usize].1.insert(i, (byte, next));
+                            nodes.push(PendingNode::default());
+                            next
+                        }
+                    };
+                }
+                nodes[node as usize].0 = Some(id);
+            }
+        }
+
+        let mut array = DoubleArray {
+            base: vec![NONE; 2],
+            check: vec![NONE; 2],
+            token_ids: vec![None; 2],
+            continuation_prefix: continuation_prefix.to_string(),
+            failure_links: OnceLock::new(),
+        };
+        array.place(&nodes);
+        array
+    }
+
+    /// Lay out the pointer trie `nodes` in breadth-first order, giving each
+    /// node's children the lowest base at which all of them land on free states.
+    /// Only free states are tried for the first child, so the search skips over
+    /// the filled part of the arrays.
+    fn place(&mut self, nodes: &[PendingNode]) {
+        // Free states below the end of the arrays, past which all are free
+        let mut free = BTreeSet::new();
+        let mut queue = VecDeque::from([(ROOT, ROOT), (SUFFIX_ROOT, SUFFIX_ROOT)]);
+
+        while let Some((node, state)) = queue.pop_front() {
+            let (token_id, children) = &nodes[node as usize];
+            self.token_ids[state as usize] = *token_id;
+            let Some(&(first_byte, _)) = children.first() else {
+                continue;
+            };
+
+            let len = self.base.len();
+            let is_free = |i: usize| i >= len || free.contains(&i);
+            let base = free
+                .range(first_byte as usize..)
+                .copied()
+                .chain(len.max(first_byte as usize)..)
+                .map(|position| position - first_byte as usize)
+                .find(|&base| children.iter().all(|&(byte, _)| is_free(base + byte as usize)))
+                .expect("the states past the end are free");
+
+            let end = base + children.last().map_or(0, |&(byte, _)| byte as usize) + 1;
+            if end > len {
+                free.extend(len..end);
+                self.base.resize(end, NONE);
+                self.check.resize(end, NONE);
+                self.token_ids.resize(end, None);
+            }
+            self.base[state as usize] = base as u32;
+            for &(byte, child) in children {
+                let next = base + byte as usize;
+                free.remove(&next);
+                self.check[next] = state;
+                queue.push_back((child, next as NodeId));
+            }
+        }
+    }
+
+    /// Unmark a token so it is no longer matched. The failure links are
+    /// rebuilt on the next word segmented.
+    pub(crate) fn remove(&mut self, token: &str) {
+        for (root, rest) in token_locations(&self.continuation_prefix, token) {
+            if let Some(state) = self.walk(root, rest) {
+                if self.token_ids[state as usize].take().is_some() {
+                    self.failure_links.take();
+                }
+            }
+        }
+    }
+
+    /// Build the failure links now if they are not built yet
+    pub(crate) fn precompile(&self) {
+        self.failure_links();
+    }
+
+    fn failure_links(&self) -> &FailureLinks {
+        self.failure_links.get_or_init(|| {
+            FailureLinks::build(
+                self.base.len(),
+                &self.edges(),
+                |state| self.token_ids[state as usize],
+                |state, byte| self.child(state, byte),
+            )
+        })
+    }
+
+    /// Segment a word into token ids with LinMaxMatch, appending them to `ids`.
+    /// Returns false, leaving `ids` in an unspecified state, if the word cannot
+    /// be segmented with the vocabulary.
+    pub(crate) fn tokenize_word(&self, word: &str, ids: &mut Vec<TokenId>) -> bool {
+        let links = self.failure_links();
+        let mut state = ROOT;
+
+        for &byte in word.as_bytes() {
+            loop {
+                if let Some(next) = self.child(state, byte) {
+                    state = next;
+                    break;
+                }
+                match links.follow(state, ids) {
+                    Some(next) => state = next,
+                    None => return false,
+                }
+            }
+        }
+
+        // Flush the pieces still pending on the matched path
+        while state != ROOT && state != SUFFIX_ROOT {
+            match links.follow(state, ids) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Estimated bytes of memory held by the arrays and the failure links,
+    /// once built
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.base.capacity() * size_of::<u32>()
+            + self.check.capacity() * size_of::<NodeId>()
+            + self.token_ids.capacity() * size_of::<Option<TokenId>>()
+            + self.failure_links.get().map_or(0, FailureLinks::memory_usage)
+            + self.continuation_prefix.capacity()
+    }
+
+    /// Check that the arrays have a state for each base, that every parent is
+    /// a state and that every token id satisfies `is_known_id`, as a loaded
+    /// double array may be corrupt
+    pub(crate) fn is_consistent<F: Fn(TokenId) -> bool>(&self, is_known_id: F) -> bool {
+        let states = self.base.len();
+        states > SUFFIX_ROOT as usize
+            && self.check.len() == states
+            && self.token_ids.len() == states
+            && self.check.iter().all(|&parent| parent == NONE || (parent as usize) < states)
+            && self.token_ids.iter().flatten().all(|&id| is_known_id(id))
+            && self.failure_links.get().is_none_or(|links| links.is_consistent(states, &is_known_id))
+    }
+
+    /// The child of `state` on `byte`. A state without children has a base of
+    /// `NONE`, which leads to no state whose parent is `state`.
+    #[inline]
+    fn child(&self, state: NodeId, byte: u8) -> Option<NodeId> {
+        let next = self.base[state as usize].wrapping_add(byte as u32);
+        match self.check.get(next as usize) {
+            Some(&parent) if parent == state => Some(next),
+            _ => None,
+        }
+    }
+
+    /// The state spelling `bytes` below `root`, if present
+    fn walk(&self, root: NodeId, rest: &str) -> Option<NodeId> {
+        let mut state = root;
+        for &byte in rest.as_bytes() {
+            state = self.child(state, byte)?;
+        }
+        Some(state)
+    }
+
+    /// Every transition as (parent, byte, child), ordered by parent and then
+    /// by byte
+    fn edges(&self) -> Vec<(NodeId, u8, NodeId)> {
+        let mut edges: Vec<(NodeId, u8, NodeId)> = self
+            .check
+            .iter()
+            .enumerate()
+            .filter(|&(_, &parent)| parent != NONE)
+            .map(|(state, &parent)| {
+                let byte = (state as u32).wrapping_sub(self.base[parent as usize]) as u8;
+                (parent, byte, state as NodeId)
+            })
+            .collect();
+        edges.sort_unstable();
+        edges
+    }
+}
+
+/// Serialized form of `DoubleArray`, with its failure links, so that loading
+/// it builds nothing
+#[derive(Serialize, Deserialize)]
+struct DoubleArrayParts {
+    base: Vec<u32>,
+    check: Vec<NodeId>,
+    token_ids: Vec<Option<TokenId>>,
+    failure_links: FailureLinks,
+    continuation_prefix: String,
+}
+
+impl Serialize for DoubleArray {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DoubleArrayParts {
+            base: self.base.clone(),
+            check: self.check.clone(),
+            token_ids: self.token_ids.clone(),
+            failure_links: self.failure_links().clone(),
+            continuation_prefix: self.continuation_prefix.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DoubleArray {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let parts = DoubleArrayParts::deserialize(deserializer)?;
+        // The arrays are checked by `is_consistent` once the vocabulary is loaded
+        Ok(DoubleArray {
+            base: parts.base,
+            check: parts.check,
+            token_ids: parts.token_ids,
+            continuation_prefix: parts.continuation_prefix,
+            failure_links: OnceLock::from(parts.failure_links),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::tests::{random_tokens_and_words, vocab_of};
+    use crate::trie::Trie;
+    use std::collections::HashMap;
+
+    fn assert_segments_like(double_array: &DoubleArray, trie: &Trie, word: &str) {
+        let (mut ids, mut expected) = (Vec::new(), Vec::new());
+        let segmented = double_array.tokenize_word(word, &mut ids).then_some(ids);
+        assert_eq!(segmented, trie.tokenize_word(word, &mut expected).then_some(expected), "{:?}", word);
+    }
+
+    fn assert_segments_like_trie(vocab: &HashMap<String, TokenId>, continuation_prefix: &str, words: &[&str]) {
+        let double_array = DoubleArray::new(vocab.iter(), continuation_prefix);
+        let trie = Trie::new(vocab.iter(), continuation_prefix);
+        for word in words {
+            assert_segments_like(&double_array, &trie, word);
+        }
+    }
+
+    #[test]
+    fn segments_like_the_trie() {
+        let vocab = vocab_of(&["un", "##aff", "##able", "a", "abcdx", "##b", "##c", "##cdy", "##dz"]);
+        assert_segments_like_trie(&vocab, "##", &["unaffable", "unable", "abcdz", "abcdx", "abc", "", "x", "a##b"]);
+
+        // Multi-byte characters sharing their leading bytes, such as é and ç,
+        // branch in the middle of a character
+        let vocab = vocab_of(&["日本", "##語", "é", "##té", "##é", "ça", "##🙂", "##🙃"]);
+        assert_segments_like_trie(&vocab, "##", &["日本語", "été", "éé", "ça🙂🙃", "日", "ç", "é日", "ça🙁"]);
+    }
+
+    #[test]
+    fn empty_continuation_prefix_segments_like_the_trie() {
+        let vocab = vocab_of(&["a", "ab", "b", "bc", "c", "日", "日本", "本語"]);
+        assert_segments_like_trie(&vocab, "", &["abc", "abbc", "日本語", "cab", "abd", ""]);
+    }
+
+    #[test]
+    fn removed_tokens_are_no_longer_matched() {
+        let vocab = vocab_of(&["a", "ab", "##b", "##bc", "##c", "é", "##日"]);
+        let mut double_array = DoubleArray::new(vocab.iter(), "##");
+        let mut trie = Trie::new(vocab.iter(), "##");
+        for removed in ["ab", "##bc", "é"] {
+            double_array.remove(removed);
+            trie.remove(removed);
+            for word in ["abc", "ab", "abbc", "é日", "a日"] {
+                assert_segments_like(&double_array, &trie, word);
+            }
+        }
+    }
+
+    #[test]
+    fn random_vocabularies_segment_like_the_trie() {
+        for continuation_prefix in ["##", ""] {
+            for seed in 1..=200 {
+                let (tokens, words) = random_tokens_and_words(seed, continuation_prefix);
+                let words: Vec<&str> = words.iter().map(String::as_str).collect();
+                assert_segments_like_trie(&vocab_of(&tokens), continuation_prefix, &words);
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::ops::Range;
+
+use crate::metrics::Stopwatch;
+use crate::offsets::{covering, OffsetConverter, OffsetUnit};
+use crate::{PreToken, Result, Token, TokenId, WordPieceTokenizer};
+
+/// Token ids of a text together with the pieces they stand for, the word each
+/// came from and where in the text it was, see `WordPieceTokenizer::encode_plus`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Encoding {
+    ids: Vec<TokenId>,
+    tokens: Vec<String>,
+    word_ids: Vec<Option<usize>>,
+    offsets: Vec<(usize, usize)>,
+    gaps: Vec<String>,
+    surfaces: Vec<String>,
+}
+
+impl Encoding {
+    /// An encoding from its columns, which the caller checks to have one entry
+    /// per token, or one more for `gaps`, unless `gaps` and `surfaces` are empty
+    #[cfg(feature = "python")]
+    pub(crate) fn from_parts(
+        ids: Vec<TokenId>,
+        tokens: Vec<String>,
+        word_ids: Vec<Option<usize>>,
+        offsets: Vec<(usize, usize)>,
+        gaps: Vec<String>,
+        surfaces: Vec<String>,
+    ) -> Self {
+        Encoding { ids, tokens, word_ids, offsets, gaps, surfaces }
+    }
+
+    pub fn ids(&self) -> &[TokenId] {
+        &self.ids
+    }
+
+    pub fn tokens(&self) -> &[String] {
+        &self.tokens
+    }
+
+    /// Index of the pre-tokenized word each token is part of, `None` for
+    /// special tokens. Pieces of the same word share an index, which is what
+    /// whole-word masking and word-level labels need.
+    pub fn word_ids(&self) -> &[Option<usize>] {
+        &self.word_ids
+    }
+
+    /// Start and end offsets of each token in the original text, in bytes unless
+    /// encoded with another `OffsetUnit`. Pieces of a word cover their part of
+    /// it, and a word mapped to the unknown token covers the whole word. For
+    /// pre-split input they are relative to the word.
+    pub fn offsets(&self) -> &[(usize, usize)] {
+        &self.offsets
+    }
+
+    /// Text of the original not covered by any token, such as whitespace and
+    /// removed characters: before each token, then after the last one. Only
+    /// recorded by `encode_lossless`, empty otherwise.
+    pub fn gaps(&self) -> &[String] {
+        &self.gaps
+    }
+
+    /// The exact original text each token was normalized from, empty for a
+    /// token covering nothing more than the one before it. Only recorded by
+    /// `encode_lossless`, empty otherwise.
+    pub fn surfaces(&self) -> &[String] {
+        &self.surfaces
+    }
+
+    /// Offsets of the token at index `token`
+    pub fn token_to_chars(&self, token: usize) -> Option<(usize, usize)> {
+        self.offsets.get(token).copied()
+    }
+
+    /// Index of the first token covering position `pos` of the text, `None`
+    /// when it is whitespace or otherwise produced no token
+    pub fn char_to_token(&self, pos: usize) -> Option<usize> {
+        self.offsets.iter().position(|&(start, end)| start <= pos && pos < end)
+    }
+
+    /// Index of the word covering position `pos` of the text
+    pub fn char_to_word(&self, pos: usize) -> Option<usize> {
+        self.char_to_token(pos).and_then(|token| self.word_ids[token])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// A token with where in the original text it came from, for highlighting
+/// tokens in a UI, see `WordPieceTokenizer::tokenize_with_spans`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSpan {
+    pub piece: String,
+    pub start: usize,
+    pub end: usize,
+    pub id: TokenId,
+    /// Whether the token continues the word of the token before it
+    pub is_continuation: bool,
+}
+
+impl WordPieceTokenizer {
+    /// Encode `text` like `encode`, also keeping the tokens, the word each one
+    /// came from and its byte offsets. Words are the units of pre-tokenization:
+    /// whitespace separated words, each punctuation character, and added tokens.
+    pub fn encode_plus(&self, text: &str) -> Encoding {
+        self.encode_plus_with_unit(text, OffsetUnit::Byte)
+    }
+
+    /// `encode_plus` within the limits of `max_input_bytes` and `max_tokens`,
+    /// failing like `try_encode`
+    pub fn try_encode_plus(&self, text: &str) -> Result<Encoding> {
+        self.try_encode_plus_with_unit(text, OffsetUnit::Byte)
+    }
+
+    /// Encode text that is already split into words, e.g. from a token
+    /// classification dataset. Each word is normalized and split on punctuation
+    /// like any text, but all of its tokens get the word's index in `words`.
+    pub fn encode_words<S: AsRef<str>>(&self, words: &[S]) -> Encoding {
+        self.encode_words_with_unit(words, OffsetUnit::Byte)
+    }
+
+    /// `encode_plus` with offsets counted in `unit`, e.g. UTF-16 code units for
+    /// annotations made in JavaScript
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(bytes = text.len())))]
+    pub fn encode_plus_with_unit(&self, text: &str, unit: OffsetUnit) -> Encoding {
+        let start = Stopwatch::start();
+        let mut encoding = Encoding::default();
+        let mut word = 0;
+        self.extend_encoding(&mut encoding, text, unit, &mut || {
+            word += 1;
+            word - 1
+        });
+        self.fill_tokens(&mut encoding);
+        self.metrics.record(start, text.len(), &encoding.ids, self.unk_token_id);
+        encoding
+    }
+
+    /// `encode_plus_with_unit` within the limits of `max_input_bytes` and
+    /// `max_tokens`
+    pub fn try_encode_plus_with_unit(&self, text: &str, unit: OffsetUnit) -> Result<Encoding> {
+        self.within_limits(text.len(), || self.encode_plus_with_unit(text, unit), |encoding| encoding.ids.len())
+    }
+
+    /// Encode `text` like `encode_plus`, also recording the exact original text
+    /// of each token and the whitespace and removed characters between them,
+    /// so that `decode_lossless` restores the text byte for byte
+    pub fn encode_lossless(&self, text: &str) -> Encoding {
+        self.encode_lossless_with_unit(text, OffsetUnit::Byte)
+    }
+
+    /// `encode_lossless` with offsets counted in `unit`
+    pub fn encode_lossless_with_unit(&self, text: &str, unit: OffsetUnit) -> Encoding {
+        let mut encoding = self.encode_plus(text);
+
+        // Offsets may overlap, e.g. for pieces of one character that
+        // normalization expanded, so each byte goes to the first token covering it
+        let mut pos = 0;
+        for &(start, end) in &encoding.offsets {
+            encoding.gaps.push(text[pos..start.max(pos)].to_string());
+            pos = pos.max(start);
+            encoding.surfaces.push(text[pos..end.max(pos)].to_string());
+            pos = pos.max(end);
+        }
+        encoding.gaps.push(text[pos..].to_string());
+
+        convert_offsets(&mut encoding.offsets, text, unit);
+        encoding
+    }
+
+    /// Rebuild text from `ids` and an encoding from `encode_lossless`. Where
+    /// `ids` match the encoding, the original text of each token and the text
+    /// around it are restored, so `decode_lossless(encoding.ids(), &encoding)`
+    /// returns the encoded text byte for byte. Other ids, e.g. corrections made
+    /// by a model, are spelled with their vocabulary pieces.
+    pub fn decode_lossless(&self, ids: &[TokenId], encoding: &Encoding) -> String {
+        let mut text = String::new();
+        for (i, id) in ids.iter().enumerate() {
+            match encoding.gaps.get(i) {
+                Some(gap) if i < encoding.len() => text.push_str(gap),
+                _ => {}
+            }
+            if encoding.ids.get(i) == Some(id) {
+                if let Some(surface) = encoding.surfaces.get(i) {
+                    text.push_str(surface);
+                    continue;
+                }
+            }
+            let Some(token) = self.vocab_lookup.get(id) else {
+                continue;
+            };
+            match self.decoder.strip_continuation(token) {
+                Some(piece) => text.push_str(piece),
+                None => {
+                    // Tokens past the end of the encoding are words of their own
+                    if i >= encoding.len() && !text.is_empty() {
+                        text.push(' ');
+                    }
+                    text.push_str(token);
+                }
+            }
+        }
+        if encoding.gaps.len() > encoding.len() {
+            text.push_str(&encoding.gaps[encoding.len()]);
+        }
+        text
+    }
+
+    /// `encode_words` with offsets counted in `unit`
+    pub fn encode_words_with_unit<S: AsRef<str>>(&self, words: &[S], unit: OffsetUnit) -> Encoding {
+        let mut encoding = Encoding::default();
+        for (i, word) in words.iter().enumerate() {
+            self.extend_encoding(&mut encoding, word.as_ref(), unit, &mut || i);
+        }
+        self.fill_tokens(&mut encoding);
+        encoding
+    }
+
+    /// The tokens of `text` with their byte offsets in it, in order, e.g. to
+    /// color each token of a prompt in a token counter
+    pub fn tokenize_with_spans(&self, text: &str) -> Vec<TokenSpan> {
+        self.tokenize_with_spans_with_unit(text, OffsetUnit::Byte)
+    }
+
+    /// `tokenize_with_spans` with offsets counted in `unit`
+    pub fn tokenize_with_spans_with_unit(&self, text: &str, unit: OffsetUnit) -> Vec<TokenSpan> {
+        let encoding = self.encode_plus_with_unit(text, unit);
+        let mut previous_word = None;
+        encoding
+            .tokens
+            .into_iter()
+            .zip(encoding.ids)
+            .zip(encoding.offsets)
+            .zip(encoding.word_ids)
+            .map(|(((piece, id), (start, end)), word_id)| {
+                let is_continuation = word_id.is_some() && word_id == previous_word;
+                previous_word = word_id;
+                TokenSpan {
+                    piece,
+                    start,
+                    end,
+                    id,
+                    is_continuation,
+                }
+            })
+            .collect()
+    }
+
+    /// The tokens of `text` as `Token`s, with their ids, byte offsets and
+    /// whether they are special together, so the pieces of `tokenize` need not
+    /// be matched up with the ids of `encode`. The unknown token counts as
+    /// special, as in `wordpiece_tokenize`.
+    pub fn tokenize_tokens(&self, text: &str) -> Vec<Token> {
+        self.tokenize_tokens_with_unit(text, OffsetUnit::Byte)
+    }
+
+    /// `tokenize_tokens` with offsets counted in `unit`
+    pub fn tokenize_tokens_with_unit(&self, text: &str, unit: OffsetUnit) -> Vec<Token> {
+        self.tokenize_with_spans_with_unit(text, unit)
+            .into_iter()
+            .map(|span| Token {
+                is_special: span.id == self.unk_token_id || self.special_tokens.get(span.piece.as_str()) == Some(&span.id),
+                text: self.shared_token(span.id, &span.piece),
+                id: Some(span.id),
+                start: span.start,
+                end: span.end,
+                is_continuation: span.is_continuation,
+            })
+            .collect()
+    }
+
+    /// Append the ids, word indices and offsets of `text` to `encoding`, taking
+    /// the index of each word from `next_word`
+    fn extend_encoding<F: FnMut() -> usize>(
+        &self,
+        encoding: &mut Encoding,
+        text: &str,
+        unit: OffsetUnit,
+        next_word: &mut F,
+    ) {
+        let first = encoding.ids.len();
+
+        self.pre_tokenize_with_offsets(text, &mut |pre_token, spans| {
+            let start = encoding.ids.len();
+            let is_word = match pre_token {
+                PreToken::Word(piece) => {
+                    self.wordpiece_ids(piece, &mut encoding.ids);
+                    self.push_piece_offsets(piece, spans, &encoding.ids[start..], &mut encoding.offsets);
+                    true
+                }
+                // The unknown token stands in for a word, e.g. a never_split one
+                // missing from the vocabulary
+                PreToken::Matched { id, is_special, .. } => {
+                    encoding.ids.push(id);
+                    let span = covering(spans);
+                    encoding.offsets.push((span.start, span.end));
+                    !is_special || id == self.unk_token_id
+                }
+            };
+
+            let word_id = is_word.then(&mut *next_word);
+            let end = encoding.ids.len();
+            encoding.word_ids.extend((start..end).map(|_| word_id));
+        });
+
+        convert_offsets(&mut encoding.offsets[first..], text, unit);
+    }
+
+    /// Append the offsets of the pieces `ids` of `word`, given the range of the
+    /// original text each byte of the word was normalized from
+    fn push_piece_offsets(&self, word: &str, spans: &[Range<usize>], ids: &[TokenId], offsets: &mut Vec<(usize, usize)>) {
+        if ids == [self.unk_token_id] {
+            let span = covering(spans);
+            offsets.push((span.start, span.end));
+            return;
+        }
+
+        // Pieces spell out the word followed by the end-of-word suffix
+        let mut pos = 0;
+        for (i, id) in ids.iter().enumerate() {
+            let mut len = self.vocab_lookup[id].len();
+            if i > 0 {
+                len -= self.continuing_subword_prefix.len();
+            }
+            let (start, end) = (pos.min(word.len()), (pos + len).min(word.len()));
+            pos += len;
+
+            offsets.push(if start < end {
+                let span = covering(&spans[start..end]);
+                (span.start, span.end)
+            } else {
+                let end = spans[spans.len() - 1].end;
+                (end, end)
+            });
+        }
+    }
+
+    fn fill_tokens(&self, encoding: &mut Encoding) {
+        encoding.tokens = encoding.ids.iter().map(|id| self.vocab_lookup[id].to_string()).collect();
+    }
+}
+
+/// Convert byte offsets into `text` to offsets counted in `unit`
+fn convert_offsets(offsets: &mut [(usize, usize)], text: &str, unit: OffsetUnit) {
+    if unit != OffsetUnit::Byte {
+        let mut converter = OffsetConverter::new(text, unit);
+        for (start, end) in offsets {
+            *start = converter.get(*start);
+            *end = converter.get(*end);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Kinds of text kept whole by the pre-tokenizer instead of being split
+/// around their punctuation, as found in social media posts and logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Entity {
+    /// `https://example.com/a?b=c` or `www.example.com`, without trailing punctuation
+    Url,
+    /// `name@example.com`
+    Email,
+    /// `#wordpiece`
+    Hashtag,
+    /// `@handle`
+    Mention,
+}
+
+impl Entity {
+    /// Pattern matching the entity in raw text. Hashtags and mentions must not
+    /// follow a word character, so that `C#` and emails are left alone.
+    fn pattern(self) -> &'static str {
+        match self {
+            Entity::Url => r#"(?i:[a-z][a-z0-9+.-]*://|www\.)[^\s<>"]*[^\s<>"'.,;:!?)\]}]"#,
+            Entity::Email => r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+",
+            Entity::Hashtag => r"\B#\w+",
+            Entity::Mention => r"\B@\w+",
+        }
+    }
+}
+
+/// Finds the entities to keep whole in raw text
+#[derive(Debug, Clone)]
+pub(crate) struct EntityFinder {
+    regex: Regex,
+    /// The entity matched by each capture group, in order
+    entities: Vec<Entity>,
+}
+
+impl EntityFinder {
+    /// A finder for `entities`, or `None` when there are none to find
+    pub(crate) fn new(entities: &[Entity]) -> Option<Self> {
+        if entities.is_empty() {
+            return None;
+        }
+        // Earlier alternatives win at the same position, so URLs take
+        // precedence over the emails and mentions they may contain
+        let mut entities = entities.to_vec();
+        entities.sort();
+        entities.dedup();
+        let pattern = entities
+            .iter()
+            .map(|entity| format!("({})", entity.pattern()))
+            .collect::<Vec<_>>()
+            .join("|");
+        Some(EntityFinder {
+            regex: Regex::new(&pattern).unwrap(),
+            entities,
+        })
+    }
+
+    /// Split raw text into stretches with their byte offsets, passing each to
+    /// `f` in order with the entity it is, or `None` for the text between them
+    pub(crate) fn split<'t, F: FnMut(usize, &'t str, Option<Entity>)>(&self, text: &'t str, mut f: F) {
+        let mut last = 0;
+        for captures in self.regex.captures_iter(text) {
+            let Some((group, mat)) = captures.iter().enumerate().skip(1).find_map(|(i, m)| Some((i, m?))) else {
+                continue;
+            };
+            if mat.start() > last {
+                f(last, &text[last..mat.start()], None);
+            }
+            f(mat.start(), mat.as_str(), Some(self.entities[group - 1]));
+            last = mat.end();
+        }
+        if last < text.len() {
+            f(last, &text[last..], None);
+        }
+    }
+}
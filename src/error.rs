@@ -0,0 +1,102 @@
+use std::fmt;
+use std::io;
+
+use crate::TokenId;
+
+/// Errors raised while building, configuring, saving or loading a tokenizer
+#[derive(Debug)]
+pub enum Error {
+    /// A special token was registered that does not appear in the vocabulary
+    SpecialTokenNotInVocab(String),
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// A compiled tokenizer file is malformed or from an incompatible version
+    InvalidSnapshot(String),
+    /// A training corpus was given a weight that is not positive and finite
+    InvalidCorpusWeight(f64),
+    /// Masking for language modeling was requested without a registered mask token
+    NoMaskToken,
+    /// A masking probability outside of [0, 1] was given
+    InvalidMaskProbability(f64),
+    /// Options given to a builder or in a configuration are missing or inconsistent
+    InvalidConfig(String),
+    /// An id being decoded is not in the vocabulary
+    UnknownId(TokenId),
+    /// A text to encode is longer than the tokenizer's `max_input_bytes`
+    InputTooLarge { bytes: usize, max: usize },
+    /// A text encodes to more tokens than the tokenizer's `max_tokens`
+    TooManyTokens { max: usize },
+    /// A SentencePiece model file could not be read
+    InvalidSentencePieceModel(String),
+    /// A byte-level BPE model is missing byte tokens or its files could not be read
+    InvalidBytePairModel(String),
+    /// A `MultiTokenizer` was routed to a vocabulary it does not have or to
+    /// spans that do not fit the text
+    InvalidRoute(String),
+    /// A parity check was given a different number of texts and reference outputs
+    ReferenceCountMismatch { texts: usize, references: usize },
+    /// The task of a `BatchingTokenizer` stopped before encoding a request,
+    /// e.g. because encoding its batch panicked
+    #[cfg(any(feature = "serve", feature = "grpc", feature = "async"))]
+    BatchingStopped,
+    /// A pretrained tokenizer could not be downloaded from the Hugging Face Hub or its files are unusable
+    #[cfg(feature = "hf-hub")]
+    Hub(String),
+    /// The dictionary of a morphological analyzer could not be loaded
+    #[cfg(feature = "lindera")]
+    Morphology(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SpecialTokenNotInVocab(token) => {
+                write!(f, "Special token '{}' is not in the vocabulary", token)
+            }
+            Error::Io(err) => write!(f, "{}", err),
+            Error::InvalidSnapshot(reason) => write!(f, "Invalid compiled tokenizer: {}", reason),
+            Error::InvalidCorpusWeight(weight) => {
+                write!(f, "Corpus weight must be positive and finite, got {}", weight)
+            }
+            Error::NoMaskToken => write!(f, "No mask token is registered"),
+            Error::InvalidMaskProbability(prob) => {
+                write!(f, "Mask probability must be between 0 and 1, got {}", prob)
+            }
+            Error::InvalidConfig(reason) => write!(f, "Invalid tokenizer configuration: {}", reason),
+            Error::UnknownId(id) => write!(f, "Id {} is not in the vocabulary", id),
+            Error::InputTooLarge { bytes, max } => {
+                write!(f, "Input of {} bytes exceeds the limit of {} bytes", bytes, max)
+            }
+            Error::TooManyTokens { max } => write!(f, "Input encodes to more than the limit of {} tokens", max),
+            Error::InvalidSentencePieceModel(reason) => write!(f, "Invalid SentencePiece model: {}", reason),
+            Error::InvalidBytePairModel(reason) => write!(f, "Invalid byte-level BPE model: {}", reason),
+            Error::InvalidRoute(reason) => write!(f, "Invalid vocabulary route: {}", reason),
+            Error::ReferenceCountMismatch { texts, references } => {
+                write!(f, "Got {} texts but {} reference outputs", texts, references)
+            }
+            #[cfg(any(feature = "serve", feature = "grpc", feature = "async"))]
+            Error::BatchingStopped => write!(f, "The batching task stopped before encoding the request"),
+            #[cfg(feature = "hf-hub")]
+            Error::Hub(reason) => write!(f, "Could not load pretrained tokenizer: {}", reason),
+            #[cfg(feature = "lindera")]
+            Error::Morphology(reason) => write!(f, "Could not load morphological analyzer: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
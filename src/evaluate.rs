@@ -0,0 +1,204 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+use crate::offsets::covering;
+use crate::trainer::COUNT_CHUNK_SIZE;
+use crate::{PreToken, WordPieceTokenizer};
+
+/// How well a vocabulary fits a corpus, see `WordPieceTokenizer::evaluate`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvaluationStats {
+    pub texts: usize,
+    /// Words after pre-tokenization, counting tokens matched whole as one word
+    pub words: usize,
+    pub tokens: usize,
+    pub unk_tokens: usize,
+    /// Characters of the words
+    pub chars: usize,
+    /// Characters of the words not mapped to the unknown token
+    pub covered_chars: usize,
+}
+
+impl EvaluationStats {
+    /// Average number of tokens per word, 1 when every word is a single token
+    pub fn fertility(&self) -> f64 {
+        ratio(self.tokens, self.words)
+    }
+
+    /// Fraction of tokens that are the unknown token
+    pub fn unk_rate(&self) -> f64 {
+        ratio(self.unk_tokens, self.tokens)
+    }
+
+    /// Fraction of word characters that are not lost to the unknown token
+    pub fn char_coverage(&self) -> f64 {
+        ratio(self.covered_chars, self.chars)
+    }
+
+    /// Average number of tokens per text
+    pub fn avg_sequence_length(&self) -> f64 {
+        ratio(self.tokens, self.texts)
+    }
+
+    /// Combine the counts of two disjoint parts of a corpus
+    pub(crate) fn add(mut self, other: EvaluationStats) -> Self {
+        self.texts += other.texts;
+        self.words += other.words;
+        self.tokens += other.tokens;
+        self.unk_tokens += other.unk_tokens;
+        self.chars += other.chars;
+        self.covered_chars += other.covered_chars;
+        self
+    }
+}
+
+/// A stretch of the original text that was mapped to the unknown token, with
+/// its byte offsets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSpan {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// `numerator / denominator`, or 0 when there is nothing to divide
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Measure how well the vocabulary fits `texts`: tokens per word, unknown
+    /// token rate, character coverage and sequence length, e.g. to compare
+    /// candidate vocabularies on the same corpus
+    pub fn evaluate<I, S>(&self, texts: I) -> EvaluationStats
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let mut stats = EvaluationStats::default();
+        let mut chunk = Vec::with_capacity(COUNT_CHUNK_SIZE);
+        for text in texts {
+            chunk.push(text);
+            if chunk.len() == COUNT_CHUNK_SIZE {
+                stats = stats.add(self.evaluate_chunk(&chunk));
+                chunk.clear();
+            }
+        }
+        stats.add(self.evaluate_chunk(&chunk))
+    }
+
+    /// Evaluate a chunk of texts in parallel
+    fn evaluate_chunk<S: AsRef<str> + Sync>(&self, texts: &[S]) -> EvaluationStats {
+        texts
+            .par_iter()
+            .map(|text| self.evaluate_text(text.as_ref()))
+            .reduce(EvaluationStats::default, EvaluationStats::add)
+    }
+
+    fn evaluate_text(&self, text: &str) -> EvaluationStats {
+        let mut stats = EvaluationStats {
+            texts: 1,
+            ..EvaluationStats::default()
+        };
+        let mut ids = Vec::new();
+
+        self.pre_tokenize(text, &mut |pre_token| {
+            let (word, start) = match pre_token {
+                PreToken::Word(word) => {
+                    let start = ids.len();
+                    self.wordpiece_ids(word, &mut ids);
+                    (word, start)
+                }
+                PreToken::Matched { text, id, .. } => {
+                    ids.push(id);
+                    (text, ids.len() - 1)
+                }
+            };
+
+            let chars = word.chars().count();
+            stats.words += 1;
+            stats.chars += chars;
+            if ids[start..] != [self.unk_token_id] {
+                stats.covered_chars += chars;
+            }
+        });
+
+        stats.tokens = ids.len();
+        stats.unk_tokens = ids.iter().filter(|&&id| id == self.unk_token_id).count();
+        stats
+    }
+
+    /// Find the stretches of `text` that were mapped to the unknown token, e.g.
+    /// words with characters missing from the vocabulary, in order and with
+    /// their offsets in the original, unnormalized text
+    pub fn analyze(&self, text: &str) -> Vec<UnknownSpan> {
+        let mut unknown = Vec::new();
+        let mut ids = Vec::new();
+
+        self.pre_tokenize_with_offsets(text, &mut |pre_token, spans| {
+            ids.clear();
+            match pre_token {
+                PreToken::Word(word) => self.wordpiece_ids(word, &mut ids),
+                PreToken::Matched { id, .. } => ids.push(id),
+            }
+
+            // The unknown token written out in the text is not a vocabulary gap
+            let span = covering(spans);
+            if ids == [self.unk_token_id] && text[span.clone()] != self.unk_token {
+                unknown.push(UnknownSpan {
+                    text: text[span.clone()].to_string(),
+                    start: span.start,
+                    end: span.end,
+                });
+            }
+        });
+
+        unknown
+    }
+
+    /// Count how often each stretch of text is mapped to the unknown token
+    /// across `texts`, to find the gaps of the vocabulary worth fixing first
+    pub fn unknown_counts<I, S>(&self, texts: I) -> HashMap<String, usize>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let mut counts = HashMap::new();
+        let mut chunk = Vec::with_capacity(COUNT_CHUNK_SIZE);
+        for text in texts {
+            chunk.push(text);
+            if chunk.len() == COUNT_CHUNK_SIZE {
+                self.count_unknown_into(&chunk, &mut counts);
+                chunk.clear();
+            }
+        }
+        self.count_unknown_into(&chunk, &mut counts);
+        counts
+    }
+
+    /// Add how often each stretch of text in a chunk of texts is mapped to the
+    /// unknown token, in parallel, to `counts`
+    pub(crate) fn count_unknown_into<S: AsRef<str> + Sync>(&self, texts: &[S], counts: &mut HashMap<String, usize>) {
+        let chunk_counts = texts
+            .par_iter()
+            .fold(HashMap::new, |mut counts, text| {
+                for span in self.analyze(text.as_ref()) {
+                    *counts.entry(span.text).or_insert(0) += 1;
+                }
+                counts
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (text, count) in b {
+                    *a.entry(text).or_insert(0) += count;
+                }
+                a
+            });
+        for (text, count) in chunk_counts {
+            *counts.entry(text).or_insert(0) += count;
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{Result, TokenId, WordPieceTokenizer, WordPieceTrainer};
+
+impl WordPieceTokenizer {
+    /// Write the vocabulary as a `vocab.txt` file with one token per line, the
+    /// line number being the id, as read by BERT, TensorFlow Text and
+    /// `transformers`. Ids missing from the vocabulary are filled with
+    /// `[unused<id>]` placeholders so that the following ids stay in place.
+    pub fn save_vocab<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut ids: Vec<(TokenId, &String)> = self.vocab.iter().map(|(token, &id)| (id, token)).collect();
+        ids.sort_unstable();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut next_id = 0;
+        for (id, token) in ids {
+            for unused in next_id..id {
+                writeln!(writer, "[unused{}]", unused)?;
+            }
+            writeln!(writer, "{}", token)?;
+            next_id = id + 1;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Read a `vocab.txt` file, one token per line with the line number as its id,
+/// as written by `WordPieceTokenizer::save_vocab` and BERT checkpoints
+pub fn load_vocab<P: AsRef<Path>>(path: P) -> Result<HashMap<String, TokenId>> {
+    Ok(parse_vocab(&fs::read_to_string(path)?))
+}
+
+/// Read the contents of a `vocab.txt` file, see `load_vocab`
+pub(crate) fn parse_vocab(text: &str) -> HashMap<String, TokenId> {
+    text.lines()
+        .enumerate()
+        .map(|(id, token)| (token.to_string(), id as TokenId))
+        .collect()
+}
+
+impl WordPieceTrainer {
+    /// Write the merges of the last training run, one `first second score` line
+    /// each in the order they were made
+    pub fn save_merges<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for (first, second, score) in self.merges() {
+            writeln!(writer, "{} {} {}", first, second, score)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
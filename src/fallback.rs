@@ -0,0 +1,121 @@
+use std::borrow::Cow;
+
+use rayon::prelude::*;
+
+use crate::{BytePairModel, Error, PreToken, Result, TokenId, WordPieceTokenizer};
+
+/// A WordPiece tokenizer that hands the words its vocabulary cannot cover to a
+/// byte-level BPE model instead of producing the unknown token. The BPE ids
+/// follow the WordPiece ids in one id space, so no text is lost to `[UNK]`.
+#[derive(Clone)]
+pub struct FallbackTokenizer {
+    tokenizer: WordPieceTokenizer,
+    fallback: BytePairModel,
+    /// Id of the first BPE token in the merged id space
+    offset: TokenId,
+}
+
+impl FallbackTokenizer {
+    /// Put the ids of `fallback` after those of `tokenizer`
+    pub fn new(tokenizer: WordPieceTokenizer, fallback: BytePairModel) -> Result<Self> {
+        let offset = tokenizer.vocab().values().max().map_or(0, |&max| max + 1);
+        let fallback_size = fallback.vocab().values().max().map_or(0, |&max| max + 1);
+        if offset.checked_add(fallback_size).is_none() {
+            return Err(Error::InvalidConfig("the vocabularies have too many ids together".to_string()));
+        }
+        Ok(FallbackTokenizer { tokenizer, fallback, offset })
+    }
+
+    /// The WordPiece tokenizer tried first
+    pub fn tokenizer(&self) -> &WordPieceTokenizer {
+        &self.tokenizer
+    }
+
+    /// The BPE model for the words WordPiece cannot cover
+    pub fn fallback(&self) -> &BytePairModel {
+        &self.fallback
+    }
+
+    /// Id of the first BPE token in the merged id space, ids below it are WordPiece ids
+    pub fn fallback_offset(&self) -> TokenId {
+        self.offset
+    }
+
+    /// Size of the merged id space, the number of embedding rows it needs
+    pub fn vocab_size(&self) -> usize {
+        self.offset as usize + self.fallback.vocab().values().max().map_or(0, |&max| max as usize + 1)
+    }
+
+    /// The token with id `id` in the merged id space, if any
+    pub fn id_to_token(&self, id: TokenId) -> Option<&str> {
+        match id.checked_sub(self.offset) {
+            Some(id) => self.fallback.id_to_token(id),
+            None => self.tokenizer.id_to_token(id),
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<TokenId> {
+        let unk = [self.tokenizer.unk_token_id];
+        let mut ids = Vec::new();
+
+        self.tokenizer.pre_tokenize(text, &mut |pre_token| match pre_token {
+            PreToken::Word(word) => {
+                let start = ids.len();
+                self.tokenizer.wordpiece_ids(word, &mut ids);
+                // Words over the length limit stay unknown, as input limits intend
+                if ids[start..] == unk && !self.tokenizer.is_word_too_long(word) {
+                    ids.truncate(start);
+                    // The leading space lets decoding tell consecutive BPE words apart
+                    self.fallback.encode_into(&format!(" {}", word), &mut ids);
+                    for id in &mut ids[start..] {
+                        *id += self.offset;
+                    }
+                }
+            }
+            PreToken::Matched { id, .. } => ids.push(id),
+        });
+
+        ids
+    }
+
+    /// Encode many texts in parallel
+    pub fn encode_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Vec<Vec<TokenId>> {
+        texts.par_iter().map(|text| self.encode(text.as_ref())).collect()
+    }
+
+    /// Tokens of `text`, WordPiece pieces and BPE tokens in their byte-level spelling
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.encode(text)
+            .into_iter()
+            .filter_map(|id| self.id_to_token(id).map(str::to_string))
+            .collect()
+    }
+
+    /// Join the tokens of `ids` back into text. BPE ids are turned back into
+    /// their words, and ids missing from both vocabularies are skipped.
+    pub fn decode(&self, ids: &[TokenId], skip_special_tokens: bool, clean_up_tokenization_spaces: bool) -> String {
+        let mut tokens: Vec<(Cow<'_, str>, bool)> = Vec::with_capacity(ids.len());
+        let mut run = Vec::new();
+        for (i, &id) in ids.iter().enumerate() {
+            if let Some(id) = id.checked_sub(self.offset) {
+                run.push(id);
+                if ids.get(i + 1).is_some_and(|&next| next >= self.offset) {
+                    continue;
+                }
+                let text = self.fallback.decode(&run);
+                tokens.extend(text.split_whitespace().map(|word| (Cow::Owned(word.to_string()), false)));
+                run.clear();
+            } else if let Some(token) = self.tokenizer.vocab_lookup.get(&id) {
+                let is_special = self.tokenizer.special_tokens.contains_key(token);
+                if !(skip_special_tokens && is_special) {
+                    tokens.push((Cow::Borrowed(&**token), is_special));
+                }
+            }
+        }
+
+        self.tokenizer.decoder.decode_tokens(
+            tokens.iter().map(|(token, is_special)| (token.as_ref(), *is_special)),
+            clean_up_tokenization_spaces,
+        )
+    }
+}
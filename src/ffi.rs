@@ -0,0 +1,272 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::{ptr, slice};
+
+use crate::{load_vocab, WordPieceTokenizer};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: impl Display) {
+    let message = CString::new(err.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Turn the result of a constructor into a pointer owned by the caller
+fn into_raw<E: Display>(result: Result<WordPieceTokenizer, E>) -> *mut WordPieceTokenizer {
+    match result {
+        Ok(tokenizer) => Box::into_raw(Box::new(tokenizer)),
+        Err(err) => {
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Run the body of a function returning a length, returning -1 and setting the
+/// last error when it fails or panics, as a panic must not unwind into C
+fn len_or_error<F: FnOnce() -> Result<usize, String>>(f: F) -> isize {
+    let err = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(len)) => return len as isize,
+        Ok(Err(err)) => err,
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message,
+                None => payload.downcast_ref::<String>().map_or("unknown cause", String::as_str),
+            };
+            format!("wordpiece_rs panicked: {}", message)
+        }
+    };
+    set_last_error(err);
+    -1
+}
+
+/// Read a NUL-terminated UTF-8 string argument
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+/// Read a UTF-8 string argument of `len` bytes, not necessarily NUL-terminated
+unsafe fn text_arg<'a>(text: *const c_char, len: usize) -> Result<&'a str, String> {
+    if text.is_null() && len > 0 {
+        return Err("text is NULL".to_string());
+    }
+    let bytes = match len {
+        0 => &[][..],
+        _ => slice::from_raw_parts(text as *const u8, len),
+    };
+    std::str::from_utf8(bytes).map_err(|_| "text is not valid UTF-8".to_string())
+}
+
+/// The message of the last error raised on the calling thread, or NULL. The
+/// string is owned by the library and valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn wordpiece_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Load a tokenizer from a `vocab.txt` file, one token per line with the line
+/// number as its id. `unk_token` may be NULL for `[UNK]`. Returns NULL on error.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string, and `unk_token` one or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn wordpiece_tokenizer_from_vocab_file(
+    path: *const c_char,
+    unk_token: *const c_char,
+    lowercase: bool,
+    strip_accents: bool,
+) -> *mut WordPieceTokenizer {
+    into_raw(from_vocab_file(path, unk_token, lowercase, strip_accents))
+}
+
+unsafe fn from_vocab_file(
+    path: *const c_char,
+    unk_token: *const c_char,
+    lowercase: bool,
+    strip_accents: bool,
+) -> Result<WordPieceTokenizer, String> {
+    let path = str_arg(path, "path")?;
+    let unk_token = match unk_token.is_null() {
+        true => "[UNK]",
+        false => str_arg(unk_token, "unk_token")?,
+    };
+    let vocab = load_vocab(path).map_err(|err| format!("{}: {}", path, err))?;
+    WordPieceTokenizer::builder()
+        .vocab(vocab)
+        .unk_token(unk_token)
+        .lowercase(lowercase)
+        .strip_accents(strip_accents)
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+/// Load a tokenizer from a file written by `save_compiled`. Returns NULL on error.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wordpiece_tokenizer_load_compiled(path: *const c_char) -> *mut WordPieceTokenizer {
+    into_raw(str_arg(path, "path").and_then(|path| {
+        WordPieceTokenizer::load_compiled(Path::new(path)).map_err(|err| format!("{}: {}", path, err))
+    }))
+}
+
+/// Free a tokenizer. Does nothing when `tokenizer` is NULL.
+///
+/// # Safety
+///
+/// `tokenizer` must come from one of the `wordpiece_tokenizer_*` constructors
+/// and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn wordpiece_tokenizer_free(tokenizer: *mut WordPieceTokenizer) {
+    if !tokenizer.is_null() {
+        drop(Box::from_raw(tokenizer));
+    }
+}
+
+/// Number of tokens in the vocabulary
+///
+/// # Safety
+///
+/// `tokenizer` must be a live tokenizer.
+#[no_mangle]
+pub unsafe extern "C" fn wordpiece_vocab_size(tokenizer: *const WordPieceTokenizer) -> usize {
+    (*tokenizer).vocab().len()
+}
+
+/// Encode `len` bytes of UTF-8 `text` into `ids`, which has room for `capacity`
+/// ids. Returns the number of ids of the text, writing only the first
+/// `capacity` of them when there are more, so a call with a capacity of 0
+/// counts the tokens. Returns -1 on error, such as a text over the tokenizer's
+/// `max_input_bytes` or `max_tokens`.
+///
+/// # Safety
+///
+/// `tokenizer` must be a live tokenizer, `text` must point to `len` bytes, and
+/// `ids` to room for `capacity` ids.
+#[no_mangle]
+pub unsafe extern "C" fn wordpiece_encode(
+    tokenizer: *const WordPieceTokenizer,
+    text: *const c_char,
+    len: usize,
+    ids: *mut u32,
+    capacity: usize,
+) -> isize {
+    len_or_error(|| {
+        let text = text_arg(text, len)?;
+        let encoded = (*tokenizer).try_encode(text).map_err(|err| err.to_string())?;
+        let written = encoded.len().min(capacity);
+        if written > 0 {
+            ptr::copy_nonoverlapping(encoded.as_ptr(), ids, written);
+        }
+        Ok(encoded.len())
+    })
+}
+
+/// Decode `len` ids into `text`, which has room for `capacity` bytes, as a
+/// NUL-terminated UTF-8 string. Returns the length of the text in bytes without
+/// the terminator. When it is longer than `capacity - 1`, as many whole
+/// characters as fit are written, much as `snprintf` does. Returns -1 on error.
+///
+/// # Safety
+///
+/// `tokenizer` must be a live tokenizer, `ids` must point to `len` ids, and
+/// `text` to room for `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn wordpiece_decode(
+    tokenizer: *const WordPieceTokenizer,
+    ids: *const u32,
+    len: usize,
+    skip_special_tokens: bool,
+    clean_up_tokenization_spaces: bool,
+    text: *mut c_char,
+    capacity: usize,
+) -> isize {
+    len_or_error(|| {
+        let ids = match len {
+            0 => &[][..],
+            _ => slice::from_raw_parts(ids, len),
+        };
+
+        let decoded = (*tokenizer).decode(ids, skip_special_tokens, clean_up_tokenization_spaces);
+        if capacity > 0 {
+            let mut written = decoded.len().min(capacity - 1);
+            while !decoded.is_char_boundary(written) {
+                written -= 1;
+            }
+            ptr::copy_nonoverlapping(decoded.as_ptr() as *const c_char, text, written);
+            *text.add(written) = 0;
+        }
+        Ok(decoded.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::tokenizer;
+
+    #[test]
+    fn committed_header_is_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/wordpiece_rs.h"));
+        let committed = include_str!("../include/wordpiece_rs.h");
+        assert!(
+            generated == committed,
+            "include/wordpiece_rs.h is stale, copy {}/wordpiece_rs.h over it",
+            env!("OUT_DIR")
+        );
+    }
+
+    #[test]
+    fn results_are_cut_to_the_buffer() {
+        let tokenizer = tokenizer(&["hello", "world", "ça"]);
+        let text = "hello world";
+        let mut ids = [0u32; 1];
+        let n = unsafe { wordpiece_encode(&tokenizer, text.as_ptr() as *const c_char, text.len(), ids.as_mut_ptr(), 1) };
+        assert_eq!((n, ids), (2, [5]));
+
+        // Room for two bytes and the terminator only fits the `ç` of `ça`
+        let mut decoded = [1 as c_char; 3];
+        let n = unsafe { wordpiece_decode(&tokenizer, [7].as_ptr(), 1, true, true, decoded.as_mut_ptr(), 3) };
+        assert_eq!(n, 3);
+        assert_eq!(unsafe { CStr::from_ptr(decoded.as_ptr()) }.to_str(), Ok("ç"));
+
+        // One byte less cuts before the `ç` rather than inside it
+        let n = unsafe { wordpiece_decode(&tokenizer, [7].as_ptr(), 1, true, true, decoded.as_mut_ptr(), 2) };
+        assert_eq!(n, 3);
+        assert_eq!(unsafe { CStr::from_ptr(decoded.as_ptr()) }.to_str(), Ok(""));
+    }
+
+    #[test]
+    fn limits_fail_with_the_last_error() {
+        let mut tokenizer = tokenizer(&["hello", "world"]);
+        tokenizer.set_max_tokens(Some(1));
+        let text = "hello world";
+        let n = unsafe { wordpiece_encode(&tokenizer, text.as_ptr() as *const c_char, text.len(), ptr::null_mut(), 0) };
+        assert_eq!(n, -1);
+        let message = unsafe { CStr::from_ptr(wordpiece_last_error()) };
+        assert_eq!(message.to_str(), Ok("Input encodes to more than the limit of 1 tokens"));
+    }
+
+    #[test]
+    fn panics_fail_with_the_last_error() {
+        // A formatted message is a `String`, a literal one a `&str`
+        assert_eq!(len_or_error(|| panic!("bad {}", "state")), -1);
+        let message = unsafe { CStr::from_ptr(wordpiece_last_error()) };
+        assert_eq!(message.to_str(), Ok("wordpiece_rs panicked: bad state"));
+
+        assert_eq!(len_or_error(|| panic!("bad state")), -1);
+        let message = unsafe { CStr::from_ptr(wordpiece_last_error()) };
+        assert_eq!(message.to_str(), Ok("wordpiece_rs panicked: bad state"));
+    }
+}
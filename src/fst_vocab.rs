@@ -0,0 +1,173 @@
+use fst::raw::{CompiledAddr, Output};
+use fst::Map;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::memory::table_size;
+use crate::TokenId;
+
+/// Vocabulary stored as a minimized finite state transducer from tokens to ids.
+///
+/// Shared prefixes and suffixes are stored once, so a large vocabulary takes a
+/// fraction of the memory of the trie at the cost of slower matching. Words are
+/// segmented by greedy longest-match-first, walking the transducer byte by byte.
+#[derive(Clone)]
+pub(crate) struct FstVocab {
+    map: Map<Vec<u8>>,
+    /// Ids of tokens that are no longer matched, e.g. special tokens
+    excluded: HashSet<TokenId>,
+    /// Prefix marking a token that continues a word
+    continuation_prefix: String,
+    /// State and output reached after consuming the continuation prefix
+    suffix_root: Option<(CompiledAddr, Output)>,
+}
+
+impl FstVocab {
+    pub(crate) fn new(vocab: &HashMap<String, TokenId>, continuation_prefix: &str) -> Self {
+        // The transducer must be built from keys in lexicographic byte order
+        let sorted: BTreeMap<&[u8], u64> = vocab
+            .iter()
+            .map(|(token, &id)| (token.as_bytes(), u64::from(id)))
+            .collect();
+        let map = Map::from_iter(sorted).expect("keys are sorted and unique");
+
+        Self::from_map(map, HashSet::new(), continuation_prefix.to_string())
+    }
+
+    fn from_map(map: Map<Vec<u8>>, excluded: HashSet<TokenId>, continuation_prefix: String) -> Self {
+        let suffix_root = walk(
+            &map,
+            map.as_fst().root().addr(),
+            Output::zero(),
+            continuation_prefix.as_bytes(),
+        );
+        FstVocab {
+            map,
+            excluded,
+            continuation_prefix,
+            suffix_root,
+        }
+    }
+
+    /// Stop matching a token, leaving the transducer itself untouched
+    pub(crate) fn remove(&mut self, token: &str) {
+        if let Some(id) = self.map.get(token) {
+            self.excluded.insert(id as TokenId);
+        }
+    }
+
+    /// Estimated bytes of memory held by the transducer and the excluded ids
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.map.as_fst().as_bytes().len()
+            + table_size(self.excluded.capacity(), std::mem::size_of::<TokenId>())
+            + self.continuation_prefix.capacity()
+    }
+
+    /// Check that every token id satisfies `is_known_id`, as a loaded vocabulary may be corrupt
+    pub(crate) fn is_consistent<F: Fn(TokenId) -> bool>(&self, is_known_id: F) -> bool {
+        let mut stream = self.map.stream();
+        let mut all_known = true;
+        while let Some((_, id)) = fst::Streamer::next(&mut stream) {
+            all_known &= is_known_id(id as TokenId);
+        }
+        all_known
+    }
+
+    /// Segment a word into token ids by greedy longest-match-first, appending
+    /// them to `ids`. Returns false if the word cannot be segmented.
+    pub(crate) fn tokenize_word(&self, word: &str, ids: &mut Vec<TokenId>) -> bool {
+        let fst = self.map.as_fst();
+        let mut start = 0;
+
+        while start < word.len() {
+            let (addr, output) = if start == 0 {
+                (fst.root().addr(), Output::zero())
+            } else {
+                match self.suffix_root {
+                    Some(suffix_root) => suffix_root,
+                    None => return false,
+                }
+            };
+
+            let mut node = fst.node(addr);
+            let mut output = output;
+            let mut longest = None;
+
+            for (offset, byte) in word[start..].bytes().enumerate() {
+                let transition = match node.find_input(byte) {
+                    Some(i) => node.transition(i),
+                    None => break,
+                };
+                output = output.cat(transition.out);
+                node = fst.node(transition.addr);
+
+                let end = start + offset + 1;
+                if node.is_final() && word.is_char_boundary(end) {
+                    let id = output.cat(node.final_output()).value() as TokenId;
+                    if !self.excluded.contains(&id) {
+                        longest = Some((end, id));
+                    }
+                }
+            }
+
+            match longest {
+                Some((end, id)) => {
+                    ids.push(id);
+                    start = end;
+                }
+                None => return false,
+            }
+        }
+
+        true
+    }
+}
+
+/// Follow `bytes` from the state at `addr`, returning the state reached and the accumulated output
+fn walk(map: &Map<Vec<u8>>, addr: CompiledAddr, output: Output, bytes: &[u8]) -> Option<(CompiledAddr, Output)> {
+    let fst = map.as_fst();
+    let mut node = fst.node(addr);
+    let mut output = output;
+    for &byte in bytes {
+        let transition = node.transition(node.find_input(byte)?);
+        output = output.cat(transition.out);
+        node = fst.node(transition.addr);
+    }
+    Some((node.addr(), output))
+}
+
+/// Serialized form of `FstVocab`: the raw transducer bytes, the excluded ids
+/// and the continuation prefix
+#[derive(Serialize, Deserialize)]
+struct FstVocabParts {
+    map: Vec<u8>,
+    excluded: Vec<TokenId>,
+    continuation_prefix: String,
+}
+
+impl Serialize for FstVocab {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut excluded: Vec<TokenId> = self.excluded.iter().copied().collect();
+        excluded.sort_unstable();
+
+        FstVocabParts {
+            map: self.map.as_fst().as_bytes().to_vec(),
+            excluded,
+            continuation_prefix: self.continuation_prefix.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FstVocab {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let parts = FstVocabParts::deserialize(deserializer)?;
+        let map = Map::new(parts.map).map_err(D::Error::custom)?;
+        Ok(FstVocab::from_map(
+            map,
+            parts.excluded.into_iter().collect(),
+            parts.continuation_prefix,
+        ))
+    }
+}
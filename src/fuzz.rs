@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use crate::{CaseFolding, NormalizationForm, PreTokenizer, TokenId, WordPieceTokenizer};
+
+/// Tokens every fuzzed vocabulary starts with
+const SPECIAL_TOKENS: [&str; 5] = ["[PAD]", "[UNK]", "[CLS]", "[SEP]", "[MASK]"];
+
+/// Run `data` through normalization, tokenization and decoding and panic if
+/// any step panics or breaks an invariant, for fuzzers such as `cargo fuzz`.
+/// The first two bytes choose the options of the tokenizer and the rest,
+/// decoded as UTF-8 with invalid sequences replaced, is the text. The
+/// vocabulary holds the characters and character pairs of the text, so most
+/// of it is segmented rather than unknown. The same input always takes the
+/// same path, so a crash can be replayed from the input alone.
+///
+/// The invariants checked are that:
+/// - `encode`, `encode_plus`, `tokenize` and `count_tokens` agree
+/// - every id is in the vocabulary
+/// - offsets are ordered character boundaries within the text
+/// - `decode_lossless` restores the text byte for byte
+/// - decoding and encoding again gives the same ids once the text has been
+///   decoded, as decoding without cleanup only joins pieces and puts spaces
+///   between words
+pub fn fuzz_roundtrip(data: &[u8]) {
+    let (options, text) = match data {
+        [a, b, rest @ ..] => ([*a, *b], String::from_utf8_lossy(rest)),
+        _ => return,
+    };
+    let Some(tokenizer) = fuzz_tokenizer(options, &text) else {
+        return;
+    };
+
+    // Normalization and pre-tokenization
+    for token in tokenizer.basic_tokenize(&text) {
+        assert!(token.start <= token.end && token.end <= text.len(), "word {:?} out of bounds", token);
+        tokenizer.wordpiece_tokenize(&token);
+    }
+
+    let ids = tokenizer.encode(&text);
+    let encoding = tokenizer.encode_plus(&text);
+    assert_eq!(encoding.ids(), ids, "encode_plus differs from encode");
+    assert_eq!(tokenizer.count_tokens(&text), ids.len(), "count_tokens differs from encode");
+    let tokens = tokenizer.tokenize(&text);
+    for (&id, token) in ids.iter().zip(&tokens) {
+        assert_eq!(tokenizer.id_to_token(id), Some(token.as_str()), "id {} is not in the vocabulary", id);
+    }
+
+    let mut previous = 0;
+    for &(start, end) in encoding.offsets() {
+        assert!(start <= end && end <= text.len(), "offsets {:?} out of bounds", (start, end));
+        assert!(text.is_char_boundary(start) && text.is_char_boundary(end), "offsets {:?} split a character", (start, end));
+        assert!(start >= previous, "offsets out of order at {}", start);
+        previous = start;
+    }
+
+    let lossless = tokenizer.encode_lossless(&text);
+    assert_eq!(tokenizer.decode_lossless(lossless.ids(), &lossless), text, "decode_lossless lost text");
+
+    // Characters removed after normalization, such as control characters
+    // between a letter and its accent, can leave text that normalizes further
+    // once decoded, so the ids only need to settle after one round
+    tokenizer.decode(&ids, true, true);
+    let decoded = tokenizer.decode(&ids, false, false);
+    let ids = tokenizer.encode(&decoded);
+    let decoded = tokenizer.decode(&ids, false, false);
+    assert_eq!(tokenizer.encode(&decoded), ids, "encoding {:?} decoded from {:?} changed its ids", decoded, text);
+}
+
+/// A tokenizer with the options of `options` and a vocabulary covering `text`
+fn fuzz_tokenizer(options: [u8; 2], text: &str) -> Option<WordPieceTokenizer> {
+    let [flags, forms] = options;
+    let suffix = if forms & 0b100 != 0 { "</w>" } else { "" };
+
+    let mut vocab: HashMap<String, TokenId> = HashMap::new();
+    let mut add = |token: String| {
+        let id = vocab.len() as TokenId;
+        vocab.entry(token).or_insert(id);
+    };
+    for token in SPECIAL_TOKENS {
+        add(token.to_string());
+    }
+    let chars: Vec<char> = text.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        let mut pieces = vec![c.to_string()];
+        pieces.extend(c.to_lowercase().map(String::from));
+        if let Some(&next) = chars.get(i + 1) {
+            pieces.push(format!("{}{}", c, next));
+        }
+        for piece in pieces {
+            add(format!("##{}", piece));
+            add(format!("##{}{}", piece, suffix));
+            add(format!("{}{}", piece, suffix));
+            add(piece);
+        }
+    }
+
+    let pre_tokenizer = match flags & 0b11 {
+        0 | 1 => PreTokenizer::Bert,
+        2 => PreTokenizer::Uax29,
+        _ => PreTokenizer::Whitespace,
+    };
+    let case_folding = match (flags >> 2) & 0b11 {
+        0 | 1 => CaseFolding::Lowercase,
+        2 => CaseFolding::Fold,
+        _ => CaseFolding::Turkic,
+    };
+    let unicode_normalization = match forms % 5 {
+        0 => NormalizationForm::Nfkc,
+        1 => NormalizationForm::Nfc,
+        2 => NormalizationForm::Nfd,
+        3 => NormalizationForm::Nfkd,
+        _ => NormalizationForm::None,
+    };
+    WordPieceTokenizer::builder()
+        .vocab(vocab)
+        .pre_tokenizer(pre_tokenizer)
+        .lowercase(flags & 0b1_0000 != 0)
+        .case_folding(case_folding)
+        .strip_accents(flags & 0b10_0000 != 0)
+        .tokenize_chinese_chars(flags & 0b100_0000 != 0)
+        .grapheme_clusters(flags & 0b1000_0000 != 0)
+        .unicode_normalization(unicode_normalization)
+        .max_input_chars_per_word(usize::from(forms >> 3) + 1)
+        .end_of_word_suffix(suffix)
+        .build()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Texts that broke invariants before, or touch the edges of them
+    const TEXTS: [&str; 6] = [
+        "a\u{11F02} b",
+        "e\u{7}\u{301} caf\u{E9}",
+        "\u{FB01}ne ﬃ İstanbul ß",
+        "I'm 3,000 km — naïve?! 🇫🇷❤\u{FE0F}",
+        "\u{0}\u{FFFD} 你好 x",
+        "",
+    ];
+
+    /// Option bytes covering each pre-tokenizer, case folding, normalization
+    /// form and flag, see `fuzz_tokenizer`
+    const OPTIONS: [[u8; 2]; 8] = [
+        [0, 0],
+        [0b1111_0000, 0b1000],
+        [0b1001_0110, 0b0101],
+        [0b0111_1011, 0b1110],
+        [0b1101_0101, 0b0011],
+        [0b0011_1110, 0b1_1100],
+        [0b1010_1001, 0b0001],
+        [0xff, 0xff],
+    ];
+
+    #[test]
+    fn roundtrip_holds_for_each_option() {
+        for text in TEXTS {
+            for options in OPTIONS {
+                fuzz_roundtrip(&[&options, text.as_bytes()].concat());
+            }
+        }
+        fuzz_roundtrip(&[1]);
+        fuzz_roundtrip(&[0, 0, 0xff, 0xfe]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::Stream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{BatchingTokenizer, Error, Result, ServeConfig, TokenId, WordPieceTokenizer, WordPieceTrainer};
+use proto::tokenizer_server::{Tokenizer, TokenizerServer};
+use proto::{
+    DecodeRequest, DecodeResponse, EncodeRequest, EncodeResponse, Encoding, TrainOptions, TrainRequest, TrainResponse,
+};
+
+/// Messages and service generated from `proto/wordpiece.proto`, including a
+/// client for Rust callers
+pub mod proto {
+    tonic::include_proto!("wordpiece.v1");
+}
+
+/// Requests of an `EncodeStream` call being encoded ahead of the one being answered
+const STREAM_PIPELINE_DEPTH: usize = 64;
+
+/// Texts received by a `Train` call ahead of the trainer
+const TRAIN_QUEUE_CAPACITY: usize = 8192;
+
+type RpcResult<T> = std::result::Result<Response<T>, Status>;
+
+/// The gRPC `wordpiece.v1.Tokenizer` service, see `grpc_service`
+#[derive(Clone)]
+pub struct GrpcService {
+    tokenizer: Arc<WordPieceTokenizer>,
+    batcher: BatchingTokenizer,
+}
+
+/// Serve the tokenizer over gRPC on `addr` until the process is stopped, with
+/// the service of `grpc_service`
+pub async fn serve_grpc<A: ToSocketAddrs>(tokenizer: WordPieceTokenizer, addr: A, config: ServeConfig) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    Server::builder()
+        .add_service(grpc_service(tokenizer, config))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .map_err(|err| Error::Io(io::Error::other(err)))
+}
+
+/// Build the `wordpiece.v1.Tokenizer` gRPC service defined in
+/// `proto/wordpiece.proto`, to be served on its own or next to other tonic
+/// services. Must be called from within a Tokio runtime, which runs the batcher.
+/// Texts of concurrent `Encode` and `EncodeStream` requests are encoded together
+/// in parallel, in batches of up to `max_batch_size` texts. Requests with a text
+/// over the tokenizer's `max_input_bytes` or `max_tokens` fail with
+/// `RESOURCE_EXHAUSTED`.
+pub fn grpc_service(tokenizer: WordPieceTokenizer, config: ServeConfig) -> TokenizerServer<GrpcService> {
+    let tokenizer = Arc::new(tokenizer);
+    let batcher = BatchingTokenizer::new(tokenizer.clone(), config);
+    TokenizerServer::new(GrpcService { tokenizer, batcher })
+}
+
+impl GrpcService {
+    async fn encode_texts(&self, request: EncodeRequest) -> std::result::Result<EncodeResponse, Status> {
+        let ids = self.batcher.encode_batch(request.texts).await.map_err(|err| match err {
+            Error::BatchingStopped => Status::internal("Encoding failed"),
+            err => Status::resource_exhausted(err.to_string()),
+        })?;
+
+        let encodings = ids
+            .into_iter()
+            .map(|ids| {
+                let ids = match request.add_special_tokens {
+                    true => self.tokenizer.build_inputs_with_special_tokens(&ids, None),
+                    false => ids,
+                };
+                let tokens = match request.return_tokens {
+                    true => ids.iter().filter_map(|&id| self.tokenizer.id_to_token(id)).map(str::to_string).collect(),
+                    false => Vec::new(),
+                };
+                Encoding { ids, tokens }
+            })
+            .collect();
+        Ok(EncodeResponse { encodings })
+    }
+}
+
+#[tonic::async_trait]
+impl Tokenizer for GrpcService {
+    async fn encode(&self, request: Request<EncodeRequest>) -> RpcResult<EncodeResponse> {
+        Ok(Response::new(self.encode_texts(request.into_inner()).await?))
+    }
+
+    type EncodeStreamStream = Pin<Box<dyn Stream<Item = std::result::Result<EncodeResponse, Status>> + Send>>;
+
+    async fn encode_stream(&self, request: Request<Streaming<EncodeRequest>>) -> RpcResult<Self::EncodeStreamStream> {
+        let mut requests = request.into_inner();
+        let (pending, mut in_flight) = mpsc::channel(STREAM_PIPELINE_DEPTH);
+        let (responses, output) = mpsc::channel(STREAM_PIPELINE_DEPTH);
+
+        // Requests are encoded as they arrive, so that a stream fills batches as
+        // well as concurrent calls do, and answered in the order they came in
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let request = match requests.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = pending.send(tokio::spawn(async { Err(status) })).await;
+                        break;
+                    }
+                };
+                let service = service.clone();
+                if pending.send(tokio::spawn(async move { service.encode_texts(request).await })).await.is_err() {
+                    break;
+                }
+            }
+        });
+        tokio::spawn(async move {
+            while let Some(encoding) = in_flight.recv().await {
+                let response = encoding.await.unwrap_or_else(|_| Err(Status::internal("Encoding failed")));
+                let failed = response.is_err();
+                if responses.send(response).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(output))))
+    }
+
+    async fn decode(&self, request: Request<DecodeRequest>) -> RpcResult<DecodeResponse> {
+        let request = request.into_inner();
+        let text = self.tokenizer.decode(
+            &request.ids,
+            request.skip_special_tokens,
+            request.clean_up_tokenization_spaces.unwrap_or(true),
+        );
+        Ok(Response::new(DecodeResponse { text }))
+    }
+
+    async fn train(&self, request: Request<Streaming<TrainRequest>>) -> RpcResult<TrainResponse> {
+        let mut requests = request.into_inner();
+        let mut request = requests
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("No training request was sent"))?;
+        let trainer = trainer_from_options(request.options.take().unwrap_or_default());
+
+        // Texts are counted while they stream in rather than held in memory
+        let (texts, mut received) = mpsc::channel::<String>(TRAIN_QUEUE_CAPACITY);
+        let training = tokio::task::spawn_blocking(move || {
+            trainer.train_from_iter(std::iter::from_fn(|| received.blocking_recv()))
+        });
+        'stream: loop {
+            for text in request.texts {
+                if texts.send(text).await.is_err() {
+                    break 'stream;
+                }
+            }
+            match requests.message().await? {
+                Some(next) => request = next,
+                None => break,
+            }
+        }
+        drop(texts);
+
+        let vocab = training.await.map_err(|_| Status::internal("Training failed"))?;
+        let mut tokens: Vec<(TokenId, String)> = vocab.into_iter().map(|(token, id)| (id, token)).collect();
+        tokens.sort_unstable();
+        Ok(Response::new(TrainResponse {
+            tokens: tokens.into_iter().map(|(_, token)| token).collect(),
+        }))
+    }
+}
+
+/// Build a trainer from the options of a `Train` call, using the defaults of
+/// the `wordpiece-rs train` command for unset fields
+fn trainer_from_options(options: TrainOptions) -> WordPieceTrainer {
+    let special_tokens = match options.special_tokens.is_empty() {
+        true => ["[UNK]", "[CLS]", "[SEP]", "[PAD]", "[MASK]"].map(String::from).to_vec(),
+        false => options.special_tokens,
+    };
+    let mut trainer = WordPieceTrainer::new(
+        match options.vocab_size {
+            0 => 30000,
+            size => size as usize,
+        },
+        match options.min_frequency {
+            0 => 2,
+            min => min as usize,
+        },
+        special_tokens,
+        !options.keep_accents,
+        !options.cased,
+    );
+    if let Some(limit) = options.limit_alphabet {
+        trainer.set_limit_alphabet(limit as usize);
+    }
+    trainer
+}
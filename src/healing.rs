@@ -0,0 +1,65 @@
+use crate::{TokenId, WordPieceTokenizer};
+
+/// Ids of a prompt continued with more text, see `WordPieceTokenizer::heal_prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealedPrefix {
+    pub ids: Vec<TokenId>,
+    /// Number of the prompt's ids left untouched at the start of `ids`, e.g.
+    /// to reuse the attention cache computed for them
+    pub kept: usize,
+}
+
+impl WordPieceTokenizer {
+    /// Continue the prompt encoded as `ids` with `suffix`. When the prompt ends
+    /// in the middle of a word, the pieces of that word could have been split
+    /// differently with the suffix after them, so they are removed and the
+    /// word is encoded again together with the suffix. Special and added
+    /// tokens, the unknown token and ids missing from the vocabulary are never
+    /// removed. Ids do not record whitespace after the prompt, so a prompt
+    /// ending with a space is continued with a suffix starting with one.
+    pub fn heal_prefix(&self, ids: &[TokenId], suffix: &str) -> HealedPrefix {
+        let kept = self.last_word_start(ids);
+        let tokens: Vec<(&str, bool)> = ids[kept..]
+            .iter()
+            .map(|id| (&*self.vocab_lookup[id], false))
+            .collect();
+        let word = self.decoder.decode_tokens(tokens, false);
+
+        let mut healed = ids[..kept].to_vec();
+        healed.extend(self.encode(&format!("{}{}", word, suffix)));
+        HealedPrefix { ids: healed, kept }
+    }
+
+    /// Index of the first piece of the word `ids` end with, or `ids.len()` when
+    /// they end with a token that cannot be split again
+    fn last_word_start(&self, ids: &[TokenId]) -> usize {
+        let prefix_marks_words = !self.continuing_subword_prefix.is_empty();
+        let suffix = self.end_of_word_suffix.as_str();
+
+        let mut start = ids.len();
+        while start > 0 {
+            let token = match self.vocab_lookup.get(&ids[start - 1]) {
+                Some(token) if ids[start - 1] != self.unk_token_id && !self.special_tokens.contains_key(token) => token,
+                _ => break,
+            };
+            if self.added_tokens.contains_key(&**token) {
+                break;
+            }
+            // A word ends on a piece with the end-of-word suffix
+            if !suffix.is_empty() && start < ids.len() && token.ends_with(suffix) {
+                break;
+            }
+            start -= 1;
+            let word_start = match (prefix_marks_words, suffix.is_empty()) {
+                (true, _) => self.decoder.strip_continuation(token).is_none(),
+                // Nothing marks words, so only the last token is encoded again
+                (false, true) => true,
+                (false, false) => false,
+            };
+            if word_start {
+                break;
+            }
+        }
+        start
+    }
+}
@@ -0,0 +1,279 @@
+use hf_hub::api::sync::{ApiBuilder, ApiError};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    default_continuing_subword_prefix, load_vocab, CaseFolding, Contractions, Error, NormalizationForm, PreTokenizer,
+    Result, SpecialTokens, TokenId, TokenizerSnapshot, TruncationStrategy, VocabBackend, WordPieceTokenizer,
+};
+
+/// Longest word WordPiece tries to split when a model doesn't say, as in `transformers`
+const DEFAULT_MAX_INPUT_CHARS_PER_WORD: usize = 100;
+
+impl WordPieceTokenizer {
+    /// Load the tokenizer of a model on the Hugging Face Hub, e.g.
+    /// `bert-base-uncased`, or saved in a local directory. Its `tokenizer.json`
+    /// is used when there is one, and `vocab.txt` with `tokenizer_config.json`
+    /// otherwise. Whether to lowercase, strip accents and normalize Unicode is
+    /// taken from the model's configuration. Files are cached in the Hugging Face cache, which
+    /// `HF_HOME` relocates, and `HF_ENDPOINT` points to a mirror.
+    pub fn from_pretrained(model_id: &str) -> Result<Self> {
+        let dir = Path::new(model_id);
+        if dir.is_dir() {
+            return Self::from_model_files(model_id, |name| {
+                let path = dir.join(name);
+                match path.is_file() {
+                    true => Ok(path),
+                    false => Err(format!("{} does not exist", path.display())),
+                }
+            });
+        }
+
+        let api = ApiBuilder::from_env().with_progress(false).build().map_err(hub_error)?;
+        let repo = api.model(model_id.to_string());
+        Self::from_model_files(model_id, |name| repo.get(name).map_err(|err| err.to_string()))
+    }
+
+    /// Build a tokenizer from the files of a model, fetched by name with `get`
+    fn from_model_files<F>(model_id: &str, get: F) -> Result<Self>
+    where
+        F: Fn(&str) -> std::result::Result<PathBuf, String>,
+    {
+        match get("tokenizer.json") {
+            Ok(path) => Self::from_tokenizer_json(&read_json(&path)?),
+            // Models saved by older versions of transformers only have vocab.txt
+            Err(json_err) => {
+                let vocab = get("vocab.txt").map_err(|err| {
+                    Error::Hub(format!(
+                        "could not get tokenizer.json ({}) or vocab.txt ({}) of '{}'",
+                        json_err, err, model_id
+                    ))
+                })?;
+                let config = match get("tokenizer_config.json") {
+                    Ok(path) => read_json(&path)?,
+                    Err(_) => Value::Null,
+                };
+                Self::from_vocab_txt(&vocab, &config)
+            }
+        }
+    }
+
+    /// Build a tokenizer from a `tokenizers` JSON file holding a WordPiece model
+    fn from_tokenizer_json(json: &Value) -> Result<Self> {
+        let model = &json["model"];
+        if model["type"] != "WordPiece" {
+            return Err(Error::Hub(format!("tokenizer.json holds a {} model, not WordPiece", model["type"])));
+        }
+        let mut vocab: HashMap<String, TokenId> = serde_json::from_value(model["vocab"].clone())
+            .map_err(|err| Error::Hub(format!("invalid vocabulary in tokenizer.json: {}", err)))?;
+
+        let mut special = Vec::new();
+        let mut added_tokens = Vec::new();
+        let mut added_only_tokens = Vec::new();
+        for token in json["added_tokens"].as_array().into_iter().flatten() {
+            let (Some(content), Some(id)) = (token["content"].as_str(), token["id"].as_i64()) else {
+                continue;
+            };
+            if !vocab.contains_key(content) {
+                vocab.insert(content.to_string(), id as TokenId);
+                added_only_tokens.push(content.to_string());
+            }
+            if token["special"].as_bool().unwrap_or(false) {
+                special.push(content.to_string());
+            } else {
+                added_tokens.push(content.to_string());
+            }
+        }
+
+        let (lowercase, strip_accents) = normalizer_flags(&json["normalizer"]);
+        let unk_token = model["unk_token"].as_str().unwrap_or("[UNK]").to_string();
+        let mut special_tokens = SpecialTokens::bert_defaults(&vocab);
+        let with_role: Vec<&String> = special_tokens.roles().map(|(_, token)| token).collect();
+        special_tokens.additional_special_tokens = special
+            .iter()
+            .filter(|token| **token != unk_token && !with_role.contains(token))
+            .cloned()
+            .collect();
+
+        Self::from_snapshot(TokenizerSnapshot {
+            vocab,
+            unk_token,
+            max_input_chars_per_word: model["max_input_chars_per_word"]
+                .as_u64()
+                .map_or(DEFAULT_MAX_INPUT_CHARS_PER_WORD, |max| max as usize),
+            max_input_chars_unit: None,
+            strip_accents,
+            lowercase,
+            case_folding: CaseFolding::default(),
+            unicode_normalization: normalization_form(&json["normalizer"]),
+            remove_control_chars: removes_control_chars(&json["normalizer"]),
+            tokenize_chinese_chars: tokenizes_chinese_chars(&json["normalizer"]),
+            grapheme_clusters: false,
+            morphology: None,
+            special_tokens,
+            added_tokens,
+            added_only_tokens,
+            never_split: Vec::new(),
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+            continuing_subword_prefix: model["continuing_subword_prefix"]
+                .as_str()
+                .map_or_else(default_continuing_subword_prefix, str::to_string),
+            end_of_word_suffix: String::new(),
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            model_max_length: None,
+            max_input_bytes: None,
+            max_tokens: None,
+            truncation_strategy: TruncationStrategy::default(),
+        })
+    }
+
+    /// Build a tokenizer from a `vocab.txt` file, with one token per line in id
+    /// order, and the `transformers` configuration of a BERT tokenizer
+    fn from_vocab_txt(path: &Path, config: &Value) -> Result<Self> {
+        let vocab = load_vocab(path)?;
+
+        // BertTokenizer lowercases unless told otherwise, and strips accents
+        // when lowercasing unless told otherwise
+        let lowercase = config["do_lower_case"].as_bool().unwrap_or(true);
+        let strip_accents = config["strip_accents"].as_bool().unwrap_or(lowercase);
+        // Tokens are saved either as strings or as serialized AddedToken objects
+        let unk_token = config["unk_token"]
+            .as_str()
+            .or_else(|| config["unk_token"]["content"].as_str())
+            .unwrap_or("[UNK]")
+            .to_string();
+        let special_tokens = SpecialTokens::bert_defaults(&vocab);
+
+        Self::from_snapshot(TokenizerSnapshot {
+            vocab,
+            unk_token,
+            max_input_chars_per_word: DEFAULT_MAX_INPUT_CHARS_PER_WORD,
+            max_input_chars_unit: None,
+            strip_accents,
+            lowercase,
+            case_folding: CaseFolding::default(),
+            // BertTokenizer does not normalize Unicode
+            unicode_normalization: NormalizationForm::None,
+            remove_control_chars: true,
+            tokenize_chinese_chars: config["tokenize_chinese_chars"].as_bool().unwrap_or(true),
+            grapheme_clusters: false,
+            morphology: None,
+            special_tokens,
+            added_tokens: Vec::new(),
+            added_only_tokens: Vec::new(),
+            never_split: Vec::new(),
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+            continuing_subword_prefix: default_continuing_subword_prefix(),
+            end_of_word_suffix: String::new(),
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            model_max_length: None,
+            max_input_bytes: None,
+            max_tokens: None,
+            truncation_strategy: TruncationStrategy::default(),
+        })
+    }
+}
+
+/// Whether a `tokenizers` normalizer lowercases and strips accents, looking into
+/// sequences of normalizers
+fn normalizer_flags(normalizer: &Value) -> (bool, bool) {
+    match normalizer["type"].as_str() {
+        Some("BertNormalizer") => {
+            let lowercase = normalizer["lowercase"].as_bool().unwrap_or(true);
+            // Accents are stripped along with lowercasing unless set explicitly
+            (lowercase, normalizer["strip_accents"].as_bool().unwrap_or(lowercase))
+        }
+        Some("Lowercase") => (true, false),
+        Some("StripAccents") => (false, true),
+        Some("Sequence") => normalizer["normalizers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(normalizer_flags)
+            .fold((false, false), |(lowercase, strip), (l, s)| (lowercase || l, strip || s)),
+        _ => (false, false),
+    }
+}
+
+/// The Unicode normalization form of a `tokenizers` normalizer, looking into
+/// sequences of normalizers. `BertNormalizer` applies none.
+fn normalization_form(normalizer: &Value) -> NormalizationForm {
+    match normalizer["type"].as_str() {
+        Some("NFC") => NormalizationForm::Nfc,
+        Some("NFD") => NormalizationForm::Nfd,
+        Some("NFKC") => NormalizationForm::Nfkc,
+        Some("NFKD") => NormalizationForm::Nfkd,
+        Some("Sequence") => normalizer["normalizers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(normalization_form)
+            .find(|&form| form != NormalizationForm::None)
+            .unwrap_or(NormalizationForm::None),
+        _ => NormalizationForm::None,
+    }
+}
+
+/// Whether a `tokenizers` normalizer drops control characters, which only
+/// `BertNormalizer` does, when `clean_text` is set
+fn removes_control_chars(normalizer: &Value) -> bool {
+    match normalizer["type"].as_str() {
+        Some("BertNormalizer") => normalizer["clean_text"].as_bool().unwrap_or(true),
+        Some("Sequence") => normalizer["normalizers"].as_array().into_iter().flatten().any(removes_control_chars),
+        _ => false,
+    }
+}
+
+/// Whether a `tokenizers` normalizer puts spaces around CJK ideographs, which
+/// only `BertNormalizer` does, when `handle_chinese_chars` is set
+fn tokenizes_chinese_chars(normalizer: &Value) -> bool {
+    match normalizer["type"].as_str() {
+        Some("BertNormalizer") => normalizer["handle_chinese_chars"].as_bool().unwrap_or(true),
+        Some("Sequence") => normalizer["normalizers"].as_array().into_iter().flatten().any(tokenizes_chinese_chars),
+        _ => false,
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|err| Error::Hub(format!("invalid JSON in {}: {}", path.display(), err)))
+}
+
+fn hub_error(err: ApiError) -> Error {
+    Error::Hub(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_tokens_missing_from_the_model_stay_out_of_wordpiece() {
+        let json = serde_json::json!({
+            "model": {
+                "type": "WordPiece",
+                "vocab": {"[UNK]": 0, "a": 1, "##b": 2, "ab": 3},
+            },
+            "added_tokens": [
+                {"id": 3, "content": "ab", "special": false},
+                {"id": 4, "content": "xyz", "special": false},
+            ],
+            "normalizer": {"type": "BertNormalizer", "lowercase": true},
+        });
+        let tokenizer = WordPieceTokenizer::from_tokenizer_json(&json).unwrap();
+
+        // `ab` is in the model's vocabulary, so WordPiece still produces it
+        assert_eq!(tokenizer.tokenize("XYZ AB xyz"), ["[UNK]", "ab", "xyz"]);
+        assert_eq!(tokenizer.added_tokens()["xyz"], 4);
+    }
+}
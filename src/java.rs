@@ -0,0 +1,217 @@
+use jni::objects::{JClass, JIntArray, JObject, JObjectArray, JString};
+use jni::sys::{jboolean, jint, jintArray, jlong, jobjectArray, jstring, JNI_FALSE};
+use jni::JNIEnv;
+
+use crate::{Error, TokenId, WordPieceTokenizer};
+
+/// Failure of a native method, thrown as a Java exception on return
+enum JavaError {
+    Jni(jni::errors::Error),
+    Tokenizer(Error),
+}
+
+impl From<jni::errors::Error> for JavaError {
+    fn from(err: jni::errors::Error) -> Self {
+        JavaError::Jni(err)
+    }
+}
+
+impl From<Error> for JavaError {
+    fn from(err: Error) -> Self {
+        JavaError::Tokenizer(err)
+    }
+}
+
+/// Run the body of a native method, throwing its error as a Java exception and
+/// returning `default` in its place
+fn run<'local, T, F>(env: &mut JNIEnv<'local>, default: T, f: F) -> T
+where
+    F: FnOnce(&mut JNIEnv<'local>) -> Result<T, JavaError>,
+{
+    let err = match f(env) {
+        Ok(value) => return value,
+        Err(err) => err,
+    };
+    let _ = match err {
+        // A Java exception is already pending
+        JavaError::Jni(jni::errors::Error::JavaException) => Ok(()),
+        JavaError::Jni(err) => env.throw_new("java/lang/RuntimeException", err.to_string()),
+        JavaError::Tokenizer(Error::Io(err)) => env.throw_new("java/io/IOException", err.to_string()),
+        JavaError::Tokenizer(err) => env.throw_new("java/lang/IllegalArgumentException", err.to_string()),
+    };
+    default
+}
+
+/// The tokenizer behind a handle held by a Java `WordPieceTokenizer`
+///
+/// # Safety
+///
+/// `handle` must come from `into_handle` and not have been freed.
+unsafe fn tokenizer<'a>(handle: jlong) -> &'a WordPieceTokenizer {
+    &*(handle as *const WordPieceTokenizer)
+}
+
+fn into_handle(tokenizer: WordPieceTokenizer) -> jlong {
+    Box::into_raw(Box::new(tokenizer)) as jlong
+}
+
+/// Java has no unsigned ints, ids are well below `jint::MAX` in any vocabulary
+fn int_array<'local>(env: &mut JNIEnv<'local>, ids: &[TokenId]) -> Result<JIntArray<'local>, JavaError> {
+    let ids: Vec<jint> = ids.iter().map(|&id| id as jint).collect();
+    let array = env.new_int_array(ids.len() as jint)?;
+    env.set_int_array_region(&array, 0, &ids)?;
+    Ok(array)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeFromVocabFile<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    unk_token: JString<'local>,
+    lowercase: jboolean,
+    strip_accents: jboolean,
+) -> jlong {
+    run(&mut env, 0, |env| {
+        let path: String = env.get_string(&path)?.into();
+        let unk_token: String = env.get_string(&unk_token)?.into();
+        let tokenizer = WordPieceTokenizer::builder()
+            .vocab_file(path)
+            .unk_token(&unk_token)
+            .lowercase(lowercase != JNI_FALSE)
+            .strip_accents(strip_accents != JNI_FALSE)
+            .build()?;
+        Ok(into_handle(tokenizer))
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeLoadCompiled<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jlong {
+    run(&mut env, 0, |env| {
+        let path: String = env.get_string(&path)?.into();
+        Ok(into_handle(WordPieceTokenizer::load_compiled(path)?))
+    })
+}
+
+/// # Safety
+///
+/// `handle` must come from one of the constructors and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeFree(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    drop(Box::from_raw(handle as *mut WordPieceTokenizer));
+}
+
+/// # Safety
+///
+/// `handle` must be a live tokenizer.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeEncode<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    text: JString<'local>,
+) -> jintArray {
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let text: String = env.get_string(&text)?.into();
+        Ok(int_array(env, &tokenizer(handle).try_encode(&text)?)?.into_raw())
+    })
+}
+
+/// # Safety
+///
+/// `handle` must be a live tokenizer.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeEncodeBatch<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    texts: JObjectArray<'local>,
+) -> jobjectArray {
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let len = env.get_array_length(&texts)?;
+        let mut strings = Vec::with_capacity(len as usize);
+        for i in 0..len {
+            let text = JString::from(env.get_object_array_element(&texts, i)?);
+            strings.push(String::from(env.get_string(&text)?));
+            env.delete_local_ref(text)?;
+        }
+
+        let batch = tokenizer(handle).try_encode_batch(&strings)?;
+        let array = env.new_object_array(len, "[I", JObject::null())?;
+        for (i, ids) in batch.iter().enumerate() {
+            let ids = int_array(env, ids)?;
+            env.set_object_array_element(&array, i as jint, &ids)?;
+            env.delete_local_ref(ids)?;
+        }
+        Ok(array.into_raw())
+    })
+}
+
+/// # Safety
+///
+/// `handle` must be a live tokenizer.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeTokenize<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    text: JString<'local>,
+) -> jobjectArray {
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let text: String = env.get_string(&text)?.into();
+        let tokens = tokenizer(handle).tokenize(&text);
+        let array = env.new_object_array(tokens.len() as jint, "java/lang/String", JObject::null())?;
+        for (i, token) in tokens.iter().enumerate() {
+            let token = env.new_string(token)?;
+            env.set_object_array_element(&array, i as jint, &token)?;
+            env.delete_local_ref(token)?;
+        }
+        Ok(array.into_raw())
+    })
+}
+
+/// # Safety
+///
+/// `handle` must be a live tokenizer.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeDecode<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    ids: JIntArray<'local>,
+    skip_special_tokens: jboolean,
+    clean_up_tokenization_spaces: jboolean,
+) -> jstring {
+    run(&mut env, std::ptr::null_mut(), |env| {
+        let mut buffer = vec![0; env.get_array_length(&ids)? as usize];
+        env.get_int_array_region(&ids, 0, &mut buffer)?;
+        // Negative ids are in no vocabulary, skipped like other unknown ids
+        let ids: Vec<TokenId> = buffer.into_iter().filter_map(|id| TokenId::try_from(id).ok()).collect();
+        let text = tokenizer(handle).decode(
+            &ids,
+            skip_special_tokens != JNI_FALSE,
+            clean_up_tokenization_spaces != JNI_FALSE,
+        );
+        Ok(env.new_string(text)?.into_raw())
+    })
+}
+
+/// # Safety
+///
+/// `handle` must be a live tokenizer.
+#[no_mangle]
+pub unsafe extern "system" fn Java_io_github_novastar53_wordpiece_WordPieceTokenizer_nativeVocabSize(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jint {
+    tokenizer(handle).vocab().len() as jint
+}
@@ -1,395 +1,1656 @@
+#[cfg(feature = "python")]
+mod arrow;
+#[cfg(any(feature = "serve", feature = "grpc", feature = "async"))]
+mod batcher;
+mod bpe;
+mod builder;
+mod cache;
+mod compiled;
+mod decoder;
+mod diff;
+mod double_array;
+#[cfg(feature = "config")]
+mod config;
+mod encoding;
+mod entities;
+mod error;
+mod evaluate;
+mod export;
+mod fallback;
+mod healing;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "fst")]
+mod fst_vocab;
+#[cfg(feature = "fuzz")]
+mod fuzz;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "hf-hub")]
+mod hub;
+#[cfg(feature = "java")]
+mod java;
+mod masking;
+mod matcher;
+mod memory;
+mod merge;
+mod metrics;
+mod morphology;
+mod nbest;
+mod multi;
+#[cfg(feature = "node")]
+mod node;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod normalizer;
+mod offsets;
+mod parity;
+mod pre_tokenizer;
+mod prune;
+#[cfg(feature = "python")]
+mod python;
+mod sentencepiece;
+#[cfg(feature = "serve")]
+mod serve;
+mod stream;
+mod template;
 mod trainer;
+mod trie;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-use pyo3::prelude::*;
-use pyo3::types::PyDict;
-use std::collections::HashMap;
-use unicode_normalization::UnicodeNormalization;
-use regex::{Regex, RegexBuilder};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use rayon::prelude::*;
+use regex::Regex;
 use std::borrow::Cow;
-use trainer::WordPieceTrainer;
-
-/// A node in the trie data structure for efficient prefix matching
-#[derive(Default)]
-struct TrieNode {
-    children: HashMap<char, TrieNode>,
-    is_word: bool,
-    token_id: i32,
-}
+use std::cell::RefCell;
+use std::ops::Range;
+use std::sync::Arc;
+use unicode_segmentation::UnicodeSegmentation;
 
-impl TrieNode {
-    fn new() -> Self {
-        Self::default()
-    }
+#[cfg(any(feature = "serve", feature = "grpc", feature = "async"))]
+pub use batcher::{BatchingTokenizer, ServeConfig};
+pub use bpe::BytePairModel;
+pub use builder::WordPieceTokenizerBuilder;
+pub use cache::CacheStats;
+#[cfg(feature = "config")]
+pub use config::TokenizerConfig;
+use cache::WordCache;
+use offsets::covering;
+use normalizer::{default_remove_control_chars, default_tokenize_chinese_chars, TextCleaner};
+pub use encoding::{Encoding, TokenSpan};
+pub use error::{Error, Result};
+pub use evaluate::{EvaluationStats, UnknownSpan};
+pub use export::load_vocab;
+pub use fallback::FallbackTokenizer;
+#[cfg(feature = "fuzz")]
+pub use fuzz::fuzz_roundtrip;
+pub use healing::HealedPrefix;
+#[cfg(feature = "grpc")]
+pub use grpc::{grpc_service, proto, serve_grpc, GrpcService};
+pub use masking::IGNORE_LABEL;
+use matcher::Matcher;
+pub use matcher::VocabBackend;
+pub use memory::MemoryUsage;
+pub use merge::{merge_vocabs, MergeStrategy, MergedVocab};
+pub use metrics::Metrics;
+use metrics::{MetricsRecorder, Stopwatch};
+pub use decoder::{Decoder, UnknownIdPolicy};
+pub use diff::VocabDiff;
+pub use entities::Entity;
+use entities::EntityFinder;
+pub use morphology::Morphology;
+pub use nbest::{LatticeEdge, Segmentation, SegmentationCost, WordLattice};
+pub use multi::{DocumentRouter, MultiTokenizer, Routing, SpanRouter};
+use morphology::Analyzer;
+#[cfg(feature = "async")]
+pub use nonblocking::AsyncTokenizer;
+pub use normalizer::{strip_accents, CaseFolding, NormalizationForm, Normalizer};
+pub use offsets::OffsetUnit;
+pub use parity::{Divergence, ParityReport};
+pub use pre_tokenizer::{Contractions, PreTokenizer};
+use pre_tokenizer::{split_bert_words, word_regex, Words};
+#[cfg(feature = "serve")]
+pub use serve::{router, serve};
+pub use stream::{EncodeLines, StreamTokenizer};
+pub use template::TruncationStrategy;
+pub use trainer::{Corpus, ProgressCallback, StopCallback, WordPieceTrainer};
 
-    /// Insert a word into the trie with its associated token ID
-    fn insert(&mut self, word: &str, token_id: i32) {
-        let mut node = self;
-        for ch in word.chars() {
-            node = node.children.entry(ch).or_insert_with(TrieNode::new);
-        }
-        node.is_word = true;
-        node.token_id = token_id;
-    }
+/// Id of a token in the vocabulary. Ids are never negative, and tokens without
+/// an id yet, such as the words of `basic_tokenize`, carry `None` instead.
+pub type TokenId = u32;
 
-    /// Find the longest prefix of a word in the trie, starting from a given position
-    fn find_longest_prefix(&self, word: &[char], start: usize) -> Option<(usize, i32)> {
-        let mut node = self;
-        let mut last_match = None;
-        let mut pos = start;
+/// Token represents a single token with its text, ID, and whether it's a special token
+#[cfg_attr(feature = "python", pyo3::pyclass(module = "wordpiece_rs", frozen, eq, hash))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Token {
+    /// Shared with the vocabulary for pieces and special tokens, so producing
+    /// a token copies no text
+    pub text: Arc<str>,
+    /// `None` for a word WordPiece has not segmented yet
+    pub id: Option<TokenId>,
+    pub is_special: bool,
+    /// Byte offsets of the token in the original text
+    pub start: usize,
+    pub end: usize,
+    /// Whether the token is a piece continuing the word before it
+    pub is_continuation: bool,
+}
 
-        while pos < word.len() {
-            if let Some(next) = node.children.get(&word[pos]) {
-                if next.is_word {
-                    last_match = Some((pos + 1, next.token_id));
-                }
-                node = next;
-                pos += 1;
-            } else {
-                break;
-            }
-        }
+/// A unit of input produced by pre-tokenization
+enum PreToken<'a> {
+    /// A normalized word to be segmented by WordPiece
+    Word(&'a str),
+    /// A token matched whole, such as an added, special or never_split token
+    Matched { text: &'a str, id: TokenId, is_special: bool },
+}
 
-        last_match
-    }
+thread_local! {
+    /// Scratch space for the word being normalized, reused across calls
+    static WORD_BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
 }
 
-/// Token represents a single token with its text, ID, and whether it's a special token
-#[pyclass]
-#[derive(Debug, Clone)]
-struct Token {
-    #[pyo3(get)]
-    text: String,
-    #[pyo3(get)]
-    id: i32,
-    #[pyo3(get)]
-    is_special: bool,
+/// Named roles a special token can be registered under, in addition to
+/// the free-form `additional_special_tokens` list
+const SPECIAL_TOKEN_ROLES: [&str; 5] = ["unk_token", "cls_token", "sep_token", "pad_token", "mask_token"];
+
+/// Special tokens to register, keyed by role
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpecialTokens {
+    pub unk_token: Option<String>,
+    pub cls_token: Option<String>,
+    pub sep_token: Option<String>,
+    pub pad_token: Option<String>,
+    pub mask_token: Option<String>,
+    pub additional_special_tokens: Vec<String>,
 }
 
-#[pymethods]
-impl Token {
-    #[new]
-    fn new(text: String, id: i32, is_special: bool) -> Self {
-        Token {
-            text,
-            id,
-            is_special,
+impl SpecialTokens {
+    /// The BERT role tokens that are present in `vocab`
+    fn bert_defaults(vocab: &HashMap<String, TokenId>) -> Self {
+        let present = |token: &str| vocab.contains_key(token).then(|| token.to_string());
+        SpecialTokens {
+            unk_token: None,
+            cls_token: present("[CLS]"),
+            sep_token: present("[SEP]"),
+            pad_token: present("[PAD]"),
+            mask_token: present("[MASK]"),
+            additional_special_tokens: Vec::new(),
         }
     }
+
+    /// The tokens paired with the role they are registered under
+    fn roles(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        let roles = [
+            &self.unk_token,
+            &self.cls_token,
+            &self.sep_token,
+            &self.pad_token,
+            &self.mask_token,
+        ];
+        SPECIAL_TOKEN_ROLES
+            .into_iter()
+            .zip(roles)
+            .filter_map(|(role, token)| token.as_ref().map(|token| (role, token)))
+            .chain(self.additional_special_tokens.iter().map(|token| ("additional_special_tokens", token)))
+    }
+}
+
+/// What `max_input_chars_per_word` counts in a word
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum LengthUnit {
+    /// Unicode scalar values, as BERT counts them
+    Chars,
+    /// Extended grapheme clusters, so that an emoji sequence counts once
+    Graphemes,
+    /// UTF-8 bytes, the cheapest to check
+    Bytes,
 }
 
-#[pyclass]
-struct WordPieceTokenizer {
-    trie: TrieNode,
-    vocab_lookup: HashMap<i32, String>,
+/// A WordPiece tokenizer. It is `Send + Sync`, so one tokenizer can serve many
+/// threads, and cloning it is cheap: clones share the vocabulary, its trie and
+/// the word cache until one of them is changed, e.g. by `add_tokens`.
+#[derive(Clone)]
+pub struct WordPieceTokenizer {
+    matcher: Arc<Matcher>,
+    vocab: Arc<HashMap<String, TokenId>>,
+    vocab_lookup: Arc<HashMap<TokenId, Arc<str>>>,
     unk_token: String,
-    unk_token_id: i32,
+    unk_token_id: TokenId,
+    cls_token: Option<String>,
+    sep_token: Option<String>,
+    pad_token: Option<String>,
+    mask_token: Option<String>,
     max_input_chars_per_word: usize,
-    special_tokens: HashMap<String, i32>,
+    max_input_chars_unit: LengthUnit,
+    special_tokens: HashMap<Arc<str>, TokenId>,
+    added_tokens: HashMap<String, TokenId>,
+    /// Added tokens missing from the vocabulary they were added to, which
+    /// WordPiece never produces as pieces
+    added_only_tokens: HashSet<String>,
+    added_tokens_pattern: Option<Regex>,
+    never_split: HashSet<String>,
+    word_cache: Arc<WordCache>,
+    metrics: Arc<MetricsRecorder>,
+    continuing_subword_prefix: String,
+    end_of_word_suffix: String,
+    decoder: Decoder,
     basic_tokenizer: Regex,
-    punctuation: Regex,
-    chinese_chars: Regex,
+    unicode_normalization: NormalizationForm,
+    remove_control_chars: bool,
+    tokenize_chinese_chars: bool,
+    grapheme_clusters: bool,
+    morphology: Option<Analyzer>,
     strip_accents: bool,
     lowercase: bool,
+    case_folding: CaseFolding,
+    pre_tokenizer: PreTokenizer,
+    contractions: Contractions,
+    entities: Vec<Entity>,
+    entity_placeholders: BTreeMap<Entity, String>,
+    entity_finder: Option<EntityFinder>,
+    model_max_length: Option<usize>,
+    truncation_strategy: TruncationStrategy,
+    max_input_bytes: Option<usize>,
+    max_tokens: Option<usize>,
 }
 
-#[pymethods]
+// Tokenizers are shared between threads, e.g. behind an `Arc` in the services
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<WordPieceTokenizer>();
+};
+
 impl WordPieceTokenizer {
-    #[new]
-    #[args(
-        unk_token = "\"[UNK]\"",
-        max_input_chars_per_word = "200",
-        strip_accents = "true",
-        lowercase = "true"
-    )]
-    fn new(
-        vocab: &PyDict,
+    /// Build a tokenizer from a token -> id vocabulary. When `special_tokens` is
+    /// `None`, `[CLS]`, `[SEP]`, `[PAD]` and `[MASK]` are registered under their
+    /// BERT roles if present in the vocabulary.
+    #[deprecated(note = "use `WordPieceTokenizer::builder()`, which names each option and validates them")]
+    pub fn new(
+        vocab: HashMap<String, TokenId>,
         unk_token: &str,
         max_input_chars_per_word: usize,
         strip_accents: bool,
         lowercase: bool,
-    ) -> Self {
-        let mut trie = TrieNode::new();
-        let mut vocab_lookup = HashMap::new();
-        let mut special_tokens = HashMap::new();
-        let unk = unk_token.to_string();
-        let mut unk_id = 0;
+        special_tokens: Option<&SpecialTokens>,
+        never_split: Option<Vec<String>>,
+    ) -> Result<Self> {
+        let special_tokens = match special_tokens {
+            Some(special_tokens) => special_tokens.clone(),
+            None => SpecialTokens::bert_defaults(&vocab),
+        };
+
+        Self::from_snapshot(TokenizerSnapshot {
+            vocab,
+            unk_token: unk_token.to_string(),
+            max_input_chars_per_word,
+            max_input_chars_unit: None,
+            strip_accents,
+            lowercase,
+            case_folding: CaseFolding::default(),
+            unicode_normalization: NormalizationForm::default(),
+            remove_control_chars: true,
+            tokenize_chinese_chars: true,
+            grapheme_clusters: false,
+            morphology: None,
+            special_tokens,
+            added_tokens: Vec::new(),
+            added_only_tokens: Vec::new(),
+            never_split: never_split.unwrap_or_default(),
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+            continuing_subword_prefix: default_continuing_subword_prefix(),
+            end_of_word_suffix: String::new(),
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            model_max_length: None,
+            truncation_strategy: TruncationStrategy::default(),
+            max_input_bytes: None,
+            max_tokens: None,
+        })
+    }
+
+    /// Build a tokenizer around a vocabulary learned by `trainer`, with its
+    /// normalization options and subword affixes. `unk_token` becomes the unknown token, `[CLS]`,
+    /// `[SEP]`, `[PAD]` and `[MASK]` are registered under their BERT roles and the
+    /// trainer's other special tokens as additional special tokens.
+    pub fn from_trained(
+        vocab: HashMap<String, TokenId>,
+        trainer: &WordPieceTrainer,
+        unk_token: &str,
+        max_input_chars_per_word: usize,
+    ) -> Result<Self> {
+        let mut special_tokens = SpecialTokens::bert_defaults(&vocab);
+        let with_role: HashSet<&String> = special_tokens.roles().map(|(_, token)| token).collect();
+        special_tokens.additional_special_tokens = trainer
+            .special_tokens
+            .iter()
+            .filter(|token| *token != unk_token && !with_role.contains(token))
+            .cloned()
+            .collect();
+
+        Self::from_snapshot(TokenizerSnapshot {
+            vocab,
+            unk_token: unk_token.to_string(),
+            max_input_chars_per_word,
+            max_input_chars_unit: None,
+            strip_accents: trainer.strip_accents,
+            lowercase: trainer.lowercase,
+            case_folding: trainer.case_folding,
+            unicode_normalization: trainer.unicode_normalization,
+            remove_control_chars: trainer.remove_control_chars,
+            tokenize_chinese_chars: trainer.tokenize_chinese_chars,
+            grapheme_clusters: trainer.grapheme_clusters,
+            morphology: trainer.morphology.as_ref().map(Analyzer::morphology),
+            special_tokens,
+            added_tokens: Vec::new(),
+            added_only_tokens: Vec::new(),
+            never_split: Vec::new(),
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+            continuing_subword_prefix: trainer.continuing_subword_prefix.clone(),
+            end_of_word_suffix: trainer.end_of_word_suffix.clone(),
+            pre_tokenizer: trainer.pre_tokenizer,
+            contractions: trainer.contractions.clone(),
+            entities: trainer.entities.clone(),
+            entity_placeholders: trainer.entity_placeholders.clone(),
+            model_max_length: None,
+            truncation_strategy: TruncationStrategy::default(),
+            max_input_bytes: None,
+            max_tokens: None,
+        })
+    }
+
+    /// Train a new tokenizer on `texts`, reusing this tokenizer's normalization
+    /// options, subword affixes and its special, added and never_split tokens
+    pub fn train_from_iterator<I, S>(&self, texts: I, vocab_size: usize, min_frequency: usize) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let vocab = self.trainer(vocab_size, min_frequency).train_from_iter(texts);
+        self.with_vocab(vocab)
+    }
+
+    /// Extend this tokenizer's vocabulary with up to `num_new_tokens` tokens learned
+    /// from `texts`, e.g. to adapt a general-domain vocabulary to a new domain.
+    /// Existing tokens keep their ids and new ones are added after the largest id.
+    pub fn extend_from_iterator<I, S>(&self, texts: I, num_new_tokens: usize, min_frequency: usize) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let vocab = self.extension_trainer(num_new_tokens, min_frequency).train_from_iter(texts);
+        self.with_vocab(vocab)
+    }
+
+    /// A trainer continuing from this tokenizer's vocabulary, see `extend_from_iterator`
+    pub(crate) fn extension_trainer(&self, num_new_tokens: usize, min_frequency: usize) -> WordPieceTrainer {
+        let mut trainer = self.trainer(self.vocab.len() + num_new_tokens, min_frequency);
+        trainer.set_initial_vocab(self.vocab.as_ref().clone());
+        trainer
+    }
+
+    /// A trainer with this tokenizer's normalization options, subword affixes and special tokens
+    pub(crate) fn trainer(&self, vocab_size: usize, min_frequency: usize) -> WordPieceTrainer {
+        let mut trainer = WordPieceTrainer::new(
+            vocab_size,
+            min_frequency,
+            self.sorted_by_id(&self.special_tokens),
+            self.strip_accents,
+            self.lowercase,
+        );
+        trainer.set_case_folding(self.case_folding);
+        trainer.set_unicode_normalization(self.unicode_normalization);
+        trainer.set_remove_control_chars(self.remove_control_chars);
+        trainer.set_tokenize_chinese_chars(self.tokenize_chinese_chars);
+        trainer.set_grapheme_clusters(self.grapheme_clusters);
+        trainer.set_pre_tokenizer(self.pre_tokenizer);
+        trainer.set_contractions(self.contractions.clone());
+        trainer.set_entities(self.entities.clone(), self.entity_placeholders.clone());
+        trainer.morphology = self.morphology.clone();
+        trainer.set_continuing_subword_prefix(&self.continuing_subword_prefix);
+        trainer.set_end_of_word_suffix(&self.end_of_word_suffix);
+        trainer
+    }
+
+    /// A tokenizer with this tokenizer's options and registered tokens around a
+    /// new vocabulary. Added tokens missing from the old vocabulary stay out of
+    /// WordPiece matching if the new one holds them, as extending and pruning do.
+    pub(crate) fn with_vocab(&self, vocab: HashMap<String, TokenId>) -> Result<Self> {
+        let mut snapshot = self.snapshot();
+        snapshot.added_only_tokens.retain(|token| vocab.contains_key(token));
+        snapshot.vocab = vocab;
+        Self::from_snapshot(snapshot)
+    }
 
+    /// Rebuild a tokenizer from a snapshot, constructing its vocabulary backend
+    fn from_snapshot(snapshot: TokenizerSnapshot) -> Result<Self> {
+        let matcher = Matcher::new(&snapshot.vocab, snapshot.vocab_backend, &snapshot.continuing_subword_prefix);
+        Self::from_parts(matcher, snapshot)
+    }
+
+    /// Assemble a tokenizer around an already built vocabulary backend
+    fn from_parts(matcher: Matcher, snapshot: TokenizerSnapshot) -> Result<Self> {
         // Compile regex patterns
-        let basic_tokenizer = RegexBuilder::new(r"'s|'t|'re|'ve|'m|'ll|'d| ?[\p{L}\p{N}]+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+")
-            .case_insensitive(true)
-            .build()
-            .unwrap();
-        
-        let punctuation = RegexBuilder::new(r"\p{P}")
-            .build()
-            .unwrap();
-
-        let chinese_chars = RegexBuilder::new(r"[\p{Script=Han}]")
-            .build()
-            .unwrap();
+        let basic_tokenizer = word_regex(&snapshot.contractions);
 
         // Process vocabulary
-        for (k, v) in vocab.iter() {
-            let key = k.extract::<String>().unwrap();
-            let value = v.extract::<i32>().unwrap();
-            
-            if key == unk {
-                unk_id = value;
+        let vocab_lookup = snapshot.vocab.iter().map(|(token, &id)| (id, Arc::from(token.as_str()))).collect();
+
+        let mut tokenizer = WordPieceTokenizer {
+            matcher: Arc::new(matcher),
+            vocab: Arc::new(snapshot.vocab),
+            vocab_lookup: Arc::new(vocab_lookup),
+            unk_token: snapshot.unk_token.clone(),
+            unk_token_id: 0,
+            cls_token: None,
+            sep_token: None,
+            pad_token: None,
+            mask_token: None,
+            max_input_chars_per_word: snapshot.max_input_chars_per_word,
+            max_input_chars_unit: snapshot.max_input_chars_unit.unwrap_or(match snapshot.grapheme_clusters {
+                true => LengthUnit::Graphemes,
+                false => LengthUnit::Chars,
+            }),
+            special_tokens: HashMap::new(),
+            added_tokens: HashMap::new(),
+            added_only_tokens: HashSet::new(),
+            added_tokens_pattern: None,
+            never_split: snapshot.never_split.into_iter().collect(),
+            word_cache: Arc::new(WordCache::new(snapshot.cache_capacity)),
+            metrics: Arc::default(),
+            decoder: Decoder::new(&snapshot.continuing_subword_prefix, &snapshot.end_of_word_suffix),
+            continuing_subword_prefix: snapshot.continuing_subword_prefix,
+            end_of_word_suffix: snapshot.end_of_word_suffix,
+            basic_tokenizer,
+            strip_accents: snapshot.strip_accents,
+            lowercase: snapshot.lowercase,
+            case_folding: snapshot.case_folding,
+            unicode_normalization: snapshot.unicode_normalization,
+            remove_control_chars: snapshot.remove_control_chars,
+            tokenize_chinese_chars: snapshot.tokenize_chinese_chars,
+            grapheme_clusters: snapshot.grapheme_clusters,
+            morphology: snapshot.morphology.as_ref().map(Analyzer::new).transpose()?,
+            pre_tokenizer: snapshot.pre_tokenizer,
+            contractions: snapshot.contractions,
+            entity_finder: EntityFinder::new(&snapshot.entities),
+            entities: snapshot.entities,
+            entity_placeholders: snapshot.entity_placeholders,
+            model_max_length: snapshot.model_max_length,
+            truncation_strategy: snapshot.truncation_strategy,
+            max_input_bytes: snapshot.max_input_bytes,
+            max_tokens: snapshot.max_tokens,
+        };
+
+        if snapshot.special_tokens.unk_token.is_none() {
+            tokenizer.register_special_token("unk_token", &snapshot.unk_token)?;
+        }
+        tokenizer.add_special_tokens(&snapshot.special_tokens)?;
+        for token in snapshot.added_only_tokens {
+            if tokenizer.vocab.contains_key(&token) {
+                Arc::make_mut(&mut tokenizer.matcher).remove(&token);
+                tokenizer.added_only_tokens.insert(token);
             }
-            
-            // Identify special tokens (those that don't start with ## and contain special chars)
-            if !key.starts_with("##") && (key.starts_with('[') || key.starts_with('<') || punctuation.is_match(&key)) {
-                special_tokens.insert(key.clone(), value);
-            } else {
-                trie.insert(&key, value);
+        }
+        tokenizer.add_tokens(snapshot.added_tokens);
+
+        Ok(tokenizer)
+    }
+
+    /// Register special tokens under their roles.
+    /// Returns the number of tokens that were not already special.
+    pub fn add_special_tokens(&mut self, special_tokens: &SpecialTokens) -> Result<usize> {
+        let mut added = 0;
+
+        for (role, token) in special_tokens.roles() {
+            if self.register_special_token(role, token)? {
+                added += 1;
             }
-            
-            vocab_lookup.insert(value, key);
         }
 
-        WordPieceTokenizer {
-            trie,
-            vocab_lookup,
-            unk_token: unk,
-            unk_token_id: unk_id,
-            max_input_chars_per_word,
-            special_tokens,
-            basic_tokenizer,
-            punctuation,
-            chinese_chars,
-            strip_accents,
-            lowercase,
+        self.reset_word_cache();
+        self.rebuild_added_tokens_pattern();
+        Ok(added)
+    }
+
+    /// Add tokens that are matched atomically in the raw input and never split by
+    /// WordPiece. Tokens missing from the vocabulary are assigned new ids after the
+    /// current largest id. Returns the number of tokens that were not already added.
+    pub fn add_tokens(&mut self, tokens: Vec<String>) -> usize {
+        let mut added = 0;
+
+        for token in tokens {
+            if token.is_empty() || self.added_tokens.contains_key(&token) || self.special_tokens.contains_key(token.as_str()) {
+                continue;
+            }
+
+            let id = match self.vocab.get(&token) {
+                Some(&id) => id,
+                None => {
+                    let id = self.vocab_lookup.keys().max().map_or(0, |&max| max + 1);
+                    Arc::make_mut(&mut self.vocab).insert(token.clone(), id);
+                    Arc::make_mut(&mut self.vocab_lookup).insert(id, Arc::from(token.as_str()));
+                    self.added_only_tokens.insert(token.clone());
+                    id
+                }
+            };
+
+            self.added_tokens.insert(token, id);
+            added += 1;
         }
+
+        self.rebuild_added_tokens_pattern();
+        added
+    }
+
+    /// The full token -> id vocabulary, including added tokens
+    pub fn vocab(&self) -> &HashMap<String, TokenId> {
+        &self.vocab
+    }
+
+    /// The token with id `id`, if any
+    pub fn id_to_token(&self, id: TokenId) -> Option<&str> {
+        self.vocab_lookup.get(&id).map(|token| &**token)
+    }
+
+    /// All tokens registered through `add_tokens` and their ids
+    pub fn added_tokens(&self) -> &HashMap<String, TokenId> {
+        &self.added_tokens
+    }
+
+    /// All registered special tokens and their ids
+    pub fn special_tokens(&self) -> &HashMap<Arc<str>, TokenId> {
+        &self.special_tokens
+    }
+
+    pub fn unk_token_id(&self) -> TokenId {
+        self.unk_token_id
+    }
+
+    pub fn cls_token_id(&self) -> Option<TokenId> {
+        self.special_token_id(&self.cls_token)
+    }
+
+    pub fn sep_token_id(&self) -> Option<TokenId> {
+        self.special_token_id(&self.sep_token)
+    }
+
+    pub fn pad_token_id(&self) -> Option<TokenId> {
+        self.special_token_id(&self.pad_token)
+    }
+
+    pub fn mask_token_id(&self) -> Option<TokenId> {
+        self.special_token_id(&self.mask_token)
+    }
+
+    /// Cache the WordPiece pieces of up to `capacity` distinct words, or disable
+    /// the cache when `capacity` is 0. Resets the cache and its statistics.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.word_cache = Arc::new(WordCache::new(capacity));
+    }
+
+    /// Switch the data structure used to segment words, rebuilding it from the vocabulary
+    pub fn set_vocab_backend(&mut self, backend: VocabBackend) {
+        if backend == self.matcher.backend() {
+            return;
+        }
+
+        self.rebuild_matcher(backend);
+    }
+
+    /// The data structure used to segment words
+    pub fn vocab_backend(&self) -> VocabBackend {
+        self.matcher.backend()
+    }
+
+    /// Treat vocabulary tokens starting with `prefix` as pieces that continue a
+    /// word, instead of those starting with `##`. With an empty prefix any token
+    /// may start or continue a word. Rebuilds the vocabulary backend.
+    pub fn set_continuing_subword_prefix(&mut self, prefix: &str) {
+        if prefix == self.continuing_subword_prefix {
+            return;
+        }
+
+        self.continuing_subword_prefix = prefix.to_string();
+        self.decoder.continuing_subword_prefix = prefix.to_string();
+        self.rebuild_matcher(self.matcher.backend());
+    }
+
+    /// The prefix marking vocabulary tokens that continue a word
+    pub fn continuing_subword_prefix(&self) -> &str {
+        &self.continuing_subword_prefix
+    }
+
+    /// Segment each word with `suffix` appended, so its last piece is one of the
+    /// vocabulary tokens ending in `suffix`, such as `</w>`. Empty by default.
+    pub fn set_end_of_word_suffix(&mut self, suffix: &str) {
+        self.end_of_word_suffix = suffix.to_string();
+        self.decoder.end_of_word_suffix = suffix.to_string();
+        self.reset_word_cache();
+    }
+
+    /// The suffix marking vocabulary tokens that end a word
+    pub fn end_of_word_suffix(&self) -> &str {
+        &self.end_of_word_suffix
+    }
+
+    /// The decoder joining tokens back into text, with this tokenizer's
+    /// subword prefix and suffix
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// How text is lowercased when lowercasing is on
+    pub fn case_folding(&self) -> CaseFolding {
+        self.case_folding
+    }
+
+    /// Unicode normalization form applied before anything else
+    pub fn unicode_normalization(&self) -> NormalizationForm {
+        self.unicode_normalization
+    }
+
+    /// Whether NUL, U+FFFD and control characters are dropped from the input
+    pub fn remove_control_chars(&self) -> bool {
+        self.remove_control_chars
+    }
+
+    /// Whether CJK ideographs are split into words of their own
+    pub fn tokenize_chinese_chars(&self) -> bool {
+        self.tokenize_chinese_chars
+    }
+
+    /// Whether grapheme clusters are kept whole, so that emoji sequences become
+    /// words of their own
+    pub fn grapheme_clusters(&self) -> bool {
+        self.grapheme_clusters
+    }
+
+    /// Morphological analysis applied before text is split into words
+    pub fn morphology(&self) -> Option<Morphology> {
+        self.morphology.as_ref().map(Analyzer::morphology)
+    }
+
+    /// What `max_input_chars_per_word` counts in a word
+    pub fn max_input_chars_unit(&self) -> LengthUnit {
+        self.max_input_chars_unit
+    }
+
+    /// How normalized text is split into words
+    pub fn pre_tokenizer(&self) -> PreTokenizer {
+        self.pre_tokenizer
+    }
+
+    /// Contractions the BERT pre-tokenizer matches as words of their own
+    pub fn contractions(&self) -> &Contractions {
+        &self.contractions
+    }
+
+    /// Kinds of text, such as URLs, kept whole instead of split around their punctuation
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    /// Tokens the entities with one are replaced with
+    pub fn entity_placeholders(&self) -> &BTreeMap<Entity, String> {
+        &self.entity_placeholders
+    }
+
+    /// Number of tokens the model accepts, the length inputs are truncated to
+    /// when no other is given
+    pub fn model_max_length(&self) -> Option<usize> {
+        self.model_max_length
+    }
+
+    pub fn set_model_max_length(&mut self, max_length: Option<usize>) {
+        self.model_max_length = max_length;
+    }
+
+    /// Longest text in bytes the `try_` encoding methods and the bindings accept
+    pub fn max_input_bytes(&self) -> Option<usize> {
+        self.max_input_bytes
+    }
+
+    pub fn set_max_input_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_input_bytes = max_bytes;
+    }
+
+    /// Most tokens the `try_` encoding methods and the bindings produce for a text
+    pub fn max_tokens(&self) -> Option<usize> {
+        self.max_tokens
+    }
+
+    pub fn set_max_tokens(&mut self, max_tokens: Option<usize>) {
+        self.max_tokens = max_tokens;
+    }
+
+    /// Which sequence of a pair loses tokens when truncation is asked for
+    /// without naming a strategy
+    pub fn truncation_strategy(&self) -> TruncationStrategy {
+        self.truncation_strategy
+    }
+
+    pub fn set_truncation_strategy(&mut self, strategy: TruncationStrategy) {
+        self.truncation_strategy = strategy;
+    }
+
+    /// Build `backend` from the vocabulary, without the special tokens and the
+    /// added tokens missing from it
+    fn rebuild_matcher(&mut self, backend: VocabBackend) {
+        let mut matcher = Matcher::new(&self.vocab, backend, &self.continuing_subword_prefix);
+        for token in self.special_tokens.keys() {
+            matcher.remove(token);
+        }
+        for token in &self.added_only_tokens {
+            matcher.remove(token);
+        }
+        self.matcher = Arc::new(matcher);
+        self.reset_word_cache();
+    }
+
+    /// Empty the word cache after the segmentation of words changed. A cache
+    /// still shared with clones is left to them and replaced by a new one.
+    fn reset_word_cache(&mut self) {
+        match Arc::get_mut(&mut self.word_cache) {
+            Some(cache) => cache.clear(),
+            None => self.word_cache = Arc::new(WordCache::new(self.word_cache.capacity())),
+        }
+    }
+
+    /// Build the trie's failure links now rather than when the first word is
+    /// segmented, e.g. before a service starts taking requests. They take a
+    /// pass over the whole trie, and are built again after tokens are removed
+    /// from WordPiece matching, e.g. by registering special tokens.
+    pub fn precompile(&self) {
+        self.matcher.precompile();
+    }
+
+    /// Capacity, size, hits and misses of the word cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.word_cache.stats()
+    }
+
+    /// Drop all cached words
+    pub fn clear_cache(&self) {
+        self.word_cache.clear();
+    }
+
+    /// Documents, tokens and unknown tokens encoded so far, with the word cache
+    /// statistics. Clones of the tokenizer share their counters.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.metrics(self.word_cache.stats())
     }
 
     fn clean_text(&self, text: &str) -> String {
-        // Normalize unicode characters
-        let text = text.nfkc().collect::<String>();
-        
-        // Replace whitespace characters with space
-        let text = text.replace(|c: char| c.is_whitespace(), " ");
-        
-        // Handle Chinese characters by adding spaces around them
-        let text = self.chinese_chars.replace_all(&text, |caps: &regex::Captures| {
-            format!(" {} ", &caps[0])
-        }).into_owned();
-        
-        text
+        let mut cleaned = String::with_capacity(text.len());
+        self.text_cleaner().clean(text, 0, &mut cleaned);
+
+        // Apply morphological analysis, e.g. to find the words of Japanese text
+        match &self.morphology {
+            Some(analyzer) => analyzer.apply(&cleaned),
+            None => cleaned,
+        }
     }
 
-    fn strip_accents_if_needed<'a>(&self, text: &'a str) -> Cow<'a, str> {
-        if !self.strip_accents {
-            return Cow::Borrowed(text);
+    fn text_cleaner(&self) -> TextCleaner {
+        TextCleaner {
+            unicode_normalization: self.unicode_normalization,
+            remove_control_chars: self.remove_control_chars,
+            tokenize_chinese_chars: self.tokenize_chinese_chars,
+            grapheme_clusters: self.grapheme_clusters,
         }
+    }
 
-        let normalized = text.nfd().collect::<String>();
-        let stripped = normalized
-            .chars()
-            .filter(|&c| !c.is_ascii_punctuation() && !c.is_ascii_control())
-            .collect::<String>();
-        Cow::Owned(stripped)
+    fn strip_accents_if_needed<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self.strip_accents {
+            true => normalizer::strip_accents(text),
+            false => Cow::Borrowed(text),
+        }
     }
 
-    fn basic_tokenize(&self, text: &str) -> Vec<Token> {
+    /// Split text into words, punctuation and whole added or special tokens,
+    /// before WordPiece. Words carry no id until `wordpiece_tokenize`.
+    pub fn basic_tokenize(&self, text: &str) -> Vec<Token> {
         let mut tokens = Vec::new();
-        let text = self.clean_text(text);
-        
-        for mat in self.basic_tokenizer.find_iter(&text) {
-            let mut token_text = mat.as_str().trim().to_string();
-            
-            // Check if it's a special token
-            if let Some(&id) = self.special_tokens.get(&token_text) {
-                tokens.push(Token {
-                    text: token_text,
-                    id,
-                    is_special: true,
-                });
-                continue;
-            }
-            
-            // Handle casing
-            if self.lowercase {
-                token_text = token_text.to_lowercase();
-            }
-            
-            // Handle accents
-            token_text = self.strip_accents_if_needed(&token_text).into_owned();
-            
-            // Split on punctuation
-            let mut char_tokens = Vec::new();
-            let mut current = String::new();
-            
-            for c in token_text.chars() {
-                if self.punctuation.is_match(&c.to_string()) {
-                    if !current.is_empty() {
-                        char_tokens.push(current);
-                        current = String::new();
-                    }
-                    char_tokens.push(c.to_string());
+
+        self.pre_tokenize_with_offsets(text, &mut |pre_token, spans| {
+            let span = covering(spans);
+            let (text, id, is_special) = match pre_token {
+                // The id will be assigned during wordpiece tokenization
+                PreToken::Word(word) => (word, None, false),
+                PreToken::Matched { text, id, is_special } => (text, Some(id), is_special),
+            };
+            tokens.push(Token {
+                text: match id {
+                    Some(id) => self.shared_token(id, text),
+                    None => Arc::from(text),
+                },
+                id,
+                is_special,
+                start: span.start,
+                end: span.end,
+                is_continuation: false,
+            })
+        });
+
+        tokens
+    }
+
+    /// Split a token from `basic_tokenize` into WordPiece pieces. Pieces cover
+    /// their part of the token when its text is as long as its offsets span,
+    /// and the whole token when normalization changed its length.
+    pub fn wordpiece_tokenize(&self, token: &Token) -> Vec<Token> {
+        // Special and added tokens already carry their id and are never split
+        if token.is_special || token.id.is_some() {
+            return vec![token.clone()];
+        }
+
+        let mut ids = Vec::new();
+        self.wordpiece_ids(&token.text, &mut ids);
+
+        let exact = token.end.saturating_sub(token.start) == token.text.len() && ids != [self.unk_token_id];
+        let mut pos = 0;
+        ids.into_iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let text = self.vocab_lookup[&id].clone();
+                let (start, end) = if exact {
+                    // Pieces spell out the word followed by the end-of-word suffix
+                    let len = text.len() - if i > 0 { self.continuing_subword_prefix.len() } else { 0 };
+                    let start = (token.start + pos).min(token.end);
+                    pos += len;
+                    (start, (token.start + pos).min(token.end))
                 } else {
-                    current.push(c);
+                    (token.start, token.end)
+                };
+                Token {
+                    text,
+                    id: Some(id),
+                    is_special: id == self.unk_token_id,
+                    start,
+                    end,
+                    is_continuation: i > 0,
                 }
+            })
+            .collect()
+    }
+
+    /// Append the piece ids of a word, or the unknown token if it cannot be segmented
+    fn wordpiece_ids(&self, word: &str, ids: &mut Vec<TokenId>) {
+        if self.is_word_too_long(word) {
+            ids.push(self.unk_token_id);
+            return;
+        }
+
+        self.word_cache.extend_with(word, ids, |ids| {
+            let start = ids.len();
+            let word = if self.end_of_word_suffix.is_empty() {
+                Cow::Borrowed(word)
+            } else {
+                Cow::Owned(format!("{}{}", word, self.end_of_word_suffix))
+            };
+            if !self.matcher.tokenize_word(&word, ids) {
+                ids.truncate(start);
+                ids.push(self.unk_token_id);
+            }
+        });
+    }
+
+    /// Whether `word` is longer than `max_input_chars_per_word`. A word has at
+    /// least as many bytes as characters or graphemes, and a character at most
+    /// four bytes, so most words are decided by their byte length alone and the
+    /// others are only counted up to the limit, however long they are.
+    fn is_word_too_long(&self, word: &str) -> bool {
+        let max = self.max_input_chars_per_word;
+        if word.len() <= max {
+            return false;
+        }
+        match self.max_input_chars_unit {
+            LengthUnit::Bytes => true,
+            LengthUnit::Chars => word.len() > max.saturating_mul(4) || word.chars().nth(max).is_some(),
+            LengthUnit::Graphemes => word.graphemes(true).nth(max).is_some(),
+        }
+    }
+
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.encode(text)
+            .into_iter()
+            .map(|id| self.vocab_lookup[&id].to_string())
+            .collect()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(bytes = text.len())))]
+    pub fn encode(&self, text: &str) -> Vec<TokenId> {
+        let start = Stopwatch::start();
+        let mut ids = Vec::new();
+
+        self.pre_tokenize(text, &mut |pre_token| match pre_token {
+            PreToken::Word(word) => self.wordpiece_ids(word, &mut ids),
+            PreToken::Matched { id, .. } => ids.push(id),
+        });
+
+        self.metrics.record(start, text.len(), &ids, self.unk_token_id);
+        ids
+    }
+
+    /// Number of tokens `encode` returns for `text`, without collecting the ids,
+    /// e.g. to check a prompt against a token budget
+    pub fn count_tokens(&self, text: &str) -> usize {
+        let mut count = 0;
+        let mut ids = Vec::new();
+
+        self.pre_tokenize(text, &mut |pre_token| match pre_token {
+            PreToken::Word(word) => {
+                ids.clear();
+                self.wordpiece_ids(word, &mut ids);
+                count += ids.len();
             }
-            
-            if !current.is_empty() {
-                char_tokens.push(current);
+            PreToken::Matched { .. } => count += 1,
+        });
+
+        count
+    }
+
+    /// `count_tokens` of many texts in parallel
+    pub fn count_tokens_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Vec<usize> {
+        texts.par_iter().map(|text| self.count_tokens(text.as_ref())).collect()
+    }
+
+    /// `count_tokens` within the limits of `max_input_bytes` and `max_tokens`,
+    /// failing like `try_encode`
+    pub fn try_count_tokens(&self, text: &str) -> Result<usize> {
+        self.within_limits(text.len(), || self.count_tokens(text), |&count| count)
+    }
+
+    /// `try_count_tokens` of many texts in parallel, failing if any text fails
+    pub fn try_count_tokens_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Result<Vec<usize>> {
+        texts.par_iter().map(|text| self.try_count_tokens(text.as_ref())).collect()
+    }
+
+    /// Encode many texts in parallel
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(texts = texts.len())))]
+    pub fn encode_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Vec<Vec<TokenId>> {
+        texts.par_iter().map(|text| self.encode(text.as_ref())).collect()
+    }
+
+    /// Encode `text` like `encode`, within the limits of `max_input_bytes` and
+    /// `max_tokens`, so that a service cannot be tied up by one huge document.
+    /// Too large a text is rejected before any work. Normalization and
+    /// pre-tokenization still cover the whole of a text within the byte limit,
+    /// and only WordPiece segmentation stops once the token limit is passed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(bytes = text.len())))]
+    pub fn try_encode(&self, text: &str) -> Result<Vec<TokenId>> {
+        let start = Stopwatch::start();
+        self.check_input_bytes(text.len())?;
+        let max_tokens = self.max_tokens.unwrap_or(usize::MAX);
+        let mut ids = Vec::new();
+
+        self.pre_tokenize(text, &mut |pre_token| {
+            if ids.len() > max_tokens {
+                return;
             }
-            
-            // Create tokens
-            for t in char_tokens {
-                tokens.push(Token {
-                    text: t,
-                    id: -1, // Will be assigned during wordpiece tokenization
-                    is_special: false,
-                });
+            match pre_token {
+                PreToken::Word(word) => self.wordpiece_ids(word, &mut ids),
+                PreToken::Matched { id, .. } => ids.push(id),
             }
+        });
+
+        if ids.len() > max_tokens {
+            return Err(Error::TooManyTokens { max: max_tokens });
         }
-        
-        tokens
+        self.metrics.record(start, text.len(), &ids, self.unk_token_id);
+        Ok(ids)
     }
 
-    fn wordpiece_tokenize(&self, token: &Token) -> Vec<Token> {
-        if token.is_special {
-            return vec![token.clone()];
+    /// `try_encode` of many texts in parallel, failing if any text fails
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(texts = texts.len())))]
+    pub fn try_encode_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Result<Vec<Vec<TokenId>>> {
+        texts.par_iter().map(|text| self.try_encode(text.as_ref())).collect()
+    }
+
+    /// Fail for a text of `bytes` over `max_input_bytes`, before any work
+    fn check_input_bytes(&self, bytes: usize) -> Result<()> {
+        match self.max_input_bytes {
+            Some(max) if bytes > max => Err(Error::InputTooLarge { bytes, max }),
+            _ => Ok(()),
         }
+    }
 
-        let chars: Vec<char> = token.text.chars().collect();
-        if chars.len() > self.max_input_chars_per_word {
-            return vec![Token {
-                text: self.unk_token.clone(),
-                id: self.unk_token_id,
-                is_special: true,
-            }];
+    /// Run `encode` on a text of `bytes` within the limits of `max_input_bytes`
+    /// and `max_tokens`, `len` giving the number of tokens of the result. The
+    /// limited methods and the bindings share it, so that limits loaded from a
+    /// config hold on every way of encoding.
+    pub(crate) fn within_limits<T>(
+        &self,
+        bytes: usize,
+        encode: impl FnOnce() -> T,
+        len: impl FnOnce(&T) -> usize,
+    ) -> Result<T> {
+        self.check_input_bytes(bytes)?;
+        let encoded = encode();
+        match self.max_tokens {
+            Some(max) if len(&encoded) > max => Err(Error::TooManyTokens { max }),
+            _ => Ok(encoded),
         }
+    }
 
-        let mut start = 0;
-        let mut sub_tokens = Vec::new();
-        let mut is_bad = false;
+    /// Join the tokens of `ids` back into text. Ids missing from the
+    /// vocabulary are skipped, see `try_decode` for other policies.
+    pub fn decode(&self, ids: &[TokenId], skip_special_tokens: bool, clean_up_tokenization_spaces: bool) -> String {
+        self.try_decode(ids, skip_special_tokens, clean_up_tokenization_spaces, UnknownIdPolicy::Skip)
+            .unwrap_or_default()
+    }
 
-        while start < chars.len() {
-            let prefix = if start == 0 {
-                self.trie.find_longest_prefix(&chars, 0)
-            } else {
-                let mut prefix_chars = Vec::with_capacity(2 + chars.len() - start);
-                prefix_chars.extend(['#', '#']);
-                prefix_chars.extend(&chars[start..]);
-                self.trie.find_longest_prefix(&prefix_chars, 0)
+    /// `decode`, handling ids missing from the vocabulary as `on_unknown_id` says
+    pub fn try_decode(
+        &self,
+        ids: &[TokenId],
+        skip_special_tokens: bool,
+        clean_up_tokenization_spaces: bool,
+        on_unknown_id: UnknownIdPolicy,
+    ) -> Result<String> {
+        let mut tokens = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let token = match (self.vocab_lookup.get(&id), on_unknown_id) {
+                (Some(token), _) => token,
+                (None, UnknownIdPolicy::Skip) => continue,
+                (None, UnknownIdPolicy::Error) => return Err(Error::UnknownId(id)),
+                (None, UnknownIdPolicy::ReplaceWithUnk) => {
+                    tokens.push((self.unk_token.as_str(), true));
+                    continue;
+                }
             };
+            let is_special = self.special_tokens.contains_key(token);
+            if !(skip_special_tokens && is_special) {
+                tokens.push((&**token, is_special));
+            }
+        }
+        Ok(self.decoder.decode_tokens(tokens, clean_up_tokenization_spaces))
+    }
+
+    /// Split text into words for WordPiece and tokens matched whole, passing
+    /// each to `f` in order without allocating a string per token
+    fn pre_tokenize<F: FnMut(PreToken<'_>)>(&self, text: &str, f: &mut F) {
+        self.pre_tokenize_spans(text, false, &mut |pre_token, _| f(pre_token));
+    }
+
+    /// Like `pre_tokenize`, also passing where in `text` each pre-token was
+    /// normalized from: the byte range of each byte of a word, or a single
+    /// range for a token matched whole
+    fn pre_tokenize_with_offsets<F: FnMut(PreToken<'_>, &[Range<usize>])>(&self, text: &str, f: &mut F) {
+        self.pre_tokenize_spans(text, true, f);
+    }
+
+    /// Pre-tokenize `text`, passing each pre-token with where in `text` it came
+    /// from, see `pre_tokenize_with_offsets`. Words are only tracked through
+    /// normalization `with_offsets` and come without ranges otherwise.
+    fn pre_tokenize_spans<F: FnMut(PreToken<'_>, &[Range<usize>])>(&self, text: &str, with_offsets: bool, f: &mut F) {
+        for (start, segment, id) in self.split_added_tokens(text) {
+            match id {
+                Some(id) => f(
+                    PreToken::Matched {
+                        text: segment,
+                        id,
+                        is_special: self.special_tokens.contains_key(segment),
+                    },
+                    std::slice::from_ref(&(start..start + segment.len())),
+                ),
+                None => self.pre_tokenize_segment(segment, start, with_offsets, f),
+            }
+        }
+    }
+
+    /// Pre-tokenize a stretch of text starting at byte `base` that contains no
+    /// added or special tokens
+    fn pre_tokenize_segment<F: FnMut(PreToken<'_>, &[Range<usize>])>(
+        &self,
+        text: &str,
+        base: usize,
+        with_offsets: bool,
+        f: &mut F,
+    ) {
+        if self.never_split.is_empty() {
+            return self.pre_tokenize_entities(text, base, with_offsets, f);
+        }
 
-            if let Some((len, token_id)) = prefix {
-                let token_text = self.vocab_lookup.get(&token_id).unwrap().clone();
-                sub_tokens.push(Token {
-                    text: token_text,
-                    id: token_id,
+        // Whitespace-delimited words listed in never_split are kept verbatim
+        let mut last = 0;
+        for (start, word) in split_whitespace_indices(text) {
+            if !self.never_split.contains(word) {
+                continue;
+            }
+            self.pre_tokenize_entities(&text[last..start], base + last, with_offsets, f);
+            let span = base + start..base + start + word.len();
+            f(match self.vocab.get(word) {
+                Some(&id) => PreToken::Matched {
+                    text: word,
+                    id,
                     is_special: false,
-                });
-                start += if start == 0 { len } else { len - 2 };
-            } else {
-                is_bad = true;
-                break;
+                },
+                None => PreToken::Matched {
+                    text: &self.unk_token,
+                    id: self.unk_token_id,
+                    is_special: true,
+                },
+            }, std::slice::from_ref(&span));
+            last = start + word.len();
+        }
+        self.pre_tokenize_entities(&text[last..], base + last, with_offsets, f);
+    }
+
+    /// Pre-tokenize text starting at byte `base`, keeping entities such as URLs
+    /// whole as single words or replacing them with their placeholders
+    fn pre_tokenize_entities<F: FnMut(PreToken<'_>, &[Range<usize>])>(
+        &self,
+        text: &str,
+        base: usize,
+        with_offsets: bool,
+        f: &mut F,
+    ) {
+        let Some(finder) = &self.entity_finder else {
+            return self.pre_tokenize_words(text, base, with_offsets, false, f);
+        };
+        finder.split(text, |start, stretch, entity| {
+            match entity.and_then(|entity| self.entity_placeholders.get(&entity)) {
+                Some(placeholder) => f(
+                    PreToken::Matched {
+                        text: placeholder,
+                        id: self.vocab.get(placeholder).copied().unwrap_or(self.unk_token_id),
+                        is_special: self.special_tokens.contains_key(placeholder.as_str()),
+                    },
+                    std::slice::from_ref(&(base + start..base + start + stretch.len())),
+                ),
+                None => self.pre_tokenize_words(stretch, base + start, with_offsets, entity.is_some(), f),
             }
+        });
+    }
+
+    /// Normalize and split text starting at byte `base` into words and
+    /// punctuation, or only normalize it into a single word when `whole`
+    fn pre_tokenize_words<F: FnMut(PreToken<'_>, &[Range<usize>])>(
+        &self,
+        text: &str,
+        base: usize,
+        with_offsets: bool,
+        whole: bool,
+        f: &mut F,
+    ) {
+        if self.pre_tokenizer == PreTokenizer::Whitespace {
+            return self.pre_tokenize_verbatim(text, base, with_offsets, f);
+        }
+        if with_offsets {
+            return self.pre_tokenize_words_aligned(text, base, whole, f);
         }
 
-        if is_bad {
-            vec![Token {
-                text: self.unk_token.clone(),
-                id: self.unk_token_id,
-                is_special: true,
-            }]
+        let text = self.clean_text(text);
+
+        WORD_BUFFER.with(|buffer| {
+            let mut word = buffer.borrow_mut();
+
+            if let (false, Some(english_contractions)) = (whole, self.single_pass_contractions()) {
+                return split_bert_words(&text, english_contractions, |range| {
+                    self.normalize_word(&text[range], &mut word);
+                    // Normalization may uncover punctuation, e.g. `=` when
+                    // stripping the accent of `≠`, so split the normalized piece
+                    // again, as the regex path does
+                    self.pre_tokenizer.split_word(&word, false, |range| f(PreToken::Word(&word[range]), &[]));
+                });
+            }
+
+            let words = match whole {
+                true => Words::whole(&text),
+                false => self.pre_tokenizer.words(&self.basic_tokenizer, &text, self.grapheme_clusters),
+            };
+            for (_, matched) in words {
+                self.normalize_word(matched, &mut word);
+
+                // Entities kept whole are not split on punctuation
+                if whole {
+                    if !word.is_empty() {
+                        f(PreToken::Word(&word), &[]);
+                    }
+                    continue;
+                }
+
+                // Split on punctuation, as the BERT pre-tokenizer does
+                self.pre_tokenizer.split_word(&word, self.grapheme_clusters, |range| {
+                    f(PreToken::Word(&word[range]), &[])
+                });
+            }
+        });
+    }
+
+    /// Lowercase and strip the accents of a word as configured, into `word`
+    fn normalize_word(&self, matched: &str, word: &mut String) {
+        // Handle casing
+        word.clear();
+        if self.lowercase {
+            self.case_folding.push_str(matched, word);
         } else {
-            sub_tokens
+            word.push_str(matched);
+        }
+
+        // Handle accents
+        if let Cow::Owned(stripped) = self.strip_accents_if_needed(word) {
+            *word = stripped;
         }
     }
 
-    fn tokenize(&self, text: &str) -> Vec<String> {
-        // First apply basic tokenization
-        let basic_tokens = self.basic_tokenize(text);
-        
-        // Then apply WordPiece tokenization to each token
-        basic_tokens
-            .into_iter()
-            .flat_map(|token| self.wordpiece_tokenize(&token))
-            .map(|token| token.text)
-            .collect()
+    /// Whether the words of cleaned text can be found by `split_bert_words`
+    /// in a single pass rather than with the word and punctuation regexes,
+    /// and if so whether to match English contractions. The regexes remain
+    /// for custom contractions, grapheme clusters and offset tracking.
+    fn single_pass_contractions(&self) -> Option<bool> {
+        if self.pre_tokenizer != PreTokenizer::Bert || self.grapheme_clusters {
+            return None;
+        }
+        match self.contractions {
+            Contractions::None => Some(false),
+            Contractions::English => Some(true),
+            _ => None,
+        }
     }
 
-    fn encode(&self, text: &str) -> Vec<i32> {
-        // First apply basic tokenization
-        let basic_tokens = self.basic_tokenize(text);
-        
-        // Then apply WordPiece tokenization to each token
-        basic_tokens
-            .into_iter()
-            .flat_map(|token| self.wordpiece_tokenize(&token))
-            .map(|token| token.id)
-            .collect()
+    /// Split text starting at byte `base` on whitespace alone, passing each word
+    /// on without normalization and, `with_offsets`, the range of each byte
+    fn pre_tokenize_verbatim<F: FnMut(PreToken<'_>, &[Range<usize>])>(
+        &self,
+        text: &str,
+        base: usize,
+        with_offsets: bool,
+        f: &mut F,
+    ) {
+        let mut spans = Vec::new();
+        for (start, word) in split_whitespace_indices(text) {
+            spans.clear();
+            if with_offsets {
+                spans.extend((base + start..base + start + word.len()).map(|i| i..i + 1));
+            }
+            f(PreToken::Word(word), &spans);
+        }
+    }
+
+    /// Split raw text into segments with their byte offsets, pairing each added
+    /// or special token match with its id. Longer tokens win when several match
+    /// at the same position.
+    fn split_added_tokens<'a>(&self, text: &'a str) -> Vec<(usize, &'a str, Option<TokenId>)> {
+        let pattern = match &self.added_tokens_pattern {
+            Some(pattern) => pattern,
+            None => return vec![(0, text, None)],
+        };
+
+        let mut segments = Vec::new();
+        let mut last = 0;
+        for mat in pattern.find_iter(text) {
+            if mat.start() > last {
+                segments.push((last, &text[last..mat.start()], None));
+            }
+            let id = self
+                .special_tokens
+                .get(mat.as_str())
+                .or_else(|| self.added_tokens.get(mat.as_str()))
+                .copied();
+            segments.push((mat.start(), mat.as_str(), id));
+            last = mat.end();
+        }
+        if last < text.len() {
+            segments.push((last, &text[last..], None));
+        }
+
+        segments
     }
 
-    fn decode(&self, ids: Vec<i32>) -> String {
-        let tokens: Vec<String> = ids
+    /// Recompile the alternation used to find added and special tokens in raw text
+    fn rebuild_added_tokens_pattern(&mut self) {
+        let mut tokens: Vec<&str> = self
+            .special_tokens
+            .keys()
+            .map(|token| &**token)
+            .chain(self.added_tokens.keys().map(String::as_str))
+            .collect();
+        if tokens.is_empty() {
+            self.added_tokens_pattern = None;
+            return;
+        }
+
+        // Regex alternation is leftmost-first, so try longer tokens first
+        tokens.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        let pattern = tokens
             .iter()
-            .filter_map(|&id| self.vocab_lookup.get(&id))
-            .map(|t| t.replace("##", ""))
+            .map(|token| regex::escape(token))
+            .collect::<Vec<_>>()
+            .join("|");
+        self.added_tokens_pattern = Some(Regex::new(&pattern).unwrap());
+    }
+
+    /// Special tokens keyed by the role they are registered under
+    fn special_tokens_by_role(&self) -> SpecialTokens {
+        let mut special_tokens = SpecialTokens {
+            unk_token: Some(self.unk_token.clone()),
+            cls_token: self.cls_token.clone(),
+            sep_token: self.sep_token.clone(),
+            pad_token: self.pad_token.clone(),
+            mask_token: self.mask_token.clone(),
+            additional_special_tokens: Vec::new(),
+        };
+
+        let with_role: HashSet<&String> = special_tokens.roles().map(|(_, token)| token).collect();
+        let additional: Vec<String> = self
+            .sorted_by_id(&self.special_tokens)
+            .into_iter()
+            .filter(|token| !with_role.contains(token))
             .collect();
+        special_tokens.additional_special_tokens = additional;
+
+        special_tokens
+    }
+
+    /// Capture the vocabulary, options and registered tokens
+    fn snapshot(&self) -> TokenizerSnapshot {
+        let mut never_split: Vec<String> = self.never_split.iter().cloned().collect();
+        never_split.sort();
+        let mut added_only_tokens: Vec<String> = self.added_only_tokens.iter().cloned().collect();
+        added_only_tokens.sort();
 
-        // Join tokens with spaces, but don't add spaces around punctuation
-        let mut result = String::new();
-        let mut prev_is_punct = false;
-        
-        for (i, token) in tokens.iter().enumerate() {
-            let is_punct = self.punctuation.is_match(token);
-            
-            if i > 0 && !is_punct && !prev_is_punct {
-                result.push(' ');
+        TokenizerSnapshot {
+            vocab: self.vocab.as_ref().clone(),
+            unk_token: self.unk_token.clone(),
+            max_input_chars_per_word: self.max_input_chars_per_word,
+            max_input_chars_unit: Some(self.max_input_chars_unit),
+            strip_accents: self.strip_accents,
+            lowercase: self.lowercase,
+            case_folding: self.case_folding,
+            unicode_normalization: self.unicode_normalization,
+            remove_control_chars: self.remove_control_chars,
+            tokenize_chinese_chars: self.tokenize_chinese_chars,
+            grapheme_clusters: self.grapheme_clusters,
+            morphology: self.morphology.as_ref().map(Analyzer::morphology),
+            special_tokens: self.special_tokens_by_role(),
+            added_tokens: self.sorted_by_id(&self.added_tokens),
+            added_only_tokens,
+            never_split,
+            cache_capacity: self.word_cache.capacity(),
+            vocab_backend: self.matcher.backend(),
+            continuing_subword_prefix: self.continuing_subword_prefix.clone(),
+            end_of_word_suffix: self.end_of_word_suffix.clone(),
+            pre_tokenizer: self.pre_tokenizer,
+            contractions: self.contractions.clone(),
+            entities: self.entities.clone(),
+            entity_placeholders: self.entity_placeholders.clone(),
+            model_max_length: self.model_max_length,
+            truncation_strategy: self.truncation_strategy,
+            max_input_bytes: self.max_input_bytes,
+            max_tokens: self.max_tokens,
+        }
+    }
+
+    /// Tokens of a token -> id map ordered by id
+    fn sorted_by_id<K: AsRef<str>>(&self, tokens: &HashMap<K, TokenId>) -> Vec<String> {
+        let mut entries: Vec<(&K, &TokenId)> = tokens.iter().collect();
+        entries.sort_by_key(|(_, &id)| id);
+        entries.into_iter().map(|(token, _)| token.as_ref().to_string()).collect()
+    }
+
+    /// Resolve the id of the special token registered under a role, if any
+    fn special_token_id(&self, token: &Option<String>) -> Option<TokenId> {
+        token.as_ref().and_then(|token| self.special_tokens.get(token.as_str())).copied()
+    }
+
+    /// Mark `token` as special under `role`, removing it from WordPiece matching.
+    /// Returns whether the token was newly registered as special.
+    fn register_special_token(&mut self, role: &str, token: &str) -> Result<bool> {
+        let id = *self
+            .vocab
+            .get(token)
+            .ok_or_else(|| Error::SpecialTokenNotInVocab(token.to_string()))?;
+
+        match role {
+            "unk_token" => {
+                self.unk_token = token.to_string();
+                self.unk_token_id = id;
             }
-            
-            result.push_str(token);
-            prev_is_punct = is_punct;
-        }
-        
-        result
-    }
-
-    #[staticmethod]
-    #[args(
-        vocab_size = "30000",
-        min_frequency = "2",
-        special_tokens = "None",
-        strip_accents = "true",
-        lowercase = "true"
-    )]
-    fn train(
-        texts: Vec<String>,
-        vocab_size: usize,
-        min_frequency: usize,
-        special_tokens: Option<Vec<String>>,
-        strip_accents: bool,
-        lowercase: bool,
-    ) -> PyResult<HashMap<String, i32>> {
-        let special_tokens = special_tokens.unwrap_or_else(|| {
-            vec![
-                "[UNK]".to_string(),
-                "[CLS]".to_string(),
-                "[SEP]".to_string(),
-                "[PAD]".to_string(),
-                "[MASK]".to_string(),
-            ]
-        });
+            "cls_token" => self.cls_token = Some(token.to_string()),
+            "sep_token" => self.sep_token = Some(token.to_string()),
+            "pad_token" => self.pad_token = Some(token.to_string()),
+            "mask_token" => self.mask_token = Some(token.to_string()),
+            _ => {}
+        }
 
-        let trainer = WordPieceTrainer::new(
-            vocab_size,
-            min_frequency,
-            special_tokens,
-            strip_accents,
-            lowercase,
-        );
+        Arc::make_mut(&mut self.matcher).remove(token);
+        Ok(self.special_tokens.insert(self.shared_token(id, token), id).is_none())
+    }
 
-        Ok(trainer.train(&texts))
+    /// `token`, shared with the vocabulary if it is the token with id `id`
+    fn shared_token(&self, id: TokenId, token: &str) -> Arc<str> {
+        match self.vocab_lookup.get(&id) {
+            Some(shared) if **shared == *token => shared.clone(),
+            _ => Arc::from(token),
+        }
     }
 }
 
-#[pymodule]
-fn wordpiece_rs(_py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_class::<WordPieceTokenizer>()?;
-    Ok(())
-}
\ No newline at end of file
+/// Serializable snapshot of everything needed to rebuild a tokenizer
+#[derive(Serialize, Deserialize)]
+struct TokenizerSnapshot {
+    #[serde(serialize_with = "serialize_by_id")]
+    vocab: HashMap<String, TokenId>,
+    unk_token: String,
+    max_input_chars_per_word: usize,
+    /// Counted in graphemes with grapheme clusters and in characters otherwise when unset
+    #[serde(default)]
+    max_input_chars_unit: Option<LengthUnit>,
+    strip_accents: bool,
+    lowercase: bool,
+    #[serde(default)]
+    case_folding: CaseFolding,
+    #[serde(default)]
+    unicode_normalization: NormalizationForm,
+    #[serde(default = "default_remove_control_chars")]
+    remove_control_chars: bool,
+    #[serde(default = "default_tokenize_chinese_chars")]
+    tokenize_chinese_chars: bool,
+    #[serde(default)]
+    grapheme_clusters: bool,
+    #[serde(default)]
+    morphology: Option<Morphology>,
+    special_tokens: SpecialTokens,
+    #[serde(default)]
+    added_tokens: Vec<String>,
+    /// Added tokens `vocab` holds only because they were added, kept out of WordPiece matching
+    #[serde(default)]
+    added_only_tokens: Vec<String>,
+    #[serde(default)]
+    never_split: Vec<String>,
+    #[serde(default)]
+    cache_capacity: usize,
+    #[serde(default)]
+    vocab_backend: VocabBackend,
+    #[serde(default = "default_continuing_subword_prefix")]
+    continuing_subword_prefix: String,
+    #[serde(default)]
+    end_of_word_suffix: String,
+    #[serde(default)]
+    pre_tokenizer: PreTokenizer,
+    #[serde(default)]
+    contractions: Contractions,
+    #[serde(default)]
+    entities: Vec<Entity>,
+    #[serde(default)]
+    entity_placeholders: BTreeMap<Entity, String>,
+    #[serde(default)]
+    model_max_length: Option<usize>,
+    #[serde(default)]
+    truncation_strategy: TruncationStrategy,
+    #[serde(default)]
+    max_input_bytes: Option<usize>,
+    #[serde(default)]
+    max_tokens: Option<usize>,
+}
+
+fn default_continuing_subword_prefix() -> String {
+    trainer::DEFAULT_CONTINUING_SUBWORD_PREFIX.to_string()
+}
+
+/// Serialize a token -> id map in id order so snapshots are stable across runs
+fn serialize_by_id<S: Serializer>(vocab: &HashMap<String, TokenId>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let mut entries: Vec<(&String, &TokenId)> = vocab.iter().collect();
+    entries.sort_by_key(|(_, &id)| id);
+
+    let mut map = serializer.serialize_map(Some(entries.len()))?;
+    for (token, id) in entries {
+        map.serialize_entry(token, id)?;
+    }
+    map.end()
+}
+
+impl Serialize for WordPieceTokenizer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for WordPieceTokenizer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let snapshot = TokenizerSnapshot::deserialize(deserializer)?;
+        WordPieceTokenizer::from_snapshot(snapshot).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Iterate over whitespace-delimited words together with their byte offsets
+fn split_whitespace_indices(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(char::is_whitespace)
+        .scan(0, |offset, word| {
+            let start = *offset;
+            *offset += word.len() + text[start + word.len()..].chars().next().map_or(0, char::len_utf8);
+            Some((start, word))
+        })
+        .filter(|(_, word)| !word.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A lowercasing tokenizer over the BERT special tokens and `tokens`
+    pub(crate) fn tokenizer(tokens: &[&str]) -> WordPieceTokenizer {
+        let specials = ["[PAD]", "[UNK]", "[CLS]", "[SEP]", "[MASK]"];
+        let vocab = specials
+            .iter()
+            .chain(tokens)
+            .enumerate()
+            .map(|(id, token)| (token.to_string(), id as TokenId))
+            .collect();
+        WordPieceTokenizer::builder().vocab(vocab).build().unwrap()
+    }
+
+    /// A tokenizer with the added tokens `xyz`, missing from its vocabulary,
+    /// and `ab`, which WordPiece segments as `a ##b`
+    pub(crate) fn with_added_tokens() -> WordPieceTokenizer {
+        let mut tokenizer = tokenizer(&["a", "##b", "c"]);
+        tokenizer.add_tokens(vec!["xyz".to_string(), "ab".to_string()]);
+        tokenizer
+    }
+
+    /// Added tokens match the raw input only, never as WordPiece pieces
+    pub(crate) fn assert_added_tokens_unsplit(tokenizer: &WordPieceTokenizer) {
+        assert_eq!(tokenizer.tokenize("XYZ AB xyz ab"), ["[UNK]", "a", "##b", "xyz", "ab"]);
+    }
+
+    #[test]
+    fn added_tokens_are_not_wordpiece_pieces() {
+        assert_added_tokens_unsplit(&with_added_tokens());
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn serde_snapshot_keeps_added_tokens_out_of_wordpiece() {
+        let tokenizer = with_added_tokens();
+        let json = serde_json::to_string(&tokenizer).unwrap();
+        let restored: WordPieceTokenizer = serde_json::from_str(&json).unwrap();
+
+        assert_added_tokens_unsplit(&restored);
+        assert_eq!(restored.added_tokens(), tokenizer.added_tokens());
+        assert_eq!(restored.vocab(), tokenizer.vocab());
+    }
+
+    #[test]
+    fn new_vocab_keeps_added_tokens_out_of_wordpiece() {
+        let tokenizer = with_added_tokens();
+        let (pruned, _) = tokenizer.prune_vocab(|_, _| true).unwrap();
+        assert_added_tokens_unsplit(&pruned);
+
+        let mut rebuilt = tokenizer.clone();
+        rebuilt.set_vocab_backend(VocabBackend::DoubleArray);
+        assert_added_tokens_unsplit(&rebuilt);
+    }
+
+    #[test]
+    fn retraining_assigns_added_tokens_new_ids() {
+        let tokenizer = with_added_tokens();
+        let trained = tokenizer.train_from_iterator(["a b c", "c b a"], 100, 1).unwrap();
+
+        assert_eq!(trained.tokenize("XYZ xyz"), ["[UNK]", "xyz"]);
+        let id = trained.added_tokens()["xyz"];
+        assert_eq!(trained.id_to_token(id), Some("xyz"));
+    }
+
+    #[test]
+    fn single_pass_splits_punctuation_uncovered_by_normalization() {
+        let build = |contractions| {
+            let tokens = ["[UNK]", "a", "b", "x", "y", "s", "=", "`", "'", "¦"];
+            let vocab = tokens.iter().enumerate().map(|(id, token)| (token.to_string(), id as TokenId)).collect();
+            WordPieceTokenizer::builder()
+                .vocab(vocab)
+                .strip_accents(true)
+                .unicode_normalization(NormalizationForm::None)
+                .contractions(contractions)
+                .build()
+                .unwrap()
+        };
+        let single_pass = build(Contractions::English);
+        // Custom contractions take the word and punctuation regexes instead
+        let english = ["'s", "'t", "'re", "'ve", "'m", "'ll", "'d"];
+        let regex = build(Contractions::Custom(english.iter().map(|c| c.to_string()).collect()));
+
+        assert_eq!(single_pass.tokenize("a ≠≠ b"), ["a", "=", "=", "b"]);
+        for text in ["a ≠≠ b", "x \u{1FEF}\u{A6} y", "Á≠b's ≠'s ÿ=x", "a\u{301}\u{338}=b"] {
+            assert_eq!(single_pass.encode(text), regex.encode(text), "{:?}", text);
+            assert_eq!(single_pass.encode(text), single_pass.encode_plus(text).ids(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn try_encode_enforces_limits() {
+        let mut tokenizer = tokenizer(&["a", "##b"]);
+        assert_eq!(tokenizer.try_encode("ab a ab").unwrap(), [5, 6, 5, 5, 6]);
+
+        tokenizer.set_max_tokens(Some(4));
+        assert!(matches!(tokenizer.try_encode("ab a ab"), Err(Error::TooManyTokens { max: 4 })));
+        assert_eq!(tokenizer.try_encode("ab a a").unwrap(), [5, 6, 5, 5]);
+
+        tokenizer.set_max_input_bytes(Some(5));
+        assert!(matches!(tokenizer.try_encode("ab a a"), Err(Error::InputTooLarge { bytes: 6, max: 5 })));
+        assert_eq!(tokenizer.try_encode("ab ab").unwrap(), [5, 6, 5, 6]);
+    }
+
+    #[test]
+    fn limits_hold_for_encode_plus_and_count_tokens() {
+        let mut tokenizer = tokenizer(&["a", "##b"]);
+        tokenizer.set_max_tokens(Some(4));
+        assert!(matches!(tokenizer.try_encode_plus("ab a ab"), Err(Error::TooManyTokens { max: 4 })));
+        assert!(matches!(tokenizer.try_count_tokens("ab a ab"), Err(Error::TooManyTokens { max: 4 })));
+        assert_eq!(tokenizer.try_encode_plus("ab a a").unwrap().ids(), [5, 6, 5, 5]);
+        assert_eq!(tokenizer.try_count_tokens_batch(&["ab a a", "a"]).unwrap(), [4, 1]);
+
+        tokenizer.set_max_input_bytes(Some(5));
+        assert!(matches!(tokenizer.try_encode_plus("ab a a"), Err(Error::InputTooLarge { bytes: 6, max: 5 })));
+        assert!(matches!(tokenizer.try_count_tokens_batch(&["a", "ab a a"]), Err(Error::InputTooLarge { .. })));
+    }
+}
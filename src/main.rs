@@ -1,3 +1,469 @@
-fn main() {
-    println!("Hello, world!");
+use clap::{Args, Parser, Subcommand};
+use rayon::prelude::*;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use wordpiece_rs::{ParityReport, TokenId, WordPieceTokenizer, WordPieceTrainer};
+
+/// Lines read and encoded in parallel at a time
+const BATCH_SIZE: usize = 8192;
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+/// Train, run and inspect WordPiece tokenizers
+#[derive(Parser)]
+#[command(name = "wordpiece-rs", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Learn a vocabulary from text files, or from stdin, and write it as vocab.txt
+    Train(TrainArgs),
+    /// Encode JSONL records to token ids
+    Encode(EncodeArgs),
+    /// Decode JSONL records of token ids back to text
+    Decode(DecodeArgs),
+    /// Compare the ids of JSONL records with reference ids, e.g. recorded from
+    /// BertTokenizerFast, and report where they differ
+    Parity(ParityArgs),
+    /// Work with vocabularies
+    Vocab {
+        #[command(subcommand)]
+        command: VocabCommand,
+    },
+    /// Serve /encode, /encode_batch and /decode over HTTP, batching concurrent requests
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+    /// Serve the wordpiece.v1.Tokenizer gRPC service, batching concurrent requests
+    #[cfg(feature = "grpc")]
+    ServeGrpc(ServeArgs),
+}
+
+#[derive(Subcommand)]
+enum VocabCommand {
+    /// Print statistics about a tokenizer's vocabulary
+    Inspect(TokenizerArgs),
+}
+
+/// Where to load a tokenizer from
+#[derive(Args)]
+struct TokenizerArgs {
+    /// A vocab.txt file, a SentencePiece .model file, a tokenizer_config.json or
+    /// .toml file, a directory written by save_pretrained or a file written by
+    /// save_compiled
+    #[arg(short, long)]
+    tokenizer: PathBuf,
+    /// Keep the case of the text (vocab.txt only)
+    #[arg(long)]
+    cased: bool,
+    /// Keep accents (vocab.txt only)
+    #[arg(long)]
+    keep_accents: bool,
+    /// The unknown token (vocab.txt only)
+    #[arg(long, default_value = "[UNK]")]
+    unk_token: String,
+}
+
+#[derive(Args)]
+struct TrainArgs {
+    /// Text files or glob patterns, read line by line; stdin when none are given
+    files: Vec<String>,
+    /// Where to write the vocabulary
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Where to write the merges with their scores
+    #[arg(long)]
+    merges: Option<PathBuf>,
+    #[arg(long, default_value_t = 30000)]
+    vocab_size: usize,
+    /// Minimum number of occurrences for a merge
+    #[arg(long, default_value_t = 2)]
+    min_frequency: usize,
+    /// Special tokens, placed first in the vocabulary
+    #[arg(long, value_delimiter = ',', default_value = "[UNK],[CLS],[SEP],[PAD],[MASK]")]
+    special_tokens: Vec<String>,
+    /// The unknown token, one of the special tokens
+    #[arg(long, default_value = "[UNK]")]
+    unk_token: String,
+    /// Keep the case of the text
+    #[arg(long)]
+    cased: bool,
+    /// Keep accents
+    #[arg(long)]
+    keep_accents: bool,
+    /// Keep at most this many distinct characters
+    #[arg(long)]
+    limit_alphabet: Option<usize>,
+}
+
+#[derive(Args)]
+struct EncodeArgs {
+    #[command(flatten)]
+    tokenizer: TokenizerArgs,
+    /// JSONL file of objects or strings; stdin when omitted or `-`
+    input: Option<PathBuf>,
+    /// Field of each object holding the text
+    #[arg(long, default_value = "text")]
+    field: String,
+    /// Field to write the ids to
+    #[arg(long, default_value = "ids")]
+    output_field: String,
+    /// Also write the tokens, to a `tokens` field
+    #[arg(long)]
+    tokens: bool,
+    /// Read plain text, one text per line, instead of JSONL
+    #[arg(long)]
+    plain: bool,
+}
+
+#[derive(Args)]
+struct DecodeArgs {
+    #[command(flatten)]
+    tokenizer: TokenizerArgs,
+    /// JSONL file of objects or arrays of ids; stdin when omitted or `-`
+    input: Option<PathBuf>,
+    /// Field of each object holding the ids
+    #[arg(long, default_value = "ids")]
+    field: String,
+    /// Field to write the text to
+    #[arg(long, default_value = "text")]
+    output_field: String,
+    /// Leave special tokens out of the text
+    #[arg(long)]
+    skip_special_tokens: bool,
+    /// Keep the spaces before punctuation and in contractions
+    #[arg(long)]
+    no_cleanup: bool,
+}
+
+#[derive(Args)]
+struct ParityArgs {
+    #[command(flatten)]
+    tokenizer: TokenizerArgs,
+    /// JSONL file of objects with a text and its reference ids; stdin when omitted or `-`
+    input: Option<PathBuf>,
+    /// Field of each object holding the text
+    #[arg(long, default_value = "text")]
+    field: String,
+    /// Field of each object holding the reference ids
+    #[arg(long, default_value = "input_ids")]
+    ids_field: String,
+    /// Compare the ids without the [CLS] and [SEP] tokens around them
+    #[arg(long)]
+    no_special_tokens: bool,
+    /// Show at most this many differing texts
+    #[arg(long, default_value_t = 10)]
+    max_divergences: usize,
+}
+
+#[cfg(any(feature = "serve", feature = "grpc"))]
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    tokenizer: TokenizerArgs,
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    /// Port to listen on, 8080 for HTTP and 50051 for gRPC by default
+    #[arg(long)]
+    port: Option<u16>,
+    /// Number of texts after which a batch is encoded without waiting for more
+    #[arg(long, default_value_t = 1024)]
+    max_batch_size: usize,
+    /// How long, in milliseconds, a request waits for others to batch with
+    #[arg(long, default_value_t = 2)]
+    max_wait_ms: u64,
+    /// Reject texts longer than this many bytes
+    #[arg(long)]
+    max_input_bytes: Option<usize>,
+    /// Reject texts that encode to more than this many tokens
+    #[arg(long)]
+    max_tokens: Option<usize>,
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        // The reader of the output, e.g. `head`, is gone
+        Err(err) if err.downcast_ref::<io::Error>().is_some_and(|err| err.kind() == ErrorKind::BrokenPipe) => {
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("wordpiece-rs: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> CliResult<()> {
+    match command {
+        Command::Train(args) => train(args),
+        Command::Encode(args) => encode(args),
+        Command::Decode(args) => decode(args),
+        Command::Parity(args) => parity(args),
+        Command::Vocab {
+            command: VocabCommand::Inspect(args),
+        } => inspect(&load_tokenizer(&args)?),
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => {
+            let port = args.port.unwrap_or(8080);
+            let (tokenizer, config) = serve_setup(&args, "http", port)?;
+            block_on(wordpiece_rs::serve(tokenizer, (args.host.as_str(), port), config))
+        }
+        #[cfg(feature = "grpc")]
+        Command::ServeGrpc(args) => {
+            let port = args.port.unwrap_or(50051);
+            let (tokenizer, config) = serve_setup(&args, "grpc", port)?;
+            block_on(wordpiece_rs::serve_grpc(tokenizer, (args.host.as_str(), port), config))
+        }
+    }
+}
+
+fn load_tokenizer(args: &TokenizerArgs) -> CliResult<WordPieceTokenizer> {
+    let path = &args.tokenizer;
+    if path.is_dir() {
+        return Ok(WordPieceTokenizer::from_config(path)?);
+    }
+    let tokenizer = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("txt") => WordPieceTokenizer::builder()
+            .vocab_file(path)
+            .unk_token(&args.unk_token)
+            .lowercase(!args.cased)
+            .strip_accents(!args.keep_accents)
+            .build()?,
+        Some("model") => WordPieceTokenizer::from_sentencepiece(path)?,
+        Some("json" | "toml") => WordPieceTokenizer::from_config(path)?,
+        _ => WordPieceTokenizer::load_compiled(path)?,
+    };
+    Ok(tokenizer)
+}
+
+fn train(args: TrainArgs) -> CliResult<()> {
+    let mut trainer = WordPieceTrainer::new(
+        args.vocab_size,
+        args.min_frequency,
+        args.special_tokens,
+        !args.keep_accents,
+        !args.cased,
+    );
+    if let Some(limit) = args.limit_alphabet {
+        trainer.set_limit_alphabet(limit);
+    }
+
+    let vocab = if args.files.is_empty() {
+        let lines: Vec<String> = io::stdin().lock().lines().collect::<io::Result<_>>()?;
+        trainer.train(&lines)
+    } else {
+        trainer.train_from_files(&args.files)?
+    };
+
+    let tokenizer = WordPieceTokenizer::from_trained(vocab, &trainer, &args.unk_token, 200)?;
+    tokenizer.save_vocab(&args.output)?;
+    if let Some(path) = args.merges {
+        trainer.save_merges(path)?;
+    }
+    eprintln!("Wrote {} tokens to {}", tokenizer.vocab().len(), args.output.display());
+    Ok(())
+}
+
+fn encode(args: EncodeArgs) -> CliResult<()> {
+    let tokenizer = load_tokenizer(&args.tokenizer)?;
+    for_each_batch(args.input.as_deref(), |batch, out| {
+        let mut records = Vec::with_capacity(batch.len());
+        let mut texts = Vec::with_capacity(batch.len());
+        for (line_number, line) in batch {
+            let (record, text) = if args.plain {
+                (Map::new(), line.clone())
+            } else {
+                match serde_json::from_str(line) {
+                    Ok(Value::Object(record)) => {
+                        let text = match record.get(&args.field) {
+                            Some(Value::String(text)) => text.clone(),
+                            _ => return Err(format!("line {}: no string field '{}'", line_number, args.field).into()),
+                        };
+                        (record, text)
+                    }
+                    Ok(Value::String(text)) => (Map::new(), text),
+                    Ok(_) => return Err(format!("line {}: expected an object or a string", line_number).into()),
+                    Err(err) => return Err(format!("line {}: {}", line_number, err).into()),
+                }
+            };
+            records.push(record);
+            texts.push(text);
+        }
+
+        // Texts over the tokenizer's limits fail with their line number
+        let batch_ids: Vec<_> = texts.par_iter().map(|text| tokenizer.try_encode(text)).collect();
+        for (((mut record, text), ids), (line_number, _)) in records.into_iter().zip(texts).zip(batch_ids).zip(batch) {
+            let ids = ids.map_err(|err| format!("line {}: {}", line_number, err))?;
+            if record.is_empty() {
+                record.insert(args.field.clone(), Value::String(text));
+            }
+            if args.tokens {
+                let tokens: Vec<&str> = ids.iter().filter_map(|&id| tokenizer.id_to_token(id)).collect();
+                record.insert("tokens".to_string(), tokens.into());
+            }
+            record.insert(args.output_field.clone(), ids.into());
+            writeln!(out, "{}", Value::Object(record))?;
+        }
+        Ok(())
+    })
+}
+
+fn decode(args: DecodeArgs) -> CliResult<()> {
+    let tokenizer = load_tokenizer(&args.tokenizer)?;
+    for_each_batch(args.input.as_deref(), |batch, out| {
+        for (line_number, line) in batch {
+            let (mut record, ids) = match serde_json::from_str(line) {
+                Ok(Value::Object(record)) => {
+                    let ids = record.get(&args.field).cloned().unwrap_or(Value::Null);
+                    (record, ids)
+                }
+                Ok(ids) => (Map::new(), ids),
+                Err(err) => return Err(format!("line {}: {}", line_number, err).into()),
+            };
+            let ids: Vec<TokenId> = serde_json::from_value(ids)
+                .map_err(|_| format!("line {}: expected an array of ids or an object with '{}'", line_number, args.field))?;
+
+            let text = tokenizer.decode(&ids, args.skip_special_tokens, !args.no_cleanup);
+            record.insert(args.output_field.clone(), Value::String(text));
+            writeln!(out, "{}", Value::Object(record))?;
+        }
+        Ok(())
+    })
+}
+
+fn parity(args: ParityArgs) -> CliResult<()> {
+    let tokenizer = load_tokenizer(&args.tokenizer)?;
+    let mut report = ParityReport::default();
+    for_each_batch(args.input.as_deref(), |batch, _| {
+        let mut texts = Vec::with_capacity(batch.len());
+        let mut reference = Vec::with_capacity(batch.len());
+        for (line_number, line) in batch {
+            let record: Map<String, Value> = serde_json::from_str(line)
+                .map_err(|_| format!("line {}: expected an object", line_number))?;
+            match record.get(&args.field) {
+                Some(Value::String(text)) => texts.push(text.clone()),
+                _ => return Err(format!("line {}: no string field '{}'", line_number, args.field).into()),
+            }
+            let ids: Vec<TokenId> = serde_json::from_value(record.get(&args.ids_field).cloned().unwrap_or(Value::Null))
+                .map_err(|_| format!("line {}: no array of ids in '{}'", line_number, args.ids_field))?;
+            reference.push(ids);
+        }
+
+        let remaining = args.max_divergences - report.divergences.len();
+        let batch_report = tokenizer.parity_check(&texts, &reference, !args.no_special_tokens, remaining)?;
+        // Number the texts across the whole input
+        report.divergences.extend(batch_report.divergences.into_iter().map(|mut divergence| {
+            divergence.index += report.texts;
+            divergence
+        }));
+        report.texts += batch_report.texts;
+        report.matching += batch_report.matching;
+        Ok(())
+    })?;
+
+    print!("{}", report);
+    match report.is_exact() {
+        true => Ok(()),
+        false => Err(format!("{} of {} texts differ from the reference", report.texts - report.matching, report.texts).into()),
+    }
+}
+
+/// Pass the non-empty lines of `input`, or of stdin, to `f` in batches, with
+/// their line numbers, along with the output
+fn for_each_batch<F>(input: Option<&Path>, mut f: F) -> CliResult<()>
+where
+    F: FnMut(&[(usize, String)], &mut dyn Write) -> CliResult<()>,
+{
+    let reader: Box<dyn BufRead> = match input {
+        Some(path) if path != Path::new("-") => Box::new(BufReader::new(File::open(path)?)),
+        _ => Box::new(io::stdin().lock()),
+    };
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        batch.push((i + 1, line));
+        if batch.len() == BATCH_SIZE {
+            f(&batch, &mut out)?;
+            batch.clear();
+        }
+    }
+    f(&batch, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Load the tokenizer to serve and the batching options
+#[cfg(any(feature = "serve", feature = "grpc"))]
+fn serve_setup(args: &ServeArgs, scheme: &str, port: u16) -> CliResult<(WordPieceTokenizer, wordpiece_rs::ServeConfig)> {
+    let mut tokenizer = load_tokenizer(&args.tokenizer)?;
+    if args.max_input_bytes.is_some() {
+        tokenizer.set_max_input_bytes(args.max_input_bytes);
+    }
+    if args.max_tokens.is_some() {
+        tokenizer.set_max_tokens(args.max_tokens);
+    }
+    let config = wordpiece_rs::ServeConfig {
+        max_batch_size: args.max_batch_size,
+        max_wait: std::time::Duration::from_millis(args.max_wait_ms),
+    };
+    eprintln!("Serving {} on {}://{}:{}", args.tokenizer.tokenizer.display(), scheme, args.host, port);
+    Ok((tokenizer, config))
+}
+
+#[cfg(any(feature = "serve", feature = "grpc"))]
+fn block_on<F: std::future::Future<Output = wordpiece_rs::Result<()>>>(server: F) -> CliResult<()> {
+    tokio::runtime::Runtime::new()?.block_on(server)?;
+    Ok(())
+}
+
+fn inspect(tokenizer: &WordPieceTokenizer) -> CliResult<()> {
+    let vocab = tokenizer.vocab();
+    let prefix = tokenizer.continuing_subword_prefix();
+    let is_continuation = |token: &str| !prefix.is_empty() && token.starts_with(prefix);
+    let len = |token: &str| token.strip_prefix(prefix).filter(|_| is_continuation(token)).unwrap_or(token).chars().count();
+
+    // Statistics of the pieces WordPiece can produce, without special and added tokens
+    let pieces: Vec<&str> = vocab
+        .keys()
+        .filter(|&token| !tokenizer.special_tokens().contains_key(token.as_str()) && !tokenizer.added_tokens().contains_key(token))
+        .map(String::as_str)
+        .collect();
+    let continuations = pieces.iter().filter(|token| is_continuation(token)).count();
+    let total_len: usize = pieces.iter().map(|token| len(token)).sum();
+    let longest = pieces.iter().max_by_key(|token| (len(token), std::cmp::Reverse(**token)));
+    // The vocabulary holds at least the unknown token
+    let max_id = vocab.values().copied().max().unwrap_or_default();
+
+    let mut special: Vec<(&Arc<str>, &TokenId)> = tokenizer.special_tokens().iter().collect();
+    special.sort_by_key(|&(_, id)| *id);
+    let special: Vec<String> = special.iter().map(|(token, id)| format!("{}={}", token, id)).collect();
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "tokens:              {}", vocab.len())?;
+    writeln!(out, "largest id:          {}", max_id)?;
+    writeln!(out, "unused ids:          {}", (max_id as usize + 1).saturating_sub(vocab.len()))?;
+    writeln!(out, "special tokens:      {}", special.join(" "))?;
+    writeln!(out, "added tokens:        {}", tokenizer.added_tokens().len())?;
+    writeln!(out, "word start pieces:   {}", pieces.len() - continuations)?;
+    writeln!(out, "continuation pieces: {} (prefix '{}')", continuations, prefix)?;
+    if let Some(longest) = longest {
+        writeln!(out, "longest piece:       {} ({} chars)", longest, len(longest))?;
+        writeln!(out, "mean piece length:   {:.2} chars", total_len as f64 / pieces.len() as f64)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,101 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::{Encoding, Error, Result, TokenId, WordPieceTokenizer};
+
+/// Label of the positions that were not selected for prediction, ignored by
+/// PyTorch's cross entropy loss
+pub const IGNORE_LABEL: i64 = -100;
+
+/// Draws used to find a random non-special token before giving up
+const RANDOM_TOKEN_ATTEMPTS: usize = 32;
+
+impl WordPieceTokenizer {
+    /// Mask `encoding` for masked language modeling the way BERT does. Each token,
+    /// or with `whole_word` each word, is selected with probability `mask_prob`.
+    /// Selected tokens are replaced by the mask token 80% of the time, by a random
+    /// token 10% of the time, and left unchanged otherwise. Returns the masked ids
+    /// and the labels: the original ids at selected positions and `IGNORE_LABEL`
+    /// elsewhere. Special tokens are never selected, and the same `seed` always
+    /// masks the same way.
+    pub fn mlm_mask(
+        &self,
+        encoding: &Encoding,
+        mask_prob: f64,
+        whole_word: bool,
+        seed: Option<u64>,
+    ) -> Result<(Vec<TokenId>, Vec<i64>)> {
+        let mask_id = self.mask_token_id().ok_or(Error::NoMaskToken)?;
+        if !(0.0..=1.0).contains(&mask_prob) {
+            return Err(Error::InvalidMaskProbability(mask_prob));
+        }
+        let mut rng = SplitMix64(seed.unwrap_or_else(|| RandomState::new().build_hasher().finish()));
+
+        let ids = encoding.ids();
+        let mut input_ids = ids.to_vec();
+        let mut labels = vec![IGNORE_LABEL; ids.len()];
+        let mut previous_word = None;
+        let mut selected = false;
+
+        for (i, (&id, &word_id)) in ids.iter().zip(encoding.word_ids()).enumerate() {
+            if word_id.is_none() || self.is_special_id(id) {
+                continue;
+            }
+            // Pieces of a word share one draw with whole_word
+            if !whole_word || word_id != previous_word {
+                selected = rng.next_f64() < mask_prob;
+                previous_word = word_id;
+            }
+            if !selected {
+                continue;
+            }
+
+            labels[i] = i64::from(id);
+            let roll = rng.next_f64();
+            if roll < 0.8 {
+                input_ids[i] = mask_id;
+            } else if roll < 0.9 {
+                input_ids[i] = self.random_token_id(&mut rng).unwrap_or(id);
+            }
+        }
+
+        Ok((input_ids, labels))
+    }
+
+    fn is_special_id(&self, id: TokenId) -> bool {
+        self.vocab_lookup
+            .get(&id)
+            .is_some_and(|token| self.special_tokens.contains_key(token))
+    }
+
+    /// A random non-special token id, if one turns up in a few draws
+    fn random_token_id(&self, rng: &mut SplitMix64) -> Option<TokenId> {
+        (0..RANDOM_TOKEN_ATTEMPTS)
+            .map(|_| rng.below(self.vocab.len()) as TokenId)
+            .find(|&id| self.vocab_lookup.contains_key(&id) && !self.is_special_id(id))
+    }
+}
+
+/// Small, fast generator so masking is reproducible from a seed across
+/// platforms and versions
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, n), n must be positive
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::double_array::DoubleArray;
+#[cfg(feature = "fst")]
+use crate::fst_vocab::FstVocab;
+use crate::trie::Trie;
+use crate::TokenId;
+
+/// Data structure used to segment words into WordPiece pieces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VocabBackend {
+    /// Flat trie with LinMaxMatch failure links, quick to build
+    #[default]
+    Trie,
+    /// Double-array trie over bytes with LinMaxMatch failure links, the fastest
+    /// to match but slower to build
+    DoubleArray,
+    /// Minimized finite state transducer, for memory-constrained deployments
+    #[cfg(feature = "fst")]
+    Fst,
+}
+
+/// A built vocabulary backend
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) enum Matcher {
+    Trie(Trie),
+    DoubleArray(DoubleArray),
+    #[cfg(feature = "fst")]
+    Fst(FstVocab),
+}
+
+impl Matcher {
+    /// Build `backend` from a vocabulary whose continuation tokens start with `continuation_prefix`
+    pub(crate) fn new(vocab: &HashMap<String, TokenId>, backend: VocabBackend, continuation_prefix: &str) -> Self {
+        match backend {
+            VocabBackend::Trie => Matcher::Trie(Trie::new(vocab, continuation_prefix)),
+            VocabBackend::DoubleArray => Matcher::DoubleArray(DoubleArray::new(vocab, continuation_prefix)),
+            #[cfg(feature = "fst")]
+            VocabBackend::Fst => Matcher::Fst(FstVocab::new(vocab, continuation_prefix)),
+        }
+    }
+
+    pub(crate) fn backend(&self) -> VocabBackend {
+        match self {
+            Matcher::Trie(_) => VocabBackend::Trie,
+            Matcher::DoubleArray(_) => VocabBackend::DoubleArray,
+            #[cfg(feature = "fst")]
+            Matcher::Fst(_) => VocabBackend::Fst,
+        }
+    }
+
+    /// Stop matching a token
+    pub(crate) fn remove(&mut self, token: &str) {
+        match self {
+            Matcher::Trie(trie) => trie.remove(token),
+            Matcher::DoubleArray(array) => array.remove(token),
+            #[cfg(feature = "fst")]
+            Matcher::Fst(fst) => fst.remove(token),
+        }
+    }
+
+    /// Build the structures that are otherwise built on the first word
+    /// segmented, see `Trie::precompile`
+    pub(crate) fn precompile(&self) {
+        match self {
+            Matcher::Trie(trie) => trie.precompile(),
+            Matcher::DoubleArray(array) => array.precompile(),
+            #[cfg(feature = "fst")]
+            Matcher::Fst(_) => {}
+        }
+    }
+
+    /// Estimated bytes of memory held by the backend
+    pub(crate) fn memory_usage(&self) -> usize {
+        match self {
+            Matcher::Trie(trie) => trie.memory_usage(),
+            Matcher::DoubleArray(array) => array.memory_usage(),
+            #[cfg(feature = "fst")]
+            Matcher::Fst(fst) => fst.memory_usage(),
+        }
+    }
+
+    /// Check a loaded backend for corruption, see `Trie::is_consistent`
+    pub(crate) fn is_consistent<F: Fn(TokenId) -> bool>(&self, is_known_id: F) -> bool {
+        match self {
+            Matcher::Trie(trie) => trie.is_consistent(is_known_id),
+            Matcher::DoubleArray(array) => array.is_consistent(is_known_id),
+            #[cfg(feature = "fst")]
+            Matcher::Fst(fst) => fst.is_consistent(is_known_id),
+        }
+    }
+
+    /// Segment a word into token ids, appending them to `ids`.
+    /// Returns false if the word cannot be segmented.
+    pub(crate) fn tokenize_word(&self, word: &str, ids: &mut Vec<TokenId>) -> bool {
+        match self {
+            Matcher::Trie(trie) => trie.tokenize_word(word, ids),
+            Matcher::DoubleArray(array) => array.tokenize_word(word, ids),
+            #[cfg(feature = "fst")]
+            Matcher::Fst(fst) => fst.tokenize_word(word, ids),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+
+use crate::WordPieceTokenizer;
+
+/// Estimated bytes of memory held by the parts of a tokenizer, for capacity
+/// planning. Parts shared by clones of a tokenizer are counted in full for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Map from tokens to ids
+    pub vocab: usize,
+    /// Map from ids to tokens, used to decode
+    pub vocab_lookup: usize,
+    /// Trie or transducer segmenting words, see `VocabBackend`
+    pub backend: usize,
+    /// Words and pieces in the word cache
+    pub word_cache: usize,
+    /// Special and added tokens, and the words never split
+    pub added_tokens: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.vocab + self.vocab_lookup + self.backend + self.word_cache + self.added_tokens
+    }
+}
+
+/// Bytes allocated by the table of a hash map or set with room for `capacity`
+/// entries of `entry` bytes: buckets are a power of two at most 7/8 full, each
+/// with a control byte
+pub(crate) fn table_size(capacity: usize, entry: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    let buckets = match capacity {
+        0..4 => 4,
+        4..8 => 8,
+        _ => (capacity * 8 / 7).next_power_of_two(),
+    };
+    buckets * (entry + 1) + 16
+}
+
+pub(crate) fn hash_map_size<K, V>(map: &HashMap<K, V>) -> usize {
+    table_size(map.capacity(), size_of::<(K, V)>())
+}
+
+/// `hash_map_size` of a map whose keys are strings, with the strings
+pub(crate) fn string_map_size<V>(map: &HashMap<String, V>) -> usize {
+    hash_map_size(map) + map.keys().map(String::capacity).sum::<usize>()
+}
+
+pub(crate) fn string_set_size(set: &HashSet<String>) -> usize {
+    table_size(set.capacity(), size_of::<String>()) + set.iter().map(String::capacity).sum::<usize>()
+}
+
+impl WordPieceTokenizer {
+    /// Estimated bytes of memory held by the vocabulary, the backend and the
+    /// word cache, counting the heap allocations of each part
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            vocab: string_map_size(&self.vocab),
+            vocab_lookup: hash_map_size(&self.vocab_lookup)
+                + self.vocab_lookup.values().map(|token| token.len()).sum::<usize>(),
+            backend: self.matcher.memory_usage(),
+            word_cache: self.word_cache.memory_usage(),
+            added_tokens: hash_map_size(&self.special_tokens)
+                + string_map_size(&self.added_tokens)
+                + string_set_size(&self.added_only_tokens)
+                + string_set_size(&self.never_split),
+        }
+    }
+}
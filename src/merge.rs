@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use crate::TokenId;
+
+/// Which vocabulary keeps its ids in `merge_vocabs`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Tokens of the first vocabulary keep their ids and new tokens of the second
+    /// are appended after them
+    #[default]
+    PreferFirst,
+    /// Tokens of the second vocabulary keep their ids and new tokens of the first
+    /// are appended after them
+    PreferSecond,
+}
+
+/// The union of two vocabularies, see `merge_vocabs`
+#[derive(Debug, Clone)]
+pub struct MergedVocab {
+    pub vocab: HashMap<String, TokenId>,
+    /// Old -> new ids of the vocabulary that did not keep its ids
+    pub id_map: HashMap<TokenId, TokenId>,
+    /// Number of tokens in both vocabularies
+    pub overlap: usize,
+    /// Number of tokens only in the vocabulary that did not keep its ids
+    pub added: usize,
+    /// Number of tokens of the vocabulary that did not keep its ids whose id changed
+    pub remapped: usize,
+}
+
+/// Union two WordPiece vocabularies, e.g. a general and a domain one. The
+/// vocabulary preferred by `strategy` keeps its ids, shared tokens take its ids,
+/// and the other tokens are appended after its largest id in their original order.
+pub fn merge_vocabs(
+    first: &HashMap<String, TokenId>,
+    second: &HashMap<String, TokenId>,
+    strategy: MergeStrategy,
+) -> MergedVocab {
+    let (base, other) = match strategy {
+        MergeStrategy::PreferFirst => (first, second),
+        MergeStrategy::PreferSecond => (second, first),
+    };
+
+    let mut vocab = base.clone();
+    let mut next_id = base.values().max().map_or(0, |&max| max + 1);
+    let mut merged = MergedVocab {
+        vocab: HashMap::new(),
+        id_map: HashMap::with_capacity(other.len()),
+        overlap: 0,
+        added: 0,
+        remapped: 0,
+    };
+
+    let mut other_tokens: Vec<(&String, &TokenId)> = other.iter().collect();
+    other_tokens.sort_by_key(|&(token, &id)| (id, token));
+    for (token, &old_id) in other_tokens {
+        let new_id = match base.get(token) {
+            Some(&id) => {
+                merged.overlap += 1;
+                id
+            }
+            None => {
+                let id = next_id;
+                next_id += 1;
+                vocab.insert(token.clone(), id);
+                merged.added += 1;
+                id
+            }
+        };
+        if new_id != old_id {
+            merged.remapped += 1;
+        }
+        merged.id_map.insert(old_id, new_id);
+    }
+
+    merged.vocab = vocab;
+    merged
+}
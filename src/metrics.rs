@@ -0,0 +1,152 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use crate::{CacheStats, TokenId};
+
+/// Counters of the documents a tokenizer encoded since it was created, for
+/// monitoring tokenization services
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    /// Number of documents encoded
+    pub documents: u64,
+    /// Number of bytes of text encoded
+    pub bytes: u64,
+    /// Number of tokens produced
+    pub tokens: u64,
+    /// Number of tokens that are the unknown token
+    pub unknown_tokens: u64,
+    /// Time spent encoding, summed over all threads
+    pub busy: Duration,
+    /// Time since the counters started
+    pub elapsed: Duration,
+    pub cache: CacheStats,
+}
+
+impl Metrics {
+    /// Documents encoded per second since the counters started
+    pub fn documents_per_second(&self) -> f64 {
+        per_second(self.documents, self.elapsed)
+    }
+
+    /// Tokens produced per second since the counters started
+    pub fn tokens_per_second(&self) -> f64 {
+        per_second(self.tokens, self.elapsed)
+    }
+
+    /// Fraction of tokens that are the unknown token, 0 before any token
+    pub fn unk_rate(&self) -> f64 {
+        if self.tokens == 0 {
+            0.0
+        } else {
+            self.unknown_tokens as f64 / self.tokens as f64
+        }
+    }
+
+    /// The counters in the Prometheus text exposition format, with metric names
+    /// starting with `prefix`, e.g. `wordpiece`. Rates are left to queries
+    /// such as `rate(wordpiece_tokens_total[1m])`.
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let metrics: [(&str, &str, &str, f64); 9] = [
+            ("documents_total", "counter", "Documents encoded", self.documents as f64),
+            ("bytes_total", "counter", "Bytes of text encoded", self.bytes as f64),
+            ("tokens_total", "counter", "Tokens produced", self.tokens as f64),
+            ("unknown_tokens_total", "counter", "Unknown tokens produced", self.unknown_tokens as f64),
+            ("busy_seconds_total", "counter", "Time spent encoding, summed over threads", self.busy.as_secs_f64()),
+            ("cache_hits_total", "counter", "Word cache hits", self.cache.hits as f64),
+            ("cache_misses_total", "counter", "Word cache misses", self.cache.misses as f64),
+            ("cache_size", "gauge", "Words in the word cache", self.cache.size as f64),
+            ("cache_capacity", "gauge", "Capacity of the word cache", self.cache.capacity as f64),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP {}_{} {}", prefix, name, help);
+            let _ = writeln!(text, "# TYPE {}_{} {}", prefix, name, kind);
+            let _ = writeln!(text, "{}_{} {}", prefix, name, value);
+        }
+        text
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
+
+/// Measures time where the platform has a clock. Browsers have none through
+/// `std`, so times stay at zero in WebAssembly.
+#[derive(Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    started: Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            started: Instant::now(),
+        }
+    }
+
+    fn elapsed(self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.started.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        Duration::ZERO
+    }
+}
+
+/// Counters updated as documents are encoded, shared by clones of a tokenizer
+/// like the word cache
+pub(crate) struct MetricsRecorder {
+    started: Stopwatch,
+    documents: AtomicU64,
+    bytes: AtomicU64,
+    tokens: AtomicU64,
+    unknown_tokens: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+impl Default for MetricsRecorder {
+    fn default() -> Self {
+        MetricsRecorder {
+            started: Stopwatch::start(),
+            documents: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            tokens: AtomicU64::new(0),
+            unknown_tokens: AtomicU64::new(0),
+            busy_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl MetricsRecorder {
+    /// Count a document of `bytes` encoded into `ids` since `start`
+    pub(crate) fn record(&self, start: Stopwatch, bytes: usize, ids: &[TokenId], unk_token_id: TokenId) {
+        let unknown = ids.iter().filter(|&&id| id == unk_token_id).count();
+        self.documents.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.tokens.fetch_add(ids.len() as u64, Ordering::Relaxed);
+        self.unknown_tokens.fetch_add(unknown as u64, Ordering::Relaxed);
+        self.busy_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn metrics(&self, cache: CacheStats) -> Metrics {
+        Metrics {
+            documents: self.documents.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            tokens: self.tokens.load(Ordering::Relaxed),
+            unknown_tokens: self.unknown_tokens.load(Ordering::Relaxed),
+            busy: Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed)),
+            elapsed: self.started.elapsed(),
+            cache,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::decompose_canonical;
+
+#[cfg(feature = "lindera")]
+use crate::Error;
+use crate::Result;
+
+/// Language-specific analysis of normalized text before it is split into
+/// words, for languages whose word boundaries BERT's rules do not find
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Morphology {
+    /// Decompose Hangul syllables into their conjoining jamo, so that Korean
+    /// vocabularies can hold pieces smaller than a syllable
+    HangulJamo,
+    /// Split words with the Lindera morphological analyzer and a compiled
+    /// dictionary, such as IPADIC or UniDic for Japanese or ko-dic for Korean,
+    /// given as a directory or a URI Lindera understands
+    #[cfg(feature = "lindera")]
+    Lindera { dictionary: String },
+}
+
+/// A morphological analyzer, loaded from its `Morphology`
+#[derive(Clone)]
+pub(crate) enum Analyzer {
+    HangulJamo,
+    #[cfg(feature = "lindera")]
+    Lindera {
+        dictionary: String,
+        segmenter: std::sync::Arc<lindera::segmenter::Segmenter>,
+    },
+}
+
+impl Analyzer {
+    pub(crate) fn new(morphology: &Morphology) -> Result<Self> {
+        match morphology {
+            Morphology::HangulJamo => Ok(Analyzer::HangulJamo),
+            #[cfg(feature = "lindera")]
+            Morphology::Lindera { dictionary } => {
+                let loaded = lindera::dictionary::load_dictionary(dictionary)
+                    .map_err(|err| Error::Morphology(format!("{}: {}", dictionary, err)))?;
+                Ok(Analyzer::Lindera {
+                    dictionary: dictionary.clone(),
+                    segmenter: std::sync::Arc::new(lindera::segmenter::Segmenter::new(lindera::mode::Mode::Normal, loaded, None)),
+                })
+            }
+        }
+    }
+
+    pub(crate) fn morphology(&self) -> Morphology {
+        match self {
+            Analyzer::HangulJamo => Morphology::HangulJamo,
+            #[cfg(feature = "lindera")]
+            Analyzer::Lindera { dictionary, .. } => Morphology::Lindera {
+                dictionary: dictionary.clone(),
+            },
+        }
+    }
+
+    /// Pass each character of the analyzed text to `f`, with the byte offset in
+    /// `text` of the character it was produced from. Words found inside runs
+    /// of text without spaces are separated by inserting a space.
+    pub(crate) fn for_each<F: FnMut(char, usize)>(&self, text: &str, mut f: F) {
+        match self {
+            Analyzer::HangulJamo => {
+                for (i, c) in text.char_indices() {
+                    match c {
+                        '\u{AC00}'..='\u{D7A3}' => decompose_canonical(c, |jamo| f(jamo, i)),
+                        _ => f(c, i),
+                    }
+                }
+            }
+            #[cfg(feature = "lindera")]
+            Analyzer::Lindera { segmenter, .. } => {
+                let starts: Vec<usize> = segmenter
+                    .segment(std::borrow::Cow::Borrowed(text))
+                    .map(|tokens| tokens.iter().map(|token| token.byte_start).collect())
+                    .unwrap_or_default();
+                let mut starts = starts.into_iter().peekable();
+                let mut previous = ' ';
+                for (i, c) in text.char_indices() {
+                    while starts.next_if(|&start| start < i).is_some() {}
+                    if starts.next_if_eq(&i).is_some() && !previous.is_whitespace() && !c.is_whitespace() {
+                        f(' ', i);
+                    }
+                    f(c, i);
+                    previous = c;
+                }
+            }
+        }
+    }
+
+    /// `text` with the analysis applied
+    pub(crate) fn apply(&self, text: &str) -> String {
+        let mut analyzed = String::with_capacity(text.len());
+        self.for_each(text, |c, _| analyzed.push(c));
+        analyzed
+    }
+}
@@ -0,0 +1,201 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::{Error, Result, TokenId, WordPieceTokenizer};
+
+/// Picks the vocabulary a whole document is encoded with, by index
+pub type DocumentRouter = dyn Fn(&str) -> usize + Send + Sync;
+
+/// Splits a document into byte ranges, each encoded with the vocabulary of the
+/// given index. Text between the ranges goes to the first vocabulary.
+pub type SpanRouter = dyn Fn(&str) -> Vec<(Range<usize>, usize)> + Send + Sync;
+
+/// How `MultiTokenizer::encode` picks vocabularies
+#[derive(Clone)]
+pub enum Routing {
+    /// Every text goes to the first vocabulary
+    First,
+    /// Each text goes whole to one vocabulary
+    Document(Arc<DocumentRouter>),
+    /// Parts of a text go to different vocabularies
+    Spans(Arc<SpanRouter>),
+}
+
+/// One vocabulary of a `MultiTokenizer` and where its ids start
+#[derive(Clone)]
+struct Domain {
+    name: String,
+    tokenizer: WordPieceTokenizer,
+    ids: Range<TokenId>,
+}
+
+/// Several tokenizers, e.g. a general, a code and a biomedical one, behind one
+/// disjoint id space for mixture-of-domain models. Each vocabulary's ids are
+/// shifted past those of the vocabularies before it, so an id tells which
+/// vocabulary produced it.
+#[derive(Clone)]
+pub struct MultiTokenizer {
+    domains: Vec<Domain>,
+    routing: Routing,
+}
+
+impl MultiTokenizer {
+    /// Combine named tokenizers in order, routing every text to the first until
+    /// `with_routing` says otherwise
+    pub fn new(tokenizers: Vec<(String, WordPieceTokenizer)>) -> Result<Self> {
+        if tokenizers.is_empty() {
+            return Err(Error::InvalidConfig("a multi-vocabulary tokenizer needs at least one tokenizer".to_string()));
+        }
+
+        let mut domains: Vec<Domain> = Vec::with_capacity(tokenizers.len());
+        let mut start: TokenId = 0;
+        for (name, tokenizer) in tokenizers {
+            if domains.iter().any(|domain| domain.name == name) {
+                return Err(Error::InvalidConfig(format!("two tokenizers are named '{}'", name)));
+            }
+            // Ids missing from a vocabulary keep their place in its range
+            let size = tokenizer.vocab().values().max().map_or(0, |&max| max + 1);
+            let end = start
+                .checked_add(size)
+                .ok_or_else(|| Error::InvalidConfig("the vocabularies have too many ids together".to_string()))?;
+            domains.push(Domain { name, tokenizer, ids: start..end });
+            start = end;
+        }
+
+        Ok(MultiTokenizer { domains, routing: Routing::First })
+    }
+
+    /// Pick vocabularies for `encode` with `routing`
+    pub fn with_routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Names of the vocabularies in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.domains.iter().map(|domain| domain.name.as_str())
+    }
+
+    /// Index of the vocabulary named `name`
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.domains.iter().position(|domain| domain.name == name)
+    }
+
+    /// The tokenizer of the vocabulary at `index`
+    pub fn tokenizer(&self, index: usize) -> Option<&WordPieceTokenizer> {
+        self.domains.get(index).map(|domain| &domain.tokenizer)
+    }
+
+    /// Ids of the vocabulary at `index` in the shared id space
+    pub fn id_range(&self, index: usize) -> Option<Range<TokenId>> {
+        self.domains.get(index).map(|domain| domain.ids.clone())
+    }
+
+    /// Size of the shared id space, the number of embedding rows it needs
+    pub fn vocab_size(&self) -> usize {
+        self.domains.last().map_or(0, |domain| domain.ids.end as usize)
+    }
+
+    /// Index of the vocabulary an id belongs to and its id in that vocabulary
+    pub fn resolve(&self, id: TokenId) -> Option<(usize, TokenId)> {
+        let index = self.domains.partition_point(|domain| domain.ids.end <= id);
+        self.domains.get(index).map(|domain| (index, id - domain.ids.start))
+    }
+
+    /// The id of `token` in the vocabulary at `index`, in the shared id space
+    pub fn token_to_id(&self, index: usize, token: &str) -> Option<TokenId> {
+        let domain = self.domains.get(index)?;
+        domain.tokenizer.vocab().get(token).map(|&id| domain.ids.start + id)
+    }
+
+    /// The token with `id` in the shared id space, if any
+    pub fn id_to_token(&self, id: TokenId) -> Option<&str> {
+        let (index, id) = self.resolve(id)?;
+        self.domains[index].tokenizer.id_to_token(id)
+    }
+
+    /// Encode `text` with the vocabularies picked by the routing
+    pub fn encode(&self, text: &str) -> Result<Vec<TokenId>> {
+        match &self.routing {
+            Routing::First => self.encode_with(text, 0),
+            Routing::Document(route) => self.encode_with(text, route(text)),
+            Routing::Spans(route) => {
+                let mut spans = Vec::new();
+                let mut end = 0;
+                for (range, index) in route(text) {
+                    if range.start < end || text.get(range.clone()).is_none() {
+                        return Err(Error::InvalidRoute(format!(
+                            "span {:?} is out of order or not on character boundaries",
+                            range
+                        )));
+                    }
+                    if range.start > end {
+                        spans.push((&text[end..range.start], 0));
+                    }
+                    spans.push((&text[range.clone()], index));
+                    end = range.end;
+                }
+                if end < text.len() {
+                    spans.push((&text[end..], 0));
+                }
+                self.encode_spans(&spans)
+            }
+        }
+    }
+
+    /// Encode many texts in parallel, failing if any text fails
+    pub fn encode_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Result<Vec<Vec<TokenId>>> {
+        texts.par_iter().map(|text| self.encode(text.as_ref())).collect()
+    }
+
+    /// Encode `text` with the vocabulary at `index`, regardless of the routing
+    pub fn encode_with(&self, text: &str, index: usize) -> Result<Vec<TokenId>> {
+        let domain = self.domain(index)?;
+        let mut ids = domain.tokenizer.encode(text);
+        for id in &mut ids {
+            *id += domain.ids.start;
+        }
+        Ok(ids)
+    }
+
+    /// Encode texts that were already routed, each with the vocabulary at its index
+    pub fn encode_spans(&self, spans: &[(&str, usize)]) -> Result<Vec<TokenId>> {
+        let mut ids = Vec::new();
+        for &(text, index) in spans {
+            ids.extend(self.encode_with(text, index)?);
+        }
+        Ok(ids)
+    }
+
+    /// Join the tokens of `ids` back into text. Each run of ids from one
+    /// vocabulary is decoded by its tokenizer, runs are joined with a space, and
+    /// ids outside all vocabularies are skipped.
+    pub fn decode(&self, ids: &[TokenId], skip_special_tokens: bool, clean_up_tokenization_spaces: bool) -> String {
+        let mut runs: Vec<(usize, Vec<TokenId>)> = Vec::new();
+        for (index, id) in ids.iter().filter_map(|&id| self.resolve(id)) {
+            match runs.last_mut() {
+                Some((last, run)) if *last == index => run.push(id),
+                _ => runs.push((index, vec![id])),
+            }
+        }
+
+        let texts: Vec<String> = runs
+            .into_iter()
+            .map(|(index, run)| {
+                self.domains[index]
+                    .tokenizer
+                    .decode(&run, skip_special_tokens, clean_up_tokenization_spaces)
+            })
+            .filter(|text| !text.is_empty())
+            .collect();
+        texts.join(" ")
+    }
+
+    fn domain(&self, index: usize) -> Result<&Domain> {
+        self.domains.get(index).ok_or_else(|| {
+            Error::InvalidRoute(format!("there is no vocabulary {} of {}", index, self.domains.len()))
+        })
+    }
+}
@@ -0,0 +1,161 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::{PreToken, TokenId, WordPieceTokenizer};
+
+/// How `tokenize_nbest` ranks segmentations
+#[derive(Debug, Clone, Default)]
+pub enum SegmentationCost {
+    /// Fewer pieces first
+    #[default]
+    PieceCount,
+    /// Higher total score first, e.g. with log-probabilities of the pieces.
+    /// Pieces without a score are not used.
+    Scores(HashMap<TokenId, f64>),
+}
+
+/// One way to segment a text, see `WordPieceTokenizer::tokenize_nbest`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segmentation {
+    pub ids: Vec<TokenId>,
+    /// Number of pieces, or the negated total score with `SegmentationCost::Scores`;
+    /// lower is better
+    pub cost: f64,
+}
+
+/// A vocabulary piece found in a word, an edge of its segmentation lattice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatticeEdge {
+    /// Byte offsets of the piece in the word, without the continuation prefix
+    pub start: usize,
+    pub end: usize,
+    pub id: TokenId,
+}
+
+/// Every vocabulary piece of a word at every position it fits, from which each
+/// segmentation is a path from the start of the word to its end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordLattice {
+    /// The normalized word followed by the end-of-word suffix, if any, or the
+    /// whole added or special token
+    pub word: String,
+    pub edges: Vec<LatticeEdge>,
+}
+
+impl WordPieceTokenizer {
+    /// The `n` best segmentations of `text` ranked by `cost`, best first. The
+    /// words are split as by `encode`, and each is segmented with any pieces of
+    /// the vocabulary instead of the longest match first. A word that cannot
+    /// be segmented at all stays the unknown token in every segmentation.
+    pub fn tokenize_nbest(&self, text: &str, n: usize, cost: &SegmentationCost) -> Vec<Segmentation> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut best = vec![Segmentation { ids: Vec::new(), cost: 0.0 }];
+
+        for lattice in self.segmentation_lattice(text) {
+            let alternatives = self.word_nbest(&lattice, n, cost);
+            let mut combined: Vec<Segmentation> = best
+                .iter()
+                .flat_map(|before| {
+                    alternatives.iter().map(move |word| Segmentation {
+                        ids: [before.ids.as_slice(), word.ids.as_slice()].concat(),
+                        cost: before.cost + word.cost,
+                    })
+                })
+                .collect();
+            combined.sort_by(|a, b| compare_cost(a.cost, b.cost));
+            combined.truncate(n);
+            best = combined;
+        }
+
+        best
+    }
+
+    /// The segmentation lattice of each word of `text`, in order. Added and
+    /// special tokens are a single edge covering the whole token.
+    pub fn segmentation_lattice(&self, text: &str) -> Vec<WordLattice> {
+        let mut lattices = Vec::new();
+        self.pre_tokenize(text, &mut |pre_token| {
+            lattices.push(match pre_token {
+                PreToken::Word(word) => self.word_lattice(word),
+                PreToken::Matched { text, id, .. } => WordLattice {
+                    word: text.to_string(),
+                    edges: vec![LatticeEdge { start: 0, end: text.len(), id }],
+                },
+            })
+        });
+        lattices
+    }
+
+    fn word_lattice(&self, word: &str) -> WordLattice {
+        // The end-of-word suffix is matched with the word, as by WordPiece
+        let full = format!("{}{}", word, self.end_of_word_suffix);
+        let mut edges = Vec::new();
+        if !self.is_word_too_long(word) {
+            let bounds: Vec<usize> = full.char_indices().map(|(i, _)| i).chain([full.len()]).collect();
+            let mut piece = String::new();
+            for (k, &start) in bounds.iter().enumerate() {
+                for &end in &bounds[k + 1..] {
+                    piece.clear();
+                    if start > 0 {
+                        piece.push_str(&self.continuing_subword_prefix);
+                    }
+                    piece.push_str(&full[start..end]);
+                    if let Some(&id) = self.vocab.get(&piece) {
+                        edges.push(LatticeEdge { start, end, id });
+                    }
+                }
+            }
+        }
+        WordLattice { word: full, edges }
+    }
+
+    /// The `n` cheapest paths through the lattice of a word, or the unknown token
+    fn word_nbest(&self, lattice: &WordLattice, n: usize, cost: &SegmentationCost) -> Vec<Segmentation> {
+        let end = lattice.word.len();
+        // Best paths reaching each byte offset of the word
+        let mut paths: Vec<Vec<Segmentation>> = vec![Vec::new(); end + 1];
+        paths[0].push(Segmentation { ids: Vec::new(), cost: 0.0 });
+
+        // Every path reaching an offset is known before the edges leaving it
+        let mut edges = lattice.edges.clone();
+        edges.sort_by_key(|edge| (edge.start, edge.end));
+        for edge in edges {
+            let edge_cost = match cost {
+                SegmentationCost::PieceCount => 1.0,
+                SegmentationCost::Scores(scores) => match scores.get(&edge.id) {
+                    Some(score) => -score,
+                    None => continue,
+                },
+            };
+            let extended: Vec<Segmentation> = paths[edge.start]
+                .iter()
+                .map(|path| {
+                    let mut ids = path.ids.clone();
+                    ids.push(edge.id);
+                    Segmentation { ids, cost: path.cost + edge_cost }
+                })
+                .collect();
+            let reached = &mut paths[edge.end];
+            reached.extend(extended);
+            reached.sort_by(|a, b| compare_cost(a.cost, b.cost));
+            reached.truncate(n);
+        }
+
+        match paths.pop().filter(|paths| !paths.is_empty() && end > 0) {
+            Some(paths) => paths,
+            None => {
+                let unk_cost = match cost {
+                    SegmentationCost::PieceCount => 1.0,
+                    SegmentationCost::Scores(scores) => -scores.get(&self.unk_token_id).copied().unwrap_or_default(),
+                };
+                vec![Segmentation { ids: vec![self.unk_token_id], cost: unk_cost }]
+            }
+        }
+    }
+}
+
+fn compare_cost(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
@@ -0,0 +1,156 @@
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{load_vocab, TokenId, WordPieceTokenizer};
+
+impl From<crate::Error> for napi::Error {
+    fn from(err: crate::Error) -> napi::Error {
+        napi::Error::from_reason(err.to_string())
+    }
+}
+
+/// Options of the tokenizer constructors, all optional
+#[napi(object)]
+pub struct TokenizerOptions {
+    /// `[UNK]` by default
+    pub unk_token: Option<String>,
+    /// `true` by default
+    pub lowercase: Option<bool>,
+    /// `true` by default
+    pub strip_accents: Option<bool>,
+    /// 200 by default
+    pub max_input_chars_per_word: Option<u32>,
+}
+
+/// The tokenizer as a JavaScript class, for Node.js serving layers
+#[napi(js_name = "WordPieceTokenizer")]
+pub struct NodeWordPieceTokenizer {
+    tokenizer: Arc<WordPieceTokenizer>,
+}
+
+#[napi]
+impl NodeWordPieceTokenizer {
+    /// Build a tokenizer from an object mapping tokens to ids
+    #[napi(constructor)]
+    pub fn new(vocab: HashMap<String, TokenId>, options: Option<TokenizerOptions>) -> Result<Self> {
+        let options = options.unwrap_or(TokenizerOptions {
+            unk_token: None,
+            lowercase: None,
+            strip_accents: None,
+            max_input_chars_per_word: None,
+        });
+        let mut builder = WordPieceTokenizer::builder().vocab(vocab);
+        if let Some(unk_token) = &options.unk_token {
+            builder = builder.unk_token(unk_token);
+        }
+        if let Some(lowercase) = options.lowercase {
+            builder = builder.lowercase(lowercase);
+        }
+        if let Some(strip_accents) = options.strip_accents {
+            builder = builder.strip_accents(strip_accents);
+        }
+        if let Some(max) = options.max_input_chars_per_word {
+            builder = builder.max_input_chars_per_word(max as usize);
+        }
+        let tokenizer = builder.build()?;
+        Ok(NodeWordPieceTokenizer {
+            tokenizer: Arc::new(tokenizer),
+        })
+    }
+
+    /// Load a tokenizer from a `vocab.txt` file, one token per line with the
+    /// line number as its id
+    #[napi(factory)]
+    pub fn from_vocab_file(path: String, options: Option<TokenizerOptions>) -> Result<Self> {
+        Self::new(load_vocab(path)?, options)
+    }
+
+    /// Load a tokenizer written by `save_compiled` without rebuilding its trie
+    #[napi(factory)]
+    pub fn load_compiled(path: String) -> Result<Self> {
+        Ok(NodeWordPieceTokenizer {
+            tokenizer: Arc::new(WordPieceTokenizer::load_compiled(path)?),
+        })
+    }
+
+    #[napi]
+    pub fn tokenize(&self, text: String) -> Vec<String> {
+        self.tokenizer.tokenize(&text)
+    }
+
+    /// Encode `text` to token ids, throwing for a text over the tokenizer's
+    /// `max_input_bytes` or `max_tokens`
+    #[napi]
+    pub fn encode(&self, text: String) -> Result<Vec<TokenId>> {
+        Ok(self.tokenizer.try_encode(&text)?)
+    }
+
+    /// Encode many texts in parallel, blocking the event loop until done
+    #[napi]
+    pub fn encode_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<TokenId>>> {
+        Ok(self.tokenizer.try_encode_batch(&texts)?)
+    }
+
+    /// Encode many texts in parallel on the libuv thread pool, resolving to
+    /// their ids without blocking the event loop, or rejecting as `encode`
+    /// throws
+    #[napi(ts_return_type = "Promise<number[][]>")]
+    pub fn encode_batch_async(&self, texts: Vec<String>) -> AsyncTask<EncodeBatchTask> {
+        AsyncTask::new(EncodeBatchTask {
+            tokenizer: self.tokenizer.clone(),
+            texts,
+        })
+    }
+
+    /// Turn ids back into text. Special tokens are kept and the spaces before
+    /// punctuation removed unless told otherwise.
+    #[napi]
+    pub fn decode(
+        &self,
+        ids: Vec<TokenId>,
+        skip_special_tokens: Option<bool>,
+        clean_up_tokenization_spaces: Option<bool>,
+    ) -> String {
+        self.tokenizer.decode(
+            &ids,
+            skip_special_tokens.unwrap_or(false),
+            clean_up_tokenization_spaces.unwrap_or(true),
+        )
+    }
+
+    #[napi]
+    pub fn token_to_id(&self, token: String) -> Option<TokenId> {
+        self.tokenizer.vocab().get(&token).copied()
+    }
+
+    #[napi]
+    pub fn id_to_token(&self, id: TokenId) -> Option<String> {
+        self.tokenizer.id_to_token(id).map(str::to_string)
+    }
+
+    #[napi(getter)]
+    pub fn vocab_size(&self) -> u32 {
+        self.tokenizer.vocab().len() as u32
+    }
+}
+
+/// Background work of `encodeBatchAsync`
+pub struct EncodeBatchTask {
+    tokenizer: Arc<WordPieceTokenizer>,
+    texts: Vec<String>,
+}
+
+impl Task for EncodeBatchTask {
+    type Output = Vec<Vec<TokenId>>;
+    type JsValue = Vec<Vec<TokenId>>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        Ok(self.tokenizer.try_encode_batch(&self.texts)?)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
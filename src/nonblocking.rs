@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{Result, TokenId, WordPieceTokenizer};
+
+/// Handle to a tokenizer for async code, such as inference servers on Tokio.
+/// Work runs on the blocking thread pool, so the async workers keep serving
+/// other tasks while long documents are encoded. Clones share the tokenizer.
+#[derive(Clone)]
+pub struct AsyncTokenizer {
+    tokenizer: Arc<WordPieceTokenizer>,
+}
+
+impl AsyncTokenizer {
+    pub fn new(tokenizer: impl Into<Arc<WordPieceTokenizer>>) -> Self {
+        AsyncTokenizer {
+            tokenizer: tokenizer.into(),
+        }
+    }
+
+    /// The tokenizer, for the methods that are cheap enough to call directly
+    pub fn tokenizer(&self) -> &Arc<WordPieceTokenizer> {
+        &self.tokenizer
+    }
+
+    /// `try_encode` without blocking the async runtime
+    pub async fn encode(&self, text: impl Into<String>) -> Result<Vec<TokenId>> {
+        let text = text.into();
+        self.spawn(move |tokenizer| tokenizer.try_encode(&text)).await
+    }
+
+    /// `try_encode_batch` without blocking the async runtime, encoding the
+    /// texts in parallel
+    pub async fn encode_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<TokenId>>> {
+        self.spawn(move |tokenizer| tokenizer.try_encode_batch(&texts)).await
+    }
+
+    /// `decode` without blocking the async runtime
+    pub async fn decode(&self, ids: Vec<TokenId>, skip_special_tokens: bool, clean_up_tokenization_spaces: bool) -> String {
+        self.spawn(move |tokenizer| tokenizer.decode(&ids, skip_special_tokens, clean_up_tokenization_spaces))
+            .await
+    }
+
+    /// Encode each text of `texts` as it arrives, yielding the results in
+    /// order, e.g. to tokenize the lines of a request body while it streams in
+    pub fn encode_stream<S>(&self, texts: S) -> impl Stream<Item = Result<Vec<TokenId>>> + Send + 'static
+    where
+        S: Stream + Send + 'static,
+        S::Item: Into<String>,
+    {
+        let this = self.clone();
+        texts.then(move |text| {
+            let this = this.clone();
+            let text = text.into();
+            async move { this.encode(text).await }
+        })
+    }
+
+    /// Run `f` on the blocking thread pool, resuming its panic if it panics
+    async fn spawn<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&WordPieceTokenizer) -> T + Send + 'static,
+    {
+        let tokenizer = self.tokenizer.clone();
+        let task: JoinHandle<T> = tokio::task::spawn_blocking(move || f(&tokenizer));
+        match task.await {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+impl From<WordPieceTokenizer> for AsyncTokenizer {
+    fn from(tokenizer: WordPieceTokenizer) -> Self {
+        AsyncTokenizer::new(tokenizer)
+    }
+}
+
+impl From<Arc<WordPieceTokenizer>> for AsyncTokenizer {
+    fn from(tokenizer: Arc<WordPieceTokenizer>) -> Self {
+        AsyncTokenizer::new(tokenizer)
+    }
+}
@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Range;
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicase::UniCase;
+use unicode_normalization::char::{canonical_combining_class, decompose_compatible};
+use unicode_normalization::{
+    is_nfc_quick, is_nfd_quick, is_nfkc_quick, is_nfkd_quick, IsNormalized, UnicodeNormalization,
+};
+
+/// How text is normalized before it is split into words. Whitespace is always
+/// unified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Normalizer {
+    /// Unicode normalization form applied first, NFKC by default
+    #[serde(default)]
+    pub unicode_normalization: NormalizationForm,
+    pub lowercase: bool,
+    /// How text is lowercased when `lowercase` is set
+    #[serde(default)]
+    pub case_folding: CaseFolding,
+    /// Remove accents, e.g. for `café` to match `cafe`
+    pub strip_accents: bool,
+    /// Drop NUL, U+FFFD and control and format characters, as BERT's
+    /// `_clean_text` does
+    #[serde(default = "default_remove_control_chars")]
+    pub remove_control_chars: bool,
+    /// Put spaces around CJK ideographs, so that each becomes a word, as
+    /// BERT's `tokenize_chinese_chars` does
+    #[serde(default = "default_tokenize_chinese_chars")]
+    pub tokenize_chinese_chars: bool,
+    /// Keep grapheme clusters whole: emoji sequences, flags and skin tones
+    /// become words of their own and count as one character each
+    #[serde(default)]
+    pub grapheme_clusters: bool,
+}
+
+impl Normalizer {
+    /// Lowercase and strip accents, as uncased BERT models expect
+    pub fn uncased() -> Self {
+        Normalizer {
+            unicode_normalization: NormalizationForm::default(),
+            lowercase: true,
+            case_folding: CaseFolding::default(),
+            strip_accents: true,
+            remove_control_chars: true,
+            tokenize_chinese_chars: true,
+            grapheme_clusters: false,
+        }
+    }
+
+    /// Keep case and accents, as cased BERT models expect
+    pub fn cased() -> Self {
+        Normalizer {
+            unicode_normalization: NormalizationForm::default(),
+            lowercase: false,
+            case_folding: CaseFolding::default(),
+            strip_accents: false,
+            remove_control_chars: true,
+            tokenize_chinese_chars: true,
+            grapheme_clusters: false,
+        }
+    }
+}
+
+/// Unicode normalization form applied to text before it is split into words
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalizationForm {
+    /// Leave text as it is, as BERT's original `BasicTokenizer` does
+    None,
+    Nfc,
+    Nfd,
+    /// Compatibility composition, which also turns full-width characters and
+    /// ligatures such as `ﬁ` into their plain forms
+    #[default]
+    Nfkc,
+    Nfkd,
+}
+
+impl NormalizationForm {
+    /// Normalize `text`
+    pub fn apply(self, text: &str) -> String {
+        self.normalize(text).into_owned()
+    }
+
+    /// Normalize `text`, borrowing it when it is already normalized: when it is
+    /// ASCII, which every form leaves as it is and which is checked a word of
+    /// text at a time, or when the Unicode quick check says so
+    pub(crate) fn normalize(self, text: &str) -> Cow<'_, str> {
+        if self.is_normalized(text) {
+            return Cow::Borrowed(text);
+        }
+        let mut normalized = String::with_capacity(text.len());
+        self.for_each(text, |c| normalized.push(c));
+        Cow::Owned(normalized)
+    }
+
+    /// Whether `text` is known to be normalized already, as it is ASCII or
+    /// passes the Unicode quick check
+    pub(crate) fn is_normalized(self, text: &str) -> bool {
+        if text.is_ascii() {
+            return true;
+        }
+        let quick_check = match self {
+            NormalizationForm::None => IsNormalized::Yes,
+            NormalizationForm::Nfc => is_nfc_quick(text.chars()),
+            NormalizationForm::Nfd => is_nfd_quick(text.chars()),
+            NormalizationForm::Nfkc => is_nfkc_quick(text.chars()),
+            NormalizationForm::Nfkd => is_nfkd_quick(text.chars()),
+        };
+        quick_check == IsNormalized::Yes
+    }
+
+    /// Call `f` with each character of the normalized `text`
+    pub(crate) fn for_each<F: FnMut(char)>(self, text: &str, f: F) {
+        match self {
+            NormalizationForm::None => text.chars().for_each(f),
+            NormalizationForm::Nfc => text.nfc().for_each(f),
+            NormalizationForm::Nfd => text.nfd().for_each(f),
+            NormalizationForm::Nfkc => text.nfkc().for_each(f),
+            NormalizationForm::Nfkd => text.nfkd().for_each(f),
+        }
+    }
+}
+
+/// How text is lowercased
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaseFolding {
+    /// Unicode lowercase mapping, as BERT does. `İ` becomes `i` followed by a
+    /// combining dot, which accent stripping removes.
+    #[default]
+    Lowercase,
+    /// Full Unicode case folding, which also folds characters without a
+    /// lowercase form, e.g. `ß` to `ss` and `ﬁ` to `fi`
+    Fold,
+    /// Lowercasing with the Turkish and Azerbaijani dotted and dotless i:
+    /// `İ` becomes `i` and `I` becomes `ı`
+    Turkic,
+}
+
+impl CaseFolding {
+    /// Lowercase or fold `text`
+    pub fn apply(self, text: &str) -> String {
+        let mut folded = String::with_capacity(text.len());
+        self.push_str(text, &mut folded);
+        folded
+    }
+
+    /// Lowercase or fold `text`, appending it to `out`
+    pub(crate) fn push_str(self, text: &str, out: &mut String) {
+        if text.is_ascii() && (self != CaseFolding::Turkic || !text.contains('I')) {
+            out.extend(text.chars().map(|c| c.to_ascii_lowercase()));
+            return;
+        }
+        match self {
+            CaseFolding::Lowercase => out.push_str(&text.to_lowercase()),
+            CaseFolding::Fold => out.push_str(&UniCase::unicode(text).to_folded_case()),
+            CaseFolding::Turkic => {
+                let dotted: String = text
+                    .chars()
+                    .map(|c| match c {
+                        'I' => 'ı',
+                        'İ' => 'i',
+                        c => c,
+                    })
+                    .collect();
+                out.push_str(&dotted.to_lowercase());
+            }
+        }
+    }
+
+    /// Number of bytes `c` takes once lowercased or folded
+    pub(crate) fn len_utf8(self, c: char) -> usize {
+        match (self, c) {
+            (CaseFolding::Lowercase, c) => c.to_lowercase().map(char::len_utf8).sum(),
+            (CaseFolding::Turkic, 'I') => 'ı'.len_utf8(),
+            (CaseFolding::Turkic, 'İ') => 1,
+            (CaseFolding::Turkic, c) => c.to_lowercase().map(char::len_utf8).sum(),
+            (CaseFolding::Fold, c) => UniCase::unicode(c.encode_utf8(&mut [0; 4])).to_folded_case().len(),
+        }
+    }
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer::uncased()
+    }
+}
+
+/// Remove accents from `text` the way BERT does: decompose it (NFD) and drop
+/// the nonspacing marks (`Mn`), so `café` becomes `cafe`. Other characters,
+/// punctuation included, are kept.
+pub fn strip_accents(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.nfd().filter(|&c| !is_nonspacing_mark(c)).collect())
+}
+
+/// Split text into runs, with their byte offsets, that Unicode normalization
+/// transforms independently of each other: each starts at a character that
+/// neither combines with nor reorders around the characters before it. Runs
+/// safe for NFKC are safe for the other forms too.
+pub(crate) fn normalization_runs(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut boundaries = text
+        .char_indices()
+        .filter(|&(i, c)| i == 0 || starts_normalization_run(c))
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .peekable();
+
+    std::iter::from_fn(move || {
+        let start = boundaries.next()?;
+        let end = *boundaries.peek()?;
+        Some((start, &text[start..end]))
+    })
+}
+
+fn starts_normalization_run(c: char) -> bool {
+    let mut first = None;
+    decompose_compatible(c, |d| {
+        first.get_or_insert(d);
+    });
+    let first = first.unwrap_or(c);
+
+    canonical_combining_class(c) == 0
+        && canonical_combining_class(first) == 0
+        && is_nfkc_quick(std::iter::once(first)) == IsNormalized::Yes
+}
+
+/// Where `TextCleaner` writes cleaned text
+pub(crate) trait CleanedText {
+    /// Whether the ranges of the original text are kept. Characters are then
+    /// passed with the range of their whole normalization run.
+    const ALIGNED: bool;
+
+    /// Append `c`, produced from the byte range `span` of the original text
+    fn push(&mut self, c: char, span: &Range<usize>);
+
+    /// Append `text`, copied as it is from byte `start` of the original text
+    fn push_verbatim(&mut self, text: &str, start: usize);
+}
+
+impl CleanedText for String {
+    const ALIGNED: bool = false;
+
+    fn push(&mut self, c: char, _: &Range<usize>) {
+        String::push(self, c);
+    }
+
+    fn push_verbatim(&mut self, text: &str, _: usize) {
+        self.push_str(text);
+    }
+}
+
+/// BERT's `_clean_text` with Unicode normalization applied first: drops
+/// control characters, turns whitespace into spaces and puts spaces around
+/// CJK ideographs. Each character is normalized, cleaned and written out in a
+/// single pass over the text, one normalization run at a time, and runs of
+/// printable ASCII, which none of it changes, are copied whole.
+#[derive(Clone, Copy)]
+pub(crate) struct TextCleaner {
+    pub(crate) unicode_normalization: NormalizationForm,
+    pub(crate) remove_control_chars: bool,
+    pub(crate) tokenize_chinese_chars: bool,
+    pub(crate) grapheme_clusters: bool,
+}
+
+impl TextCleaner {
+    /// Clean `text`, which starts at byte `base` of the original text, into `out`
+    pub(crate) fn clean<T: CleanedText>(self, text: &str, base: usize, out: &mut T) {
+        // Text already normalized is cleaned a character at a time, unless the
+        // spans of normalization runs are kept. Other text is cleaned as the
+        // normalizer yields it when there are no spans to keep.
+        let by_char = !T::ALIGNED && self.unicode_normalization.is_normalized(text);
+        if !T::ALIGNED && !by_char {
+            let span = base..base + text.len();
+            return self.unicode_normalization.for_each(text, |c| self.clean_char(c, &span, out));
+        }
+
+        let mut start = 0;
+        while start < text.len() {
+            let rest = &text[start..];
+
+            // Otherwise the last character of a run of ASCII may combine with
+            // the character after it
+            let mut ascii = printable_ascii_len(rest.as_bytes());
+            if ascii < rest.len() && !by_char {
+                ascii = ascii.saturating_sub(1);
+            }
+            if ascii > 0 {
+                out.push_verbatim(&rest[..ascii], base + start);
+                start += ascii;
+                continue;
+            }
+
+            let run = match by_char {
+                true => &rest[..rest.chars().next().map_or(0, char::len_utf8)],
+                false => normalization_runs(rest).next().map_or(rest, |(_, run)| run),
+            };
+            let span = base + start..base + start + run.len();
+            if by_char || self.unicode_normalization.is_normalized(run) {
+                run.chars().for_each(|c| self.clean_char(c, &span, out));
+            } else {
+                self.unicode_normalization.for_each(run, |c| self.clean_char(c, &span, out));
+            }
+            start += run.len();
+        }
+    }
+
+    fn clean_char<T: CleanedText>(self, c: char, span: &Range<usize>, out: &mut T) {
+        if self.remove_control_chars && is_removed_control(c, self.grapheme_clusters) {
+            // Drop control characters
+        } else if c.is_whitespace() {
+            // Replace whitespace characters with space
+            out.push(' ', span);
+        } else if self.tokenize_chinese_chars && is_chinese_char(c) {
+            // Handle Chinese characters by adding spaces around them
+            out.push(' ', span);
+            out.push(c, span);
+            out.push(' ', span);
+        } else {
+            out.push(c, span);
+        }
+    }
+}
+
+/// Whether BERT's `_clean_text` drops `c`: NUL, the replacement character
+/// U+FFFD and any control, format, private use or unassigned character, except
+/// for tab, newline and carriage return, which are whitespace. With grapheme
+/// clusters, the zero-width joiner and tag characters that hold emoji
+/// sequences together are kept.
+pub(crate) fn is_removed_control(c: char, grapheme_clusters: bool) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => false,
+        '\u{200D}' | '\u{E0020}'..='\u{E007F}' if grapheme_clusters => false,
+        '\0' | '\u{FFFD}' => true,
+        _ if c.is_ascii() => c.is_ascii_control(),
+        _ => matches!(
+            get_general_category(c),
+            GeneralCategory::Control
+                | GeneralCategory::Format
+                | GeneralCategory::PrivateUse
+                | GeneralCategory::Unassigned
+        ),
+    }
+}
+
+/// Length of the run of printable ASCII `bytes` start with, which cleaning
+/// leaves as it is. Eight bytes are tested at a time with bitwise arithmetic
+/// on a `u64`, as `memchr` does without SIMD instructions: a byte ends the run
+/// if its high bit is set, if it is below a space or if it is DEL.
+fn printable_ascii_len(bytes: &[u8]) -> usize {
+    const ONES: u64 = u64::from_le_bytes([0x01; 8]);
+    const HIGH: u64 = u64::from_le_bytes([0x80; 8]);
+
+    let mut chunks = bytes.chunks_exact(8);
+    let mut len = 0;
+    for chunk in &mut chunks {
+        let x = u64::from_le_bytes(chunk.try_into().unwrap());
+        let del = x ^ (ONES * 0x7F);
+        // Borrows only carry upwards, so the lowest flagged byte is exact
+        let below_space = x.wrapping_sub(ONES * 0x20) & !x;
+        let is_del = del.wrapping_sub(ONES) & !del;
+        let flagged = (x | below_space | is_del) & HIGH;
+        if flagged != 0 {
+            return len + flagged.trailing_zeros() as usize / 8;
+        }
+        len += 8;
+    }
+    len + chunks.remainder().iter().take_while(|&&b| (b' '..0x7F).contains(&b)).count()
+}
+
+pub(crate) fn default_remove_control_chars() -> bool {
+    true
+}
+
+/// Whether `c` is a CJK ideograph as BERT's `_is_chinese_char` defines it: the
+/// CJK Unified Ideographs block, its extensions and the compatibility
+/// ideographs. Extension F, encoded after BERT was released, is included too.
+/// Hangul, hiragana and katakana are not, as in BERT.
+pub(crate) fn is_chinese_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x4E00..=0x9FFF
+            | 0x3400..=0x4DBF
+            | 0x20000..=0x2A6DF
+            | 0x2A700..=0x2B73F
+            | 0x2B740..=0x2B81F
+            | 0x2B820..=0x2CEAF
+            | 0x2CEB0..=0x2EBEF
+            | 0xF900..=0xFAFF
+            | 0x2F800..=0x2FA1F
+    )
+}
+
+pub(crate) fn default_tokenize_chinese_chars() -> bool {
+    true
+}
+
+/// Whether `c` is a nonspacing combining mark, such as U+0301 COMBINING ACUTE ACCENT
+pub(crate) fn is_nonspacing_mark(c: char) -> bool {
+    !c.is_ascii() && get_general_category(c) == GeneralCategory::NonspacingMark
+}
@@ -0,0 +1,176 @@
+use std::mem;
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::normalizer::{is_nonspacing_mark, normalization_runs, CleanedText};
+use crate::pre_tokenizer::Words;
+use crate::{PreToken, WordPieceTokenizer};
+
+/// A normalized string remembering, for each of its bytes, the byte range of
+/// the original text it was produced from
+#[derive(Default)]
+struct AlignedString {
+    text: String,
+    spans: Vec<Range<usize>>,
+}
+
+impl AlignedString {
+    fn clear(&mut self) {
+        self.text.clear();
+        self.spans.clear();
+    }
+
+    fn push(&mut self, c: char, span: &Range<usize>) {
+        self.text.push(c);
+        self.spans.extend((0..c.len_utf8()).map(|_| span.clone()));
+    }
+
+    /// Range of the original text the non-empty `range` of this string came from
+    fn span(&self, range: Range<usize>) -> Range<usize> {
+        covering(&self.spans[range])
+    }
+}
+
+impl CleanedText for AlignedString {
+    const ALIGNED: bool = true;
+
+    fn push(&mut self, c: char, span: &Range<usize>) {
+        AlignedString::push(self, c, span);
+    }
+
+    fn push_verbatim(&mut self, text: &str, start: usize) {
+        self.text.push_str(text);
+        self.spans.extend((start..start + text.len()).map(|i| i..i + 1));
+    }
+}
+
+impl WordPieceTokenizer {
+    /// `pre_tokenize_words`, also passing the byte range of the original text
+    /// each byte of a word was normalized from. Yields exactly the same words.
+    pub(crate) fn pre_tokenize_words_aligned<F: FnMut(PreToken<'_>, &[Range<usize>])>(
+        &self,
+        text: &str,
+        base: usize,
+        whole: bool,
+        f: &mut F,
+    ) {
+        let cleaned = self.clean_text_aligned(text, base);
+        let mut word = AlignedString::default();
+        let mut stripped = AlignedString::default();
+
+        let words = match whole {
+            true => Words::whole(&cleaned.text),
+            false => self.pre_tokenizer.words(&self.basic_tokenizer, &cleaned.text, self.grapheme_clusters),
+        };
+        for (offset, matched) in words {
+
+            // Handle casing. Lowercasing character by character takes as many
+            // bytes as lowercasing the whole word, final sigma included.
+            word.clear();
+            if self.lowercase {
+                self.case_folding.push_str(matched, &mut word.text);
+            } else {
+                word.text.push_str(matched);
+            }
+            for (i, c) in matched.char_indices() {
+                let span = cleaned.span(offset + i..offset + i + c.len_utf8());
+                let len = if self.lowercase { self.case_folding.len_utf8(c) } else { c.len_utf8() };
+                word.spans.extend((0..len).map(|_| span.clone()));
+            }
+
+            // Handle accents, the same way as `strip_accents_if_needed`
+            if self.strip_accents && !word.text.is_ascii() {
+                stripped.clear();
+                for (start, run) in normalization_runs(&word.text) {
+                    let span = word.span(start..start + run.len());
+                    for c in run.nfd().filter(|&c| !is_nonspacing_mark(c)) {
+                        stripped.push(c, &span);
+                    }
+                }
+                mem::swap(&mut word, &mut stripped);
+            }
+
+            // Entities kept whole are not split on punctuation
+            if whole {
+                if !word.text.is_empty() {
+                    f(PreToken::Word(&word.text), &word.spans);
+                }
+                continue;
+            }
+
+            // Split on punctuation, as the BERT pre-tokenizer does
+            self.pre_tokenizer.split_word(&word.text, self.grapheme_clusters, |range| {
+                f(PreToken::Word(&word.text[range.clone()]), &word.spans[range])
+            });
+        }
+    }
+
+    /// `clean_text` of text starting at byte `base`, keeping track of where each
+    /// normalized byte came from
+    fn clean_text_aligned(&self, text: &str, base: usize) -> AlignedString {
+        let mut cleaned = AlignedString::default();
+        self.text_cleaner().clean(text, base, &mut cleaned);
+        if let Some(analyzer) = &self.morphology {
+            let mut analyzed = AlignedString::default();
+            analyzer.for_each(&cleaned.text, |c, i| analyzed.push(c, &cleaned.spans[i]));
+            cleaned = analyzed;
+        }
+        cleaned
+    }
+}
+
+/// Unit offsets into a text are counted in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OffsetUnit {
+    /// UTF-8 bytes, for slicing a Rust `str`
+    #[default]
+    Byte,
+    /// Unicode scalar values, for indexing a Python `str`
+    Char,
+    /// UTF-16 code units, as JavaScript strings and many annotation tools count
+    Utf16,
+}
+
+/// Converts byte offsets into a text to offsets in another unit, counting on
+/// from the previous offset when they come in order
+pub(crate) struct OffsetConverter<'a> {
+    text: &'a str,
+    unit: OffsetUnit,
+    byte: usize,
+    offset: usize,
+}
+
+impl<'a> OffsetConverter<'a> {
+    pub(crate) fn new(text: &'a str, unit: OffsetUnit) -> Self {
+        OffsetConverter {
+            text,
+            unit,
+            byte: 0,
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, byte: usize) -> usize {
+        let chars = match self.unit {
+            OffsetUnit::Byte => return byte,
+            OffsetUnit::Char | OffsetUnit::Utf16 => {
+                if byte < self.byte {
+                    self.byte = 0;
+                    self.offset = 0;
+                }
+                self.text[self.byte..byte].chars()
+            }
+        };
+        self.offset += match self.unit {
+            OffsetUnit::Utf16 => chars.map(char::len_utf16).sum(),
+            _ => chars.count(),
+        };
+        self.byte = byte;
+        self.offset
+    }
+}
+
+/// The range from the start of the first to the end of the last of non-empty `spans`
+pub(crate) fn covering(spans: &[Range<usize>]) -> Range<usize> {
+    spans[0].start..spans[spans.len() - 1].end
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use rayon::prelude::*;
+
+use crate::{Error, Result, TokenId, WordPieceTokenizer};
+
+/// Tokens shown on each side of the first differing token
+const CONTEXT_TOKENS: usize = 3;
+
+/// Characters of text shown on each side of the first differing token
+const CONTEXT_CHARS: usize = 30;
+
+/// A text whose ids differ from the reference, see `WordPieceTokenizer::parity_check`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the text in the checked texts
+    pub index: usize,
+    /// Index of the first token that differs
+    pub position: usize,
+    /// Reference tokens around `position`, ids missing from the vocabulary as `<id N>`
+    pub expected: Vec<String>,
+    /// This tokenizer's tokens around `position`
+    pub actual: Vec<String>,
+    /// The text around the first differing token
+    pub context: String,
+}
+
+/// How closely this tokenizer reproduces reference outputs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParityReport {
+    pub texts: usize,
+    /// Number of texts encoded to exactly the reference ids
+    pub matching: usize,
+    /// The first divergent texts, in order, up to the requested number
+    pub divergences: Vec<Divergence>,
+}
+
+impl ParityReport {
+    /// Whether every text matched its reference
+    pub fn is_exact(&self) -> bool {
+        self.matching == self.texts
+    }
+
+    /// Fraction of texts matching their reference, 1 when there are none
+    pub fn match_rate(&self) -> f64 {
+        match self.texts {
+            0 => 1.0,
+            texts => self.matching as f64 / texts as f64,
+        }
+    }
+}
+
+impl fmt::Display for ParityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} of {} texts match ({:.2}%)",
+            self.matching,
+            self.texts,
+            self.match_rate() * 100.0
+        )?;
+        for divergence in &self.divergences {
+            writeln!(f)?;
+            writeln!(f, "text {}, token {}: ...{}...", divergence.index, divergence.position, divergence.context)?;
+            writeln!(f, "  expected: {}", divergence.expected.join(" "))?;
+            writeln!(f, "  actual:   {}", divergence.actual.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Encode `texts` and compare the ids with `reference` ids recorded from
+    /// another implementation, such as `BertTokenizerFast` of `transformers`,
+    /// e.g. to make sure a migration changes no model inputs. With
+    /// `add_special_tokens` the ids are wrapped like `[CLS] text [SEP]`, as
+    /// `transformers` does by default. The first `max_divergences` texts that
+    /// differ are reported with the tokens and the text where they part.
+    pub fn parity_check<S: AsRef<str> + Sync>(
+        &self,
+        texts: &[S],
+        reference: &[Vec<TokenId>],
+        add_special_tokens: bool,
+        max_divergences: usize,
+    ) -> Result<ParityReport> {
+        if texts.len() != reference.len() {
+            return Err(Error::ReferenceCountMismatch { texts: texts.len(), references: reference.len() });
+        }
+
+        let divergent: Vec<(usize, usize)> = texts
+            .par_iter()
+            .zip(reference)
+            .enumerate()
+            .filter_map(|(index, (text, expected))| {
+                let actual = self.parity_ids(text.as_ref(), add_special_tokens);
+                first_difference(expected, &actual).map(|position| (index, position))
+            })
+            .collect();
+
+        let divergences = divergent
+            .iter()
+            .take(max_divergences)
+            .map(|&(index, position)| {
+                let text = texts[index].as_ref();
+                self.divergence(text, index, position, &reference[index], add_special_tokens)
+            })
+            .collect();
+
+        Ok(ParityReport {
+            texts: texts.len(),
+            matching: texts.len() - divergent.len(),
+            divergences,
+        })
+    }
+
+    fn parity_ids(&self, text: &str, add_special_tokens: bool) -> Vec<TokenId> {
+        let ids = self.encode(text);
+        match add_special_tokens {
+            true => self.build_inputs_with_special_tokens(&ids, None),
+            false => ids,
+        }
+    }
+
+    fn divergence(
+        &self,
+        text: &str,
+        index: usize,
+        position: usize,
+        expected: &[TokenId],
+        add_special_tokens: bool,
+    ) -> Divergence {
+        let encoding = self.encode_plus(text);
+        let actual = match add_special_tokens {
+            true => self.build_inputs_with_special_tokens(encoding.ids(), None),
+            false => encoding.ids().to_vec(),
+        };
+
+        // Where the differing token starts in the text, past any leading special token
+        let leading = usize::from(add_special_tokens && self.cls_token_id().is_some());
+        let offset = match position.checked_sub(leading) {
+            Some(i) => encoding.offsets().get(i).map_or(text.len(), |&(start, _)| start),
+            None => 0,
+        };
+
+        Divergence {
+            index,
+            position,
+            expected: self.context_tokens(expected, position),
+            actual: self.context_tokens(&actual, position),
+            context: context_text(text, offset),
+        }
+    }
+
+    /// Tokens of `ids` within `CONTEXT_TOKENS` of `position`
+    fn context_tokens(&self, ids: &[TokenId], position: usize) -> Vec<String> {
+        let start = position.saturating_sub(CONTEXT_TOKENS);
+        let end = (position + CONTEXT_TOKENS + 1).min(ids.len());
+        ids.get(start..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|&id| match self.id_to_token(id) {
+                Some(token) => token.to_string(),
+                None => format!("<id {}>", id),
+            })
+            .collect()
+    }
+}
+
+/// Index of the first id that differs, or the length of the shorter ids when
+/// one is a prefix of the other
+fn first_difference(expected: &[TokenId], actual: &[TokenId]) -> Option<usize> {
+    match expected.iter().zip(actual).position(|(a, b)| a != b) {
+        Some(position) => Some(position),
+        None if expected.len() != actual.len() => Some(expected.len().min(actual.len())),
+        None => None,
+    }
+}
+
+/// Up to `CONTEXT_CHARS` characters of `text` on each side of byte `offset`
+fn context_text(text: &str, offset: usize) -> String {
+    let before = text[..offset].char_indices().rev().nth(CONTEXT_CHARS - 1).map_or(0, |(i, _)| i);
+    let after = text[offset..].char_indices().nth(CONTEXT_CHARS).map_or(text.len(), |(i, _)| offset + i);
+    text[before..after].replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::tokenizer;
+
+    #[test]
+    fn first_difference_covers_prefixes() {
+        assert_eq!(first_difference(&[1, 2, 3], &[1, 2, 3]), None);
+        assert_eq!(first_difference(&[1, 2, 3], &[1, 4, 3]), Some(1));
+        assert_eq!(first_difference(&[1, 2], &[1, 2, 3]), Some(2));
+        assert_eq!(first_difference(&[], &[1]), Some(0));
+    }
+
+    #[test]
+    fn report_counts_matches_and_shows_divergences() {
+        // [CLS] = 2, [SEP] = 3, a = 5, ##b = 6
+        let tokenizer = tokenizer(&["a", "##b"]);
+        let texts = ["ab", "a", "ab a"];
+        let reference = vec![vec![2, 5, 6, 3], vec![2, 6, 3], vec![2, 5, 6, 5, 3]];
+
+        let report = tokenizer.parity_check(&texts, &reference, true, 1).unwrap();
+        assert_eq!((report.texts, report.matching), (3, 2));
+        assert!(!report.is_exact());
+        assert_eq!(
+            report.divergences,
+            [Divergence {
+                index: 1,
+                position: 1,
+                expected: vec!["[CLS]".to_string(), "##b".to_string(), "[SEP]".to_string()],
+                actual: vec!["[CLS]".to_string(), "a".to_string(), "[SEP]".to_string()],
+                context: "a".to_string(),
+            }]
+        );
+
+        let report = tokenizer.parity_check(&texts[..1], &reference[..1], true, 1).unwrap();
+        assert!(report.is_exact());
+        assert_eq!(report.match_rate(), 1.0);
+    }
+
+    #[test]
+    fn reference_count_must_match() {
+        let tokenizer = tokenizer(&["a"]);
+        let result = tokenizer.parity_check(&["a"], &[], false, 1);
+        assert!(matches!(result, Err(Error::ReferenceCountMismatch { texts: 1, references: 0 })));
+    }
+
+    #[test]
+    fn context_stays_on_character_boundaries() {
+        let text = "é".repeat(80);
+        assert_eq!(context_text(&text, 80).chars().count(), 2 * CONTEXT_CHARS);
+        assert_eq!(context_text(&text, 0).chars().count(), CONTEXT_CHARS);
+        assert_eq!(context_text(&text, text.len()).chars().count(), CONTEXT_CHARS);
+    }
+}
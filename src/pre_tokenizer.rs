@@ -0,0 +1,426 @@
+use regex::{Matches, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::iter::Peekable;
+use std::ops::Range;
+use std::sync::OnceLock;
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_segmentation::{GraphemeCursor, UWordBoundIndices, UnicodeSegmentation};
+
+/// How normalized text is split into the words WordPiece segments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum PreTokenizer {
+    /// Split on whitespace and around every punctuation character, as BERT does
+    #[default]
+    Bert,
+    /// Split on the Unicode word boundaries of UAX #29. Words such as `can't`,
+    /// `U.S.` and `3.14` stay whole, while scripts written without spaces, such
+    /// as Thai, Lao and Khmer, are split between their grapheme clusters instead
+    /// of forming one word per sentence.
+    Uax29,
+    /// Split on whitespace alone and pass words to WordPiece verbatim, without
+    /// any normalization, for vocabularies such as those of code tokenizers
+    /// built without the BERT basic tokenizer
+    Whitespace,
+}
+
+impl PreTokenizer {
+    /// The words of cleaned text, see `Words`. `regex` is the BERT word pattern.
+    pub(crate) fn words<'r, 't>(self, regex: &'r Regex, text: &'t str, grapheme_clusters: bool) -> Words<'r, 't> {
+        let segments = match self {
+            PreTokenizer::Bert => Segments::Regex(regex.find_iter(text).peekable()),
+            PreTokenizer::Uax29 => Segments::Uax29(text.split_word_bound_indices()),
+            PreTokenizer::Whitespace => {
+                static NON_WHITESPACE: OnceLock<Regex> = OnceLock::new();
+                let regex = NON_WHITESPACE.get_or_init(|| Regex::new(r"\S+").unwrap());
+                Segments::Regex(regex.find_iter(text).peekable())
+            }
+        };
+        Words {
+            text,
+            segments,
+            grapheme_clusters,
+        }
+    }
+
+    /// Split a word into the pieces WordPiece segments, passing the byte range
+    /// of each to `f` in order. BERT splits around punctuation, see
+    /// `split_punctuation`, while UAX #29 and whitespace-delimited words are
+    /// kept as they are.
+    pub(crate) fn split_word<F: FnMut(Range<usize>)>(self, word: &str, grapheme_clusters: bool, mut f: F) {
+        match self {
+            PreTokenizer::Bert => split_punctuation(word, grapheme_clusters, f),
+            PreTokenizer::Uax29 | PreTokenizer::Whitespace if !word.is_empty() => f(0..word.len()),
+            PreTokenizer::Uax29 | PreTokenizer::Whitespace => {}
+        }
+    }
+}
+
+/// English contractions the BERT word pattern splits from the word before them
+const ENGLISH_CONTRACTIONS: [&str; 7] = ["'s", "'t", "'re", "'ve", "'m", "'ll", "'d"];
+
+/// Which contractions the BERT pre-tokenizer matches as words of their own,
+/// e.g. `'ll` in `we'll`. A contraction is matched wherever its apostrophe
+/// starts a word, ignoring case, so the English set also breaks up quoted
+/// words in other languages, e.g. `'très'` into `'`, `t`, `rès` and `'`. The
+/// apostrophe itself is split off as punctuation either way.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Contractions {
+    /// Match no contractions, so words after an apostrophe stay whole
+    None,
+    /// `'s`, `'t`, `'re`, `'ve`, `'m`, `'ll` and `'d`, as BERT does
+    #[default]
+    English,
+    /// The given contractions, the longest first when several match
+    Custom(Vec<String>),
+}
+
+/// The BERT word pattern matching `contractions`. Unlike GPT-2's pattern it
+/// ends in a plain `\s+` rather than `\s+(?!\S)`, as the `regex` crate has no
+/// look-ahead. The two only differ in which match the space before a word
+/// belongs to, and words are trimmed, so they split text into the same words.
+pub(crate) fn word_regex(contractions: &Contractions) -> Regex {
+    let mut alternatives: Vec<String> = match contractions {
+        Contractions::None => Vec::new(),
+        Contractions::English => ENGLISH_CONTRACTIONS.iter().map(|c| c.to_string()).collect(),
+        Contractions::Custom(list) => {
+            let mut list: Vec<&String> = list.iter().filter(|c| !c.is_empty()).collect();
+            list.sort_by_key(|c| std::cmp::Reverse(c.len()));
+            list.into_iter().map(|c| regex::escape(c)).collect()
+        }
+    };
+    alternatives.push(r" ?[\p{L}\p{M}\p{N}]+| ?[^\s\p{L}\p{M}\p{N}]+|\s+".to_string());
+    RegexBuilder::new(&alternatives.join("|"))
+        .case_insensitive(true)
+        .build()
+        .unwrap()
+}
+
+/// Emoji that start a grapheme cluster: pictographs and regional indicators
+/// of flags. Keycaps such as `1️⃣` are recognized by their enclosing keycap.
+fn is_emoji(cluster: &str) -> bool {
+    static EMOJI: OnceLock<Regex> = OnceLock::new();
+    let emoji = EMOJI
+        .get_or_init(|| Regex::new(r"^[\p{Extended_Pictographic}\p{Regional_Indicator}]|\x{20E3}").unwrap());
+    !cluster.is_ascii() && emoji.is_match(cluster)
+}
+
+/// Iterator over the words of cleaned text, trimmed and with their byte
+/// offsets, as matched by the BERT word regex or between UAX #29 word
+/// boundaries. Words may be empty. With grapheme clusters, a regex match
+/// starting inside a cluster, such as a variation selector after an emoji, is
+/// joined to the match before it.
+pub(crate) struct Words<'r, 't> {
+    text: &'t str,
+    segments: Segments<'r, 't>,
+    grapheme_clusters: bool,
+}
+
+enum Segments<'r, 't> {
+    Regex(Peekable<Matches<'r, 't>>),
+    Uax29(UWordBoundIndices<'t>),
+    /// The whole text, until it has been yielded
+    Whole(bool),
+}
+
+impl<'t> Words<'_, 't> {
+    /// The whole of cleaned text as a single word, e.g. for a URL kept whole
+    pub(crate) fn whole(text: &'t str) -> Self {
+        Words {
+            text,
+            segments: Segments::Whole(true),
+            grapheme_clusters: false,
+        }
+    }
+}
+
+impl<'t> Iterator for Words<'_, 't> {
+    type Item = (usize, &'t str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, mut end) = match &mut self.segments {
+            Segments::Regex(matches) => {
+                let mat = matches.next()?;
+                (mat.start(), mat.end())
+            }
+            Segments::Uax29(segments) => {
+                let (start, segment) = segments.next()?;
+                (start, start + segment.len())
+            }
+            Segments::Whole(pending) => {
+                if !std::mem::take(pending) {
+                    return None;
+                }
+                (0, self.text.len())
+            }
+        };
+        if let (true, Segments::Regex(matches)) = (self.grapheme_clusters, &mut self.segments) {
+            while let Some(next) = matches.peek() {
+                let mut cursor = GraphemeCursor::new(end, self.text.len(), true);
+                // A prepended mark such as U+11F02 clusters with a space after
+                // it, which must still separate the words
+                let spaced = self.text[end..].starts_with(char::is_whitespace);
+                if next.start() != end || spaced || cursor.is_boundary(self.text, 0).unwrap_or(true) {
+                    break;
+                }
+                end = next.end();
+                matches.next();
+            }
+        }
+        let matched = self.text[start..end].trim_start();
+        Some((end - matched.len(), matched.trim_end()))
+    }
+}
+
+/// Split a word around punctuation, see `is_punctuation`, passing the byte
+/// range of each piece to `f` in order. With grapheme clusters, the word is
+/// only split between clusters, and emoji become pieces of their own too.
+fn split_punctuation<F: FnMut(Range<usize>)>(word: &str, grapheme_clusters: bool, mut f: F) {
+    let mut start = 0;
+    let mut split = |i: usize, end: usize| {
+        if start < i {
+            f(start..i);
+        }
+        f(i..end);
+        start = end;
+    };
+    if grapheme_clusters {
+        for (i, cluster) in word.grapheme_indices(true) {
+            if cluster.chars().any(is_punctuation) || is_emoji(cluster) {
+                split(i, i + cluster.len());
+            }
+        }
+    } else {
+        for (i, c) in word.char_indices() {
+            if is_punctuation(c) {
+                split(i, i + c.len_utf8());
+            }
+        }
+    }
+    if start < word.len() {
+        f(start..word.len());
+    }
+}
+
+/// Classes of characters the BERT word pattern tells apart
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    /// `\s`
+    Space,
+    /// `[\p{L}\p{M}\p{N}]`
+    Word,
+    /// Anything else, e.g. punctuation and symbols
+    Other,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_ascii_alphanumeric() {
+        return CharClass::Word;
+    }
+    if c.is_whitespace() {
+        return CharClass::Space;
+    }
+    if c.is_ascii() {
+        return CharClass::Other;
+    }
+    match get_general_category(c) {
+        GeneralCategory::UppercaseLetter
+        | GeneralCategory::LowercaseLetter
+        | GeneralCategory::TitlecaseLetter
+        | GeneralCategory::ModifierLetter
+        | GeneralCategory::OtherLetter
+        | GeneralCategory::NonspacingMark
+        | GeneralCategory::SpacingMark
+        | GeneralCategory::EnclosingMark
+        | GeneralCategory::DecimalNumber
+        | GeneralCategory::LetterNumber
+        | GeneralCategory::OtherNumber => CharClass::Word,
+        _ => CharClass::Other,
+    }
+}
+
+/// Whether `c` is punctuation as BERT's `_is_punctuation` defines it: any
+/// character of the Unicode punctuation categories, and every printable ASCII
+/// character that is not a letter or digit, so symbols such as `$`, `+`, `^`
+/// and `~` too
+pub(crate) fn is_punctuation(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_punctuation();
+    }
+    matches!(
+        get_general_category(c),
+        GeneralCategory::ConnectorPunctuation
+            | GeneralCategory::DashPunctuation
+            | GeneralCategory::OpenPunctuation
+            | GeneralCategory::ClosePunctuation
+            | GeneralCategory::InitialPunctuation
+            | GeneralCategory::FinalPunctuation
+            | GeneralCategory::OtherPunctuation
+    )
+}
+
+/// Length of the English contraction `text` starts with, matched ignoring
+/// case as the word pattern does, so `ſ` counts as an `s`
+fn english_contraction_len(text: &str) -> Option<usize> {
+    let rest = text.strip_prefix('\'')?;
+    let first = rest.chars().next()?;
+    let len = match first {
+        's' | 'S' | 'ſ' | 't' | 'T' | 'm' | 'M' | 'd' | 'D' => first.len_utf8(),
+        _ => match rest.get(..2) {
+            Some(two) if ["re", "ve", "ll"].iter().any(|c| two.eq_ignore_ascii_case(c)) => 2,
+            _ => return None,
+        },
+    };
+    Some(1 + len)
+}
+
+/// Split cleaned text into the pieces BERT pre-tokenization yields, passing
+/// the byte range of each to `f` in order: the matches of the word pattern
+/// with `Contractions::None` or `Contractions::English`, trimmed and split
+/// around punctuation as `split_punctuation` does without grapheme clusters.
+///
+/// This is the pre-tokenization half of end-to-end LinMaxMatch: one pass over
+/// the characters, classifying each once, finds word and punctuation
+/// boundaries as it goes, with no regex search and no second pass over each
+/// word for punctuation. Punctuation only occurs in runs of characters that
+/// are neither letters, marks, numbers nor spaces, so only those runs are
+/// split.
+pub(crate) fn split_bert_words<F: FnMut(Range<usize>)>(text: &str, english_contractions: bool, mut f: F) {
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        // Contractions are matched first wherever a match starts
+        if let Some(len) = english_contraction_len(&text[start..]).filter(|_| english_contractions) {
+            f(start..start + 1);
+            f(start + 1..start + len);
+            while chars.next_if(|&(i, _)| i < start + len).is_some() {}
+            continue;
+        }
+
+        // A space starts the match of the run after it, unless spaces follow
+        chars.next();
+        let (first, c) = match chars.peek() {
+            Some(&(i, next)) if c == ' ' && char_class(next) != CharClass::Space => {
+                chars.next();
+                (i, next)
+            }
+            _ => (start, c),
+        };
+        let class = char_class(c);
+        let run = std::iter::once((first, c)).chain(std::iter::from_fn(|| {
+            chars.next_if(|&(_, c)| char_class(c) == class)
+        }));
+
+        match class {
+            CharClass::Space => run.for_each(drop),
+            CharClass::Word => {
+                let end = run.last().map_or(first, |(i, c)| i + c.len_utf8());
+                f(first..end);
+            }
+            CharClass::Other => {
+                let (mut piece, mut end) = (first, first);
+                for (i, c) in run {
+                    end = i + c.len_utf8();
+                    if is_punctuation(c) {
+                        if piece < i {
+                            f(piece..i);
+                        }
+                        f(i..end);
+                        piece = end;
+                    }
+                }
+                if piece < end {
+                    f(piece..end);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pieces of `text` found with the word pattern and punctuation splitting
+    fn regex_pieces<'t>(text: &'t str, contractions: &Contractions) -> Vec<&'t str> {
+        let regex = word_regex(contractions);
+        let mut pieces = Vec::new();
+        for (start, word) in PreTokenizer::Bert.words(&regex, text, false) {
+            split_punctuation(word, false, |range| pieces.push(&text[start + range.start..start + range.end]));
+        }
+        pieces
+    }
+
+    /// Pieces of `text` found by `split_bert_words`
+    fn single_pass_pieces(text: &str, english_contractions: bool) -> Vec<&str> {
+        let mut pieces = Vec::new();
+        split_bert_words(text, english_contractions, |range| pieces.push(&text[range]));
+        pieces
+    }
+
+    const TEXTS: [&str; 8] = [
+        "I'm sure they'll say it's JOHN'S, can't they? We'd've 'sup",
+        "In 2024 there were 3,000,000 people, 1.5x more than ½ of 10²",
+        "wait...what?! (really) --- ok ;-) $5+$6=~11 @you #tag",
+        "  leading \t tabs\n\nand   runs of  spaces  ",
+        "naïve café — «quoted» ‘single’ 'très' ſ'ſ",
+        "a\u{3000}b\u{a0}c\u{2028}d",
+        "x'",
+        "",
+    ];
+
+    #[test]
+    fn single_pass_matches_word_pattern() {
+        for text in TEXTS {
+            assert_eq!(single_pass_pieces(text, true), regex_pieces(text, &Contractions::English), "{:?}", text);
+            assert_eq!(single_pass_pieces(text, false), regex_pieces(text, &Contractions::None), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn contractions_split_from_the_word_before() {
+        let text = "I'm sure they'll say it's JOHN'S, can't they? 'sup";
+        assert_eq!(
+            regex_pieces(text, &Contractions::English),
+            [
+                "I", "'", "m", "sure", "they", "'", "ll", "say", "it", "'", "s", "JOHN", "'", "S", ",", "can", "'", "t",
+                "they", "?", "'", "sup"
+            ]
+        );
+        // Only an apostrophe starting a match, not one after a space, starts a contraction
+        assert_eq!(regex_pieces("'sup x'llama", &Contractions::English), ["'", "s", "up", "x", "'", "ll", "ama"]);
+        assert_eq!(regex_pieces("'sup x'llama", &Contractions::None), ["'", "sup", "x", "'", "llama"]);
+        let custom = Contractions::Custom(vec!["'n".to_string(), "'nt".to_string()]);
+        assert_eq!(regex_pieces("ain'nt 'n'", &custom), ["ain", "'", "nt", "'", "n", "'"]);
+    }
+
+    #[test]
+    fn digits_and_punctuation_runs_are_split() {
+        assert_eq!(
+            regex_pieces("3,000,000 1.5x 10² v2", &Contractions::English),
+            ["3", ",", "000", ",", "000", "1", ".", "5x", "10²", "v2"]
+        );
+        assert_eq!(
+            regex_pieces("wait...what?! ;-) $5+=~", &Contractions::English),
+            ["wait", ".", ".", ".", "what", "?", "!", ";", "-", ")", "$", "5", "+", "=", "~"]
+        );
+    }
+
+    #[test]
+    fn whitespace_only_separates_words() {
+        assert_eq!(
+            regex_pieces("  a \t b\n\nc  d\u{3000}e ", &Contractions::English),
+            ["a", "b", "c", "d", "e"]
+        );
+        assert!(regex_pieces(" \t\n ", &Contractions::English).is_empty());
+    }
+
+    #[test]
+    fn grapheme_clusters_do_not_join_words_across_spaces() {
+        // U+11F02 is a prepended mark, clustering with the character after it
+        let text = "a\u{11F02} b \u{11F02}\u{11F02}  c ❤\u{FE0F}x";
+        let regex = word_regex(&Contractions::English);
+        let words: Vec<&str> = PreTokenizer::Bert.words(&regex, text, true).map(|(_, word)| word).collect();
+        assert_eq!(words, ["a\u{11F02}", "b", "\u{11F02}\u{11F02}", "", "c", "❤\u{FE0F}x"]);
+    }
+}
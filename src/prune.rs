@@ -0,0 +1,120 @@
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use crate::trainer::COUNT_CHUNK_SIZE;
+use crate::{Result, TokenId, WordPieceTokenizer};
+
+impl WordPieceTokenizer {
+    /// Count how often each token id is produced when encoding `texts`, e.g. to
+    /// measure which pieces of the vocabulary a reference corpus actually uses
+    pub fn token_counts<I, S>(&self, texts: I) -> HashMap<TokenId, usize>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let mut counts = HashMap::new();
+        let mut chunk = Vec::with_capacity(COUNT_CHUNK_SIZE);
+        for text in texts {
+            chunk.push(text);
+            if chunk.len() == COUNT_CHUNK_SIZE {
+                self.count_tokens_into(&chunk, &mut counts);
+                chunk.clear();
+            }
+        }
+        self.count_tokens_into(&chunk, &mut counts);
+        counts
+    }
+
+    /// Add how often each token id is produced when encoding a chunk of texts,
+    /// in parallel, to `counts`
+    pub(crate) fn count_tokens_into<S: AsRef<str> + Sync>(&self, texts: &[S], counts: &mut HashMap<TokenId, usize>) {
+        let chunk_counts = texts
+            .par_iter()
+            .fold(HashMap::new, |mut counts, text| {
+                for id in self.encode(text.as_ref()) {
+                    *counts.entry(id).or_insert(0) += 1;
+                }
+                counts
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (id, count) in b {
+                    *a.entry(id).or_insert(0) += count;
+                }
+                a
+            });
+        for (id, count) in chunk_counts {
+            *counts.entry(id).or_insert(0) += count;
+        }
+    }
+
+    /// A tokenizer keeping only the tokens for which `keep` returns true, with ids
+    /// remapped densely in their original order, and the old -> new id mapping so
+    /// embedding matrices can be shrunk to match. Special, added and never_split
+    /// tokens and single characters are always kept, so every word that could be
+    /// segmented before still can be.
+    pub fn prune_vocab<F: FnMut(&str, TokenId) -> bool>(&self, mut keep: F) -> Result<(Self, HashMap<TokenId, TokenId>)> {
+        let mut kept: Vec<(&String, TokenId)> = self
+            .vocab
+            .iter()
+            .filter(|(token, &id)| self.is_protected(token) || keep(token, id))
+            .map(|(token, &id)| (token, id))
+            .collect();
+        kept.sort_by_key(|&(_, id)| id);
+
+        let id_map: HashMap<TokenId, TokenId> = kept
+            .iter()
+            .enumerate()
+            .map(|(new_id, &(_, old_id))| (old_id, new_id as TokenId))
+            .collect();
+        let vocab = kept.into_iter().map(|(token, old_id)| (token.clone(), id_map[&old_id])).collect();
+
+        Ok((self.with_vocab(vocab)?, id_map))
+    }
+
+    /// Prune the tokens a reference corpus never uses, given the counts returned by
+    /// `token_counts`, and with `target_size` also the least used ones until at
+    /// most that many tokens remain. Tokens that must be kept, see
+    /// `prune_vocab`, count towards `target_size` and may exceed it.
+    pub fn prune_by_usage(
+        &self,
+        token_counts: &HashMap<TokenId, usize>,
+        target_size: Option<usize>,
+    ) -> Result<(Self, HashMap<TokenId, TokenId>)> {
+        let mut used: Vec<(usize, TokenId)> = self
+            .vocab
+            .iter()
+            .filter(|(token, _)| !self.is_protected(token))
+            .filter_map(|(_, id)| token_counts.get(id).filter(|&&count| count > 0).map(|&count| (count, *id)))
+            .collect();
+        // Most used first, ties going to the earlier id
+        used.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        if let Some(target_size) = target_size {
+            let protected = self.vocab.keys().filter(|token| self.is_protected(token)).count();
+            used.truncate(target_size.saturating_sub(protected));
+        }
+
+        let keep: HashSet<TokenId> = used.into_iter().map(|(_, id)| id).collect();
+        self.prune_vocab(|_, id| keep.contains(&id))
+    }
+
+    /// Whether pruning must keep `token`: registered special, added and
+    /// never_split tokens, and pieces of a single character
+    fn is_protected(&self, token: &str) -> bool {
+        if self.special_tokens.contains_key(token)
+            || self.added_tokens.contains_key(token)
+            || self.never_split.contains(token)
+        {
+            return true;
+        }
+
+        let mut piece = token;
+        if !self.continuing_subword_prefix.is_empty() {
+            piece = piece.strip_prefix(self.continuing_subword_prefix.as_str()).unwrap_or(piece);
+        }
+        if !self.end_of_word_suffix.is_empty() {
+            piece = piece.strip_suffix(self.end_of_word_suffix.as_str()).unwrap_or(piece);
+        }
+        piece.chars().count() <= 1
+    }
+}
@@ -0,0 +1,2918 @@
+use pyo3::create_exception;
+use pyo3::exceptions::{PyKeyboardInterrupt, PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes, PyCFunction, PyCapsule, PyDict, PyIterator, PyList, PyString, PyType};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::arrow::{large_strings, ArrowArray, ArrowSchema};
+use crate::offsets::OffsetConverter;
+use crate::stream::StreamBuffer;
+use crate::trainer::{weight_counts, COUNT_CHUNK_SIZE};
+use crate::{
+    merge_vocabs as merge_vocab_maps, CaseFolding, Contractions, Encoding, Entity, Error, EvaluationStats,
+    BytePairModel, FallbackTokenizer, LengthUnit, MergeStrategy, Morphology, MultiTokenizer, NormalizationForm, OffsetUnit, PreTokenizer, SegmentationCost, SpecialTokens, Token,
+    TokenId, TruncationStrategy, UnknownIdPolicy, VocabBackend, WordPieceTokenizer, WordPieceTrainer,
+    SPECIAL_TOKEN_ROLES,
+};
+
+create_exception!(
+    wordpiece_rs,
+    WordPieceError,
+    PyValueError,
+    "Base class of the errors of wordpiece_rs, a ValueError so that existing handlers keep catching them"
+);
+create_exception!(
+    wordpiece_rs,
+    VocabError,
+    WordPieceError,
+    "A token or id is not in the vocabulary, or a vocabulary or model file is unusable"
+);
+create_exception!(
+    wordpiece_rs,
+    NormalizationError,
+    WordPieceError,
+    "A normalization option, such as unicode_normalization or case_folding, is unknown"
+);
+create_exception!(
+    wordpiece_rs,
+    TruncationError,
+    WordPieceError,
+    "A text exceeds the input limits, or truncation was requested without a length to truncate to"
+);
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> PyErr {
+        match err {
+            Error::Io(err) => err.into(),
+            #[cfg(feature = "hf-hub")]
+            err @ Error::Hub(_) => pyo3::exceptions::PyOSError::new_err(err.to_string()),
+            #[cfg(feature = "lindera")]
+            err @ Error::Morphology(_) => pyo3::exceptions::PyOSError::new_err(err.to_string()),
+            err @ (Error::SpecialTokenNotInVocab(_)
+            | Error::UnknownId(_)
+            | Error::InvalidSentencePieceModel(_)
+            | Error::InvalidBytePairModel(_)
+            | Error::InvalidRoute(_)) => VocabError::new_err(err.to_string()),
+            err @ (Error::InputTooLarge { .. } | Error::TooManyTokens { .. }) => TruncationError::new_err(err.to_string()),
+            err => WordPieceError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// Convert a dict of role -> token into `SpecialTokens`, where the role is one of
+/// `unk_token`, `cls_token`, `sep_token`, `pad_token`, `mask_token`, or
+/// `additional_special_tokens` (a list of tokens without a role)
+fn extract_special_tokens(dict: &Bound<'_, PyDict>) -> PyResult<SpecialTokens> {
+    let mut special_tokens = SpecialTokens::default();
+
+    for (k, v) in dict.iter() {
+        let role = k.extract::<String>()?;
+        match role.as_str() {
+            "unk_token" => special_tokens.unk_token = Some(v.extract()?),
+            "cls_token" => special_tokens.cls_token = Some(v.extract()?),
+            "sep_token" => special_tokens.sep_token = Some(v.extract()?),
+            "pad_token" => special_tokens.pad_token = Some(v.extract()?),
+            "mask_token" => special_tokens.mask_token = Some(v.extract()?),
+            "additional_special_tokens" => special_tokens.additional_special_tokens = v.extract()?,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown special token role '{}', expected one of {:?} or 'additional_special_tokens'",
+                    role, SPECIAL_TOKEN_ROLES
+                )))
+            }
+        }
+    }
+
+    Ok(special_tokens)
+}
+
+/// Convert a token -> id vocabulary into a dict ordered by id, so its iteration
+/// order is the same on every run
+fn vocab_to_dict<'py>(py: Python<'py>, vocab: &HashMap<String, TokenId>) -> PyResult<Bound<'py, PyDict>> {
+    let mut entries: Vec<(&String, &TokenId)> = vocab.iter().collect();
+    entries.sort_by_key(|(_, &id)| id);
+
+    let dict = PyDict::new(py);
+    for (token, id) in entries {
+        dict.set_item(token, id)?;
+    }
+    Ok(dict)
+}
+
+/// Parse a vocabulary backend name, `"trie"`, `"double_array"` or `"fst"`
+fn parse_vocab_backend(name: &str) -> PyResult<VocabBackend> {
+    match name {
+        "trie" => Ok(VocabBackend::Trie),
+        "double_array" => Ok(VocabBackend::DoubleArray),
+        #[cfg(feature = "fst")]
+        "fst" => Ok(VocabBackend::Fst),
+        #[cfg(not(feature = "fst"))]
+        "fst" => Err(PyValueError::new_err(
+            "The 'fst' vocab backend requires building wordpiece_rs with the `fst` feature",
+        )),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown vocab backend '{}', expected 'trie', 'double_array' or 'fst'",
+            name
+        ))),
+    }
+}
+
+/// Inverse of `parse_vocab_backend`
+fn vocab_backend_name(backend: VocabBackend) -> &'static str {
+    match backend {
+        VocabBackend::Trie => "trie",
+        VocabBackend::DoubleArray => "double_array",
+        #[cfg(feature = "fst")]
+        VocabBackend::Fst => "fst",
+    }
+}
+
+fn parse_case_folding(name: &str) -> PyResult<CaseFolding> {
+    match name {
+        "lowercase" => Ok(CaseFolding::Lowercase),
+        "fold" => Ok(CaseFolding::Fold),
+        "turkic" => Ok(CaseFolding::Turkic),
+        _ => Err(NormalizationError::new_err(format!(
+            "Unknown case folding '{}', expected 'lowercase', 'fold' or 'turkic'",
+            name
+        ))),
+    }
+}
+
+fn case_folding_name(case_folding: CaseFolding) -> &'static str {
+    match case_folding {
+        CaseFolding::Lowercase => "lowercase",
+        CaseFolding::Fold => "fold",
+        CaseFolding::Turkic => "turkic",
+    }
+}
+
+fn parse_unknown_id_policy(name: &str) -> PyResult<UnknownIdPolicy> {
+    match name {
+        "skip" => Ok(UnknownIdPolicy::Skip),
+        "error" => Ok(UnknownIdPolicy::Error),
+        "replace_with_unk" => Ok(UnknownIdPolicy::ReplaceWithUnk),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown on_unknown_id '{}', expected 'skip', 'error' or 'replace_with_unk'",
+            name
+        ))),
+    }
+}
+
+fn parse_normalization_form(name: Option<&str>) -> PyResult<NormalizationForm> {
+    match name {
+        None => Ok(NormalizationForm::None),
+        Some("nfc") => Ok(NormalizationForm::Nfc),
+        Some("nfd") => Ok(NormalizationForm::Nfd),
+        Some("nfkc") => Ok(NormalizationForm::Nfkc),
+        Some("nfkd") => Ok(NormalizationForm::Nfkd),
+        Some(name) => Err(NormalizationError::new_err(format!(
+            "Unknown unicode_normalization '{}', expected 'nfc', 'nfd', 'nfkc', 'nfkd' or None",
+            name
+        ))),
+    }
+}
+
+fn normalization_form_name(form: NormalizationForm) -> Option<&'static str> {
+    match form {
+        NormalizationForm::None => None,
+        NormalizationForm::Nfc => Some("nfc"),
+        NormalizationForm::Nfd => Some("nfd"),
+        NormalizationForm::Nfkc => Some("nfkc"),
+        NormalizationForm::Nfkd => Some("nfkd"),
+    }
+}
+
+fn parse_pre_tokenizer(name: &str) -> PyResult<PreTokenizer> {
+    match name {
+        "bert" => Ok(PreTokenizer::Bert),
+        "uax29" => Ok(PreTokenizer::Uax29),
+        "whitespace" => Ok(PreTokenizer::Whitespace),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown pre_tokenizer '{}', expected 'bert', 'uax29' or 'whitespace'",
+            name
+        ))),
+    }
+}
+
+fn parse_length_unit(name: &str) -> PyResult<LengthUnit> {
+    match name {
+        "char" => Ok(LengthUnit::Chars),
+        "grapheme" => Ok(LengthUnit::Graphemes),
+        "byte" => Ok(LengthUnit::Bytes),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown max_input_chars_unit '{}', expected 'char', 'grapheme' or 'byte'",
+            name
+        ))),
+    }
+}
+
+fn length_unit_name(unit: LengthUnit) -> &'static str {
+    match unit {
+        LengthUnit::Chars => "char",
+        LengthUnit::Graphemes => "grapheme",
+        LengthUnit::Bytes => "byte",
+    }
+}
+
+fn pre_tokenizer_name(pre_tokenizer: PreTokenizer) -> &'static str {
+    match pre_tokenizer {
+        PreTokenizer::Bert => "bert",
+        PreTokenizer::Uax29 => "uax29",
+        PreTokenizer::Whitespace => "whitespace",
+    }
+}
+
+/// `contractions`: "english", "none" or None, or a list of contractions
+#[derive(FromPyObject)]
+enum ContractionsArg {
+    Name(String),
+    List(Vec<String>),
+}
+
+fn parse_contractions(contractions: Option<ContractionsArg>) -> PyResult<Contractions> {
+    match contractions {
+        None => Ok(Contractions::None),
+        Some(ContractionsArg::Name(name)) => match name.as_str() {
+            "english" => Ok(Contractions::English),
+            "none" => Ok(Contractions::None),
+            _ => Err(PyValueError::new_err(format!(
+                "Unknown contractions '{}', expected 'english', 'none' or a list of contractions",
+                name
+            ))),
+        },
+        Some(ContractionsArg::List(list)) => Ok(Contractions::Custom(list)),
+    }
+}
+
+fn parse_entity(name: &str) -> PyResult<Entity> {
+    match name {
+        "url" => Ok(Entity::Url),
+        "email" => Ok(Entity::Email),
+        "hashtag" => Ok(Entity::Hashtag),
+        "mention" => Ok(Entity::Mention),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown entity '{}', expected 'url', 'email', 'hashtag' or 'mention'",
+            name
+        ))),
+    }
+}
+
+fn entity_name(entity: Entity) -> &'static str {
+    match entity {
+        Entity::Url => "url",
+        Entity::Email => "email",
+        Entity::Hashtag => "hashtag",
+        Entity::Mention => "mention",
+    }
+}
+
+/// The entities to keep whole and their placeholders. Entities with a
+/// placeholder are kept whole without being listed.
+fn parse_entities(
+    entities: Option<Vec<String>>,
+    placeholders: Option<HashMap<String, String>>,
+) -> PyResult<(Vec<Entity>, BTreeMap<Entity, String>)> {
+    let mut entities = entities
+        .unwrap_or_default()
+        .iter()
+        .map(|name| parse_entity(name))
+        .collect::<PyResult<Vec<_>>>()?;
+    let mut by_entity = BTreeMap::new();
+    for (name, placeholder) in placeholders.unwrap_or_default() {
+        let entity = parse_entity(&name)?;
+        if !entities.contains(&entity) {
+            entities.push(entity);
+        }
+        by_entity.insert(entity, placeholder);
+    }
+    Ok((entities, by_entity))
+}
+
+/// The morphological analysis named by `morphology`, with the dictionary it needs
+fn parse_morphology(morphology: Option<&str>, dictionary: Option<String>) -> PyResult<Option<Morphology>> {
+    match (morphology, dictionary) {
+        (None, _) => Ok(None),
+        (Some("hangul_jamo"), _) => Ok(Some(Morphology::HangulJamo)),
+        #[cfg(feature = "lindera")]
+        (Some("lindera"), Some(dictionary)) => Ok(Some(Morphology::Lindera { dictionary })),
+        #[cfg(feature = "lindera")]
+        (Some("lindera"), None) => Err(PyValueError::new_err("morphology 'lindera' needs a morphology_dictionary")),
+        #[cfg(not(feature = "lindera"))]
+        (Some("lindera"), _) => Err(PyValueError::new_err("wordpiece_rs was built without the lindera feature")),
+        (Some(name), _) => Err(PyValueError::new_err(format!(
+            "Unknown morphology '{}', expected 'hangul_jamo', 'lindera' or None",
+            name
+        ))),
+    }
+}
+
+fn morphology_name(morphology: &Morphology) -> &'static str {
+    match morphology {
+        Morphology::HangulJamo => "hangul_jamo",
+        #[cfg(feature = "lindera")]
+        Morphology::Lindera { .. } => "lindera",
+    }
+}
+
+fn parse_offset_unit(name: &str) -> PyResult<OffsetUnit> {
+    match name {
+        "byte" => Ok(OffsetUnit::Byte),
+        "char" => Ok(OffsetUnit::Char),
+        "utf16" => Ok(OffsetUnit::Utf16),
+        _ => Err(PyValueError::new_err(format!(
+            "Unknown offset unit '{}', expected 'char', 'byte' or 'utf16'",
+            name
+        ))),
+    }
+}
+
+#[pymethods]
+impl Token {
+    #[new]
+    /// A negative `id` marks a word without an id yet, as `basic_tokenize` returns
+    #[pyo3(signature = (text, id, is_special, start = 0, end = 0, is_continuation = false))]
+    fn new(text: &str, id: i64, is_special: bool, start: usize, end: usize, is_continuation: bool) -> PyResult<Self> {
+        let id = match id {
+            id if id < 0 => None,
+            id => Some(TokenId::try_from(id).map_err(|_| VocabError::new_err(format!("Token id {} is too large", id)))?),
+        };
+        Ok(Token {
+            text: Arc::from(text),
+            id,
+            is_special,
+            start,
+            end,
+            is_continuation,
+        })
+    }
+
+    #[getter]
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// -1 for a word without an id yet, as Python code has always compared against
+    #[getter]
+    fn id(&self) -> i64 {
+        self.id.map_or(-1, i64::from)
+    }
+
+    #[getter]
+    fn is_special(&self) -> bool {
+        self.is_special
+    }
+
+    #[getter]
+    fn start(&self) -> usize {
+        self.start
+    }
+
+    #[getter]
+    fn end(&self) -> usize {
+        self.end
+    }
+
+    #[getter]
+    fn is_continuation(&self) -> bool {
+        self.is_continuation
+    }
+
+    /// Pickle support: the arguments to construct an equal token
+    #[allow(clippy::type_complexity)]
+    fn __reduce__<'py>(slf: &Bound<'py, Self>) -> (Bound<'py, PyType>, (String, i64, bool, usize, usize, bool)) {
+        let token = slf.get();
+        let args = (
+            token.text.to_string(),
+            token.id(),
+            token.is_special,
+            token.start,
+            token.end,
+            token.is_continuation,
+        );
+        (slf.get_type(), args)
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!(
+            "Token(text={}, id={}, is_special={}, start={}, end={}, is_continuation={})",
+            PyString::new(py, &self.text).repr()?,
+            self.id(),
+            if self.is_special { "True" } else { "False" },
+            self.start,
+            self.end,
+            if self.is_continuation { "True" } else { "False" },
+        ))
+    }
+}
+
+/// Run `work` on the rayon thread pool and return an asyncio future of its
+/// result, resolved on the running event loop, which is never blocked
+fn spawn_awaitable<'py, T, F>(py: Python<'py>, work: F) -> PyResult<Bound<'py, PyAny>>
+where
+    F: FnOnce() -> PyResult<T> + Send + 'static,
+    T: for<'a> IntoPyObject<'a> + Send + 'static,
+{
+    let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+    let future = event_loop.call_method0("create_future")?;
+    let (event_loop, awaited) = (event_loop.unbind(), future.clone().unbind());
+
+    rayon::spawn(move || {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(work))
+            .unwrap_or_else(|_| Err(PyRuntimeError::new_err("Tokenization panicked")));
+        let result = Mutex::new(Some(result));
+        Python::attach(|py| {
+            let resolve = PyCFunction::new_closure(py, None, None, move |args, _| -> PyResult<()> {
+                let py = args.py();
+                let future = awaited.bind(py);
+                let Some(result) = result.lock().unwrap_or_else(PoisonError::into_inner).take() else {
+                    return Ok(());
+                };
+                // The awaiting task may have been cancelled in the meantime
+                if future.call_method0("done")?.is_truthy()? {
+                    return Ok(());
+                }
+                match result {
+                    Ok(value) => future.call_method1("set_result", (value,))?,
+                    Err(err) => future.call_method1("set_exception", (err.into_value(py),))?,
+                };
+                Ok(())
+            });
+            // The event loop may have been closed before the work was done
+            if let Ok(resolve) = resolve {
+                let _ = event_loop.bind(py).call_method1("call_soon_threadsafe", (resolve,));
+            }
+        });
+    });
+
+    Ok(future)
+}
+
+/// `token` with byte offsets into `text` converted to character offsets
+fn char_offsets(token: Token, converter: &mut OffsetConverter<'_>) -> Token {
+    Token {
+        start: converter.get(token.start),
+        end: converter.get(token.end),
+        ..token
+    }
+}
+
+/// The columns of an `Encoding` as given to its Python constructor: ids,
+/// tokens, word ids, offsets, gaps and surfaces
+type EncodingParts = (
+    Vec<TokenId>,
+    Vec<String>,
+    Vec<Option<usize>>,
+    Vec<(usize, usize)>,
+    Vec<String>,
+    Vec<String>,
+);
+
+#[pyclass(name = "Encoding", module = "wordpiece_rs", eq)]
+#[derive(PartialEq)]
+struct PyEncoding {
+    encoding: Encoding,
+}
+
+#[pymethods]
+impl PyEncoding {
+    /// An encoding made in Python, e.g. from the outputs of another tokenizer.
+    /// `word_ids` default to None and `offsets` to (0, 0) for every token, and
+    /// `gaps` and `surfaces` are only needed for `decode_lossless`.
+    #[new]
+    #[pyo3(signature = (ids, tokens, word_ids = None, offsets = None, gaps = None, surfaces = None))]
+    fn new(
+        ids: Vec<TokenId>,
+        tokens: Vec<String>,
+        word_ids: Option<Vec<Option<usize>>>,
+        offsets: Option<Vec<(usize, usize)>>,
+        gaps: Option<Vec<String>>,
+        surfaces: Option<Vec<String>>,
+    ) -> PyResult<Self> {
+        let len = ids.len();
+        let word_ids = word_ids.unwrap_or_else(|| vec![None; len]);
+        let offsets = offsets.unwrap_or_else(|| vec![(0, 0); len]);
+        let (gaps, surfaces) = (gaps.unwrap_or_default(), surfaces.unwrap_or_default());
+
+        let lengths = [("tokens", tokens.len()), ("word_ids", word_ids.len()), ("offsets", offsets.len())];
+        if let Some((name, other)) = lengths.into_iter().find(|&(_, other)| other != len) {
+            return Err(PyValueError::new_err(format!("{} ids but {} {}", len, other, name)));
+        }
+        if !(gaps.is_empty() && surfaces.is_empty()) && (gaps.len() != len + 1 || surfaces.len() != len) {
+            return Err(PyValueError::new_err(format!(
+                "{} ids need {} gaps and {} surfaces, got {} and {}",
+                len,
+                len + 1,
+                len,
+                gaps.len(),
+                surfaces.len()
+            )));
+        }
+
+        Ok(PyEncoding {
+            encoding: Encoding::from_parts(ids, tokens, word_ids, offsets, gaps, surfaces),
+        })
+    }
+
+    #[getter]
+    fn ids(&self) -> Vec<TokenId> {
+        self.encoding.ids().to_vec()
+    }
+
+    #[getter]
+    fn tokens(&self) -> Vec<String> {
+        self.encoding.tokens().to_vec()
+    }
+
+    /// Index of the word each token is part of, None for special tokens
+    fn word_ids(&self) -> Vec<Option<usize>> {
+        self.encoding.word_ids().to_vec()
+    }
+
+    /// Start and end offsets of each token in the text
+    #[getter]
+    fn offsets(&self) -> Vec<(usize, usize)> {
+        self.encoding.offsets().to_vec()
+    }
+
+    /// Text not covered by any token before each token, then after the last
+    /// one, recorded with `lossless`
+    #[getter]
+    fn gaps(&self) -> Vec<String> {
+        self.encoding.gaps().to_vec()
+    }
+
+    /// The exact original text of each token, recorded with `lossless`
+    #[getter]
+    fn surfaces(&self) -> Vec<String> {
+        self.encoding.surfaces().to_vec()
+    }
+
+    /// Start and end offsets of the token at index `token`
+    fn token_to_chars(&self, token: usize) -> Option<(usize, usize)> {
+        self.encoding.token_to_chars(token)
+    }
+
+    /// Index of the token covering offset `pos`, None if no token does
+    fn char_to_token(&self, pos: usize) -> Option<usize> {
+        self.encoding.char_to_token(pos)
+    }
+
+    /// Index of the word covering offset `pos`, None if no word does
+    fn char_to_word(&self, pos: usize) -> Option<usize> {
+        self.encoding.char_to_word(pos)
+    }
+
+    fn __len__(&self) -> usize {
+        self.encoding.len()
+    }
+
+    /// Pickle support: the arguments to construct an equal encoding
+    fn __reduce__<'py>(slf: &Bound<'py, Self>) -> (Bound<'py, PyType>, EncodingParts) {
+        let encoding = &slf.borrow().encoding;
+        let parts = (
+            encoding.ids().to_vec(),
+            encoding.tokens().to_vec(),
+            encoding.word_ids().to_vec(),
+            encoding.offsets().to_vec(),
+            encoding.gaps().to_vec(),
+            encoding.surfaces().to_vec(),
+        );
+        (slf.get_type(), parts)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Encoding(num_tokens={})", self.encoding.len())
+    }
+}
+
+/// A `TokenSpan` as returned to Python: piece, start, end, id and is_continuation
+type TokenSpanTuple = (String, usize, usize, TokenId, bool);
+
+/// A `LatticeEdge` as returned to Python: start, end, token and id
+type LatticeEdgeTuple = (usize, usize, String, TokenId);
+
+#[pyclass(name = "WordPieceTokenizer", module = "wordpiece_rs")]
+struct PyWordPieceTokenizer {
+    tokenizer: WordPieceTokenizer,
+}
+
+#[pymethods]
+impl PyWordPieceTokenizer {
+    #[new]
+    #[pyo3(signature = (
+        vocab,
+        unk_token = "[UNK]",
+        max_input_chars_per_word = 200,
+        strip_accents = true,
+        lowercase = true,
+        special_tokens = None,
+        never_split = None,
+        cache_capacity = 0,
+        vocab_backend = "trie",
+        continuing_subword_prefix = "##",
+        end_of_word_suffix = "",
+        case_folding = "lowercase",
+        model_max_length = None,
+        unicode_normalization = Some("nfkc"),
+        remove_control_chars = true,
+        tokenize_chinese_chars = true,
+        grapheme_clusters = false,
+        pre_tokenizer = "bert",
+        contractions = Some(ContractionsArg::Name("english".to_string())),
+        entities = None,
+        entity_placeholders = None,
+        morphology = None,
+        morphology_dictionary = None,
+        max_input_chars_unit = None,
+        max_input_bytes = None,
+        max_tokens = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        vocab: HashMap<String, TokenId>,
+        unk_token: &str,
+        max_input_chars_per_word: usize,
+        strip_accents: bool,
+        lowercase: bool,
+        special_tokens: Option<&Bound<'_, PyDict>>,
+        never_split: Option<Vec<String>>,
+        cache_capacity: usize,
+        vocab_backend: &str,
+        continuing_subword_prefix: &str,
+        end_of_word_suffix: &str,
+        case_folding: &str,
+        model_max_length: Option<usize>,
+        unicode_normalization: Option<&str>,
+        remove_control_chars: bool,
+        tokenize_chinese_chars: bool,
+        grapheme_clusters: bool,
+        pre_tokenizer: &str,
+        contractions: Option<ContractionsArg>,
+        entities: Option<Vec<String>>,
+        entity_placeholders: Option<HashMap<String, String>>,
+        morphology: Option<&str>,
+        morphology_dictionary: Option<String>,
+        max_input_chars_unit: Option<&str>,
+        max_input_bytes: Option<usize>,
+        max_tokens: Option<usize>,
+    ) -> PyResult<Self> {
+        let vocab_backend = parse_vocab_backend(vocab_backend)?;
+        let special_tokens = special_tokens.map(extract_special_tokens).transpose()?;
+        let (entities, entity_placeholders) = parse_entities(entities, entity_placeholders)?;
+        let mut builder = WordPieceTokenizer::builder()
+            .vocab(vocab)
+            .unk_token(unk_token)
+            .max_input_chars_per_word(max_input_chars_per_word)
+            .strip_accents(strip_accents)
+            .lowercase(lowercase)
+            .case_folding(parse_case_folding(case_folding)?)
+            .unicode_normalization(parse_normalization_form(unicode_normalization)?)
+            .remove_control_chars(remove_control_chars)
+            .tokenize_chinese_chars(tokenize_chinese_chars)
+            .grapheme_clusters(grapheme_clusters)
+            .pre_tokenizer(parse_pre_tokenizer(pre_tokenizer)?)
+            .contractions(parse_contractions(contractions)?)
+            .entities(entities)
+            .never_split(never_split.unwrap_or_default())
+            .cache_capacity(cache_capacity)
+            .vocab_backend(vocab_backend)
+            .continuing_subword_prefix(continuing_subword_prefix)
+            .end_of_word_suffix(end_of_word_suffix);
+        if let Some(special_tokens) = special_tokens {
+            builder = builder.special_tokens(special_tokens);
+        }
+        if let Some(max_length) = model_max_length {
+            builder = builder.model_max_length(max_length);
+        }
+        for (entity, placeholder) in &entity_placeholders {
+            builder = builder.entity_placeholder(*entity, placeholder);
+        }
+        if let Some(morphology) = parse_morphology(morphology, morphology_dictionary)? {
+            builder = builder.morphology(morphology);
+        }
+        if let Some(unit) = max_input_chars_unit {
+            builder = builder.max_input_chars_unit(parse_length_unit(unit)?);
+        }
+        if let Some(max_bytes) = max_input_bytes {
+            builder = builder.max_input_bytes(max_bytes);
+        }
+        if let Some(max_tokens) = max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        let tokenizer = builder.build()?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+
+    /// Register special tokens from a dict of role -> token, where the role is one
+    /// of `unk_token`, `cls_token`, `sep_token`, `pad_token`, `mask_token`, or
+    /// `additional_special_tokens` (a list of tokens without a role).
+    /// Returns the number of tokens that were not already special.
+    fn add_special_tokens(&mut self, special_tokens: &Bound<'_, PyDict>) -> PyResult<usize> {
+        let special_tokens = extract_special_tokens(special_tokens)?;
+        Ok(self.tokenizer.add_special_tokens(&special_tokens)?)
+    }
+
+    /// Add tokens that are matched atomically in the raw input and never split by
+    /// WordPiece. Tokens missing from the vocabulary are assigned new ids after the
+    /// current largest id. Returns the number of tokens that were not already added.
+    fn add_tokens(&mut self, tokens: Vec<String>) -> usize {
+        self.tokenizer.add_tokens(tokens)
+    }
+
+    /// The full token -> id vocabulary, including added tokens, ordered by id
+    fn get_vocab<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        vocab_to_dict(py, self.tokenizer.vocab())
+    }
+
+    /// All tokens registered through `add_tokens` and their ids
+    #[getter]
+    fn added_tokens(&self) -> HashMap<String, TokenId> {
+        self.tokenizer.added_tokens().clone()
+    }
+
+    /// All registered special tokens and their ids
+    #[getter]
+    fn special_tokens(&self) -> HashMap<String, TokenId> {
+        self.tokenizer
+            .special_tokens()
+            .iter()
+            .map(|(token, &id)| (token.to_string(), id))
+            .collect()
+    }
+
+    #[getter]
+    fn unk_token_id(&self) -> TokenId {
+        self.tokenizer.unk_token_id()
+    }
+
+    #[getter]
+    fn cls_token_id(&self) -> Option<TokenId> {
+        self.tokenizer.cls_token_id()
+    }
+
+    #[getter]
+    fn sep_token_id(&self) -> Option<TokenId> {
+        self.tokenizer.sep_token_id()
+    }
+
+    #[getter]
+    fn pad_token_id(&self) -> Option<TokenId> {
+        self.tokenizer.pad_token_id()
+    }
+
+    #[getter]
+    fn mask_token_id(&self) -> Option<TokenId> {
+        self.tokenizer.mask_token_id()
+    }
+
+    /// The data structure used to segment words, `"trie"` or `"fst"`
+    #[getter]
+    fn vocab_backend(&self) -> &'static str {
+        vocab_backend_name(self.tokenizer.vocab_backend())
+    }
+
+    /// The prefix marking vocabulary tokens that continue a word
+    #[getter]
+    fn continuing_subword_prefix(&self) -> &str {
+        self.tokenizer.continuing_subword_prefix()
+    }
+
+    /// The suffix marking vocabulary tokens that end a word
+    #[getter]
+    fn end_of_word_suffix(&self) -> &str {
+        self.tokenizer.end_of_word_suffix()
+    }
+
+    /// How text is lowercased: "lowercase", "fold" or "turkic"
+    #[getter]
+    fn case_folding(&self) -> &'static str {
+        case_folding_name(self.tokenizer.case_folding())
+    }
+
+    /// Unicode normalization form applied first: "nfc", "nfd", "nfkc", "nfkd"
+    /// or None
+    #[getter]
+    fn unicode_normalization(&self) -> Option<&'static str> {
+        normalization_form_name(self.tokenizer.unicode_normalization())
+    }
+
+    /// Whether NUL, U+FFFD and control characters are dropped from the input
+    #[getter]
+    fn remove_control_chars(&self) -> bool {
+        self.tokenizer.remove_control_chars()
+    }
+
+    /// Whether CJK ideographs are split into words of their own
+    #[getter]
+    fn tokenize_chinese_chars(&self) -> bool {
+        self.tokenizer.tokenize_chinese_chars()
+    }
+
+    /// Whether grapheme clusters such as emoji sequences are kept whole
+    #[getter]
+    fn grapheme_clusters(&self) -> bool {
+        self.tokenizer.grapheme_clusters()
+    }
+
+    /// What `max_input_chars_per_word` counts: "char", "grapheme" or "byte"
+    #[getter]
+    fn max_input_chars_unit(&self) -> &'static str {
+        length_unit_name(self.tokenizer.max_input_chars_unit())
+    }
+
+    /// How text is split into words: "bert", "uax29" or "whitespace"
+    #[getter]
+    fn pre_tokenizer(&self) -> &'static str {
+        pre_tokenizer_name(self.tokenizer.pre_tokenizer())
+    }
+
+    /// Contractions the BERT pre-tokenizer splits off as words of their own:
+    /// "english", "none" or a list of contractions
+    #[getter]
+    fn contractions<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        match self.tokenizer.contractions() {
+            Contractions::English => Ok("english".into_pyobject(py)?.into_any()),
+            Contractions::Custom(list) => Ok(list.into_pyobject(py)?.into_any()),
+            Contractions::None => Ok("none".into_pyobject(py)?.into_any()),
+        }
+    }
+
+    /// Kinds of text kept whole instead of split around their punctuation:
+    /// "url", "email", "hashtag" and "mention"
+    #[getter]
+    fn entities(&self) -> Vec<&'static str> {
+        self.tokenizer.entities().iter().map(|&entity| entity_name(entity)).collect()
+    }
+
+    /// Tokens entities are replaced with, keyed by entity
+    #[getter]
+    fn entity_placeholders(&self) -> HashMap<&'static str, String> {
+        self.tokenizer
+            .entity_placeholders()
+            .iter()
+            .map(|(&entity, placeholder)| (entity_name(entity), placeholder.clone()))
+            .collect()
+    }
+
+    /// Morphological analysis applied before text is split into words:
+    /// "hangul_jamo", "lindera" or None
+    #[getter]
+    fn morphology(&self) -> Option<&'static str> {
+        self.tokenizer.morphology().as_ref().map(morphology_name)
+    }
+
+    /// Number of tokens the model accepts, the length `__call__` truncates and
+    /// pads to when not given `max_length`
+    #[getter]
+    fn model_max_length(&self) -> Option<usize> {
+        self.tokenizer.model_max_length()
+    }
+
+    #[setter]
+    fn set_model_max_length(&mut self, max_length: Option<usize>) {
+        self.tokenizer.set_model_max_length(max_length);
+    }
+
+    /// Longest text in bytes `encode` and `encode_batch` accept
+    #[getter]
+    fn max_input_bytes(&self) -> Option<usize> {
+        self.tokenizer.max_input_bytes()
+    }
+
+    #[setter]
+    fn set_max_input_bytes(&mut self, max_bytes: Option<usize>) {
+        self.tokenizer.set_max_input_bytes(max_bytes);
+    }
+
+    /// Most tokens `encode` and `encode_batch` produce for a text
+    #[getter]
+    fn max_tokens(&self) -> Option<usize> {
+        self.tokenizer.max_tokens()
+    }
+
+    #[setter]
+    fn set_max_tokens(&mut self, max_tokens: Option<usize>) {
+        self.tokenizer.set_max_tokens(max_tokens);
+    }
+
+    /// Capacity, size, hits, misses and hit rate of the word cache
+    #[getter]
+    fn cache_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = self.tokenizer.cache_stats();
+        let dict = PyDict::new(py);
+        dict.set_item("capacity", stats.capacity)?;
+        dict.set_item("size", stats.size)?;
+        dict.set_item("hits", stats.hits)?;
+        dict.set_item("misses", stats.misses)?;
+        dict.set_item("hit_rate", stats.hit_rate())?;
+        Ok(dict)
+    }
+
+    /// Build the trie's failure links now rather than on the first call that
+    /// segments a word
+    fn precompile(&self, py: Python<'_>) {
+        py.detach(|| self.tokenizer.precompile());
+    }
+
+    /// Drop all cached words
+    fn clear_cache(&self) {
+        self.tokenizer.clear_cache();
+    }
+
+    /// Documents, bytes, tokens and unknown tokens encoded so far, with their
+    /// rates per second and the word cache statistics. Copies of the
+    /// tokenizer share their counters.
+    fn metrics<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let metrics = self.tokenizer.metrics();
+        let dict = PyDict::new(py);
+        dict.set_item("documents", metrics.documents)?;
+        dict.set_item("bytes", metrics.bytes)?;
+        dict.set_item("tokens", metrics.tokens)?;
+        dict.set_item("unknown_tokens", metrics.unknown_tokens)?;
+        dict.set_item("documents_per_second", metrics.documents_per_second())?;
+        dict.set_item("tokens_per_second", metrics.tokens_per_second())?;
+        dict.set_item("unk_rate", metrics.unk_rate())?;
+        dict.set_item("busy_seconds", metrics.busy.as_secs_f64())?;
+        dict.set_item("elapsed_seconds", metrics.elapsed.as_secs_f64())?;
+        dict.set_item("cache_hit_rate", metrics.cache.hit_rate())?;
+        Ok(dict)
+    }
+
+    /// Estimated bytes of memory held by the vocabulary, the map from ids to
+    /// tokens, the trie or transducer, the word cache and the added tokens,
+    /// with their total
+    fn memory_usage<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let usage = self.tokenizer.memory_usage();
+        let dict = PyDict::new(py);
+        dict.set_item("vocab", usage.vocab)?;
+        dict.set_item("vocab_lookup", usage.vocab_lookup)?;
+        dict.set_item("backend", usage.backend)?;
+        dict.set_item("word_cache", usage.word_cache)?;
+        dict.set_item("added_tokens", usage.added_tokens)?;
+        dict.set_item("total", usage.total())?;
+        Ok(dict)
+    }
+
+    /// `metrics` in the Prometheus text format, with names starting with `prefix`
+    #[pyo3(signature = (prefix = "wordpiece"))]
+    fn metrics_prometheus(&self, prefix: &str) -> String {
+        self.tokenizer.metrics().to_prometheus(prefix)
+    }
+
+    /// Pickle support: a placeholder tokenizer replaced by the compiled form of
+    /// this one in `__setstate__`, keeping every option and skipping the trie
+    /// construction
+    #[allow(clippy::type_complexity)]
+    fn __reduce__<'py>(
+        slf: &Bound<'py, Self>,
+    ) -> PyResult<(Bound<'py, PyType>, (HashMap<String, TokenId>,), Bound<'py, PyBytes>)> {
+        let bytes = slf.borrow().tokenizer.to_compiled_bytes()?;
+        let placeholder = HashMap::from([("[UNK]".to_string(), 0)]);
+        Ok((slf.get_type(), (placeholder,), PyBytes::new(slf.py(), &bytes)))
+    }
+
+    fn __setstate__(&mut self, state: &[u8]) -> PyResult<()> {
+        self.tokenizer = WordPieceTokenizer::from_compiled_bytes(state)?;
+        Ok(())
+    }
+
+    /// A copy sharing the vocabulary, trie and word cache with this tokenizer
+    /// until either is changed, e.g. to give each worker its own settings
+    fn __copy__(&self) -> Self {
+        PyWordPieceTokenizer {
+            tokenizer: self.tokenizer.clone(),
+        }
+    }
+
+    /// Same as `__copy__`, as the shared parts are never changed in place
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.__copy__()
+    }
+
+    /// Save the tokenizer with its built trie to a binary file for fast startup
+    fn save_compiled(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.tokenizer.save_compiled(path)?)
+    }
+
+    /// Write the vocabulary as a vocab.txt file, one token per line in id order
+    fn save_vocab(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.tokenizer.save_vocab(path)?)
+    }
+
+    /// Load a tokenizer written by `save_compiled` without rebuilding its trie
+    #[staticmethod]
+    fn load_compiled(path: PathBuf) -> PyResult<Self> {
+        let tokenizer = WordPieceTokenizer::load_compiled(path)?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+
+    /// Write vocab.txt and tokenizer_config.json, which holds every option of
+    /// the tokenizer, to a directory
+    fn save_pretrained(&self, dir: PathBuf) -> PyResult<()> {
+        Ok(self.tokenizer.save_pretrained(dir)?)
+    }
+
+    /// Load a tokenizer from a directory written by `save_pretrained`, or from
+    /// a tokenizer_config.json or TOML file and the vocab.txt it names
+    #[staticmethod]
+    fn from_config(path: PathBuf) -> PyResult<Self> {
+        let tokenizer = WordPieceTokenizer::from_config(path)?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+
+    /// Load the tokenizer of a model on the Hugging Face Hub, e.g.
+    /// "bert-base-uncased", or in a local directory, from its tokenizer.json or
+    /// vocab.txt, with its lowercasing and accent stripping. Downloads are cached.
+    #[staticmethod]
+    fn from_pretrained(py: Python<'_>, model_id: &str) -> PyResult<Self> {
+        #[cfg(feature = "hf-hub")]
+        {
+            let tokenizer = py.detach(|| WordPieceTokenizer::from_pretrained(model_id))?;
+            Ok(PyWordPieceTokenizer { tokenizer })
+        }
+        #[cfg(not(feature = "hf-hub"))]
+        {
+            let _ = (py, model_id);
+            Err(PyValueError::new_err(
+                "from_pretrained requires building wordpiece_rs with the `hf-hub` feature",
+            ))
+        }
+    }
+
+    /// Load the vocabulary of a SentencePiece `.model` file, keeping its ids.
+    /// Pieces starting with `▁` become words and the others `##` continuations.
+    #[staticmethod]
+    fn from_sentencepiece(path: PathBuf) -> PyResult<Self> {
+        let tokenizer = WordPieceTokenizer::from_sentencepiece(path)?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+
+    // Tokenization runs without the GIL so other Python threads keep running
+    // and thread pools can tokenize in parallel
+
+    fn clean_text(&self, py: Python<'_>, text: &str) -> String {
+        py.detach(|| self.tokenizer.clean_text(text))
+    }
+
+    /// Split `text` into words, punctuation and whole added or special tokens,
+    /// with character offsets into `text`
+    fn basic_tokenize(&self, py: Python<'_>, text: &str) -> Vec<Token> {
+        let tokens = py.detach(|| self.tokenizer.basic_tokenize(text));
+        let mut converter = OffsetConverter::new(text, OffsetUnit::Char);
+        tokens.into_iter().map(|token| char_offsets(token, &mut converter)).collect()
+    }
+
+    /// Split a token from `basic_tokenize` into WordPiece pieces, each with the
+    /// character offsets of its part of the token
+    fn wordpiece_tokenize(&self, py: Python<'_>, token: Token) -> Vec<Token> {
+        // Offsets are counted in bytes in Rust, so the token is measured in bytes
+        // when its characters span its offsets exactly, and left alone otherwise
+        let chars = token.text.chars().count();
+        if token.end.saturating_sub(token.start) != chars {
+            return py
+                .detach(|| self.tokenizer.wordpiece_tokenize(&token))
+                .into_iter()
+                .map(|piece| Token {
+                    start: token.start,
+                    end: token.end,
+                    ..piece
+                })
+                .collect();
+        }
+
+        let bytes = Token {
+            start: 0,
+            end: token.text.len(),
+            ..token.clone()
+        };
+        let pieces = py.detach(|| self.tokenizer.wordpiece_tokenize(&bytes));
+        let mut converter = OffsetConverter::new(&token.text, OffsetUnit::Char);
+        pieces
+            .into_iter()
+            .map(|piece| {
+                let piece = char_offsets(piece, &mut converter);
+                Token {
+                    start: token.start + piece.start,
+                    end: token.start + piece.end,
+                    ..piece
+                }
+            })
+            .collect()
+    }
+
+    fn tokenize(&self, py: Python<'_>, text: &str) -> Vec<String> {
+        py.detach(|| self.tokenizer.tokenize(text))
+    }
+
+    /// Tokenize `text` into `Token`s carrying each piece with its id, offsets
+    /// counted in `offset_unit` and whether it is special, in one pass
+    #[pyo3(signature = (text, offset_unit = "char"))]
+    fn tokenize_tokens(&self, py: Python<'_>, text: &str, offset_unit: &str) -> PyResult<Vec<Token>> {
+        let unit = parse_offset_unit(offset_unit)?;
+        Ok(py.detach(|| self.tokenizer.tokenize_tokens_with_unit(text, unit)))
+    }
+
+    /// Encode `text` to token ids. With `is_split_into_words`, `text` is a list
+    /// of words that are each tokenized separately, as in token classification
+    /// datasets. With `add_special_tokens`, the ids are wrapped as
+    /// `build_inputs_with_special_tokens` does, e.g. `[CLS] ... [SEP]`. Raises
+    /// a ValueError for a text over `max_input_bytes` or `max_tokens`.
+    #[pyo3(signature = (text, is_split_into_words = false, add_special_tokens = false))]
+    fn encode(
+        &self,
+        py: Python<'_>,
+        text: &Bound<'_, PyAny>,
+        is_split_into_words: bool,
+        add_special_tokens: bool,
+    ) -> PyResult<Vec<TokenId>> {
+        let ids = if is_split_into_words {
+            let words = extract_words(text)?;
+            py.detach(|| try_encode_words(&self.tokenizer, &words, OffsetUnit::Byte))?.ids().to_vec()
+        } else {
+            let text: String = text.extract()?;
+            py.detach(|| self.tokenizer.try_encode(&text))?
+        };
+        Ok(match add_special_tokens {
+            true => self.tokenizer.build_inputs_with_special_tokens(&ids, None),
+            false => ids,
+        })
+    }
+
+    /// Awaitable `encode` for asyncio, e.g. in FastAPI handlers. The text is
+    /// encoded on the Rust thread pool while the event loop keeps running.
+    #[pyo3(signature = (text, add_special_tokens = false))]
+    fn encode_async<'py>(&self, py: Python<'py>, text: String, add_special_tokens: bool) -> PyResult<Bound<'py, PyAny>> {
+        let tokenizer = self.tokenizer.clone();
+        spawn_awaitable(py, move || {
+            let ids = tokenizer.try_encode(&text)?;
+            Ok(match add_special_tokens {
+                true => tokenizer.build_inputs_with_special_tokens(&ids, None),
+                false => ids,
+            })
+        })
+    }
+
+    /// Awaitable `encode_batch`, encoding the texts in parallel on the Rust
+    /// thread pool
+    #[pyo3(signature = (texts, add_special_tokens = false))]
+    fn encode_batch_async<'py>(
+        &self,
+        py: Python<'py>,
+        texts: Vec<String>,
+        add_special_tokens: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let tokenizer = self.tokenizer.clone();
+        spawn_awaitable(py, move || {
+            let mut batch = tokenizer.try_encode_batch(&texts)?;
+            if add_special_tokens {
+                for ids in &mut batch {
+                    *ids = tokenizer.build_inputs_with_special_tokens(ids, None);
+                }
+            }
+            Ok(batch)
+        })
+    }
+
+    /// Number of tokens `encode` returns for `text`, without building the ids.
+    /// The limits apply as in `encode`.
+    #[pyo3(signature = (text, add_special_tokens = false))]
+    fn count_tokens(&self, py: Python<'_>, text: &str, add_special_tokens: bool) -> PyResult<usize> {
+        let special = if add_special_tokens { self.tokenizer.num_special_tokens_to_add(false) } else { 0 };
+        Ok(py.detach(|| self.tokenizer.try_count_tokens(text))? + special)
+    }
+
+    /// `count_tokens` of a list of texts, counted in parallel
+    #[pyo3(signature = (texts, add_special_tokens = false))]
+    fn count_tokens_batch(&self, py: Python<'_>, texts: Vec<String>, add_special_tokens: bool) -> PyResult<Vec<usize>> {
+        let special = if add_special_tokens { self.tokenizer.num_special_tokens_to_add(false) } else { 0 };
+        let counts = py.detach(|| self.tokenizer.try_count_tokens_batch(&texts))?;
+        Ok(counts.into_iter().map(|count| count + special).collect())
+    }
+
+    /// Encode a list of texts in parallel. With `return_numpy`, return a dict of
+    /// 2-D int64 NumPy arrays padded to the longest text: `input_ids`,
+    /// `attention_mask` and `token_type_ids`. With `return_arrow`, return the ids
+    /// as a pyarrow `ListArray` of int32, ready for `datasets` or Polars. Both are
+    /// built without a Python int per token. `add_special_tokens` wraps each
+    /// text's ids and the limits apply as in `encode`.
+    #[pyo3(signature = (texts, return_numpy = false, return_arrow = false, add_special_tokens = false))]
+    fn encode_batch<'py>(
+        &self,
+        py: Python<'py>,
+        texts: Vec<String>,
+        return_numpy: bool,
+        return_arrow: bool,
+        add_special_tokens: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        if return_numpy && return_arrow {
+            return Err(PyValueError::new_err("Pass at most one of return_numpy and return_arrow"));
+        }
+        let pad_id = match (return_numpy, self.tokenizer.pad_token_id()) {
+            (true, None) => return Err(PyValueError::new_err("return_numpy pads the batch and requires a pad token")),
+            (_, pad_id) => pad_id.unwrap_or_default(),
+        };
+        let mut batch = py.detach(|| self.tokenizer.try_encode_batch(&texts))?;
+        if add_special_tokens {
+            for ids in &mut batch {
+                *ids = self.tokenizer.build_inputs_with_special_tokens(ids, None);
+            }
+        }
+        if return_numpy {
+            Ok(batch_to_numpy(py, &batch, pad_id)?.into_any())
+        } else if return_arrow {
+            batch_to_arrow(py, &batch)
+        } else {
+            Ok(batch.into_pyobject(py)?.into_any())
+        }
+    }
+
+    /// Encode a Polars or pandas Series of strings. The strings are read in place
+    /// through the Arrow C data interface and encoded in parallel, and the ids
+    /// come back as a Series of the same library holding lists of ids, with
+    /// empty lists for nulls. The limits apply as in `encode`. Requires pyarrow.
+    fn encode_series<'py>(&self, py: Python<'py>, series: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let pyarrow = py.import("pyarrow")?;
+        let is_polars = series.get_type().module()?.to_str()?.starts_with("polars");
+        let array = if is_polars {
+            series.call_method0("to_arrow")?
+        } else {
+            pyarrow.getattr("Array")?.call_method1("from_pandas", (series,))?
+        };
+        let array = array.call_method1("cast", (pyarrow.call_method0("large_string")?,))?;
+
+        let (schema, array): (Bound<'py, PyCapsule>, Bound<'py, PyCapsule>) =
+            array.call_method0("__arrow_c_array__")?.extract()?;
+        let schema = schema.pointer_checked(Some(c"arrow_schema"))?.cast::<ArrowSchema>();
+        let array = array.pointer_checked(Some(c"arrow_array"))?.cast::<ArrowArray>();
+        // The capsules, and so the buffers, stay alive until the end of this call
+        let texts = unsafe { large_strings(schema.as_ref(), array.as_ref()) }.map_err(PyValueError::new_err)?;
+        let ids = batch_to_arrow(py, &py.detach(|| self.tokenizer.try_encode_batch(&texts))?)?;
+
+        let name = series.getattr("name")?;
+        if is_polars {
+            py.import("polars")?.getattr("Series")?.call1((name, ids))
+        } else {
+            let pandas = py.import("pandas")?;
+            let values = pandas.getattr("arrays")?.getattr("ArrowExtensionArray")?.call1((ids,))?;
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("index", series.getattr("index")?)?;
+            kwargs.set_item("name", name)?;
+            pandas.getattr("Series")?.call((values,), Some(&kwargs))
+        }
+    }
+
+    /// Encode `text`, keeping the tokens, the index of the word each came from
+    /// and their offsets, counted in `offset_unit`: "char" to index Python
+    /// strings, "byte" for UTF-8 bytes or "utf16" for UTF-16 code units as in
+    /// JavaScript. With `is_split_into_words`, `text` is a list of words, all
+    /// tokens of a word get its index in the list, and offsets are relative to
+    /// the word. With `lossless`, the exact text of each token and the text
+    /// between them are recorded too, for `decode_lossless`. The limits apply
+    /// as in `encode`.
+    #[pyo3(signature = (text, is_split_into_words = false, offset_unit = "char", lossless = false))]
+    fn encode_plus(
+        &self,
+        py: Python<'_>,
+        text: &Bound<'_, PyAny>,
+        is_split_into_words: bool,
+        offset_unit: &str,
+        lossless: bool,
+    ) -> PyResult<PyEncoding> {
+        let unit = parse_offset_unit(offset_unit)?;
+        let encoding = if is_split_into_words {
+            if lossless {
+                return Err(PyValueError::new_err("lossless encoding needs a text, not a list of words"));
+            }
+            let words = extract_words(text)?;
+            py.detach(|| try_encode_words(&self.tokenizer, &words, unit))?
+        } else if lossless {
+            let text: String = text.extract()?;
+            py.detach(|| {
+                self.tokenizer.within_limits(
+                    text.len(),
+                    || self.tokenizer.encode_lossless_with_unit(&text, unit),
+                    |encoding| encoding.ids().len(),
+                )
+            })?
+        } else {
+            let text: String = text.extract()?;
+            py.detach(|| self.tokenizer.try_encode_plus_with_unit(&text, unit))?
+        };
+        Ok(PyEncoding { encoding })
+    }
+
+    /// Rebuild text from `ids` and an encoding made with `lossless=True`. Ids
+    /// matching the encoding get their original text back, along with the
+    /// whitespace and removed characters around them, so decoding the
+    /// encoding's own ids returns the encoded text exactly. Other ids are
+    /// spelled with their vocabulary pieces.
+    fn decode_lossless(&self, ids: Vec<TokenId>, encoding: PyRef<'_, PyEncoding>) -> String {
+        self.tokenizer.decode_lossless(&ids, &encoding.encoding)
+    }
+
+    /// Mask an encoding for masked language modeling following BERT's 80/10/10
+    /// rule, selecting whole words with `whole_word`. Returns the masked ids and
+    /// the labels, -100 where nothing is to be predicted. The same `seed` always
+    /// masks the same way.
+    #[pyo3(signature = (encoding, mask_prob = 0.15, whole_word = true, seed = None))]
+    fn mlm_mask(
+        &self,
+        py: Python<'_>,
+        encoding: PyRef<'_, PyEncoding>,
+        mask_prob: f64,
+        whole_word: bool,
+        seed: Option<u64>,
+    ) -> PyResult<(Vec<TokenId>, Vec<i64>)> {
+        let encoding = &encoding.encoding;
+        Ok(py.detach(|| self.tokenizer.mlm_mask(encoding, mask_prob, whole_word, seed))?)
+    }
+
+    /// Always true: the tokenizer is backed by Rust like `transformers` fast
+    /// tokenizers
+    #[getter]
+    fn is_fast(&self) -> bool {
+        true
+    }
+
+    /// Number of special tokens added around a single sequence, or a pair with
+    /// `pair`
+    #[pyo3(signature = (pair = false))]
+    fn num_special_tokens_to_add(&self, pair: bool) -> usize {
+        self.tokenizer.num_special_tokens_to_add(pair)
+    }
+
+    /// Wrap ids with special tokens: `[CLS] A [SEP]` or `[CLS] A [SEP] B [SEP]`
+    #[pyo3(signature = (token_ids_0, token_ids_1 = None))]
+    fn build_inputs_with_special_tokens(&self, token_ids_0: Vec<TokenId>, token_ids_1: Option<Vec<TokenId>>) -> Vec<TokenId> {
+        self.tokenizer.build_inputs_with_special_tokens(&token_ids_0, token_ids_1.as_deref())
+    }
+
+    /// Segment ids of the output of `build_inputs_with_special_tokens`
+    #[pyo3(signature = (token_ids_0, token_ids_1 = None))]
+    fn create_token_type_ids_from_sequences(&self, token_ids_0: Vec<TokenId>, token_ids_1: Option<Vec<TokenId>>) -> Vec<u32> {
+        self.tokenizer.create_token_type_ids_from_sequences(&token_ids_0, token_ids_1.as_deref())
+    }
+
+    /// Prepare model inputs like a `transformers` tokenizer: `text` is a string,
+    /// or a list of them for a batch, and `text_pair` the second sequences of
+    /// pairs. Returns a dict of `input_ids`, `token_type_ids` and
+    /// `attention_mask`, as lists or with `return_tensors="np"` or `"pt"` as
+    /// 2-D arrays. `padding` is `True`/`"longest"` or `"max_length"`, and
+    /// `truncation` is `True`/`"longest_first"`, `"only_first"` or
+    /// `"only_second"`, truncating to `max_length`. Without `max_length`, the
+    /// tokenizer's `model_max_length` is used. The limits apply to each
+    /// sequence as in `encode`, before truncation.
+    #[pyo3(signature = (
+        text,
+        text_pair = None,
+        add_special_tokens = true,
+        padding = PaddingArg::Bool(false),
+        truncation = TruncationArg::Bool(false),
+        max_length = None,
+        return_tensors = None,
+        return_token_type_ids = true,
+        return_attention_mask = true,
+        is_split_into_words = false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn __call__<'py>(
+        &self,
+        py: Python<'py>,
+        text: &Bound<'py, PyAny>,
+        text_pair: Option<&Bound<'py, PyAny>>,
+        add_special_tokens: bool,
+        padding: PaddingArg,
+        truncation: TruncationArg,
+        max_length: Option<usize>,
+        return_tensors: Option<&str>,
+        return_token_type_ids: bool,
+        return_attention_mask: bool,
+        is_split_into_words: bool,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let options = ModelInputOptions::new(
+            &self.tokenizer,
+            add_special_tokens,
+            padding,
+            truncation,
+            max_length,
+            return_tensors,
+            return_token_type_ids,
+            return_attention_mask,
+        )?;
+
+        let is_batch = !text.is_instance_of::<PyString>()
+            && (!is_split_into_words || text.get_item(0).is_ok_and(|first| !first.is_instance_of::<PyString>()));
+        let sequences = |texts: &Bound<'py, PyAny>| -> PyResult<Vec<Sequence>> {
+            if is_batch {
+                texts.try_iter()?.map(|text| Sequence::extract(&text?, is_split_into_words)).collect()
+            } else {
+                Ok(vec![Sequence::extract(texts, is_split_into_words)?])
+            }
+        };
+
+        let first = sequences(text)?;
+        let second = text_pair.map(sequences).transpose()?;
+        if second.as_ref().is_some_and(|second| second.len() != first.len()) {
+            return Err(PyValueError::new_err("text and text_pair must have the same number of sequences"));
+        }
+        self.model_inputs(py, first, second, &options, is_batch)
+    }
+
+    /// Prepare model inputs for a batch of texts or of `(text, text_pair)`
+    /// tuples, with the options of `__call__`
+    #[pyo3(signature = (
+        batch_text_or_text_pairs,
+        add_special_tokens = true,
+        padding = PaddingArg::Bool(false),
+        truncation = TruncationArg::Bool(false),
+        max_length = None,
+        return_tensors = None,
+        return_token_type_ids = true,
+        return_attention_mask = true,
+        is_split_into_words = false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn batch_encode_plus<'py>(
+        &self,
+        py: Python<'py>,
+        batch_text_or_text_pairs: &Bound<'py, PyAny>,
+        add_special_tokens: bool,
+        padding: PaddingArg,
+        truncation: TruncationArg,
+        max_length: Option<usize>,
+        return_tensors: Option<&str>,
+        return_token_type_ids: bool,
+        return_attention_mask: bool,
+        is_split_into_words: bool,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let options = ModelInputOptions::new(
+            &self.tokenizer,
+            add_special_tokens,
+            padding,
+            truncation,
+            max_length,
+            return_tensors,
+            return_token_type_ids,
+            return_attention_mask,
+        )?;
+
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        for item in batch_text_or_text_pairs.try_iter()? {
+            let item = item?;
+            // A pair is a tuple of two texts, or of two lists of words
+            let is_pair = !item.is_instance_of::<PyString>()
+                && (!is_split_into_words || item.get_item(0).is_ok_and(|first| !first.is_instance_of::<PyString>()));
+            if is_pair {
+                let (text, text_pair): (Bound<'py, PyAny>, Bound<'py, PyAny>) = item.extract()?;
+                first.push(Sequence::extract(&text, is_split_into_words)?);
+                second.push(Sequence::extract(&text_pair, is_split_into_words)?);
+            } else {
+                first.push(Sequence::extract(&item, is_split_into_words)?);
+            }
+        }
+        let second = match second.len() {
+            0 => None,
+            len if len == first.len() => Some(second),
+            _ => return Err(PyValueError::new_err("Either all or none of the batch must be pairs")),
+        };
+        self.model_inputs(py, first, second, &options, true)
+    }
+
+    /// Join the tokens of `ids` back into text. Ids missing from the vocabulary
+    /// are skipped, raise a ValueError with `on_unknown_id="error"` or become
+    /// the unknown token with `on_unknown_id="replace_with_unk"`.
+    #[pyo3(signature = (ids, skip_special_tokens = false, clean_up_tokenization_spaces = true, on_unknown_id = "skip"))]
+    fn decode(
+        &self,
+        py: Python<'_>,
+        ids: Vec<TokenId>,
+        skip_special_tokens: bool,
+        clean_up_tokenization_spaces: bool,
+        on_unknown_id: &str,
+    ) -> PyResult<String> {
+        let policy = parse_unknown_id_policy(on_unknown_id)?;
+        Ok(py.detach(|| {
+            self.tokenizer.try_decode(&ids, skip_special_tokens, clean_up_tokenization_spaces, policy)
+        })?)
+    }
+
+    /// Lazily encode each line of a file, yielding one list of ids per line.
+    /// The limits apply to each line as in `encode`.
+    fn encode_file(slf: Py<Self>, path: PathBuf) -> PyResult<EncodeFileIterator> {
+        let file = File::open(path).map_err(Error::from)?;
+        Ok(EncodeFileIterator {
+            tokenizer: slf,
+            lines: BufReader::new(file).lines(),
+        })
+    }
+
+    /// Lazily tokenize each text of an iterable, yielding one list of tokens per text
+    fn tokenize_iter(slf: Py<Self>, texts: &Bound<'_, PyAny>) -> PyResult<TokenizeIterator> {
+        Ok(TokenizeIterator {
+            tokenizer: slf,
+            texts: texts.try_iter()?.unbind(),
+        })
+    }
+
+    /// Return a copy of this tokenizer with a smaller vocabulary, and a dict mapping
+    /// old ids to new ids so embeddings can be shrunk to match. Either keep only
+    /// `keep_tokens`, or measure usage on `texts`, any iterable of strings such as
+    /// an open file, and drop unused tokens, then the least used ones until at most
+    /// `target_size` remain. Special, added and never_split tokens and single
+    /// characters are always kept, and ids stay in their original order.
+    #[pyo3(signature = (keep_tokens = None, texts = None, target_size = None))]
+    fn prune_vocab(
+        &self,
+        py: Python<'_>,
+        keep_tokens: Option<Vec<String>>,
+        texts: Option<&Bound<'_, PyAny>>,
+        target_size: Option<usize>,
+    ) -> PyResult<(Self, HashMap<TokenId, TokenId>)> {
+        let (tokenizer, id_map) = match (keep_tokens, texts) {
+            (Some(keep_tokens), None) => {
+                if target_size.is_some() {
+                    return Err(PyValueError::new_err("target_size requires a reference corpus in texts"));
+                }
+                let keep_tokens: HashSet<String> = keep_tokens.into_iter().collect();
+                self.tokenizer.prune_vocab(|token, _| keep_tokens.contains(token))?
+            }
+            (None, Some(texts)) => {
+                let token_counts = token_counts(py, &self.tokenizer, texts)?;
+                py.detach(|| self.tokenizer.prune_by_usage(&token_counts, target_size))?
+            }
+            _ => {
+                return Err(PyValueError::new_err(
+                    "Pass either keep_tokens or a reference corpus in texts",
+                ))
+            }
+        };
+        Ok((PyWordPieceTokenizer { tokenizer }, id_map))
+    }
+
+    /// Compare the vocabulary with `other`'s, e.g. before replacing the one a
+    /// deployed model was trained with. Returns a dict with the `added` and
+    /// `removed` tokens as (token, id) lists, the `moved` tokens as (token, id,
+    /// other id) lists, the number of `unchanged` tokens, the `changed_options`
+    /// mapping text to tokens differently, and whether the vocabularies are
+    /// `identical` or `compatible`, i.e. every token keeps its id.
+    fn compare_vocab<'py>(
+        &self,
+        py: Python<'py>,
+        other: PyRef<'py, PyWordPieceTokenizer>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let diff = self.tokenizer.compare_vocab(&other.tokenizer);
+        let dict = PyDict::new(py);
+        dict.set_item("identical", diff.is_identical())?;
+        dict.set_item("compatible", diff.is_compatible())?;
+        dict.set_item("added", diff.added)?;
+        dict.set_item("removed", diff.removed)?;
+        dict.set_item("moved", diff.moved)?;
+        dict.set_item("unchanged", diff.unchanged)?;
+        dict.set_item("changed_options", diff.changed_options)?;
+        Ok(dict)
+    }
+
+    /// Compare the ids of `texts` with `reference_ids` recorded from another
+    /// tokenizer, e.g. `BertTokenizerFast(texts)["input_ids"]`, to make sure a
+    /// migration changes no model inputs. Returns a dict with the number of
+    /// `texts`, of `matching` ones and the `match_rate`, the first
+    /// `max_divergences` differing texts as dicts of their `index`, the
+    /// `position` of the first differing token, the `expected` and `actual`
+    /// tokens around it and the text `context`, and a printable `report`.
+    #[pyo3(signature = (texts, reference_ids, add_special_tokens=true, max_divergences=10))]
+    fn parity_check<'py>(
+        &self,
+        py: Python<'py>,
+        texts: Vec<String>,
+        reference_ids: Vec<Vec<TokenId>>,
+        add_special_tokens: bool,
+        max_divergences: usize,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let report = py.detach(|| {
+            self.tokenizer
+                .parity_check(&texts, &reference_ids, add_special_tokens, max_divergences)
+        })?;
+
+        let divergences = PyList::empty(py);
+        for divergence in &report.divergences {
+            let item = PyDict::new(py);
+            item.set_item("index", divergence.index)?;
+            item.set_item("position", divergence.position)?;
+            item.set_item("expected", &divergence.expected)?;
+            item.set_item("actual", &divergence.actual)?;
+            item.set_item("context", &divergence.context)?;
+            divergences.append(item)?;
+        }
+
+        let dict = PyDict::new(py);
+        dict.set_item("texts", report.texts)?;
+        dict.set_item("matching", report.matching)?;
+        dict.set_item("match_rate", report.match_rate())?;
+        dict.set_item("divergences", divergences)?;
+        dict.set_item("report", report.to_string())?;
+        Ok(dict)
+    }
+
+    /// Measure how well the vocabulary fits `texts`, any iterable of strings such
+    /// as an open file. Returns a dict with the raw counts (texts, words, tokens,
+    /// unk_tokens, chars, covered_chars) and the `fertility` in tokens per word,
+    /// the `unk_rate` over tokens, the `char_coverage`, the fraction of word
+    /// characters not lost to the unknown token, and the `avg_sequence_length`.
+    fn evaluate<'py>(&self, py: Python<'py>, texts: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyDict>> {
+        let mut stats = EvaluationStats::default();
+        for_each_chunk(texts, |chunk| {
+            let chunk_stats = py.detach(|| self.tokenizer.evaluate(chunk));
+            stats = stats.add(chunk_stats);
+        })?;
+
+        let dict = PyDict::new(py);
+        dict.set_item("texts", stats.texts)?;
+        dict.set_item("words", stats.words)?;
+        dict.set_item("tokens", stats.tokens)?;
+        dict.set_item("unk_tokens", stats.unk_tokens)?;
+        dict.set_item("chars", stats.chars)?;
+        dict.set_item("covered_chars", stats.covered_chars)?;
+        dict.set_item("fertility", stats.fertility())?;
+        dict.set_item("unk_rate", stats.unk_rate())?;
+        dict.set_item("char_coverage", stats.char_coverage())?;
+        dict.set_item("avg_sequence_length", stats.avg_sequence_length())?;
+        Ok(dict)
+    }
+
+    /// Return the stretches of `text` that were mapped to the unknown token, as
+    /// (substring, start, end) tuples with offsets into `text` counted in
+    /// `offset_unit`, so the words the vocabulary cannot represent can be found
+    /// and fixed
+    #[pyo3(signature = (text, offset_unit = "char"))]
+    fn analyze(&self, py: Python<'_>, text: &str, offset_unit: &str) -> PyResult<Vec<(String, usize, usize)>> {
+        let mut converter = OffsetConverter::new(text, parse_offset_unit(offset_unit)?);
+        let unknown = py.detach(|| self.tokenizer.analyze(text));
+        Ok(unknown
+            .into_iter()
+            .map(|span| (span.text, converter.get(span.start), converter.get(span.end)))
+            .collect())
+    }
+
+    /// Continue the prompt encoded as `ids` with `suffix`, encoding the word the
+    /// prompt ends in again together with the suffix, as its pieces may split
+    /// differently once the word goes on. Returns the ids of the whole text and
+    /// how many of the prompt's ids were kept at their start. A prompt ending
+    /// with a space is continued with a suffix starting with one.
+    fn heal_prefix(&self, py: Python<'_>, ids: Vec<TokenId>, suffix: &str) -> (Vec<TokenId>, usize) {
+        let healed = py.detach(|| self.tokenizer.heal_prefix(&ids, suffix));
+        (healed.ids, healed.kept)
+    }
+
+    /// The `n` best segmentations of `text` as (tokens, cost) pairs, best first.
+    /// Words are segmented with any pieces of the vocabulary instead of the
+    /// longest match first. The cost is the number of pieces, or with `scores`,
+    /// a dict of id -> score such as log-probabilities, the negated total
+    /// score; pieces without a score are then not used.
+    #[pyo3(signature = (text, n = 5, scores = None))]
+    fn tokenize_nbest(
+        &self,
+        py: Python<'_>,
+        text: &str,
+        n: usize,
+        scores: Option<HashMap<TokenId, f64>>,
+    ) -> Vec<(Vec<String>, f64)> {
+        let cost = scores.map_or(SegmentationCost::PieceCount, SegmentationCost::Scores);
+        let segmentations = py.detach(|| self.tokenizer.tokenize_nbest(text, n, &cost));
+        segmentations
+            .into_iter()
+            .map(|segmentation| {
+                let tokens = segmentation
+                    .ids
+                    .iter()
+                    .filter_map(|&id| self.tokenizer.id_to_token(id).map(str::to_string))
+                    .collect();
+                (tokens, segmentation.cost)
+            })
+            .collect()
+    }
+
+    /// The segmentation lattice of each word of `text` as (word, edges) pairs,
+    /// where each edge is a vocabulary piece fitting the word as a (start, end,
+    /// token, id) tuple with character offsets into the word
+    fn segmentation_lattice(&self, py: Python<'_>, text: &str) -> Vec<(String, Vec<LatticeEdgeTuple>)> {
+        let lattices = py.detach(|| self.tokenizer.segmentation_lattice(text));
+        lattices
+            .into_iter()
+            .map(|lattice| {
+                let chars = |offset: usize| lattice.word[..offset].chars().count();
+                let edges = lattice
+                    .edges
+                    .iter()
+                    .map(|edge| {
+                        let token = self.tokenizer.id_to_token(edge.id).unwrap_or_default().to_string();
+                        (chars(edge.start), chars(edge.end), token, edge.id)
+                    })
+                    .collect();
+                (lattice.word, edges)
+            })
+            .collect()
+    }
+
+    /// Return the tokens of `text` as (piece, start, end, id, is_continuation)
+    /// tuples, with offsets into `text` counted in `offset_unit`, for
+    /// highlighting each token in a UI. `is_continuation` is true for pieces
+    /// continuing the word of the token before them.
+    #[pyo3(signature = (text, offset_unit = "char"))]
+    fn tokenize_with_spans(
+        &self,
+        py: Python<'_>,
+        text: &str,
+        offset_unit: &str,
+    ) -> PyResult<Vec<TokenSpanTuple>> {
+        let unit = parse_offset_unit(offset_unit)?;
+        let spans = py.detach(|| self.tokenizer.tokenize_with_spans_with_unit(text, unit));
+        Ok(spans
+            .into_iter()
+            .map(|span| (span.piece, span.start, span.end, span.id, span.is_continuation))
+            .collect())
+    }
+
+    /// Count how often each substring is mapped to the unknown token across
+    /// `texts`, any iterable of strings such as an open file. The dict is ordered
+    /// from the most to the least frequent.
+    fn unknown_counts<'py>(&self, py: Python<'py>, texts: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyDict>> {
+        let mut counts = HashMap::new();
+        for_each_chunk(texts, |chunk| py.detach(|| self.tokenizer.count_unknown_into(chunk, &mut counts)))?;
+
+        let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let dict = PyDict::new(py);
+        for (text, count) in counts {
+            dict.set_item(text, count)?;
+        }
+        Ok(dict)
+    }
+
+    /// Learn a vocabulary from `texts`, any iterable of strings such as a list or
+    /// a generator, the lines of `files`, a list of paths or glob patterns, and/or
+    /// `word_counts`, a precomputed dict of word -> frequency. Words are counted
+    /// as they are read, so the corpus never has to fit in memory. Alternatively,
+    /// `corpora` is a list of dicts with `texts`, `files` and/or `word_counts` and
+    /// a relative `weight`, rescaled so each corpus makes up its share of the words.
+    /// The result is ordered by id, and the same input always yields the same ids.
+    /// `progress` is called with the phase, step and total, which is None when
+    /// unknown, e.g. to drive a progress bar. Pieces continuing a word start with
+    /// `continuing_subword_prefix` and pieces ending one with `end_of_word_suffix`.
+    /// At most `limit_alphabet` distinct characters are kept, most frequent first,
+    /// and the first character of each string in `initial_alphabet` is always kept.
+    /// Learned tokens span at most `max_token_length` characters, and pieces that
+    /// continue a word span at least `min_subword_length`. Training continues from
+    /// `initial_vocab` when given, keeping its ids and growing it to `vocab_size`.
+    /// The merges, with their scores, are written to `merges_file` when given.
+    #[staticmethod]
+    #[pyo3(signature = (
+        texts = None,
+        vocab_size = 30000,
+        min_frequency = 2,
+        special_tokens = None,
+        strip_accents = true,
+        lowercase = true,
+        case_folding = "lowercase",
+        unicode_normalization = Some("nfkc"),
+        remove_control_chars = true,
+        tokenize_chinese_chars = true,
+        grapheme_clusters = false,
+        pre_tokenizer = "bert",
+        contractions = Some(ContractionsArg::Name("english".to_string())),
+        entities = None,
+        entity_placeholders = None,
+        morphology = None,
+        morphology_dictionary = None,
+        files = None,
+        progress = None,
+        continuing_subword_prefix = "##",
+        end_of_word_suffix = "",
+        limit_alphabet = None,
+        initial_alphabet = None,
+        max_token_length = None,
+        min_subword_length = 1,
+        initial_vocab = None,
+        word_counts = None,
+        corpora = None,
+        merges_file = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn train<'py>(
+        py: Python<'py>,
+        texts: Option<&Bound<'_, PyAny>>,
+        vocab_size: usize,
+        min_frequency: usize,
+        special_tokens: Option<Vec<String>>,
+        strip_accents: bool,
+        lowercase: bool,
+        case_folding: &str,
+        unicode_normalization: Option<&str>,
+        remove_control_chars: bool,
+        tokenize_chinese_chars: bool,
+        grapheme_clusters: bool,
+        pre_tokenizer: &str,
+        contractions: Option<ContractionsArg>,
+        entities: Option<Vec<String>>,
+        entity_placeholders: Option<HashMap<String, String>>,
+        morphology: Option<&str>,
+        morphology_dictionary: Option<String>,
+        files: Option<Vec<String>>,
+        progress: Option<Py<PyAny>>,
+        continuing_subword_prefix: &str,
+        end_of_word_suffix: &str,
+        limit_alphabet: Option<usize>,
+        initial_alphabet: Option<Vec<String>>,
+        max_token_length: Option<usize>,
+        min_subword_length: usize,
+        initial_vocab: Option<HashMap<String, TokenId>>,
+        word_counts: Option<HashMap<String, usize>>,
+        corpora: Option<Vec<Bound<'_, PyDict>>>,
+        merges_file: Option<PathBuf>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let mut trainer = new_trainer(
+            vocab_size,
+            min_frequency,
+            special_tokens,
+            strip_accents,
+            lowercase,
+            parse_case_folding(case_folding)?,
+            parse_normalization_form(unicode_normalization)?,
+            remove_control_chars,
+            tokenize_chinese_chars,
+            grapheme_clusters,
+            parse_pre_tokenizer(pre_tokenizer)?,
+            parse_contractions(contractions)?,
+            parse_entities(entities, entity_placeholders)?,
+            parse_morphology(morphology, morphology_dictionary)?,
+            continuing_subword_prefix,
+            end_of_word_suffix,
+            limit_alphabet,
+            initial_alphabet,
+            max_token_length,
+            min_subword_length,
+            initial_vocab,
+        )?;
+        let vocab = train_vocab(py, &mut trainer, texts, files, word_counts, corpora, progress, merges_file)?;
+        vocab_to_dict(py, &vocab)
+    }
+
+    /// Like `train`, but return a ready-to-use tokenizer instead of the vocabulary.
+    /// `[CLS]`, `[SEP]`, `[PAD]` and `[MASK]` are registered under their roles and
+    /// other special tokens besides `unk_token` as additional special tokens.
+    #[staticmethod]
+    #[pyo3(signature = (
+        texts = None,
+        vocab_size = 30000,
+        min_frequency = 2,
+        special_tokens = None,
+        strip_accents = true,
+        lowercase = true,
+        case_folding = "lowercase",
+        unicode_normalization = Some("nfkc"),
+        remove_control_chars = true,
+        tokenize_chinese_chars = true,
+        grapheme_clusters = false,
+        pre_tokenizer = "bert",
+        contractions = Some(ContractionsArg::Name("english".to_string())),
+        entities = None,
+        entity_placeholders = None,
+        morphology = None,
+        morphology_dictionary = None,
+        files = None,
+        progress = None,
+        unk_token = "[UNK]",
+        max_input_chars_per_word = 200,
+        continuing_subword_prefix = "##",
+        end_of_word_suffix = "",
+        limit_alphabet = None,
+        initial_alphabet = None,
+        max_token_length = None,
+        min_subword_length = 1,
+        initial_vocab = None,
+        word_counts = None,
+        corpora = None,
+        merges_file = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn train_new(
+        py: Python<'_>,
+        texts: Option<&Bound<'_, PyAny>>,
+        vocab_size: usize,
+        min_frequency: usize,
+        special_tokens: Option<Vec<String>>,
+        strip_accents: bool,
+        lowercase: bool,
+        case_folding: &str,
+        unicode_normalization: Option<&str>,
+        remove_control_chars: bool,
+        tokenize_chinese_chars: bool,
+        grapheme_clusters: bool,
+        pre_tokenizer: &str,
+        contractions: Option<ContractionsArg>,
+        entities: Option<Vec<String>>,
+        entity_placeholders: Option<HashMap<String, String>>,
+        morphology: Option<&str>,
+        morphology_dictionary: Option<String>,
+        files: Option<Vec<String>>,
+        progress: Option<Py<PyAny>>,
+        unk_token: &str,
+        max_input_chars_per_word: usize,
+        continuing_subword_prefix: &str,
+        end_of_word_suffix: &str,
+        limit_alphabet: Option<usize>,
+        initial_alphabet: Option<Vec<String>>,
+        max_token_length: Option<usize>,
+        min_subword_length: usize,
+        initial_vocab: Option<HashMap<String, TokenId>>,
+        word_counts: Option<HashMap<String, usize>>,
+        corpora: Option<Vec<Bound<'_, PyDict>>>,
+        merges_file: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        let mut trainer = new_trainer(
+            vocab_size,
+            min_frequency,
+            special_tokens,
+            strip_accents,
+            lowercase,
+            parse_case_folding(case_folding)?,
+            parse_normalization_form(unicode_normalization)?,
+            remove_control_chars,
+            tokenize_chinese_chars,
+            grapheme_clusters,
+            parse_pre_tokenizer(pre_tokenizer)?,
+            parse_contractions(contractions)?,
+            parse_entities(entities, entity_placeholders)?,
+            parse_morphology(morphology, morphology_dictionary)?,
+            continuing_subword_prefix,
+            end_of_word_suffix,
+            limit_alphabet,
+            initial_alphabet,
+            max_token_length,
+            min_subword_length,
+            initial_vocab,
+        )?;
+        let vocab = train_vocab(py, &mut trainer, texts, files, word_counts, corpora, progress, merges_file)?;
+        let tokenizer = WordPieceTokenizer::from_trained(vocab, &trainer, unk_token, max_input_chars_per_word)?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+
+    /// Train a new tokenizer on another corpus, reusing this tokenizer's
+    /// normalization options, subword affixes and its special, added and never_split tokens
+    #[pyo3(signature = (
+        texts = None,
+        vocab_size = 30000,
+        min_frequency = 2,
+        files = None,
+        progress = None,
+        word_counts = None,
+        corpora = None,
+        merges_file = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn train_from_iterator(
+        &self,
+        py: Python<'_>,
+        texts: Option<&Bound<'_, PyAny>>,
+        vocab_size: usize,
+        min_frequency: usize,
+        files: Option<Vec<String>>,
+        progress: Option<Py<PyAny>>,
+        word_counts: Option<HashMap<String, usize>>,
+        corpora: Option<Vec<Bound<'_, PyDict>>>,
+        merges_file: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        let mut trainer = self.tokenizer.trainer(vocab_size, min_frequency);
+        let vocab = train_vocab(py, &mut trainer, texts, files, word_counts, corpora, progress, merges_file)?;
+        let tokenizer = self.tokenizer.with_vocab(vocab)?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+
+    /// Return a copy of this tokenizer whose vocabulary is extended with up to
+    /// `num_new_tokens` tokens learned from `texts` and/or `files`, without
+    /// retraining from scratch. Existing tokens keep their ids.
+    #[pyo3(signature = (
+        texts = None,
+        num_new_tokens = 1000,
+        min_frequency = 2,
+        files = None,
+        progress = None,
+        word_counts = None,
+        corpora = None,
+        merges_file = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn extend_from_iterator(
+        &self,
+        py: Python<'_>,
+        texts: Option<&Bound<'_, PyAny>>,
+        num_new_tokens: usize,
+        min_frequency: usize,
+        files: Option<Vec<String>>,
+        progress: Option<Py<PyAny>>,
+        word_counts: Option<HashMap<String, usize>>,
+        corpora: Option<Vec<Bound<'_, PyDict>>>,
+        merges_file: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        let mut trainer = self.tokenizer.extension_trainer(num_new_tokens, min_frequency);
+        let vocab = train_vocab(py, &mut trainer, texts, files, word_counts, corpora, progress, merges_file)?;
+        let tokenizer = self.tokenizer.with_vocab(vocab)?;
+        Ok(PyWordPieceTokenizer { tokenizer })
+    }
+}
+
+/// The special tokens of BERT vocabularies
+impl PyWordPieceTokenizer {
+    /// Encode sequences, or pairs of them, in parallel and assemble them into
+    /// model inputs following `options`. Without `is_batch` the single row is
+    /// returned as flat lists.
+    fn model_inputs<'py>(
+        &self,
+        py: Python<'py>,
+        first: Vec<Sequence>,
+        second: Option<Vec<Sequence>>,
+        options: &ModelInputOptions,
+        is_batch: bool,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let tokenizer = &self.tokenizer;
+        let pad_id = match (&options.padding, tokenizer.pad_token_id()) {
+            (Some(_), None) => return Err(PyValueError::new_err("Padding requires a pad token")),
+            (_, pad_id) => pad_id.unwrap_or_default(),
+        };
+
+        let (mut input_ids, mut token_type_ids) = py.detach(|| -> Result<_, Error> {
+            let encode = |sequences: &[Sequence]| -> Result<Vec<Vec<TokenId>>, Error> {
+                sequences
+                    .par_iter()
+                    .map(|sequence| match sequence {
+                        Sequence::Text(text) => tokenizer.try_encode(text),
+                        Sequence::Words(words) => Ok(try_encode_words(tokenizer, words, OffsetUnit::Byte)?.ids().to_vec()),
+                    })
+                    .collect()
+            };
+            let first = encode(&first)?;
+            let second = second.as_deref().map(encode).transpose()?;
+
+            let mut rows = (Vec::with_capacity(first.len()), Vec::with_capacity(first.len()));
+            for (i, mut ids) in first.into_iter().enumerate() {
+                let mut pair = second.as_ref().map(|second| second[i].clone());
+                if let Some((strategy, max_length)) = options.truncation {
+                    let special = if options.add_special_tokens {
+                        tokenizer.num_special_tokens_to_add(pair.is_some())
+                    } else {
+                        0
+                    };
+                    let total = ids.len() + pair.as_ref().map_or(0, Vec::len) + special;
+                    tokenizer.truncate_sequences(&mut ids, pair.as_mut(), total.saturating_sub(max_length), strategy);
+                }
+
+                if options.add_special_tokens {
+                    rows.0.push(tokenizer.build_inputs_with_special_tokens(&ids, pair.as_deref()));
+                    rows.1.push(tokenizer.create_token_type_ids_from_sequences(&ids, pair.as_deref()));
+                } else {
+                    let pair = pair.unwrap_or_default();
+                    rows.1.push([vec![0; ids.len()], vec![1; pair.len()]].concat());
+                    rows.0.push([ids, pair].concat());
+                }
+            }
+            Ok(rows)
+        })?;
+
+        let lengths: Vec<usize> = input_ids.iter().map(Vec::len).collect();
+        let padded_len = match options.padding {
+            Some(Padding::Longest) => lengths.iter().copied().max(),
+            Some(Padding::MaxLength(max_length)) => Some(max_length),
+            None => None,
+        };
+        if let Some(padded_len) = padded_len {
+            for (ids, type_ids) in input_ids.iter_mut().zip(&mut token_type_ids) {
+                ids.resize(padded_len.max(ids.len()), pad_id);
+                type_ids.resize(padded_len.max(type_ids.len()), 0);
+            }
+        }
+        let attention_mask: Vec<Vec<u32>> = lengths
+            .iter()
+            .zip(&input_ids)
+            .map(|(&len, ids)| (0..ids.len()).map(|i| (i < len) as u32).collect())
+            .collect();
+
+        let mut columns = vec![("input_ids", input_ids)];
+        if options.return_token_type_ids {
+            columns.push(("token_type_ids", token_type_ids));
+        }
+        if options.return_attention_mask {
+            columns.push(("attention_mask", attention_mask));
+        }
+
+        let dict = PyDict::new(py);
+        for (name, rows) in columns {
+            let value = match options.return_tensors.as_deref() {
+                Some(framework) => {
+                    let cols = rows.first().map_or(0, Vec::len);
+                    if rows.iter().any(|row| row.len() != cols) {
+                        return Err(PyValueError::new_err(
+                            "Sequences of different lengths can only be returned as tensors with padding",
+                        ));
+                    }
+                    let array = int64_array(&py.import("numpy")?, &rows, cols, &|ids, col| ids[col] as i64)?;
+                    if framework == "pt" {
+                        py.import("torch")?.call_method1("from_numpy", (array,))?
+                    } else {
+                        array
+                    }
+                }
+                None if is_batch => rows.into_pyobject(py)?.into_any(),
+                None => rows.into_iter().next().unwrap_or_default().into_pyobject(py)?.into_any(),
+            };
+            dict.set_item(name, value)?;
+        }
+        Ok(dict)
+    }
+}
+
+fn default_special_tokens() -> Vec<String> {
+    vec![
+        "[UNK]".to_string(),
+        "[CLS]".to_string(),
+        "[SEP]".to_string(),
+        "[PAD]".to_string(),
+        "[MASK]".to_string(),
+    ]
+}
+
+/// A trainer configured from the keyword arguments of `train` and `train_new`
+#[allow(clippy::too_many_arguments)]
+fn new_trainer(
+    vocab_size: usize,
+    min_frequency: usize,
+    special_tokens: Option<Vec<String>>,
+    strip_accents: bool,
+    lowercase: bool,
+    case_folding: CaseFolding,
+    unicode_normalization: NormalizationForm,
+    remove_control_chars: bool,
+    tokenize_chinese_chars: bool,
+    grapheme_clusters: bool,
+    pre_tokenizer: PreTokenizer,
+    contractions: Contractions,
+    (entities, entity_placeholders): (Vec<Entity>, BTreeMap<Entity, String>),
+    morphology: Option<Morphology>,
+    continuing_subword_prefix: &str,
+    end_of_word_suffix: &str,
+    limit_alphabet: Option<usize>,
+    initial_alphabet: Option<Vec<String>>,
+    max_token_length: Option<usize>,
+    min_subword_length: usize,
+    initial_vocab: Option<HashMap<String, TokenId>>,
+) -> PyResult<WordPieceTrainer> {
+    let mut trainer = WordPieceTrainer::new(
+        vocab_size,
+        min_frequency,
+        special_tokens.unwrap_or_else(default_special_tokens),
+        strip_accents,
+        lowercase,
+    );
+    trainer.set_case_folding(case_folding);
+    trainer.set_unicode_normalization(unicode_normalization);
+    trainer.set_remove_control_chars(remove_control_chars);
+    trainer.set_tokenize_chinese_chars(tokenize_chinese_chars);
+    trainer.set_grapheme_clusters(grapheme_clusters);
+    trainer.set_pre_tokenizer(pre_tokenizer);
+    trainer.set_contractions(contractions);
+    trainer.set_entities(entities, entity_placeholders);
+    trainer.set_morphology(morphology.as_ref())?;
+    trainer.set_continuing_subword_prefix(continuing_subword_prefix);
+    trainer.set_end_of_word_suffix(end_of_word_suffix);
+    if let Some(limit) = limit_alphabet {
+        trainer.set_limit_alphabet(limit);
+    }
+    if let Some(alphabet) = initial_alphabet {
+        // Like the Hugging Face trainers, only the first character of each string is used
+        trainer.set_initial_alphabet(alphabet.iter().filter_map(|s| s.chars().next()));
+    }
+    if let Some(length) = max_token_length {
+        trainer.set_max_token_length(length);
+    }
+    trainer.set_min_subword_length(min_subword_length);
+    if let Some(vocab) = initial_vocab {
+        trainer.set_initial_vocab(vocab);
+    }
+    Ok(trainer)
+}
+
+/// Count how often `tokenizer` produces each id on `texts`, any iterable of
+/// strings, pulling chunks from Python and encoding them without the GIL
+fn token_counts(
+    py: Python<'_>,
+    tokenizer: &WordPieceTokenizer,
+    texts: &Bound<'_, PyAny>,
+) -> PyResult<HashMap<TokenId, usize>> {
+    let mut counts = HashMap::new();
+    for_each_chunk(texts, |chunk| py.detach(|| tokenizer.count_tokens_into(chunk, &mut counts)))?;
+    Ok(counts)
+}
+
+/// Pad `batch` to its longest sequence into a dict of 2-D int64 NumPy arrays of
+/// input ids, attention masks and token type ids. The arrays wrap buffers
+/// filled here, so only numpy itself is needed at runtime.
+fn batch_to_numpy<'py>(py: Python<'py>, batch: &[Vec<TokenId>], pad_id: TokenId) -> PyResult<Bound<'py, PyDict>> {
+    let numpy = py.import("numpy")?;
+    let cols = batch.iter().map(Vec::len).max().unwrap_or(0);
+    let array = |value: &dyn Fn(&[TokenId], usize) -> i64| int64_array(&numpy, batch, cols, value);
+
+    let dict = PyDict::new(py);
+    dict.set_item("input_ids", array(&|ids, col| ids.get(col).map_or(pad_id, |&id| id) as i64)?)?;
+    dict.set_item("attention_mask", array(&|ids, col| (col < ids.len()) as i64)?)?;
+    dict.set_item("token_type_ids", array(&|_, _| 0)?)?;
+    Ok(dict)
+}
+
+/// A 2-D int64 NumPy array with a row per sequence of `rows` and `cols`
+/// columns, filled with `value(row, col)`
+fn int64_array<'py>(
+    numpy: &Bound<'py, PyModule>,
+    rows: &[Vec<TokenId>],
+    cols: usize,
+    value: &dyn Fn(&[TokenId], usize) -> i64,
+) -> PyResult<Bound<'py, PyAny>> {
+    let buffer = PyByteArray::new_with(numpy.py(), rows.len() * cols * 8, |bytes| {
+        for (row, ids) in rows.iter().enumerate() {
+            for col in 0..cols {
+                let start = (row * cols + col) * 8;
+                bytes[start..start + 8].copy_from_slice(&value(ids, col).to_ne_bytes());
+            }
+        }
+        Ok(())
+    })?;
+    numpy
+        .call_method1("frombuffer", (buffer, "int64"))?
+        .call_method1("reshape", ((rows.len(), cols),))
+}
+
+/// The ids of `batch` as a pyarrow `ListArray` of int32, or a `LargeListArray`
+/// once there are too many ids for 32-bit offsets. The arrays wrap value and
+/// offset buffers filled here, so only pyarrow itself is needed at runtime.
+fn batch_to_arrow<'py>(py: Python<'py>, batch: &[Vec<TokenId>]) -> PyResult<Bound<'py, PyAny>> {
+    let pyarrow = py.import("pyarrow")?;
+    let total: usize = batch.iter().map(Vec::len).sum();
+
+    // Ids stay far below i32::MAX, so they keep the int32 type existing code expects
+    let values = PyByteArray::new_with(py, total * 4, |bytes| {
+        for (chunk, &id) in bytes.chunks_exact_mut(4).zip(batch.iter().flatten()) {
+            chunk.copy_from_slice(&id.to_le_bytes());
+        }
+        Ok(())
+    })?;
+
+    let large = total > i32::MAX as usize;
+    let width = if large { 8 } else { 4 };
+    let offsets = PyByteArray::new_with(py, (batch.len() + 1) * width, |bytes| {
+        let ends = batch.iter().scan(0, |end, ids| {
+            *end += ids.len();
+            Some(*end)
+        });
+        for (chunk, offset) in bytes.chunks_exact_mut(width).zip(std::iter::once(0).chain(ends)) {
+            if large {
+                chunk.copy_from_slice(&(offset as i64).to_le_bytes());
+            } else {
+                chunk.copy_from_slice(&(offset as i32).to_le_bytes());
+            }
+        }
+        Ok(())
+    })?;
+
+    let array = |arrow_type: &str, length: usize, buffer: Bound<'py, PyByteArray>| -> PyResult<Bound<'py, PyAny>> {
+        let buffers = PyList::new(py, [py.None(), pyarrow.call_method1("py_buffer", (buffer,))?.unbind()])?;
+        pyarrow
+            .getattr("Array")?
+            .call_method1("from_buffers", (pyarrow.call_method0(arrow_type)?, length, buffers))
+    };
+    let (offset_type, list_type) = if large { ("int64", "LargeListArray") } else { ("int32", "ListArray") };
+    pyarrow.getattr(list_type)?.call_method1(
+        "from_arrays",
+        (array(offset_type, batch.len() + 1, offsets)?, array("int32", total, values)?),
+    )
+}
+
+/// A text, or a list of words with `is_split_into_words`
+enum Sequence {
+    Text(String),
+    Words(Vec<String>),
+}
+
+impl Sequence {
+    fn extract(text: &Bound<'_, PyAny>, is_split_into_words: bool) -> PyResult<Self> {
+        if is_split_into_words {
+            Ok(Sequence::Words(extract_words(text)?))
+        } else {
+            Ok(Sequence::Text(text.extract()?))
+        }
+    }
+}
+
+/// `padding` of `__call__`: a bool or the name of a strategy
+#[derive(FromPyObject)]
+enum PaddingArg {
+    Bool(bool),
+    Name(String),
+}
+
+/// `truncation` of `__call__`: a bool or the name of a strategy
+#[derive(FromPyObject)]
+enum TruncationArg {
+    Bool(bool),
+    Name(String),
+}
+
+enum Padding {
+    Longest,
+    MaxLength(usize),
+}
+
+/// Options of `__call__` and `batch_encode_plus`
+struct ModelInputOptions {
+    add_special_tokens: bool,
+    padding: Option<Padding>,
+    truncation: Option<(TruncationStrategy, usize)>,
+    return_tensors: Option<String>,
+    return_token_type_ids: bool,
+    return_attention_mask: bool,
+}
+
+impl ModelInputOptions {
+    /// Truncation without a strategy or length uses the tokenizer's defaults
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        tokenizer: &WordPieceTokenizer,
+        add_special_tokens: bool,
+        padding: PaddingArg,
+        truncation: TruncationArg,
+        max_length: Option<usize>,
+        return_tensors: Option<&str>,
+        return_token_type_ids: bool,
+        return_attention_mask: bool,
+    ) -> PyResult<Self> {
+        let max_length = max_length.or(tokenizer.model_max_length());
+        let padding = match padding {
+            PaddingArg::Bool(false) => None,
+            PaddingArg::Bool(true) => Some(Padding::Longest),
+            PaddingArg::Name(name) => match name.as_str() {
+                "do_not_pad" => None,
+                "longest" => Some(Padding::Longest),
+                "max_length" => Some(Padding::MaxLength(max_length.ok_or_else(|| {
+                    PyValueError::new_err("padding='max_length' requires max_length or model_max_length")
+                })?)),
+                _ => {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown padding '{}', expected 'longest', 'max_length' or 'do_not_pad'",
+                        name
+                    )))
+                }
+            },
+        };
+
+        let strategy = match truncation {
+            TruncationArg::Bool(false) => None,
+            TruncationArg::Bool(true) => Some(tokenizer.truncation_strategy()),
+            TruncationArg::Name(name) => match name.as_str() {
+                "do_not_truncate" => None,
+                "longest_first" => Some(TruncationStrategy::LongestFirst),
+                "only_first" => Some(TruncationStrategy::OnlyFirst),
+                "only_second" => Some(TruncationStrategy::OnlySecond),
+                _ => {
+                    return Err(TruncationError::new_err(format!(
+                        "Unknown truncation '{}', expected 'longest_first', 'only_first', 'only_second' or \
+                         'do_not_truncate'",
+                        name
+                    )))
+                }
+            },
+        };
+        let truncation = match (strategy, max_length) {
+            (Some(strategy), Some(max_length)) => Some((strategy, max_length)),
+            (Some(_), None) => {
+                return Err(TruncationError::new_err("truncation requires max_length or model_max_length"))
+            }
+            (None, _) => None,
+        };
+
+        if let Some(name) = return_tensors.filter(|name| !matches!(*name, "np" | "pt")) {
+            return Err(PyValueError::new_err(format!(
+                "Unsupported return_tensors '{}', expected 'np' or 'pt'",
+                name
+            )));
+        }
+
+        Ok(ModelInputOptions {
+            add_special_tokens,
+            padding,
+            truncation,
+            return_tensors: return_tensors.map(str::to_string),
+            return_token_type_ids,
+            return_attention_mask,
+        })
+    }
+}
+
+/// Extract the list of words passed with `is_split_into_words`
+fn extract_words(words: &Bound<'_, PyAny>) -> PyResult<Vec<String>> {
+    if words.is_instance_of::<PyString>() {
+        return Err(PyTypeError::new_err(
+            "With is_split_into_words, text must be a list of words, not a string",
+        ));
+    }
+    words.extract()
+}
+
+/// `encode_words_with_unit` within the limits of the tokenizer, the words
+/// counting together towards `max_input_bytes`
+fn try_encode_words(tokenizer: &WordPieceTokenizer, words: &[String], unit: OffsetUnit) -> Result<Encoding, Error> {
+    let bytes = words.iter().map(String::len).sum();
+    tokenizer.within_limits(bytes, || tokenizer.encode_words_with_unit(words, unit), |encoding| encoding.ids().len())
+}
+
+/// Pull `texts`, any iterable of strings, from Python in chunks of
+/// `COUNT_CHUNK_SIZE` and pass each chunk to `f`
+fn for_each_chunk<F: FnMut(&[String])>(texts: &Bound<'_, PyAny>, mut f: F) -> PyResult<()> {
+    if texts.is_instance_of::<PyString>() {
+        return Err(PyTypeError::new_err("texts must be an iterable of strings, not a string"));
+    }
+
+    let mut chunk = Vec::with_capacity(COUNT_CHUNK_SIZE);
+    for text in texts.try_iter()? {
+        chunk.push(text?.extract::<String>()?);
+        if chunk.len() == COUNT_CHUNK_SIZE {
+            f(&chunk);
+            chunk.clear();
+        }
+    }
+    f(&chunk);
+    Ok(())
+}
+
+/// Count the words of `texts`, any iterable of strings, of the lines of the files
+/// matching the `files` patterns and of the `word_counts` frequency table, or of
+/// the weighted `corpora`, then learn a vocabulary with `trainer`, writing its
+/// merges to `merges_file` when given
+#[allow(clippy::too_many_arguments)]
+fn train_vocab(
+    py: Python<'_>,
+    trainer: &mut WordPieceTrainer,
+    texts: Option<&Bound<'_, PyAny>>,
+    files: Option<Vec<String>>,
+    word_counts: Option<HashMap<String, usize>>,
+    corpora: Option<Vec<Bound<'_, PyDict>>>,
+    progress: Option<Py<PyAny>>,
+    merges_file: Option<PathBuf>,
+) -> PyResult<HashMap<String, TokenId>> {
+    let has_sources = texts.is_some() || files.is_some() || word_counts.is_some();
+    if corpora.is_none() && !has_sources {
+        return Err(PyValueError::new_err(
+            "Either texts, files, word_counts or corpora must be given",
+        ));
+    }
+    if corpora.is_some() && has_sources {
+        return Err(PyValueError::new_err(
+            "Pass training text either through corpora or through texts, files and word_counts",
+        ));
+    }
+
+    let interrupt = Interrupt::default();
+    if let Some(progress) = progress {
+        // Training runs without the GIL, so take it back for each report
+        let interrupt = interrupt.clone();
+        trainer.set_progress(move |phase, step, total| {
+            Python::attach(|py| {
+                if let Err(err) = progress.call1(py, (phase, step, total)) {
+                    match err.is_instance_of::<PyKeyboardInterrupt>(py) {
+                        true => interrupt.set(err),
+                        false => err.write_unraisable(py, Some(progress.bind(py))),
+                    }
+                }
+            })
+        });
+    }
+    let should_stop = interrupt.clone();
+    trainer.set_should_stop(move || should_stop.check_signals());
+
+    let word_counts = match corpora {
+        Some(corpora) => {
+            let mut weighted = Vec::with_capacity(corpora.len());
+            for corpus in &corpora {
+                weighted.push(count_corpus(py, trainer, corpus, &interrupt)?);
+            }
+            weight_counts(weighted)?
+        }
+        None => count_sources(py, trainer, texts, files, word_counts, &interrupt)?,
+    };
+
+    let vocab = py.detach(|| trainer.train_on_counts(&word_counts));
+    interrupt.raise()?;
+    if let Some(path) = merges_file {
+        trainer.save_merges(path)?;
+    }
+    Ok(vocab)
+}
+
+/// Count the words of a corpus given as a dict with `texts`, `files` and/or
+/// `word_counts`, returning them with the corpus `weight`, 1 by default
+fn count_corpus(
+    py: Python<'_>,
+    trainer: &WordPieceTrainer,
+    corpus: &Bound<'_, PyDict>,
+    interrupt: &Interrupt,
+) -> PyResult<(HashMap<String, usize>, f64)> {
+    for key in corpus.keys() {
+        let key = key.extract::<String>()?;
+        if !matches!(key.as_str(), "texts" | "files" | "word_counts" | "weight") {
+            return Err(PyValueError::new_err(format!(
+                "Unknown corpus key '{}', expected texts, files, word_counts or weight",
+                key
+            )));
+        }
+    }
+
+    let texts = corpus.get_item("texts")?;
+    let files = corpus.get_item("files")?.map(|files| files.extract()).transpose()?;
+    let word_counts = corpus.get_item("word_counts")?.map(|counts| counts.extract()).transpose()?;
+    if texts.is_none() && files.is_none() && word_counts.is_none() {
+        return Err(PyValueError::new_err("Each corpus needs texts, files or word_counts"));
+    }
+    let weight = corpus.get_item("weight")?.map(|weight| weight.extract()).transpose()?.unwrap_or(1.0);
+
+    Ok((count_sources(py, trainer, texts.as_ref(), files, word_counts, interrupt)?, weight))
+}
+
+/// Count the words of `texts`, `files` and `word_counts`, see `train_vocab`
+fn count_sources(
+    py: Python<'_>,
+    trainer: &WordPieceTrainer,
+    texts: Option<&Bound<'_, PyAny>>,
+    files: Option<Vec<String>>,
+    table: Option<HashMap<String, usize>>,
+    interrupt: &Interrupt,
+) -> PyResult<HashMap<String, usize>> {
+    if texts.is_some_and(|texts| texts.is_instance_of::<PyString>()) {
+        return Err(PyTypeError::new_err(
+            "texts must be an iterable of strings, not a string; pass paths through files",
+        ));
+    }
+
+    // Pull texts from Python in chunks, counting each chunk in parallel without the GIL
+    let mut word_counts = HashMap::new();
+    if let Some(texts) = texts {
+        let mut chunk = Vec::with_capacity(COUNT_CHUNK_SIZE);
+        for text in texts.try_iter()? {
+            chunk.push(text?.extract::<String>()?);
+            if chunk.len() == COUNT_CHUNK_SIZE {
+                py.detach(|| trainer.count_texts(chunk.drain(..), &mut word_counts));
+                interrupt.raise()?;
+            }
+        }
+        py.detach(|| trainer.count_texts(chunk, &mut word_counts));
+    }
+    if let Some(files) = files {
+        py.detach(|| trainer.count_files(&files, &mut word_counts))?;
+    }
+    if let Some(table) = table {
+        py.detach(|| trainer.count_table(&table, &mut word_counts));
+    }
+    interrupt.raise()?;
+
+    Ok(word_counts)
+}
+
+/// The `KeyboardInterrupt`, or other error of a signal handler, that stopped a
+/// training run. Ctrl-C only sets a flag until Python code runs, so training
+/// without the GIL checks for it between merges and raises it once stopped.
+#[derive(Clone, Default)]
+struct Interrupt(Arc<Mutex<Option<PyErr>>>);
+
+impl Interrupt {
+    fn set(&self, err: PyErr) {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).get_or_insert(err);
+    }
+
+    /// Run the pending signal handlers, returning whether training should stop
+    fn check_signals(&self) -> bool {
+        if let Err(err) = Python::attach(|py| py.check_signals()) {
+            self.set(err);
+        }
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+    }
+
+    /// The error that stopped training, if any
+    fn raise(&self) -> PyResult<()> {
+        match self.0.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Iterator returned by `WordPieceTokenizer.encode_file`
+#[pyclass(module = "wordpiece_rs")]
+struct EncodeFileIterator {
+    tokenizer: Py<PyWordPieceTokenizer>,
+    lines: Lines<BufReader<File>>,
+}
+
+#[pymethods]
+impl EncodeFileIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Vec<TokenId>>> {
+        let tokenizer = &self.tokenizer.borrow(py).tokenizer;
+        let lines = &mut self.lines;
+        let ids = py.detach(|| lines.next().map(|line| tokenizer.try_encode(&line.map_err(Error::from)?)));
+        Ok(ids.transpose()?)
+    }
+}
+
+/// Iterator returned by `WordPieceTokenizer.tokenize_iter`
+#[pyclass(module = "wordpiece_rs")]
+struct TokenizeIterator {
+    tokenizer: Py<PyWordPieceTokenizer>,
+    texts: Py<PyIterator>,
+}
+
+#[pymethods]
+impl TokenizeIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<Vec<String>>> {
+        let text = match self.texts.bind(py).clone().next() {
+            Some(text) => text?.extract::<String>()?,
+            None => return Ok(None),
+        };
+        let tokenizer = &self.tokenizer.borrow(py).tokenizer;
+        Ok(Some(py.detach(|| tokenizer.tokenize(&text))))
+    }
+}
+
+/// Tokenizes text arriving in arbitrary chunks. Words are only emitted once the
+/// whitespace after them arrives; call `finish` at the end of the stream.
+#[pyclass(name = "StreamTokenizer", module = "wordpiece_rs")]
+struct PyStreamTokenizer {
+    tokenizer: Py<PyWordPieceTokenizer>,
+    buffer: StreamBuffer,
+}
+
+#[pymethods]
+impl PyStreamTokenizer {
+    #[new]
+    fn new(tokenizer: Py<PyWordPieceTokenizer>) -> Self {
+        PyStreamTokenizer {
+            tokenizer,
+            buffer: StreamBuffer::default(),
+        }
+    }
+
+    /// Add a chunk of text, returning the ids of the words it completed
+    fn feed(&mut self, py: Python<'_>, chunk: &str) -> Vec<TokenId> {
+        let text = match self.buffer.push(chunk) {
+            Some(text) => text,
+            None => return Vec::new(),
+        };
+        let tokenizer = &self.tokenizer.borrow(py).tokenizer;
+        py.detach(|| tokenizer.encode(&text))
+    }
+
+    /// Signal the end of the stream, returning the ids of the buffered trailing word
+    fn finish(&mut self, py: Python<'_>) -> Vec<TokenId> {
+        let text = self.buffer.take();
+        let tokenizer = &self.tokenizer.borrow(py).tokenizer;
+        py.detach(|| tokenizer.encode(&text))
+    }
+}
+
+/// Several tokenizers, e.g. a general, a code and a biomedical one, behind one
+/// disjoint id space. Each vocabulary's ids are shifted past those of the
+/// vocabularies before it. `router` is called with each text and returns the
+/// name of the vocabulary to encode it with; without one texts go to the first.
+#[pyclass(name = "MultiTokenizer", module = "wordpiece_rs")]
+struct PyMultiTokenizer {
+    tokenizer: MultiTokenizer,
+    router: Option<Py<PyAny>>,
+}
+
+impl PyMultiTokenizer {
+    fn index(&self, name: &str) -> PyResult<usize> {
+        self.tokenizer
+            .index_of(name)
+            .ok_or_else(|| VocabError::new_err(format!("Unknown vocabulary '{}'", name)))
+    }
+}
+
+#[pymethods]
+impl PyMultiTokenizer {
+    #[new]
+    #[pyo3(signature = (tokenizers, router = None))]
+    fn new(py: Python<'_>, tokenizers: Vec<(String, Py<PyWordPieceTokenizer>)>, router: Option<Py<PyAny>>) -> PyResult<Self> {
+        let tokenizers = tokenizers
+            .into_iter()
+            .map(|(name, tokenizer)| (name, tokenizer.borrow(py).tokenizer.clone()))
+            .collect();
+        Ok(PyMultiTokenizer {
+            tokenizer: MultiTokenizer::new(tokenizers)?,
+            router,
+        })
+    }
+
+    /// Names of the vocabularies in order
+    #[getter]
+    fn names(&self) -> Vec<String> {
+        self.tokenizer.names().map(str::to_string).collect()
+    }
+
+    /// Size of the shared id space, the number of embedding rows it needs
+    #[getter]
+    fn vocab_size(&self) -> usize {
+        self.tokenizer.vocab_size()
+    }
+
+    /// The `(start, end)` ids of a vocabulary in the shared id space
+    fn id_range(&self, name: &str) -> PyResult<(TokenId, TokenId)> {
+        let ids = self.tokenizer.id_range(self.index(name)?).unwrap_or_default();
+        Ok((ids.start, ids.end))
+    }
+
+    /// Name of the vocabulary an id belongs to, if any
+    fn domain_of(&self, id: TokenId) -> Option<String> {
+        let (index, _) = self.tokenizer.resolve(id)?;
+        self.tokenizer.names().nth(index).map(str::to_string)
+    }
+
+    /// The token with `id` in the shared id space, if any
+    fn id_to_token(&self, id: TokenId) -> Option<String> {
+        self.tokenizer.id_to_token(id).map(str::to_string)
+    }
+
+    /// Encode `text` with the vocabulary named `domain`, or the one the router picks
+    #[pyo3(signature = (text, domain = None))]
+    fn encode(&self, py: Python<'_>, text: &str, domain: Option<String>) -> PyResult<Vec<TokenId>> {
+        let domain = match (domain, &self.router) {
+            (Some(domain), _) => Some(domain),
+            (None, Some(router)) => Some(router.call1(py, (text,))?.extract(py)?),
+            (None, None) => None,
+        };
+        let index = domain.map_or(Ok(0), |domain| self.index(&domain))?;
+        Ok(py.detach(|| self.tokenizer.encode_with(text, index))?)
+    }
+
+    /// Encode `(text, vocabulary name)` pairs that were already routed, in order
+    fn encode_spans(&self, py: Python<'_>, spans: Vec<(String, String)>) -> PyResult<Vec<TokenId>> {
+        let spans = spans
+            .iter()
+            .map(|(text, name)| Ok((text.as_str(), self.index(name)?)))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(py.detach(|| self.tokenizer.encode_spans(&spans))?)
+    }
+
+    /// Join ids back into text, decoding each run of ids from one vocabulary
+    /// with its tokenizer and joining the runs with a space
+    #[pyo3(signature = (ids, skip_special_tokens = false, clean_up_tokenization_spaces = true))]
+    fn decode(
+        &self,
+        py: Python<'_>,
+        ids: Vec<TokenId>,
+        skip_special_tokens: bool,
+        clean_up_tokenization_spaces: bool,
+    ) -> String {
+        py.detach(|| self.tokenizer.decode(&ids, skip_special_tokens, clean_up_tokenization_spaces))
+    }
+}
+
+/// A WordPiece tokenizer handing the words its vocabulary cannot cover to a
+/// byte-level BPE model, given as its `vocab` and `merges` in learned order,
+/// instead of producing `[UNK]`. The BPE ids follow the WordPiece ids.
+#[pyclass(name = "FallbackTokenizer", module = "wordpiece_rs")]
+struct PyFallbackTokenizer {
+    tokenizer: FallbackTokenizer,
+}
+
+#[pymethods]
+impl PyFallbackTokenizer {
+    #[new]
+    fn new(
+        tokenizer: PyRef<'_, PyWordPieceTokenizer>,
+        vocab: HashMap<String, TokenId>,
+        merges: Vec<(String, String)>,
+    ) -> PyResult<Self> {
+        let fallback = BytePairModel::new(vocab, merges)?;
+        Ok(PyFallbackTokenizer {
+            tokenizer: FallbackTokenizer::new(tokenizer.tokenizer.clone(), fallback)?,
+        })
+    }
+
+    /// Fall back to the BPE model of a GPT-2 style `vocab.json` and `merges.txt`
+    #[staticmethod]
+    fn from_files(tokenizer: PyRef<'_, PyWordPieceTokenizer>, vocab_file: PathBuf, merges_file: PathBuf) -> PyResult<Self> {
+        let fallback = BytePairModel::from_files(vocab_file, merges_file)?;
+        Ok(PyFallbackTokenizer {
+            tokenizer: FallbackTokenizer::new(tokenizer.tokenizer.clone(), fallback)?,
+        })
+    }
+
+    /// Id of the first BPE token, ids below it are WordPiece ids
+    #[getter]
+    fn fallback_offset(&self) -> TokenId {
+        self.tokenizer.fallback_offset()
+    }
+
+    /// Size of the merged id space, the number of embedding rows it needs
+    #[getter]
+    fn vocab_size(&self) -> usize {
+        self.tokenizer.vocab_size()
+    }
+
+    /// The token with `id` in the merged id space, if any
+    fn id_to_token(&self, id: TokenId) -> Option<String> {
+        self.tokenizer.id_to_token(id).map(str::to_string)
+    }
+
+    fn tokenize(&self, py: Python<'_>, text: &str) -> Vec<String> {
+        py.detach(|| self.tokenizer.tokenize(text))
+    }
+
+    fn encode(&self, py: Python<'_>, text: &str) -> Vec<TokenId> {
+        py.detach(|| self.tokenizer.encode(text))
+    }
+
+    /// Encode many texts in parallel
+    fn encode_batch(&self, py: Python<'_>, texts: Vec<String>) -> Vec<Vec<TokenId>> {
+        py.detach(|| self.tokenizer.encode_batch(&texts))
+    }
+
+    /// Join ids back into text, turning BPE ids back into their words
+    #[pyo3(signature = (ids, skip_special_tokens = false, clean_up_tokenization_spaces = true))]
+    fn decode(
+        &self,
+        py: Python<'_>,
+        ids: Vec<TokenId>,
+        skip_special_tokens: bool,
+        clean_up_tokenization_spaces: bool,
+    ) -> String {
+        py.detach(|| self.tokenizer.decode(&ids, skip_special_tokens, clean_up_tokenization_spaces))
+    }
+}
+
+/// Union two token -> id vocabularies, e.g. a general and a domain one. With the
+/// `"prefer_first"` strategy the first keeps its ids and tokens only in the second
+/// are appended after them, and `"prefer_second"` does the reverse. Returns the
+/// merged vocabulary ordered by id and a dict with the `overlap`, `added` and
+/// `remapped` token counts and the `id_map` of old -> new ids of the other vocabulary.
+#[pyfunction]
+#[pyo3(signature = (first, second, strategy = "prefer_first"))]
+fn merge_vocabs<'py>(
+    py: Python<'py>,
+    first: HashMap<String, TokenId>,
+    second: HashMap<String, TokenId>,
+    strategy: &str,
+) -> PyResult<(Bound<'py, PyDict>, Bound<'py, PyDict>)> {
+    let strategy = match strategy {
+        "prefer_first" => MergeStrategy::PreferFirst,
+        "prefer_second" => MergeStrategy::PreferSecond,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "Unknown merge strategy '{}', expected 'prefer_first' or 'prefer_second'",
+                strategy
+            )))
+        }
+    };
+
+    let merged = merge_vocab_maps(&first, &second, strategy);
+    let stats = PyDict::new(py);
+    stats.set_item("overlap", merged.overlap)?;
+    stats.set_item("added", merged.added)?;
+    stats.set_item("remapped", merged.remapped)?;
+    stats.set_item("id_map", merged.id_map)?;
+    Ok((vocab_to_dict(py, &merged.vocab)?, stats))
+}
+
+#[pymodule]
+fn wordpiece_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyWordPieceTokenizer>()?;
+    m.add_class::<PyStreamTokenizer>()?;
+    m.add_class::<PyMultiTokenizer>()?;
+    m.add_class::<PyFallbackTokenizer>()?;
+    m.add_class::<PyEncoding>()?;
+    m.add_class::<Token>()?;
+    m.add("WordPieceError", m.py().get_type::<WordPieceError>())?;
+    m.add("VocabError", m.py().get_type::<VocabError>())?;
+    m.add("NormalizationError", m.py().get_type::<NormalizationError>())?;
+    m.add("TruncationError", m.py().get_type::<TruncationError>())?;
+    m.add_function(wrap_pyfunction!(merge_vocabs, m)?)?;
+    Ok(())
+}
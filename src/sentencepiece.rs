@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    default_continuing_subword_prefix, CaseFolding, Contractions, Error, NormalizationForm, PreTokenizer, Result,
+    SpecialTokens, TokenId, TokenizerSnapshot, TruncationStrategy, VocabBackend, WordPieceTokenizer,
+};
+
+/// Marks the start of a word in SentencePiece pieces
+const WORD_START: char = '\u{2581}';
+
+/// Longest word WordPiece tries to split, as for BERT vocabularies
+const MAX_INPUT_CHARS_PER_WORD: usize = 200;
+
+// Types of `SentencePiece` in sentencepiece_model.proto
+const NORMAL: u64 = 1;
+const UNKNOWN: u64 = 2;
+const CONTROL: u64 = 3;
+const USER_DEFINED: u64 = 4;
+const UNUSED: u64 = 5;
+const BYTE: u64 = 6;
+
+/// A field of a protobuf message with its value
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Reads the fields of a protobuf message one after another
+struct ProtoReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ProtoReader<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.buf.split_first().ok_or_else(|| invalid("truncated varint"))?;
+            self.buf = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.buf.len() {
+            return Err(invalid("truncated field"));
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, Field<'a>)>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Field::Fixed
+            }
+            2 => {
+                let len = self.varint()?;
+                Field::Bytes(self.take(usize::try_from(len).map_err(|_| invalid("field is too long"))?)?)
+            }
+            5 => {
+                self.take(4)?;
+                Field::Fixed
+            }
+            wire_type => return Err(invalid(&format!("unsupported wire type {}", wire_type))),
+        };
+        Ok(Some((key >> 3, field)))
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidSentencePieceModel(reason.to_string())
+}
+
+fn utf8(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| invalid("piece is not valid UTF-8"))
+}
+
+/// The parts of a SentencePiece `ModelProto` needed to rebuild its vocabulary
+#[derive(Default)]
+struct SentencePieceModel {
+    pieces: Vec<(String, u64)>,
+    pad_id: Option<usize>,
+    normalizer: String,
+}
+
+impl SentencePieceModel {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut model = SentencePieceModel::default();
+        let mut reader = ProtoReader { buf: bytes };
+
+        while let Some((number, field)) = reader.next_field()? {
+            match (number, field) {
+                // repeated SentencePiece pieces = 1
+                (1, Field::Bytes(bytes)) => {
+                    let mut piece = ProtoReader { buf: bytes };
+                    let (mut text, mut kind) = (String::new(), NORMAL);
+                    while let Some((number, field)) = piece.next_field()? {
+                        match (number, field) {
+                            (1, Field::Bytes(bytes)) => text = utf8(bytes)?,
+                            (3, Field::Varint(value)) => kind = value,
+                            _ => {}
+                        }
+                    }
+                    model.pieces.push((text, kind));
+                }
+                // TrainerSpec trainer_spec = 2, of which int32 pad_id = 43
+                (2, Field::Bytes(bytes)) => {
+                    let mut spec = ProtoReader { buf: bytes };
+                    while let Some((number, field)) = spec.next_field()? {
+                        if let (43, Field::Varint(value)) = (number, field) {
+                            // Negative ids, i.e. no pad piece, are sign extended
+                            model.pad_id = usize::try_from(value as i64).ok();
+                        }
+                    }
+                }
+                // NormalizerSpec normalizer_spec = 3, of which string name = 1
+                (3, Field::Bytes(bytes)) => {
+                    let mut spec = ProtoReader { buf: bytes };
+                    while let Some((number, field)) = spec.next_field()? {
+                        if let (1, Field::Bytes(bytes)) = (number, field) {
+                            model.normalizer = utf8(bytes)?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if model.pieces.is_empty() {
+            return Err(invalid("no pieces"));
+        }
+        Ok(model)
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Load the vocabulary of a SentencePiece model file (`.model`), keeping the
+    /// id of every piece. Pieces starting a word with `▁` become plain tokens and
+    /// the others `##` continuations. Control pieces become special tokens, user
+    /// defined pieces added tokens, and byte fallback pieces are kept as they
+    /// are. Text is case folded if the model's normalizer case folds. Words are
+    /// split by greedy longest match, so the segmentation can differ from
+    /// SentencePiece's own.
+    pub fn from_sentencepiece<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let model = SentencePieceModel::parse(&fs::read(path)?)?;
+        let prefix = default_continuing_subword_prefix();
+
+        let mut vocab = HashMap::new();
+        let mut unk_token = None;
+        let mut pad_token = None;
+        let mut control = Vec::new();
+        let mut added_tokens = Vec::new();
+
+        for (id, (piece, kind)) in model.pieces.iter().enumerate() {
+            let token = match *kind {
+                UNUSED => continue,
+                UNKNOWN | CONTROL | USER_DEFINED | BYTE => piece.trim_start_matches(WORD_START).to_string(),
+                _ => match piece.strip_prefix(WORD_START) {
+                    Some(word) => word.to_string(),
+                    None => format!("{}{}", prefix, piece),
+                },
+            };
+            // A bare word start only separates words, which whitespace already does
+            if token.is_empty() {
+                continue;
+            }
+            // Keep the first of two pieces spelling the same token
+            if vocab.contains_key(&token) {
+                continue;
+            }
+            if model.pad_id == Some(id) {
+                pad_token = Some(token.clone());
+            }
+            vocab.insert(token.clone(), TokenId::try_from(id).map_err(|_| invalid("too many pieces"))?);
+
+            match *kind {
+                UNKNOWN => unk_token = Some(token),
+                CONTROL => control.push(token),
+                USER_DEFINED => added_tokens.push(token),
+                _ => {}
+            }
+        }
+        let unk_token = unk_token.ok_or_else(|| invalid("no unknown piece"))?;
+
+        let mut special_tokens = SpecialTokens::bert_defaults(&vocab);
+        if pad_token.is_some() {
+            special_tokens.pad_token = pad_token;
+        }
+        let with_role: Vec<&String> = special_tokens.roles().map(|(_, token)| token).collect();
+        special_tokens.additional_special_tokens =
+            control.iter().filter(|token| !with_role.contains(token)).cloned().collect();
+
+        Self::from_snapshot(TokenizerSnapshot {
+            vocab,
+            unk_token,
+            max_input_chars_per_word: MAX_INPUT_CHARS_PER_WORD,
+            max_input_chars_unit: None,
+            strip_accents: false,
+            lowercase: model.normalizer.ends_with("_cf"),
+            case_folding: CaseFolding::Fold,
+            unicode_normalization: match model.normalizer.as_str() {
+                "identity" => NormalizationForm::None,
+                _ => NormalizationForm::Nfkc,
+            },
+            // The `nmt_` rules of SentencePiece drop control characters too
+            remove_control_chars: model.normalizer.starts_with("nmt"),
+            tokenize_chinese_chars: true,
+            grapheme_clusters: false,
+            morphology: None,
+            special_tokens,
+            added_tokens,
+            added_only_tokens: Vec::new(),
+            never_split: Vec::new(),
+            cache_capacity: 0,
+            vocab_backend: VocabBackend::default(),
+            continuing_subword_prefix: prefix,
+            end_of_word_suffix: String::new(),
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            model_max_length: None,
+            max_input_bytes: None,
+            max_tokens: None,
+            truncation_strategy: TruncationStrategy::default(),
+        })
+    }
+}
@@ -0,0 +1,157 @@
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::{BatchingTokenizer, Error, Result, ServeConfig, TokenId, WordPieceTokenizer};
+
+#[derive(Clone)]
+struct AppState {
+    tokenizer: Arc<WordPieceTokenizer>,
+    batcher: BatchingTokenizer,
+}
+
+#[derive(Deserialize)]
+struct EncodeOptions {
+    /// Wrap the ids in `[CLS]` and `[SEP]`
+    #[serde(default)]
+    add_special_tokens: bool,
+    /// Also return the tokens
+    #[serde(default)]
+    return_tokens: bool,
+}
+
+#[derive(Deserialize)]
+struct EncodeRequest {
+    text: String,
+    #[serde(flatten)]
+    options: EncodeOptions,
+}
+
+#[derive(Serialize)]
+struct EncodeResponse {
+    ids: Vec<TokenId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tokens: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct EncodeBatchRequest {
+    texts: Vec<String>,
+    #[serde(flatten)]
+    options: EncodeOptions,
+}
+
+#[derive(Serialize)]
+struct EncodeBatchResponse {
+    ids: Vec<Vec<TokenId>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tokens: Option<Vec<Vec<String>>>,
+}
+
+#[derive(Deserialize)]
+struct DecodeRequest {
+    ids: Vec<TokenId>,
+    #[serde(default)]
+    skip_special_tokens: bool,
+    #[serde(default = "default_clean_up_tokenization_spaces")]
+    clean_up_tokenization_spaces: bool,
+}
+
+#[derive(Serialize)]
+struct DecodeResponse {
+    text: String,
+}
+
+type HandlerResult<T> = std::result::Result<Json<T>, (StatusCode, String)>;
+
+/// Serve the tokenizer over HTTP on `addr` until the process is stopped, with
+/// the endpoints of `router`
+pub async fn serve<A: ToSocketAddrs>(tokenizer: WordPieceTokenizer, addr: A, config: ServeConfig) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, router(tokenizer, config)).await?;
+    Ok(())
+}
+
+/// Build the routes of the tokenization service, to be served on their own or
+/// nested in an existing axum application. Must be called from within a Tokio
+/// runtime, which runs the batcher.
+///
+/// - `POST /encode` takes `{"text": ...}` and returns `{"ids": [...]}`
+/// - `POST /encode_batch` takes `{"texts": [...]}` and returns `{"ids": [[...], ...]}`
+/// - `POST /decode` takes `{"ids": [...]}` and returns `{"text": ...}`
+/// - `GET /metrics` returns the tokenizer's `metrics` for Prometheus
+///
+/// Both encode endpoints accept `add_special_tokens` and `return_tokens`, the
+/// latter adding a `tokens` field, and answer `413 Payload Too Large` when a
+/// text exceeds the tokenizer's `max_input_bytes` or `max_tokens`. Decoding
+/// accepts `skip_special_tokens` and `clean_up_tokenization_spaces`. Texts of concurrent encode requests are
+/// encoded together in parallel, in batches of up to `max_batch_size` texts.
+pub fn router(tokenizer: WordPieceTokenizer, config: ServeConfig) -> Router {
+    let tokenizer = Arc::new(tokenizer);
+    let batcher = BatchingTokenizer::new(tokenizer.clone(), config);
+
+    Router::new()
+        .route("/encode", post(encode))
+        .route("/encode_batch", post(encode_batch))
+        .route("/decode", post(decode))
+        .route("/metrics", get(metrics))
+        .with_state(AppState { tokenizer, batcher })
+}
+
+impl AppState {
+    /// Queue texts for the next batch and wait for their ids
+    async fn encode(
+        &self,
+        texts: Vec<String>,
+        options: &EncodeOptions,
+    ) -> std::result::Result<Vec<Vec<TokenId>>, (StatusCode, String)> {
+        let ids = self.batcher.encode_batch(texts).await.map_err(|err| match err {
+            Error::BatchingStopped => (StatusCode::INTERNAL_SERVER_ERROR, "Encoding failed".to_string()),
+            err => (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()),
+        })?;
+
+        Ok(match options.add_special_tokens {
+            true => ids.iter().map(|ids| self.tokenizer.build_inputs_with_special_tokens(ids, None)).collect(),
+            false => ids,
+        })
+    }
+
+    fn tokens(&self, ids: &[TokenId]) -> Vec<String> {
+        ids.iter().filter_map(|&id| self.tokenizer.id_to_token(id)).map(str::to_string).collect()
+    }
+}
+
+async fn encode(State(state): State<AppState>, Json(request): Json<EncodeRequest>) -> HandlerResult<EncodeResponse> {
+    let ids = state.encode(vec![request.text], &request.options).await?.pop().unwrap_or_default();
+    let tokens = request.options.return_tokens.then(|| state.tokens(&ids));
+    Ok(Json(EncodeResponse { ids, tokens }))
+}
+
+async fn encode_batch(
+    State(state): State<AppState>,
+    Json(request): Json<EncodeBatchRequest>,
+) -> HandlerResult<EncodeBatchResponse> {
+    let ids = state.encode(request.texts, &request.options).await?;
+    let tokens = request.options.return_tokens.then(|| ids.iter().map(|ids| state.tokens(ids)).collect());
+    Ok(Json(EncodeBatchResponse { ids, tokens }))
+}
+
+async fn decode(State(state): State<AppState>, Json(request): Json<DecodeRequest>) -> HandlerResult<DecodeResponse> {
+    let text = state
+        .tokenizer
+        .decode(&request.ids, request.skip_special_tokens, request.clean_up_tokenization_spaces);
+    Ok(Json(DecodeResponse { text }))
+}
+
+async fn metrics(State(state): State<AppState>) -> ([(header::HeaderName, &'static str); 1], String) {
+    let text = state.tokenizer.metrics().to_prometheus("wordpiece");
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text)
+}
+
+fn default_clean_up_tokenization_spaces() -> bool {
+    true
+}
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines};
+use std::path::Path;
+
+use crate::{Result, TokenId, WordPieceTokenizer};
+
+/// Iterator encoding a reader line by line, see `WordPieceTokenizer::encode_lines`
+pub struct EncodeLines<'a, R> {
+    tokenizer: &'a WordPieceTokenizer,
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> Iterator for EncodeLines<'_, R> {
+    type Item = io::Result<Vec<TokenId>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.next().map(|line| line.map(|line| self.tokenizer.encode(&line)))
+    }
+}
+
+/// Text received so far that may still end in the middle of a word
+#[derive(Default)]
+pub(crate) struct StreamBuffer {
+    pending: String,
+}
+
+impl StreamBuffer {
+    /// Append a chunk and take the text up to the last whitespace, whose words
+    /// are complete and can be tokenized
+    pub(crate) fn push(&mut self, chunk: &str) -> Option<String> {
+        self.pending.push_str(chunk);
+
+        let (i, c) = self.pending.char_indices().rev().find(|(_, c)| c.is_whitespace())?;
+        let rest = self.pending.split_off(i + c.len_utf8());
+        Some(std::mem::replace(&mut self.pending, rest))
+    }
+
+    /// Take all buffered text, complete or not
+    pub(crate) fn take(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Tokenizes text arriving in arbitrary chunks, such as from a socket or a live
+/// transcription. Words are only emitted once the whitespace after them arrives,
+/// so splitting a word across chunks yields the same ids as encoding it whole.
+pub struct StreamTokenizer<'a> {
+    tokenizer: &'a WordPieceTokenizer,
+    buffer: StreamBuffer,
+}
+
+impl StreamTokenizer<'_> {
+    /// Add a chunk of text, returning the ids of the words it completed
+    pub fn feed(&mut self, chunk: &str) -> Vec<TokenId> {
+        match self.buffer.push(chunk) {
+            Some(text) => self.tokenizer.encode(&text),
+            None => Vec::new(),
+        }
+    }
+
+    /// Signal the end of the stream, returning the ids of the buffered trailing word
+    pub fn finish(&mut self) -> Vec<TokenId> {
+        self.tokenizer.encode(&self.buffer.take())
+    }
+}
+
+impl WordPieceTokenizer {
+    /// Start tokenizing a stream of text chunks, see `StreamTokenizer`
+    pub fn stream(&self) -> StreamTokenizer<'_> {
+        StreamTokenizer {
+            tokenizer: self,
+            buffer: StreamBuffer::default(),
+        }
+    }
+
+    /// Lazily encode each line of `reader`, so inputs larger than memory can be
+    /// tokenized. Line endings are not part of the encoded text.
+    pub fn encode_lines<R: BufRead>(&self, reader: R) -> EncodeLines<'_, R> {
+        EncodeLines {
+            tokenizer: self,
+            lines: reader.lines(),
+        }
+    }
+
+    /// Lazily encode each line of the file at `path`, see `encode_lines`
+    pub fn encode_file<P: AsRef<Path>>(&self, path: P) -> Result<EncodeLines<'_, BufReader<File>>> {
+        Ok(self.encode_lines(BufReader::new(File::open(path)?)))
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{TokenId, WordPieceTokenizer};
+
+/// Which sequence of a pair loses tokens when truncating to a maximum length
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TruncationStrategy {
+    /// Remove tokens one at a time from whichever sequence is longer
+    #[default]
+    LongestFirst,
+    /// Only remove tokens from the first sequence
+    OnlyFirst,
+    /// Only remove tokens from the second sequence
+    OnlySecond,
+}
+
+impl WordPieceTokenizer {
+    /// Number of special tokens `build_inputs_with_special_tokens` adds to a
+    /// single sequence, or to a pair with `pair`
+    pub fn num_special_tokens_to_add(&self, pair: bool) -> usize {
+        let cls = self.cls_token_id().is_some() as usize;
+        let sep = self.sep_token_id().is_some() as usize;
+        if pair {
+            cls + 2 * sep
+        } else {
+            cls + sep
+        }
+    }
+
+    /// Wrap the ids of a sequence, or of a pair, with special tokens the way
+    /// BERT expects them: `[CLS] A [SEP]` or `[CLS] A [SEP] B [SEP]`. Tokens
+    /// without a registered id are left out.
+    pub fn build_inputs_with_special_tokens(&self, ids: &[TokenId], pair: Option<&[TokenId]>) -> Vec<TokenId> {
+        let cls = self.cls_token_id();
+        let sep = self.sep_token_id();
+
+        let mut inputs = Vec::with_capacity(ids.len() + pair.map_or(0, <[TokenId]>::len) + 3);
+        inputs.extend(cls);
+        inputs.extend_from_slice(ids);
+        inputs.extend(sep);
+        if let Some(pair) = pair {
+            inputs.extend_from_slice(pair);
+            inputs.extend(sep);
+        }
+        inputs
+    }
+
+    /// Encode `text` like `encode`, wrapped with special tokens the way
+    /// `build_inputs_with_special_tokens` wraps a single sequence
+    pub fn encode_with_special_tokens(&self, text: &str) -> Vec<TokenId> {
+        self.build_inputs_with_special_tokens(&self.encode(text), None)
+    }
+
+    /// Segment ids matching `build_inputs_with_special_tokens`: 0 for the first
+    /// sequence and its special tokens, 1 for the second and its `[SEP]`
+    pub fn create_token_type_ids_from_sequences(&self, ids: &[TokenId], pair: Option<&[TokenId]>) -> Vec<u32> {
+        let sep = self.sep_token_id().is_some() as usize;
+        let first = self.num_special_tokens_to_add(false) + ids.len();
+        let mut type_ids = vec![0; first];
+        if let Some(pair) = pair {
+            type_ids.resize(first + pair.len() + sep, 1);
+        }
+        type_ids
+    }
+
+    /// Remove `num_tokens_to_remove` tokens from the ends of `ids` and `pair`
+    /// following `strategy`, as many as there are when too few are left
+    pub fn truncate_sequences(
+        &self,
+        ids: &mut Vec<TokenId>,
+        mut pair: Option<&mut Vec<TokenId>>,
+        num_tokens_to_remove: usize,
+        strategy: TruncationStrategy,
+    ) {
+        for _ in 0..num_tokens_to_remove {
+            let longer = match (&mut pair, strategy) {
+                (Some(pair), TruncationStrategy::OnlySecond) => pair,
+                (None, TruncationStrategy::OnlySecond) => return,
+                (Some(pair), TruncationStrategy::LongestFirst) if pair.len() >= ids.len() => pair,
+                _ => &mut *ids,
+            };
+            if longer.pop().is_none() {
+                return;
+            }
+        }
+    }
+}
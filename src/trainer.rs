@@ -1,35 +1,110 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::cmp::Ordering;
-use unicode_normalization::UnicodeNormalization;
-use regex::{Regex, RegexBuilder};
-
-#[derive(Debug, Clone)]
-struct Symbol {
-    text: String,
-    count: usize,
-    score: f64,
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::fmt;
+use rayon::prelude::*;
+use regex::Regex;
+
+use crate::entities::EntityFinder;
+use crate::morphology::Analyzer;
+use crate::normalizer::TextCleaner;
+use crate::pre_tokenizer::{word_regex, Words};
+use crate::trie::Trie;
+use crate::{
+    strip_accents, CaseFolding, Contractions, Entity, Error, Morphology, NormalizationForm, PreTokenizer, Result,
+    TokenId,
+};
+
+/// Number of texts pre-tokenized in parallel at a time while counting words
+pub(crate) const COUNT_CHUNK_SIZE: usize = 8192;
+
+/// Callback receiving the training phase, the current step and the total number
+/// of steps when it is known, see `WordPieceTrainer::set_progress`
+pub type ProgressCallback = dyn Fn(&str, usize, Option<usize>) + Send + Sync;
+
+/// Callback telling training to stop early, see `WordPieceTrainer::set_should_stop`
+pub type StopCallback = dyn Fn() -> bool + Send + Sync;
+
+/// A source of training text, see `WordPieceTrainer::train_from_corpora`
+pub enum Corpus<'a> {
+    /// Texts from any iterator
+    Texts(Box<dyn Iterator<Item = String> + 'a>),
+    /// The lines of the files matching glob patterns
+    Files(Vec<String>),
+    /// A precomputed word frequency table
+    Counts(HashMap<String, usize>),
 }
 
-impl Symbol {
-    fn new(text: String, count: usize) -> Self {
-        Symbol {
-            text,
-            count,
-            score: 0.0,
-        }
-    }
-}
-
-#[derive(Debug)]
+/// Learns a WordPiece vocabulary from a corpus.
+///
+/// Training is deterministic: the same input and options always assign the same
+/// ids, whatever the number of threads or the hashing seed. Special tokens come
+/// first in the given order, then the alphabet sorted by symbol, then learned
+/// tokens in the order they were merged, ties between pairs broken by their
+/// symbols. No randomness is involved, so there is no seed to set.
 pub struct WordPieceTrainer {
     vocab_size: usize,
     min_frequency: usize,
-    special_tokens: Vec<String>,
+    pub(crate) special_tokens: Vec<String>,
     basic_tokenizer: Regex,
-    punctuation: Regex,
-    chinese_chars: Regex,
-    strip_accents: bool,
-    lowercase: bool,
+    pub(crate) strip_accents: bool,
+    pub(crate) lowercase: bool,
+    pub(crate) case_folding: CaseFolding,
+    pub(crate) unicode_normalization: NormalizationForm,
+    pub(crate) remove_control_chars: bool,
+    pub(crate) tokenize_chinese_chars: bool,
+    pub(crate) grapheme_clusters: bool,
+    pub(crate) pre_tokenizer: PreTokenizer,
+    pub(crate) contractions: Contractions,
+    pub(crate) entities: Vec<Entity>,
+    pub(crate) entity_placeholders: BTreeMap<Entity, String>,
+    entity_finder: Option<EntityFinder>,
+    pub(crate) morphology: Option<Analyzer>,
+    pub(crate) continuing_subword_prefix: String,
+    pub(crate) end_of_word_suffix: String,
+    limit_alphabet: Option<usize>,
+    initial_alphabet: HashSet<char>,
+    max_token_length: Option<usize>,
+    min_subword_length: usize,
+    initial_vocab: Option<HashMap<String, TokenId>>,
+    progress: Option<Arc<ProgressCallback>>,
+    should_stop: Option<Arc<StopCallback>>,
+    texts_counted: AtomicUsize,
+    merges: Mutex<Vec<(String, String, f64)>>,
+}
+
+impl fmt::Debug for WordPieceTrainer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WordPieceTrainer")
+            .field("vocab_size", &self.vocab_size)
+            .field("min_frequency", &self.min_frequency)
+            .field("special_tokens", &self.special_tokens)
+            .field("strip_accents", &self.strip_accents)
+            .field("lowercase", &self.lowercase)
+            .field("case_folding", &self.case_folding)
+            .field("unicode_normalization", &self.unicode_normalization)
+            .field("remove_control_chars", &self.remove_control_chars)
+            .field("tokenize_chinese_chars", &self.tokenize_chinese_chars)
+            .field("grapheme_clusters", &self.grapheme_clusters)
+            .field("pre_tokenizer", &self.pre_tokenizer)
+            .field("contractions", &self.contractions)
+            .field("entities", &self.entities)
+            .field("entity_placeholders", &self.entity_placeholders)
+            .field("morphology", &self.morphology.as_ref().map(Analyzer::morphology))
+            .field("continuing_subword_prefix", &self.continuing_subword_prefix)
+            .field("end_of_word_suffix", &self.end_of_word_suffix)
+            .field("limit_alphabet", &self.limit_alphabet)
+            .field("initial_alphabet", &self.initial_alphabet)
+            .field("max_token_length", &self.max_token_length)
+            .field("min_subword_length", &self.min_subword_length)
+            .field("initial_vocab", &self.initial_vocab.as_ref().map(HashMap::len))
+            .finish_non_exhaustive()
+    }
 }
 
 impl WordPieceTrainer {
@@ -40,44 +115,198 @@ impl WordPieceTrainer {
         strip_accents: bool,
         lowercase: bool,
     ) -> Self {
-        let basic_tokenizer = RegexBuilder::new(r"'s|'t|'re|'ve|'m|'ll|'d| ?[\p{L}\p{N}]+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+")
-            .case_insensitive(true)
-            .build()
-            .unwrap();
-        
-        let punctuation = RegexBuilder::new(r"\p{P}")
-            .build()
-            .unwrap();
-
-        let chinese_chars = RegexBuilder::new(r"[\p{Script=Han}]")
-            .build()
-            .unwrap();
+        let basic_tokenizer = word_regex(&Contractions::default());
 
         WordPieceTrainer {
             vocab_size,
             min_frequency,
             special_tokens,
             basic_tokenizer,
-            punctuation,
-            chinese_chars,
             strip_accents,
             lowercase,
+            case_folding: CaseFolding::default(),
+            unicode_normalization: NormalizationForm::default(),
+            remove_control_chars: true,
+            tokenize_chinese_chars: true,
+            grapheme_clusters: false,
+            pre_tokenizer: PreTokenizer::default(),
+            contractions: Contractions::default(),
+            entities: Vec::new(),
+            entity_placeholders: BTreeMap::new(),
+            entity_finder: None,
+            morphology: None,
+            continuing_subword_prefix: DEFAULT_CONTINUING_SUBWORD_PREFIX.to_string(),
+            end_of_word_suffix: String::new(),
+            limit_alphabet: None,
+            initial_alphabet: HashSet::new(),
+            max_token_length: None,
+            min_subword_length: 1,
+            initial_vocab: None,
+            progress: None,
+            should_stop: None,
+            texts_counted: AtomicUsize::new(0),
+            merges: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Lowercase with `case_folding` instead of the Unicode lowercase mapping
+    pub fn set_case_folding(&mut self, case_folding: CaseFolding) {
+        self.case_folding = case_folding;
+    }
+
+    /// Apply `form` to text before splitting it into words, instead of NFKC
+    pub fn set_unicode_normalization(&mut self, form: NormalizationForm) {
+        self.unicode_normalization = form;
+    }
+
+    /// Whether to drop NUL, U+FFFD and control characters from the corpus, as BERT does
+    pub fn set_remove_control_chars(&mut self, remove: bool) {
+        self.remove_control_chars = remove;
+    }
+
+    /// Whether to split CJK ideographs into words of their own, as BERT does
+    pub fn set_tokenize_chinese_chars(&mut self, tokenize: bool) {
+        self.tokenize_chinese_chars = tokenize;
+    }
+
+    /// Whether to keep grapheme clusters whole, so that emoji sequences become
+    /// words of their own
+    pub fn set_grapheme_clusters(&mut self, grapheme_clusters: bool) {
+        self.grapheme_clusters = grapheme_clusters;
+    }
+
+    /// Split the corpus into words with `pre_tokenizer` instead of as BERT does
+    pub fn set_pre_tokenizer(&mut self, pre_tokenizer: PreTokenizer) {
+        self.pre_tokenizer = pre_tokenizer;
+    }
+
+    /// Which contractions the BERT pre-tokenizer matches as words of their own
+    pub fn set_contractions(&mut self, contractions: Contractions) {
+        self.basic_tokenizer = word_regex(&contractions);
+        self.contractions = contractions;
+    }
+
+    /// Keep `entities` such as URLs whole instead of splitting them around their
+    /// punctuation. Entities with a placeholder are left out of the counts and
+    /// their placeholders added to the special tokens.
+    pub fn set_entities(&mut self, entities: Vec<Entity>, placeholders: BTreeMap<Entity, String>) {
+        for placeholder in placeholders.values() {
+            if !self.special_tokens.contains(placeholder) {
+                self.special_tokens.push(placeholder.clone());
+            }
+        }
+        self.entity_finder = EntityFinder::new(&entities);
+        self.entities = entities;
+        self.entity_placeholders = placeholders;
+    }
+
+    /// Apply `morphology` to the corpus before splitting it into words, loading
+    /// its dictionary if it has one
+    pub fn set_morphology(&mut self, morphology: Option<&Morphology>) -> Result<()> {
+        self.morphology = morphology.map(Analyzer::new).transpose()?;
+        Ok(())
+    }
+
+    /// Mark pieces that continue a word with `prefix` instead of `##`. An empty
+    /// prefix leaves continuation pieces unmarked, as in many BPE vocabularies.
+    pub fn set_continuing_subword_prefix(&mut self, prefix: &str) {
+        self.continuing_subword_prefix = prefix.to_string();
+    }
+
+    /// Mark pieces that end a word with `suffix`, e.g. `</w>`. Empty by default,
+    /// leaving word ends unmarked.
+    pub fn set_end_of_word_suffix(&mut self, suffix: &str) {
+        self.end_of_word_suffix = suffix.to_string();
+    }
+
+    /// Keep at most `limit` distinct characters, dropping the least frequent ones
+    /// from the words they occur in. Characters of the initial alphabet are kept
+    /// first, so noisy Unicode corpora cannot flood the vocabulary with rare symbols.
+    pub fn set_limit_alphabet(&mut self, limit: usize) {
+        self.limit_alphabet = Some(limit);
+    }
+
+    /// Characters always included in the vocabulary, even if they do not occur in
+    /// the corpus, in every form they can take within a word
+    pub fn set_initial_alphabet<I: IntoIterator<Item = char>>(&mut self, alphabet: I) {
+        self.initial_alphabet = alphabet.into_iter().collect();
+    }
+
+    /// Never merge pieces into a token spanning more than `length` characters of a
+    /// word, not counting the continuation prefix and end-of-word suffix
+    pub fn set_max_token_length(&mut self, length: usize) {
+        self.max_token_length = Some(length);
+    }
+
+    /// Leave pieces that continue a word and span fewer than `length` characters
+    /// out of the vocabulary, single characters included. They are still merged
+    /// into longer pieces while training, but words that can only be segmented
+    /// with them map to the unknown token.
+    pub fn set_min_subword_length(&mut self, length: usize) {
+        self.min_subword_length = length;
+    }
+
+    /// Continue training from an existing vocabulary instead of from scratch. Its
+    /// tokens keep their ids, words are first segmented with it, and merges on top
+    /// of its tokens add new ones after the largest id until `vocab_size` is reached.
+    pub fn set_initial_vocab(&mut self, vocab: HashMap<String, TokenId>) {
+        self.initial_vocab = Some(vocab);
+    }
+
+    /// Report progress to `callback` while training. It is called with the phase,
+    /// `"count"` while reading the corpus and `"merge"` while learning the
+    /// vocabulary, the current step, and the total number of steps when known:
+    /// texts counted so far with no total, or the vocabulary size so far out of
+    /// `vocab_size`.
+    pub fn set_progress<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, usize, Option<usize>) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+    }
+
+    /// Stop training once `callback` returns true, e.g. when the user asks to
+    /// cancel a long run. It is checked between chunks of counted texts and
+    /// between merges, and training then returns the vocabulary learned so far,
+    /// from the words counted so far.
+    pub fn set_should_stop<F>(&mut self, callback: F)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.should_stop = Some(Arc::new(callback));
+    }
+
+    /// The pairs merged by the last training run, in order, with the score they
+    /// were picked with
+    pub fn merges(&self) -> Vec<(String, String, f64)> {
+        self.merges.lock().unwrap().clone()
+    }
+
+    fn report(&self, phase: &str, step: usize, total: Option<usize>) {
+        if let Some(progress) = &self.progress {
+            progress(phase, step, total);
         }
     }
 
+    fn should_stop(&self) -> bool {
+        self.should_stop.as_ref().is_some_and(|should_stop| should_stop())
+    }
+
     fn clean_text(&self, text: &str) -> String {
-        // Normalize unicode characters
-        let text = text.nfkc().collect::<String>();
-        
-        // Replace whitespace characters with space
-        let text = text.replace(|c: char| c.is_whitespace(), " ");
-        
-        // Handle Chinese characters by adding spaces around them
-        let text = self.chinese_chars.replace_all(&text, |caps: &regex::Captures| {
-            format!(" {} ", &caps[0])
-        }).into_owned();
-        
-        text
+        let cleaner = TextCleaner {
+            unicode_normalization: self.unicode_normalization,
+            remove_control_chars: self.remove_control_chars,
+            tokenize_chinese_chars: self.tokenize_chinese_chars,
+            grapheme_clusters: self.grapheme_clusters,
+        };
+        let mut cleaned = String::with_capacity(text.len());
+        cleaner.clean(text, 0, &mut cleaned);
+
+        // Apply morphological analysis, e.g. to find the words of Japanese text
+        match &self.morphology {
+            Some(analyzer) => analyzer.apply(&cleaned),
+            None => cleaned,
+        }
     }
 
     fn strip_accents_if_needed(&self, text: &str) -> String {
@@ -85,220 +314,607 @@ impl WordPieceTrainer {
             return text.to_string();
         }
 
-        text.nfd()
-            .filter(|&c| !c.is_ascii_punctuation() && !c.is_ascii_control())
-            .collect::<String>()
+        strip_accents(text).into_owned()
     }
 
     fn basic_tokenize(&self, text: &str) -> Vec<String> {
         let mut tokens = Vec::new();
+        match &self.entity_finder {
+            Some(finder) => finder.split(text, |_, stretch, entity| match entity {
+                Some(entity) if self.entity_placeholders.contains_key(&entity) => {}
+                _ => self.basic_tokenize_words(stretch, entity.is_some(), &mut tokens),
+            }),
+            None => self.basic_tokenize_words(text, false, &mut tokens),
+        }
+        tokens
+    }
+
+    /// Normalize and split text into words and punctuation, or only normalize
+    /// it into a single word when `whole`
+    fn basic_tokenize_words(&self, text: &str, whole: bool, tokens: &mut Vec<String>) {
+        if self.pre_tokenizer == PreTokenizer::Whitespace {
+            tokens.extend(text.split_whitespace().map(str::to_string));
+            return;
+        }
         let text = self.clean_text(text);
-        
-        for mat in self.basic_tokenizer.find_iter(&text) {
-            let mut token_text = mat.as_str().trim().to_string();
+        let words = match whole {
+            true => Words::whole(&text),
+            false => self.pre_tokenizer.words(&self.basic_tokenizer, &text, self.grapheme_clusters),
+        };
+
+        for (_, matched) in words {
+            let mut token_text = matched.to_string();
             
             // Handle casing
             if self.lowercase {
-                token_text = token_text.to_lowercase();
+                token_text = self.case_folding.apply(&token_text);
             }
             
             // Handle accents
             token_text = self.strip_accents_if_needed(&token_text);
-            
-            // Split on punctuation
-            let mut char_tokens = Vec::new();
-            let mut current = String::new();
-            
-            for c in token_text.chars() {
-                if self.punctuation.is_match(&c.to_string()) {
-                    if !current.is_empty() {
-                        char_tokens.push(current);
-                        current = String::new();
-                    }
-                    char_tokens.push(c.to_string());
-                } else {
-                    current.push(c);
+
+            // Entities kept whole are not split on punctuation
+            if whole {
+                if !token_text.is_empty() {
+                    tokens.push(token_text);
                 }
+                continue;
             }
-            
-            if !current.is_empty() {
-                char_tokens.push(current);
-            }
-            
-            tokens.extend(char_tokens);
+
+            // Split on punctuation, as the BERT pre-tokenizer does
+            self.pre_tokenizer.split_word(&token_text, self.grapheme_clusters, |range| {
+                tokens.push(token_text[range].to_string())
+            });
         }
-        
-        tokens
     }
 
-    fn get_initial_symbols(&self, texts: &[String]) -> HashMap<String, Symbol> {
-        let mut char_counts: HashMap<String, usize> = HashMap::new();
-        let mut word_counts: HashMap<String, usize> = HashMap::new();
-
-        // First pass: count characters and words
+    /// Count the words of each text into `word_counts`, pre-tokenizing chunks of
+    /// texts in parallel
+    pub(crate) fn count_texts<I, S>(&self, texts: I, word_counts: &mut HashMap<String, usize>)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let mut chunk = Vec::with_capacity(COUNT_CHUNK_SIZE);
         for text in texts {
-            let tokens = self.basic_tokenize(text);
-            for token in tokens {
-                *word_counts.entry(token.clone()).or_insert(0) += 1;
-                for c in token.chars() {
-                    *char_counts.entry(c.to_string()).or_insert(0) += 1;
+            chunk.push(text);
+            if chunk.len() == COUNT_CHUNK_SIZE {
+                self.count_chunk(&chunk, word_counts);
+                chunk.clear();
+                if self.should_stop() {
+                    return;
                 }
             }
         }
+        self.count_chunk(&chunk, word_counts);
+    }
 
-        // Create initial symbols from characters that appear in frequent words
-        let mut symbols: HashMap<String, Symbol> = HashMap::new();
-        
-        // Add special tokens first
-        for token in &self.special_tokens {
-            symbols.insert(
-                token.clone(),
-                Symbol::new(token.clone(), word_counts.get(token).copied().unwrap_or(0)),
-            );
+    fn count_chunk<S: AsRef<str> + Sync>(&self, texts: &[S], word_counts: &mut HashMap<String, usize>) {
+        if texts.is_empty() {
+            return;
         }
 
-        // Add characters from words that meet minimum frequency
-        for (word, &count) in &word_counts {
-            if count >= self.min_frequency {
-                for c in word.chars() {
-                    let c_str = c.to_string();
-                    if !symbols.contains_key(&c_str) {
-                        symbols.insert(
-                            c_str.clone(),
-                            Symbol::new(c_str, *char_counts.get(&c.to_string()).unwrap()),
-                        );
-                    }
+        let chunk_counts = texts
+            .par_iter()
+            .fold(HashMap::new, |mut counts, text| {
+                for word in self.basic_tokenize(text.as_ref()) {
+                    *counts.entry(word).or_insert(0) += 1;
+                }
+                counts
+            })
+            .reduce(HashMap::new, merge_counts);
+        merge_counts_into(word_counts, chunk_counts);
+
+        let texts_counted = self.texts_counted.fetch_add(texts.len(), AtomicOrdering::Relaxed) + texts.len();
+        self.report("count", texts_counted, None);
+    }
+
+    /// Count the words of every line of the files matching each glob pattern
+    /// into `word_counts`, reading one line at a time
+    pub(crate) fn count_files<S: AsRef<str>>(&self, patterns: &[S], word_counts: &mut HashMap<String, usize>) -> Result<()> {
+        for path in expand_patterns(patterns)? {
+            let mut lines = BufReader::new(File::open(path)?).lines();
+            loop {
+                let chunk = lines.by_ref().take(COUNT_CHUNK_SIZE).collect::<io::Result<Vec<String>>>()?;
+                if chunk.is_empty() {
+                    break;
+                }
+                self.count_chunk(&chunk, word_counts);
+                if self.should_stop() {
+                    return Ok(());
                 }
             }
         }
+        Ok(())
+    }
 
-        symbols
+    /// Count the words of a precomputed frequency table into `word_counts`. Its
+    /// words are normalized and split like corpus text, so the table may hold raw words.
+    pub(crate) fn count_table(&self, table: &HashMap<String, usize>, word_counts: &mut HashMap<String, usize>) {
+        let table_counts = table
+            .par_iter()
+            .fold(HashMap::new, |mut counts, (word, &count)| {
+                for word in self.basic_tokenize(word) {
+                    *counts.entry(word).or_insert(0) += count;
+                }
+                counts
+            })
+            .reduce(HashMap::new, merge_counts);
+        merge_counts_into(word_counts, table_counts);
     }
 
-    fn compute_pair_scores(
-        &self,
-        texts: &[String],
-        symbols: &HashMap<String, Symbol>,
-    ) -> HashMap<(String, String), usize> {
-        let mut pair_counts: HashMap<(String, String), usize> = HashMap::new();
-        let symbol_set: HashSet<_> = symbols.keys().collect();
+    pub fn train(&self, texts: &[String]) -> HashMap<String, TokenId> {
+        self.train_from_iter(texts)
+    }
 
-        for text in texts {
-            let tokens = self.basic_tokenize(text);
-            for token in tokens {
-                if token.len() <= 1 {
-                    continue;
-                }
+    /// Train on texts from any iterator, counting words as they are produced so
+    /// the corpus never has to be held in memory
+    pub fn train_from_iter<I, S>(&self, texts: I) -> HashMap<String, TokenId>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str> + Sync,
+    {
+        let mut word_counts = HashMap::new();
+        self.texts_counted.store(0, AtomicOrdering::Relaxed);
+        self.count_texts(texts, &mut word_counts);
+        self.train_on_counts(&word_counts)
+    }
 
-                let mut current_symbols = Vec::new();
-                let mut current = String::new();
+    /// Train on the lines of the files matching each glob pattern, e.g. `data/*.txt`
+    pub fn train_from_files<S: AsRef<str>>(&self, patterns: &[S]) -> Result<HashMap<String, TokenId>> {
+        let mut word_counts = HashMap::new();
+        self.texts_counted.store(0, AtomicOrdering::Relaxed);
+        self.count_files(patterns, &mut word_counts)?;
+        Ok(self.train_on_counts(&word_counts))
+    }
 
-                // Split word into known symbols
-                for c in token.chars() {
-                    current.push(c);
-                    if symbol_set.contains(&current) {
-                        current_symbols.push(current.clone());
-                        current.clear();
-                    }
-                }
+    /// Train on a precomputed word frequency table, e.g. from a Spark job, skipping
+    /// the corpus entirely. Words are normalized and split like corpus text.
+    pub fn train_from_counts(&self, word_counts: &HashMap<String, usize>) -> HashMap<String, TokenId> {
+        let mut counts = HashMap::new();
+        self.count_table(word_counts, &mut counts);
+        self.train_on_counts(&counts)
+    }
 
-                // Count adjacent pairs
-                for pair in current_symbols.windows(2) {
-                    if let [first, second] = pair {
-                        *pair_counts.entry((first.clone(), second.clone())).or_insert(0) += 1;
-                    }
-                }
+    /// Train on several corpora with relative weights, e.g. 0.7 for news and 0.3
+    /// for code. Word counts are rescaled so each corpus makes up its share of the
+    /// word occurrences whatever its size, so small domains are not drowned out.
+    pub fn train_from_corpora(&self, corpora: Vec<(Corpus<'_>, f64)>) -> Result<HashMap<String, TokenId>> {
+        self.texts_counted.store(0, AtomicOrdering::Relaxed);
+        let mut weighted = Vec::with_capacity(corpora.len());
+        for (corpus, weight) in corpora {
+            let mut word_counts = HashMap::new();
+            match corpus {
+                Corpus::Texts(texts) => self.count_texts(texts, &mut word_counts),
+                Corpus::Files(patterns) => self.count_files(&patterns, &mut word_counts)?,
+                Corpus::Counts(table) => self.count_table(&table, &mut word_counts),
             }
+            weighted.push((word_counts, weight));
         }
-
-        pair_counts
+        Ok(self.train_on_counts(&weight_counts(weighted)?))
     }
 
-    fn merge_symbols(
-        &self,
-        symbols: &mut HashMap<String, Symbol>,
-        pair_counts: &HashMap<(String, String), usize>,
-    ) -> Option<(String, String)> {
-        // Find the best pair to merge
-        let mut best_pair = None;
-        let mut best_score = 0.0;
+    /// Learn a vocabulary from word frequencies: start from the characters of the
+    /// words, with the continuation prefix marking characters inside a word and
+    /// the end-of-word suffix appended to the last one, then repeatedly merge
+    /// the adjacent pair with the highest `count(pair) / (count(first) * count(second))`.
+    /// With an initial vocabulary, words start from their pieces in it instead.
+    pub(crate) fn train_on_counts(&self, word_counts: &HashMap<String, usize>) -> HashMap<String, TokenId> {
+        let mut vocab = VocabBuilder::new(self.initial_vocab.clone().unwrap_or_default());
 
-        for ((first, second), &count) in pair_counts {
-            if count < self.min_frequency {
-                continue;
+        // Add special tokens first
+        for token in &self.special_tokens {
+            vocab.add(token);
+        }
+
+        // Split every distinct word into its pieces in the initial vocabulary, or
+        // else into the characters of the alphabet it contains
+        let mut segmenter = self.initial_vocab.as_ref().map(|initial_vocab| self.segmenter(initial_vocab));
+        let mut pieces = Vec::new();
+        let mut symbols = SymbolTable::default();
+        let mut sorted_words: Vec<(&String, &usize)> = word_counts
+            .iter()
+            .filter(|(word, &count)| count > 0 && !word.is_empty())
+            .collect();
+        sorted_words.sort();
+        let alphabet = self.alphabet(&sorted_words);
+        let mut words: Vec<Word> = sorted_words
+            .into_iter()
+            .filter_map(|(word, &count)| {
+                if let Some(segmenter) = &mut segmenter {
+                    if segmenter.segment(word, &self.end_of_word_suffix, &mut pieces) {
+                        let word_symbols = pieces
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &piece)| {
+                                let len = self.piece_len(piece, i > 0, i == pieces.len() - 1);
+                                symbols.intern(piece.to_string(), len, i > 0)
+                            })
+                            .collect();
+                        return Some(Word {
+                            symbols: word_symbols,
+                            count,
+                        });
+                    }
+                }
+
+                let last = word.chars().count() - 1;
+                let word_symbols: Vec<u32> = word
+                    .chars()
+                    .enumerate()
+                    .filter(|(_, c)| alphabet.contains(c))
+                    .map(|(i, c)| symbols.intern(self.symbol(c, i == 0, i == last), 1, i > 0))
+                    .collect();
+                (!word_symbols.is_empty()).then_some(Word {
+                    symbols: word_symbols,
+                    count,
+                })
+            })
+            .collect();
+
+        let mut initial_alphabet: Vec<char> = self.initial_alphabet.iter().copied().collect();
+        initial_alphabet.sort_unstable();
+        for c in initial_alphabet {
+            for (is_first, is_last) in [(true, true), (true, false), (false, true), (false, false)] {
+                symbols.intern(self.symbol(c, is_first, is_last), 1, !is_first);
             }
+        }
 
-            // Skip if either symbol is not in vocabulary (might have been merged)
-            if !symbols.contains_key(first) || !symbols.contains_key(second) {
-                continue;
+        // The alphabet is always kept so every word made of its characters can be tokenized
+        let mut alphabet_symbols: Vec<u32> = (0..symbols.names.len() as u32).collect();
+        alphabet_symbols.sort_by_key(|&symbol| &symbols.names[symbol as usize]);
+        for symbol in alphabet_symbols {
+            if self.is_kept(&symbols, symbol) {
+                vocab.add(&symbols.names[symbol as usize]);
             }
+        }
 
-            // Compute score using frequency-based heuristic
-            let score = count as f64 / 
-                (symbols[first].count as f64 * symbols[second].count as f64);
+        let mut merges = Vec::new();
+        while vocab.len() < self.vocab_size && !self.should_stop() {
+            let ((first, second), score) = match self.best_pair(&words, &symbols) {
+                Some(pair) => pair,
+                None => break, // No more pairs to merge
+            };
+            merges.push((symbols.names[first as usize].clone(), symbols.names[second as usize].clone(), score));
 
-            match score.partial_cmp(&best_score) {
-                Some(Ordering::Greater) => {
-                    best_score = score;
-                    best_pair = Some((first.clone(), second.clone()));
-                }
-                _ => {}
+            let merged = format!(
+                "{}{}",
+                symbols.names[first as usize],
+                &symbols.names[second as usize][self.continuing_subword_prefix.len()..]
+            );
+            let merged = symbols.intern(
+                merged,
+                symbols.lens[first as usize] + symbols.lens[second as usize],
+                symbols.continuation[first as usize],
+            );
+            words.par_iter_mut().for_each(|word| word.merge(first, second, merged));
+            if self.is_kept(&symbols, merged) {
+                vocab.add(&symbols.names[merged as usize]);
             }
+            self.report("merge", vocab.len(), Some(self.vocab_size));
         }
 
-        // If we found a pair to merge, create new symbol
-        if let Some((first, second)) = best_pair.clone() {
-            let merged = format!("{}{}", first, second);
-            let count = pair_counts[&(first.clone(), second.clone())];
-            
-            symbols.insert(
-                merged.clone(),
-                Symbol::new(merged, count),
-            );
+        *self.merges.lock().unwrap() = merges;
+        vocab.tokens
+    }
+
+    /// A segmenter over the initial vocabulary, without the special tokens
+    fn segmenter<'v>(&self, initial_vocab: &'v HashMap<String, TokenId>) -> Segmenter<'v> {
+        let mut trie = Trie::new(initial_vocab, &self.continuing_subword_prefix);
+        for token in &self.special_tokens {
+            trie.remove(token);
         }
 
-        best_pair
+        Segmenter {
+            trie,
+            tokens: initial_vocab.iter().map(|(token, &id)| (id, token.as_str())).collect(),
+            ids: Vec::new(),
+        }
     }
 
-    pub fn train(&self, texts: &[String]) -> HashMap<String, i32> {
-        let mut symbols = self.get_initial_symbols(texts);
-        let mut vocab: HashMap<String, i32> = HashMap::new();
-        let mut next_id = 0;
+    /// Number of characters of a word spanned by `piece`, without its affixes
+    fn piece_len(&self, piece: &str, is_continuation: bool, is_last: bool) -> usize {
+        let mut piece = piece;
+        if is_continuation {
+            piece = piece.strip_prefix(self.continuing_subword_prefix.as_str()).unwrap_or(piece);
+        }
+        if is_last && !self.end_of_word_suffix.is_empty() {
+            piece = piece.strip_suffix(self.end_of_word_suffix.as_str()).unwrap_or(piece);
+        }
+        piece.chars().count()
+    }
 
-        // Add special tokens first
-        for token in &self.special_tokens {
-            vocab.insert(token.clone(), next_id);
-            next_id += 1;
+    /// The characters words are split into: the initial alphabet, then the most
+    /// frequent characters of the corpus up to `limit_alphabet`, ties broken by character
+    fn alphabet(&self, words: &[(&String, &usize)]) -> HashSet<char> {
+        let mut char_counts: HashMap<char, usize> = HashMap::new();
+        for (word, &count) in words {
+            for c in word.chars() {
+                *char_counts.entry(c).or_insert(0) += count;
+            }
+        }
+        for &c in &self.initial_alphabet {
+            char_counts.insert(c, usize::MAX);
         }
 
-        while vocab.len() < self.vocab_size {
-            // Compute pair frequencies
-            let pair_counts = self.compute_pair_scores(texts, &symbols);
-            
-            // Find and merge best pair
-            match self.merge_symbols(&mut symbols, &pair_counts) {
-                Some((first, second)) => {
-                    let merged = format!("{}{}", first, second);
-                    if !vocab.contains_key(&merged) {
-                        vocab.insert(merged, next_id);
-                        next_id += 1;
+        let mut alphabet: Vec<(char, usize)> = char_counts.into_iter().collect();
+        if let Some(limit) = self.limit_alphabet {
+            alphabet.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            alphabet.truncate(limit);
+        }
+        alphabet.into_iter().map(|(c, _)| c).collect()
+    }
+
+    /// The symbol of character `c` at a position of a word, carrying the
+    /// continuation prefix unless it starts the word and the end-of-word suffix
+    /// if it ends the word
+    fn symbol(&self, c: char, is_first: bool, is_last: bool) -> String {
+        let prefix = if is_first { "" } else { &self.continuing_subword_prefix };
+        let suffix = if is_last { &self.end_of_word_suffix[..] } else { "" };
+        format!("{}{}{}", prefix, c, suffix)
+    }
+
+    /// Whether `symbol` belongs in the vocabulary under `min_subword_length`
+    fn is_kept(&self, symbols: &SymbolTable, symbol: u32) -> bool {
+        !symbols.continuation[symbol as usize] || symbols.lens[symbol as usize] >= self.min_subword_length
+    }
+
+    /// The adjacent pair with the highest score among those occurring at least
+    /// `min_frequency` times and merging into a token no longer than
+    /// `max_token_length`, with its score, ties broken by the pair's symbols
+    fn best_pair(&self, words: &[Word], symbols: &SymbolTable) -> Option<((u32, u32), f64)> {
+        let (symbol_counts, pair_counts) = words
+            .par_iter()
+            .fold(
+                || (vec![0usize; symbols.names.len()], HashMap::new()),
+                |(mut symbol_counts, mut pair_counts), word| {
+                    for &symbol in &word.symbols {
+                        symbol_counts[symbol as usize] += word.count;
                     }
+                    for pair in word.symbols.windows(2) {
+                        *pair_counts.entry((pair[0], pair[1])).or_insert(0) += word.count;
+                    }
+                    (symbol_counts, pair_counts)
+                },
+            )
+            .reduce(
+                || (vec![0usize; symbols.names.len()], HashMap::new()),
+                |(mut symbol_counts, pair_counts), (other_symbol_counts, other_pair_counts)| {
+                    for (count, other) in symbol_counts.iter_mut().zip(other_symbol_counts) {
+                        *count += other;
+                    }
+                    (symbol_counts, merge_counts(pair_counts, other_pair_counts))
+                },
+            );
+
+        let min_frequency = self.min_frequency.max(1);
+        pair_counts
+            .par_iter()
+            .filter(|(_, &count)| count >= min_frequency)
+            .filter(|(&(first, second), _)| {
+                self.max_token_length
+                    .is_none_or(|max| symbols.lens[first as usize] + symbols.lens[second as usize] <= max)
+            })
+            .map(|(&(first, second), &count)| {
+                let score = count as f64 / (symbol_counts[first as usize] as f64 * symbol_counts[second as usize] as f64);
+                ((first, second), score)
+            })
+            .reduce_with(|a, b| {
+                // A total order, so the winner doesn't depend on how the work was split
+                let names = |(first, second): (u32, u32)| (&symbols.names[first as usize], &symbols.names[second as usize]);
+                match a.1.partial_cmp(&b.1) {
+                    Some(Ordering::Greater) => a,
+                    Some(Ordering::Less) => b,
+                    _ if names(a.0) <= names(b.0) => a,
+                    _ => b,
                 }
-                None => break, // No more pairs to merge
+            })
+    }
+}
+
+/// Prefix marking a piece that continues a word, unless configured otherwise
+pub(crate) const DEFAULT_CONTINUING_SUBWORD_PREFIX: &str = "##";
+
+/// A distinct word of the corpus split into symbols, with its frequency
+struct Word {
+    symbols: Vec<u32>,
+    count: usize,
+}
+
+impl Word {
+    /// Replace every non-overlapping occurrence of `first` followed by `second`, left to right
+    fn merge(&mut self, first: u32, second: u32, merged: u32) {
+        let mut i = 0;
+        while i + 1 < self.symbols.len() {
+            if self.symbols[i] == first && self.symbols[i + 1] == second {
+                self.symbols[i] = merged;
+                self.symbols.remove(i + 1);
             }
+            i += 1;
         }
+    }
+}
 
-        // Add remaining single-character symbols if space permits
-        for (symbol, _) in symbols.iter() {
-            if vocab.len() >= self.vocab_size {
-                break;
-            }
-            if !vocab.contains_key(symbol) {
-                vocab.insert(symbol.clone(), next_id);
-                next_id += 1;
+/// Interned symbol strings, referred to by index while training
+#[derive(Default)]
+struct SymbolTable {
+    names: Vec<String>,
+    /// Number of characters of a word each symbol spans
+    lens: Vec<usize>,
+    /// Whether each symbol only ever occurs after the start of a word
+    continuation: Vec<bool>,
+    ids: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    fn intern(&mut self, name: String, len: usize, continuation: bool) -> u32 {
+        if let Some(&id) = self.ids.get(&name) {
+            // Without a continuation prefix the same symbol can start and continue words
+            self.continuation[id as usize] &= continuation;
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.ids.insert(name.clone(), id);
+        self.names.push(name);
+        self.lens.push(len);
+        self.continuation.push(continuation);
+        id
+    }
+}
+
+/// Combine the word counts of several corpora, scaling each so its share of all
+/// word occurrences matches its share of the total weight. Words whose scaled
+/// count rounds to zero are dropped.
+pub(crate) fn weight_counts(corpora: Vec<(HashMap<String, usize>, f64)>) -> Result<HashMap<String, usize>> {
+    if let Some(&(_, weight)) = corpora.iter().find(|(_, weight)| !(weight.is_finite() && *weight > 0.0)) {
+        return Err(Error::InvalidCorpusWeight(weight));
+    }
+
+    let corpus_totals: Vec<usize> = corpora.iter().map(|(counts, _)| counts.values().sum()).collect();
+    let total = corpus_totals.iter().sum::<usize>() as f64;
+    let total_weight: f64 = corpora.iter().map(|(_, weight)| weight).sum();
+
+    let mut word_counts = HashMap::new();
+    for ((counts, weight), corpus_total) in corpora.into_iter().zip(corpus_totals) {
+        if corpus_total == 0 {
+            continue;
+        }
+        let scale = weight / total_weight * total / corpus_total as f64;
+        for (word, count) in counts {
+            let count = (count as f64 * scale).round() as usize;
+            if count > 0 {
+                *word_counts.entry(word).or_insert(0) += count;
             }
         }
+    }
+    Ok(word_counts)
+}
+
+/// Add the counts of `other` to those of `counts`, reusing the larger map
+fn merge_counts<K: Hash + Eq>(mut counts: HashMap<K, usize>, mut other: HashMap<K, usize>) -> HashMap<K, usize> {
+    if counts.len() < other.len() {
+        std::mem::swap(&mut counts, &mut other);
+    }
+    merge_counts_into(&mut counts, other);
+    counts
+}
+
+fn merge_counts_into<K: Hash + Eq>(counts: &mut HashMap<K, usize>, other: HashMap<K, usize>) {
+    for (key, count) in other {
+        *counts.entry(key).or_insert(0) += count;
+    }
+}
+
+/// Vocabulary being learned, assigning ids in order after the largest id so far
+struct VocabBuilder {
+    tokens: HashMap<String, TokenId>,
+    next_id: TokenId,
+}
+
+impl VocabBuilder {
+    fn new(tokens: HashMap<String, TokenId>) -> Self {
+        let next_id = tokens.values().max().map_or(0, |&max| max + 1);
+        VocabBuilder { tokens, next_id }
+    }
+
+    fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Assign the next id to `token` unless it is already in the vocabulary
+    fn add(&mut self, token: &str) {
+        if !self.tokens.contains_key(token) {
+            self.tokens.insert(token.to_string(), self.next_id);
+            self.next_id += 1;
+        }
+    }
+}
+
+/// Segments words into their WordPiece pieces in an initial vocabulary
+struct Segmenter<'v> {
+    trie: Trie,
+    tokens: HashMap<TokenId, &'v str>,
+    ids: Vec<TokenId>,
+}
+
+impl<'v> Segmenter<'v> {
+    /// Replace `pieces` with the pieces of `word`, returning false if the word
+    /// cannot be segmented with the vocabulary
+    fn segment(&mut self, word: &str, end_of_word_suffix: &str, pieces: &mut Vec<&'v str>) -> bool {
+        self.ids.clear();
+        pieces.clear();
+        if !self.trie.tokenize_word(&format!("{}{}", word, end_of_word_suffix), &mut self.ids) {
+            return false;
+        }
+        pieces.extend(self.ids.iter().map(|id| self.tokens[id]));
+        true
+    }
+}
 
-        vocab
+/// Resolve glob patterns to the files they match, in sorted order per pattern
+fn expand_patterns<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let matches = glob::glob(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let start = paths.len();
+        for path in matches {
+            paths.push(path.map_err(io::Error::from)?);
+        }
+        if paths.len() == start {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No files match '{}'", pattern)).into());
+        }
     }
-}
\ No newline at end of file
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trainer() -> WordPieceTrainer {
+        WordPieceTrainer::new(100, 1, vec!["[UNK]".to_string()], true, true)
+    }
+
+    /// A corpus whose second chunk holds the only `z`
+    fn corpus() -> Vec<&'static str> {
+        let mut texts = vec!["low lower lowest newer wider"; COUNT_CHUNK_SIZE];
+        texts.push("zebra");
+        texts
+    }
+
+    #[test]
+    fn stopping_keeps_the_words_counted_so_far() {
+        let mut trainer = trainer();
+        trainer.set_should_stop(|| true);
+        let vocab = trainer.train_from_iter(corpus());
+
+        assert!(trainer.merges().is_empty());
+        assert!(vocab.contains_key("l") && vocab.contains_key("##w"));
+        assert!(!vocab.contains_key("z"), "the chunk after the stop was counted");
+    }
+
+    #[test]
+    fn stopping_between_merges_keeps_the_merges_so_far() {
+        let full = trainer();
+        full.train_from_iter(corpus());
+        let all_merges = full.merges();
+        assert!(all_merges.len() > 3);
+
+        let merged = Arc::new(AtomicUsize::new(0));
+        let mut trainer = trainer();
+        let counter = merged.clone();
+        trainer.set_progress(move |phase, _, _| {
+            if phase == "merge" {
+                counter.fetch_add(1, AtomicOrdering::Relaxed);
+            }
+        });
+        trainer.set_should_stop(move || merged.load(AtomicOrdering::Relaxed) >= 3);
+        let vocab = trainer.train_from_iter(corpus());
+
+        assert_eq!(trainer.merges(), all_merges[..3]);
+        assert!(vocab.contains_key("z"));
+    }
+}
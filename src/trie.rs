@@ -0,0 +1,574 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::OnceLock;
+
+use crate::memory::hash_map_size;
+use crate::TokenId;
+
+/// Index of a node in `Trie::nodes`, or of a state of a `DoubleArray`
+pub(crate) type NodeId = u32;
+
+/// Sentinel for a missing failure link, child or table of children
+pub(crate) const NONE: NodeId = NodeId::MAX;
+
+/// The root node, reached before consuming any characters
+pub(crate) const ROOT: NodeId = 0;
+
+/// The root of continuation tokens, standing for the continuation prefix
+/// (`##` by default) without storing it as characters
+pub(crate) const SUFFIX_ROOT: NodeId = 1;
+
+/// A node in the trie. Nodes and their child tables live in arenas owned by
+/// the trie and refer to each other by index, so building a trie makes no
+/// allocation per node.
+#[derive(Clone, Copy)]
+struct TrieNode {
+    /// Index in `Trie::ascii_children` of the node's table of ASCII children,
+    /// allocated with its first ASCII child
+    ascii: u32,
+    token_id: Option<TokenId>,
+}
+
+impl Default for TrieNode {
+    fn default() -> Self {
+        TrieNode {
+            ascii: NONE,
+            token_id: None,
+        }
+    }
+}
+
+/// Where matching continues from a node when no child matches the next character
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct FailureLink {
+    /// Node to continue matching from, `NONE` at the roots
+    node: NodeId,
+    /// Range in `FailureLinks::pops` of the token ids emitted when following the link
+    pops: (u32, u32),
+}
+
+/// The failure links of every node, built together in one pass over the trie
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct FailureLinks {
+    links: Vec<FailureLink>,
+    pops: Vec<TokenId>,
+}
+
+impl FailureLinks {
+    /// Compute the failure link and failure pops of every node of an automaton
+    /// with `num_nodes` nodes in breadth-first order, given its edges as
+    /// (parent, label, child) ordered by parent. The root and the suffix root
+    /// both start at depth zero, since a failure link never leads to a node
+    /// with a longer match below either.
+    pub(crate) fn build<L, T, C>(num_nodes: usize, edges: &[(NodeId, L, NodeId)], token_id: T, child: C) -> Self
+    where
+        L: Copy,
+        T: Fn(NodeId) -> Option<TokenId>,
+        C: Fn(NodeId, L) -> Option<NodeId>,
+    {
+        let none = FailureLink {
+            node: NONE,
+            pops: (0, 0),
+        };
+        let mut failure_links = FailureLinks {
+            links: vec![none; num_nodes],
+            pops: Vec::new(),
+        };
+
+        let mut queue = VecDeque::from([ROOT, SUFFIX_ROOT]);
+        let mut pops = Vec::new();
+
+        while let Some(parent) = queue.pop_front() {
+            let first = edges.partition_point(|&(from, _, _)| from < parent);
+            let children = edges[first..].iter().take_while(|&&(from, _, _)| from == parent);
+            for &(_, label, node) in children {
+                queue.push_back(node);
+
+                let (fail_link, fail_pops) = match token_id(node) {
+                    // A complete token: emit it and continue with a `##` piece
+                    Some(id) => {
+                        pops.clear();
+                        pops.push(id);
+                        (SUFFIX_ROOT, &pops)
+                    }
+                    // Otherwise inherit the parent's pops and fall back until
+                    // some node can consume `label`
+                    None => {
+                        pops.clear();
+                        let FailureLink { node: mut fallback, pops: (start, len) } =
+                            failure_links.links[parent as usize];
+                        pops.extend_from_slice(&failure_links.pops[start as usize..(start + len) as usize]);
+
+                        let mut fail_link = NONE;
+                        while fallback != NONE {
+                            if let Some(next) = child(fallback, label) {
+                                fail_link = next;
+                                break;
+                            }
+                            let link = failure_links.links[fallback as usize];
+                            let (start, len) = link.pops;
+                            pops.extend_from_slice(&failure_links.pops[start as usize..(start + len) as usize]);
+                            fallback = link.node;
+                        }
+                        (fail_link, &pops)
+                    }
+                };
+
+                let start = failure_links.pops.len() as u32;
+                failure_links.pops.extend_from_slice(fail_pops);
+                failure_links.links[node as usize] = FailureLink {
+                    node: fail_link,
+                    pops: (start, fail_pops.len() as u32),
+                };
+            }
+        }
+
+        failure_links
+    }
+
+    /// Emit the failure pops of `node` and return its failure link, if it has one
+    #[inline]
+    pub(crate) fn follow(&self, node: NodeId, ids: &mut Vec<TokenId>) -> Option<NodeId> {
+        let link = self.links[node as usize];
+        if link.node == NONE {
+            return None;
+        }
+        let (start, len) = link.pops;
+        ids.extend_from_slice(&self.pops[start as usize..(start + len) as usize]);
+        Some(link.node)
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.links.capacity() * size_of::<FailureLink>() + self.pops.capacity() * size_of::<TokenId>()
+    }
+
+    /// Check that there is a link for each of `num_nodes` nodes, leading to one
+    /// of them, and that every failure pop range stays inside the pops and every
+    /// popped id satisfies `is_known_id`
+    pub(crate) fn is_consistent<F: Fn(TokenId) -> bool>(&self, num_nodes: usize, is_known_id: F) -> bool {
+        self.links.len() == num_nodes
+            && self.pops.iter().all(|&id| is_known_id(id))
+            && self.links.iter().all(|link| {
+                let (start, len) = link.pops;
+                (link.node == NONE || (link.node as usize) < num_nodes)
+                    && (start as usize).saturating_add(len as usize) <= self.pops.len()
+            })
+    }
+}
+
+/// The roots a token is stored under, each with the rest of the token spelled
+/// below it. Continuation tokens go below the suffix root without their prefix,
+/// and with an empty prefix every token goes below both roots.
+pub(crate) fn token_locations<'t>(continuation_prefix: &str, token: &'t str) -> Vec<(NodeId, &'t str)> {
+    if continuation_prefix.is_empty() {
+        return vec![(ROOT, token), (SUFFIX_ROOT, token)];
+    }
+    match token.strip_prefix(continuation_prefix) {
+        Some(rest) => vec![(SUFFIX_ROOT, rest)],
+        None => vec![(ROOT, token)],
+    }
+}
+
+/// Vocabulary trie with Aho-Corasick style failure links, implementing the
+/// LinMaxMatch algorithm from "Fast WordPiece Tokenization" (Song et al., 2021).
+///
+/// Continuation tokens are stored without their `##` prefix below a separate
+/// suffix root, so a word is never matched against them from its first
+/// character and no prefix has to be consumed between pieces. With an empty
+/// prefix every token is stored below both roots, as any token may start or
+/// continue a word. Following the failure links yields exactly the same
+/// segmentation as greedy longest-match-first WordPiece, but each character
+/// of the word is visited a constant number of times on average.
+///
+/// ASCII characters, which dominate English text, are looked up in a table of
+/// children by direct indexing. The rarer other characters are looked up by
+/// hashing the node with the character.
+///
+/// Failure links are built on the first word segmented, as they take a pass
+/// over the whole trie, or ahead of time with `precompile`.
+#[derive(Clone)]
+pub(crate) struct Trie {
+    nodes: Vec<TrieNode>,
+    /// Child for each ASCII character of the nodes that have ASCII children
+    ascii_children: Vec<[NodeId; 128]>,
+    /// Children reached with characters outside of ASCII
+    other_children: HashMap<(NodeId, char), NodeId>,
+    /// Prefix marking a token that continues a word
+    continuation_prefix: String,
+    /// Built on first use and dropped when tokens are removed
+    failure_links: OnceLock<FailureLinks>,
+}
+
+impl Trie {
+    /// Build a trie, with failure links, from (token, id) pairs whose continuation
+    /// tokens start with `continuation_prefix`
+    pub(crate) fn new<'a, I: IntoIterator<Item = (&'a String, &'a TokenId)>>(vocab: I, continuation_prefix: &str) -> Self {
+        let mut trie = Trie {
+            nodes: vec![TrieNode::default(), TrieNode::default()],
+            ascii_children: Vec::new(),
+            other_children: HashMap::new(),
+            continuation_prefix: continuation_prefix.to_string(),
+            failure_links: OnceLock::new(),
+        };
+        for (token, &id) in vocab {
+            for (root, chars) in trie.locations(token) {
+                // A bare prefix would be an empty continuation and can never be matched
+                if chars.is_empty() {
+                    continue;
+                }
+                let node = trie.walk_or_insert(root, chars);
+                trie.nodes[node as usize].token_id = Some(id);
+            }
+        }
+
+        trie
+    }
+
+    /// Unmark a token so it is no longer matched, leaving the rest of the trie
+    /// intact. The failure links are rebuilt on the next word segmented.
+    pub(crate) fn remove(&mut self, token: &str) {
+        for (root, chars) in self.locations(token) {
+            if let Some(node) = self.walk(root, chars) {
+                if self.nodes[node as usize].token_id.take().is_some() {
+                    self.failure_links.take();
+                }
+            }
+        }
+    }
+
+    /// Build the failure links now if they are not built yet
+    pub(crate) fn precompile(&self) {
+        self.failure_links();
+    }
+
+    fn failure_links(&self) -> &FailureLinks {
+        self.failure_links.get_or_init(|| self.build_failure_links())
+    }
+
+    /// Segment a word into token ids with LinMaxMatch, appending them to `ids`.
+    /// Returns false, leaving `ids` in an unspecified state, if the word cannot
+    /// be segmented with the vocabulary.
+    pub(crate) fn tokenize_word(&self, word: &str, ids: &mut Vec<TokenId>) -> bool {
+        let links = self.failure_links();
+        let mut node = ROOT;
+
+        for c in word.chars() {
+            loop {
+                if let Some(next) = self.child(node, c) {
+                    node = next;
+                    break;
+                }
+                match links.follow(node, ids) {
+                    Some(next) => node = next,
+                    None => return false,
+                }
+            }
+        }
+
+        // Flush the pieces still pending on the matched path
+        while node != ROOT && node != SUFFIX_ROOT {
+            match links.follow(node, ids) {
+                Some(next) => node = next,
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Estimated bytes of memory held by the nodes, their child tables and the
+    /// failure links, once built
+    pub(crate) fn memory_usage(&self) -> usize {
+        let failure_links = self.failure_links.get().map_or(0, FailureLinks::memory_usage);
+        self.nodes.capacity() * size_of::<TrieNode>()
+            + self.ascii_children.capacity() * size_of::<[NodeId; 128]>()
+            + hash_map_size(&self.other_children)
+            + failure_links
+            + self.continuation_prefix.capacity()
+    }
+
+    /// Check that every link and failure pop range stays inside the trie and that
+    /// every token id satisfies `is_known_id`, as a loaded trie may be corrupt
+    pub(crate) fn is_consistent<F: Fn(TokenId) -> bool>(&self, is_known_id: F) -> bool {
+        let in_bounds = |node: NodeId| (node as usize) < self.nodes.len();
+        in_bounds(SUFFIX_ROOT)
+            && self.failure_links.get().is_none_or(|links| links.is_consistent(self.nodes.len(), &is_known_id))
+            && self.ascii_children.iter().flatten().all(|&child| child == NONE || in_bounds(child))
+            && self.other_children.iter().all(|(&(parent, _), &child)| in_bounds(parent) && in_bounds(child))
+            && self.nodes.iter().all(|node| {
+                (node.ascii == NONE || (node.ascii as usize) < self.ascii_children.len())
+                    && node.token_id.is_none_or(&is_known_id)
+            })
+    }
+
+    /// The child of `node` reached with `c`
+    #[inline]
+    fn child(&self, node: NodeId, c: char) -> Option<NodeId> {
+        if c.is_ascii() {
+            let table = self.nodes[node as usize].ascii;
+            match self.ascii_children.get(table as usize) {
+                Some(children) if children[c as usize] != NONE => Some(children[c as usize]),
+                _ => None,
+            }
+        } else {
+            self.other_children.get(&(node, c)).copied()
+        }
+    }
+
+    fn insert_child(&mut self, node: NodeId, c: char, child: NodeId) {
+        if c.is_ascii() {
+            if self.nodes[node as usize].ascii == NONE {
+                self.nodes[node as usize].ascii = self.ascii_children.len() as u32;
+                self.ascii_children.push([NONE; 128]);
+            }
+            self.ascii_children[self.nodes[node as usize].ascii as usize][c as usize] = child;
+        } else {
+            self.other_children.insert((node, c), child);
+        }
+    }
+
+    /// Every edge of the trie as (parent, character, child), ordered by parent
+    /// and then by character
+    fn edges(&self) -> Vec<(NodeId, char, NodeId)> {
+        let mut edges: Vec<(NodeId, char, NodeId)> =
+            self.other_children.iter().map(|(&(parent, c), &child)| (parent, c, child)).collect();
+        for (parent, node) in self.nodes.iter().enumerate() {
+            let Some(children) = self.ascii_children.get(node.ascii as usize) else {
+                continue;
+            };
+            for (c, &child) in children.iter().enumerate().filter(|(_, &child)| child != NONE) {
+                edges.push((parent as NodeId, c as u8 as char, child));
+            }
+        }
+        edges.sort_unstable();
+        edges
+    }
+
+    fn locations<'t>(&self, token: &'t str) -> Vec<(NodeId, &'t str)> {
+        token_locations(&self.continuation_prefix, token)
+    }
+
+    /// The node spelling `chars` below `root`, if present
+    fn walk(&self, root: NodeId, chars: &str) -> Option<NodeId> {
+        let mut node = root;
+        for c in chars.chars() {
+            node = self.child(node, c)?;
+        }
+        Some(node)
+    }
+
+    fn walk_or_insert(&mut self, root: NodeId, chars: &str) -> NodeId {
+        let mut node = root;
+        for c in chars.chars() {
+            node = match self.child(node, c) {
+                Some(next) => next,
+                None => {
+                    let next = self.nodes.len() as NodeId;
+                    self.nodes.push(TrieNode::default());
+                    self.insert_child(node, c, next);
+                    next
+                }
+            };
+        }
+        node
+    }
+
+    fn build_failure_links(&self) -> FailureLinks {
+        FailureLinks::build(
+            self.nodes.len(),
+            &self.edges(),
+            |node| self.nodes[node as usize].token_id,
+            |node, c| self.child(node, c),
+        )
+    }
+}
+
+/// Serialized form of `Trie`: the token id of each node, the edges between
+/// them rather than the mostly empty ASCII tables, and the failure links, so
+/// that loading a trie builds nothing
+#[derive(Serialize, Deserialize)]
+struct TrieParts {
+    nodes: Vec<Option<TokenId>>,
+    edges: Vec<(NodeId, char, NodeId)>,
+    failure_links: FailureLinks,
+    continuation_prefix: String,
+}
+
+impl Serialize for Trie {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TrieParts {
+            nodes: self.nodes.iter().map(|node| node.token_id).collect(),
+            edges: self.edges(),
+            failure_links: self.failure_links().clone(),
+            continuation_prefix: self.continuation_prefix.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Trie {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let parts = TrieParts::deserialize(deserializer)?;
+        let mut trie = Trie {
+            nodes: parts
+                .nodes
+                .into_iter()
+                .map(|token_id| TrieNode {
+                    token_id,
+                    ..TrieNode::default()
+                })
+                .collect(),
+            ascii_children: Vec::new(),
+            other_children: HashMap::new(),
+            continuation_prefix: parts.continuation_prefix,
+            failure_links: OnceLock::from(parts.failure_links),
+        };
+        // Children and failure links are checked by `is_consistent` once the
+        // vocabulary is loaded
+        for (parent, c, child) in parts.edges {
+            if parent as usize >= trie.nodes.len() {
+                return Err(D::Error::custom(format!("edge from missing node {}", parent)));
+            }
+            trie.insert_child(parent, c, child);
+        }
+        Ok(trie)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Letters of the generated vocabularies, mixing one to three byte characters
+    const LETTERS: [char; 5] = ['a', 'b', 'c', 'é', '日'];
+
+    /// Xorshift generator, so that the generated cases are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn string(&mut self, max_len: usize) -> String {
+            (0..=self.below(max_len)).map(|_| LETTERS[self.below(LETTERS.len())]).collect()
+        }
+    }
+
+    /// Small random tokens, some continuing a word with `continuation_prefix`,
+    /// and words joining their spellings and stray letters, so that words need
+    /// several pieces and some cannot be segmented
+    pub(crate) fn random_tokens_and_words(seed: u64, continuation_prefix: &str) -> (Vec<String>, Vec<String>) {
+        let mut rng = Rng(seed);
+        let spellings: Vec<String> = (0..16).map(|_| rng.string(3)).collect();
+        let tokens = spellings
+            .iter()
+            .map(|spelling| match rng.below(2) {
+                0 => spelling.clone(),
+                _ => format!("{}{}", continuation_prefix, spelling),
+            })
+            .collect();
+        let words = (0..40)
+            .map(|_| {
+                (0..=rng.below(4))
+                    .map(|_| match rng.below(5) {
+                        0 => rng.string(1),
+                        _ => spellings[rng.below(spellings.len())].clone(),
+                    })
+                    .collect()
+            })
+            .collect();
+        (tokens, words)
+    }
+
+    /// Segment `word` with greedy longest-match-first WordPiece, as in BERT
+    pub(crate) fn greedy_longest_match(
+        vocab: &HashMap<String, TokenId>,
+        continuation_prefix: &str,
+        word: &str,
+    ) -> Option<Vec<TokenId>> {
+        let mut ids = Vec::new();
+        let mut start = 0;
+        while start < word.len() {
+            let ends: Vec<usize> = word[start..].char_indices().map(|(i, c)| start + i + c.len_utf8()).collect();
+            let (end, id) = ends.into_iter().rev().find_map(|end| {
+                let piece = match start {
+                    0 => word[..end].to_string(),
+                    _ => format!("{}{}", continuation_prefix, &word[start..end]),
+                };
+                vocab.get(&piece).map(|&id| (end, id))
+            })?;
+            ids.push(id);
+            start = end;
+        }
+        Some(ids)
+    }
+
+    /// Vocabulary numbering `tokens` in order
+    pub(crate) fn vocab_of<S: AsRef<str>>(tokens: &[S]) -> HashMap<String, TokenId> {
+        tokens.iter().enumerate().map(|(id, token)| (token.as_ref().to_string(), id as TokenId)).collect()
+    }
+
+    fn assert_segments_like_greedy(trie: &Trie, vocab: &HashMap<String, TokenId>, continuation_prefix: &str, word: &str) {
+        let mut ids = Vec::new();
+        let segmented = trie.tokenize_word(word, &mut ids).then_some(ids);
+        assert_eq!(segmented, greedy_longest_match(vocab, continuation_prefix, word), "{:?}", word);
+    }
+
+    fn assert_matches_greedy(vocab: &HashMap<String, TokenId>, continuation_prefix: &str, words: &[&str]) {
+        let trie = Trie::new(vocab.iter(), continuation_prefix);
+        for word in words {
+            assert_segments_like_greedy(&trie, vocab, continuation_prefix, word);
+        }
+    }
+
+    #[test]
+    fn failure_links_match_greedy_longest_match_first() {
+        // The example from the Fast WordPiece paper, where "abcdz" backs off
+        // from the path to "abcdx" through several failure links
+        let vocab = vocab_of(&["a", "abcdx", "##b", "##c", "##cdy", "##dz"]);
+        assert_matches_greedy(&vocab, "##", &["abcdz", "abcdx", "abcdy", "abcd", "abc", "a", "", "b", "abz"]);
+
+        let vocab = vocab_of(&["un", "una", "##aff", "##able", "##a", "##ffa", "##ble", "日本", "##語", "é", "##té", "##é"]);
+        let words = ["unaffable", "unaffa", "unable", "una", "日本語", "日本", "日", "été", "éé", "éb", "unaffable日"];
+        assert_matches_greedy(&vocab, "##", &words);
+    }
+
+    #[test]
+    fn empty_continuation_prefix_matches_greedy_longest_match_first() {
+        let vocab = vocab_of(&["a", "ab", "b", "bc", "c", "日", "日本", "本語"]);
+        assert_matches_greedy(&vocab, "", &["abc", "abbc", "日本語", "cab", "abd", ""]);
+        for seed in 1..=200 {
+            let (tokens, words) = random_tokens_and_words(seed, "");
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            assert_matches_greedy(&vocab_of(&tokens), "", &words);
+        }
+    }
+
+    #[test]
+    fn removed_tokens_are_no_longer_matched() {
+        let mut vocab = vocab_of(&["a", "ab", "##b", "##bc", "##c", "é", "##日"]);
+        let mut trie = Trie::new(vocab.iter(), "##");
+        for removed in ["ab", "##bc", "é"] {
+            trie.remove(removed);
+            vocab.remove(removed);
+            for word in ["abc", "ab", "abbc", "é日", "a日"] {
+                assert_segments_like_greedy(&trie, &vocab, "##", word);
+            }
+        }
+    }
+
+    #[test]
+    fn random_vocabularies_match_greedy_longest_match_first() {
+        for seed in 1..=200 {
+            let (tokens, words) = random_tokens_and_words(seed, "##");
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            assert_matches_greedy(&vocab_of(&tokens), "##", &words);
+        }
+    }
+}
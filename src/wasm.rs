@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+use crate::export::parse_vocab;
+use crate::{TokenId, WordPieceTokenizer};
+
+/// The tokenizer as a JavaScript class, for browsers and edge runtimes
+#[wasm_bindgen(js_name = WordPieceTokenizer)]
+pub struct WasmWordPieceTokenizer {
+    tokenizer: WordPieceTokenizer,
+}
+
+#[wasm_bindgen(js_class = WordPieceTokenizer)]
+impl WasmWordPieceTokenizer {
+    /// Build a tokenizer from an array of tokens, the index of each being its id.
+    /// Lowercasing and accent stripping are on unless set to `false`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        tokens: Vec<String>,
+        unk_token: Option<String>,
+        lowercase: Option<bool>,
+        strip_accents: Option<bool>,
+    ) -> Result<WasmWordPieceTokenizer, JsError> {
+        let vocab = tokens.into_iter().enumerate().map(|(id, token)| (token, id as TokenId)).collect();
+        Self::from_vocab(vocab, unk_token, lowercase, strip_accents)
+    }
+
+    /// Build a tokenizer from the contents of a `vocab.txt` file, with the same
+    /// options as the constructor
+    #[wasm_bindgen(js_name = fromVocabTxt)]
+    pub fn from_vocab_txt(
+        text: &str,
+        unk_token: Option<String>,
+        lowercase: Option<bool>,
+        strip_accents: Option<bool>,
+    ) -> Result<WasmWordPieceTokenizer, JsError> {
+        Self::from_vocab(parse_vocab(text), unk_token, lowercase, strip_accents)
+    }
+
+    /// Load a tokenizer from the bytes of a file written by `save_compiled`,
+    /// which skips building the vocabulary trie
+    #[wasm_bindgen(js_name = fromCompiled)]
+    pub fn from_compiled(bytes: &[u8]) -> Result<WasmWordPieceTokenizer, JsError> {
+        Ok(WasmWordPieceTokenizer {
+            tokenizer: WordPieceTokenizer::from_compiled_bytes(bytes)?,
+        })
+    }
+
+    fn from_vocab(
+        vocab: HashMap<String, TokenId>,
+        unk_token: Option<String>,
+        lowercase: Option<bool>,
+        strip_accents: Option<bool>,
+    ) -> Result<WasmWordPieceTokenizer, JsError> {
+        let tokenizer = WordPieceTokenizer::builder()
+            .vocab(vocab)
+            .unk_token(unk_token.as_deref().unwrap_or("[UNK]"))
+            .lowercase(lowercase.unwrap_or(true))
+            .strip_accents(strip_accents.unwrap_or(true))
+            .build()?;
+        Ok(WasmWordPieceTokenizer { tokenizer })
+    }
+
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.tokenizer.tokenize(text)
+    }
+
+    /// Encode `text` to token ids, throwing for a text over the tokenizer's
+    /// `max_input_bytes` or `max_tokens`
+    pub fn encode(&self, text: &str) -> Result<Vec<TokenId>, JsError> {
+        Ok(self.tokenizer.try_encode(text)?)
+    }
+
+    /// Number of tokens `text` encodes to, e.g. to show how much of a model's
+    /// context an input uses as it is typed. Throws as `encode` does.
+    #[wasm_bindgen(js_name = countTokens)]
+    pub fn count_tokens(&self, text: &str) -> Result<usize, JsError> {
+        Ok(self.tokenizer.try_count_tokens(text)?)
+    }
+
+    /// Turn ids back into text. Special tokens are kept and the spaces before
+    /// punctuation removed unless told otherwise.
+    pub fn decode(
+        &self,
+        ids: &[TokenId],
+        skip_special_tokens: Option<bool>,
+        clean_up_tokenization_spaces: Option<bool>,
+    ) -> String {
+        self.tokenizer.decode(
+            ids,
+            skip_special_tokens.unwrap_or(false),
+            clean_up_tokenization_spaces.unwrap_or(true),
+        )
+    }
+
+    #[wasm_bindgen(js_name = tokenToId)]
+    pub fn token_to_id(&self, token: &str) -> Option<TokenId> {
+        self.tokenizer.vocab().get(token).copied()
+    }
+
+    #[wasm_bindgen(js_name = idToToken)]
+    pub fn id_to_token(&self, id: TokenId) -> Option<String> {
+        self.tokenizer.id_to_token(id).map(str::to_string)
+    }
+
+    #[wasm_bindgen(getter, js_name = vocabSize)]
+    pub fn vocab_size(&self) -> usize {
+        self.tokenizer.vocab().len()
+    }
+}